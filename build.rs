@@ -0,0 +1,15 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tonic_build::configure()
+        .build_server(true)
+        .build_client(true)
+        .compile(
+            &[
+                "proto/gateway.proto",
+                "proto/chirpstack_gw.proto",
+                "proto/packetbroker.proto",
+                "proto/packetbroker_routing.proto",
+            ],
+            &["proto"],
+        )?;
+    Ok(())
+}
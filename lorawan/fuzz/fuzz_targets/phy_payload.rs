@@ -0,0 +1,17 @@
+//! Fuzzes `PHYPayload::read` in both directions. LoRaWAN frames are received
+//! over the air and parsed before any authentication, so no input may cause a
+//! panic. Frames that parse are written back out to exercise the writers on
+//! the parsed values.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lorawan::{Direction, PHYPayload};
+
+fuzz_target!(|data: &[u8]| {
+    for direction in [Direction::Uplink, Direction::Downlink] {
+        if let Ok(payload) = PHYPayload::read(direction, &mut &data[..]) {
+            let mut output = Vec::with_capacity(data.len());
+            let _ = payload.write(&mut output);
+        }
+    }
+});
@@ -0,0 +1,199 @@
+//! LoRaWAN 1.0.x message integrity codes and frame payload encryption.
+//!
+//! Data frames are signed with the network session key and their frame
+//! payload is encrypted with the application session key, or the network
+//! session key for MAC commands on port 0. Join requests and (decrypted) join
+//! accepts are signed with the application key. All keys are AES-128 keys.
+
+use super::{Direction, LoraWanError, MACPayload, MType, PHYPayload, PHYPayloadFrame};
+use aes::{
+    cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit},
+    Aes128,
+};
+use cmac::{Cmac, Mac};
+
+/// An AES-128 key, like the application or network session key
+pub type AesKey = [u8; 16];
+
+const BLOCK_SIZE: usize = 16;
+const B0_PREFIX: u8 = 0x49;
+const A_PREFIX: u8 = 0x01;
+
+impl PHYPayload {
+    /// Computes the message integrity code of the frame. Data frames use the
+    /// network session key and the frame counter in the frame header as the
+    /// low 16 bits of the 32 bit frame counter. Join frames use the
+    /// application key.
+    pub fn compute_mic(&self, key: &AesKey) -> Result<[u8; 4], LoraWanError> {
+        let mut msg = vec![];
+        self.mhdr.write(&mut msg)?;
+        self.payload.write(&mut msg)?;
+        let mut cmac = <Cmac<Aes128> as KeyInit>::new(key.into());
+        match &self.payload {
+            PHYPayloadFrame::MACPayload(payload) => {
+                let direction = data_direction(self.mtype())?;
+                let block = block(
+                    B0_PREFIX,
+                    direction,
+                    payload.dev_addr(),
+                    payload.fhdr.fcnt.into(),
+                    msg.len() as u8,
+                );
+                cmac.update(&block);
+            }
+            PHYPayloadFrame::JoinRequest(_) | PHYPayloadFrame::JoinAccept(_) => (),
+            PHYPayloadFrame::Proprietary(_) => {
+                return Err(LoraWanError::InvalidPacketType(MType::Proprietary.into()))
+            }
+        }
+        cmac.update(&msg);
+        let mut mic = [0u8; 4];
+        mic.copy_from_slice(&cmac.finalize().into_bytes()[..4]);
+        Ok(mic)
+    }
+
+    /// Whether the message integrity code of the frame matches the one
+    /// computed with the given key. Frames without a MIC never match.
+    pub fn verify_mic(&self, key: &AesKey) -> Result<bool, LoraWanError> {
+        match self.mic {
+            Some(mic) => Ok(self.compute_mic(key)? == mic),
+            None => Ok(false),
+        }
+    }
+}
+
+impl MACPayload {
+    /// Returns the decrypted frame payload, or None if the frame has no
+    /// payload. The key is the application session key, or the network
+    /// session key for frames on port 0. The mtype is the message type of the
+    /// frame, which determines its direction.
+    pub fn decrypt_frm_payload(
+        &self,
+        mtype: MType,
+        key: &AesKey,
+    ) -> Result<Option<Vec<u8>>, LoraWanError> {
+        let Some(payload) = &self.payload else {
+            return Ok(None);
+        };
+        let mut data = vec![];
+        payload.write(&mut data)?;
+        crypt_frm_payload(
+            key,
+            data_direction(mtype)?,
+            self.dev_addr(),
+            self.fhdr.fcnt.into(),
+            &mut data,
+        );
+        Ok(Some(data))
+    }
+}
+
+/// Encrypts or decrypts a frame payload in place. The operation is its own
+/// inverse, so the same call encrypts a plain payload and decrypts an
+/// encrypted one.
+pub fn crypt_frm_payload(
+    key: &AesKey,
+    direction: Direction,
+    dev_addr: u32,
+    fcnt: u32,
+    data: &mut [u8],
+) {
+    let cipher = Aes128::new(key.into());
+    for (index, chunk) in data.chunks_mut(BLOCK_SIZE).enumerate() {
+        let mut block = GenericArray::from(self::block(
+            A_PREFIX,
+            direction,
+            dev_addr,
+            fcnt,
+            (index + 1) as u8,
+        ));
+        cipher.encrypt_block(&mut block);
+        for (byte, key_byte) in chunk.iter_mut().zip(block.iter()) {
+            *byte ^= key_byte;
+        }
+    }
+}
+
+/// The B0 and Ai blocks, which differ only in their prefix and last byte
+fn block(prefix: u8, direction: Direction, dev_addr: u32, fcnt: u32, last: u8) -> [u8; 16] {
+    let mut block = [0u8; BLOCK_SIZE];
+    block[0] = prefix;
+    block[5] = match direction {
+        Direction::Uplink => 0,
+        Direction::Downlink => 1,
+    };
+    block[6..10].copy_from_slice(&dev_addr.to_le_bytes());
+    block[10..14].copy_from_slice(&fcnt.to_le_bytes());
+    block[15] = last;
+    block
+}
+
+fn data_direction(mtype: MType) -> Result<Direction, LoraWanError> {
+    match mtype {
+        MType::UnconfirmedUp | MType::ConfirmedUp => Ok(Direction::Uplink),
+        MType::UnconfirmedDown | MType::ConfirmedDown => Ok(Direction::Downlink),
+        other => Err(LoraWanError::InvalidPacketType(other.into())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(start: u8) -> AesKey {
+        std::array::from_fn(|index| start + index as u8)
+    }
+
+    #[test]
+    fn test_data_mic_and_payload() {
+        let (nwk_skey, app_skey) = (key(0x10), key(0x20));
+        let data: &[u8] = &[
+            64, 52, 18, 1, 38, 0, 7, 0, 1, 163, 237, 215, 229, 132, 93, 68, 111, 31, 233, 162, 151,
+            195, 69, 79, 189, 9, 169, 119, 67, 17, 232, 171, 149, 140,
+        ];
+        let mut payload = PHYPayload::read(Direction::Uplink, &mut &data[..]).unwrap();
+        assert!(payload.verify_mic(&nwk_skey).unwrap());
+        assert!(!payload.verify_mic(&app_skey).unwrap());
+        let PHYPayloadFrame::MACPayload(mac_payload) = &payload.payload else {
+            panic!("expected a data frame")
+        };
+        let plain = mac_payload
+            .decrypt_frm_payload(payload.mtype(), &app_skey)
+            .unwrap();
+        assert_eq!(Some(b"hello world, lorawan!".to_vec()), plain);
+
+        // A changed frame counter invalidates the mic
+        if let PHYPayloadFrame::MACPayload(mac_payload) = &mut payload.payload {
+            mac_payload.fhdr.fcnt += 1;
+        }
+        assert!(!payload.verify_mic(&nwk_skey).unwrap());
+    }
+
+    #[test]
+    fn test_join_request_mic() {
+        let data: &[u8] = &[
+            0, 1, 0, 0, 208, 126, 213, 179, 112, 48, 5, 28, 0, 11, 163, 4, 0, 18, 52, 72, 113, 128,
+            75,
+        ];
+        let payload = PHYPayload::read(Direction::Uplink, &mut &data[..]).unwrap();
+        assert_eq!(Some([72, 113, 128, 75]), payload.mic);
+        assert_eq!([72, 113, 128, 75], payload.compute_mic(&key(0x30)).unwrap());
+        assert!(!payload.verify_mic(&key(0x31)).unwrap());
+    }
+
+    #[test]
+    fn test_crypt_frm_payload_roundtrip() {
+        let mut data = b"a payload longer than one aes block".to_vec();
+        crypt_frm_payload(&key(0), Direction::Downlink, 1, 2, &mut data);
+        assert_ne!(b"a payload longer than one aes block".to_vec(), data);
+        crypt_frm_payload(&key(0), Direction::Downlink, 1, 2, &mut data);
+        assert_eq!(b"a payload longer than one aes block".to_vec(), data);
+    }
+
+    #[test]
+    fn test_proprietary_mic() {
+        let payload = PHYPayload::proprietary(&[1, 2, 3]);
+        assert!(payload.compute_mic(&key(0)).is_err());
+        assert!(!payload.verify_mic(&key(0)).unwrap());
+    }
+}
@@ -5,7 +5,12 @@ pub enum LoraWanError {
     InvalidPacketType(u8),
     InvalidPacketVersion(u8),
     InvalidFPortForFopts,
+    InvalidFOptsLen(usize),
+    InvalidNetId(String),
     InvalidPacketSize(super::MType, usize),
+    InvalidMacCommand(u8),
+    InvalidMacCommandSize(u8, usize),
+    InvalidMacCommandFrequency(u32),
     Io(io::Error),
 }
 
@@ -15,9 +20,18 @@ impl fmt::Display for LoraWanError {
             LoraWanError::InvalidPacketType(v) => write!(f, "Invalid packet type: {v:#02x}"),
             LoraWanError::InvalidPacketVersion(v) => write!(f, "Invalid packet version: {v:#02x}"),
             LoraWanError::InvalidFPortForFopts => write!(f, "Invalid: fport 0 with fopts"),
+            LoraWanError::InvalidFOptsLen(len) => write!(f, "Invalid fopts length: {len}"),
+            LoraWanError::InvalidNetId(net_id) => write!(f, "Invalid net id: {net_id}"),
             LoraWanError::InvalidPacketSize(mtype, s) => {
                 write!(f, "Invalid packet size {s} for type {mtype:?}")
             }
+            LoraWanError::InvalidMacCommand(cid) => write!(f, "Invalid mac command: {cid:#02x}"),
+            LoraWanError::InvalidMacCommandSize(cid, s) => {
+                write!(f, "Invalid mac command size {s} for command {cid:#02x}")
+            }
+            LoraWanError::InvalidMacCommandFrequency(frequency) => {
+                write!(f, "Invalid mac command frequency: {frequency}")
+            }
             LoraWanError::Io(err) => err.fmt(f),
         }
     }
@@ -2,9 +2,14 @@ use bitfield::bitfield;
 use bytes::{Buf, BufMut, Bytes};
 use std::{convert::From, fmt, mem::size_of, result};
 
+pub mod crypto;
 pub mod error;
+pub mod mac_command;
+pub mod subnet;
 pub use bytes;
+pub use crypto::AesKey;
 pub use error::LoraWanError;
+pub use mac_command::MacCommand;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Direction {
@@ -64,8 +69,39 @@ bitfield! {
 }
 
 const MHDR_SIZE: usize = size_of::<u8>();
+/// Largest major version that fits the 2 bit major field
+const MHDR_MAJOR_MAX: u8 = 0b11;
 
 impl MHDR {
+    /// Constructs a header from the given message type and major version,
+    /// rejecting values that do not fit their fields.
+    pub fn new(mtype: MType, major: u8) -> Result<Self, LoraWanError> {
+        let mut mhdr = Self(0);
+        mhdr.try_set_mtype(mtype)?;
+        mhdr.try_set_major(major)?;
+        Ok(mhdr)
+    }
+
+    /// Sets the message type. Unlike `set_mtype` this rejects invalid message
+    /// types instead of truncating them to the 3 bit field.
+    pub fn try_set_mtype(&mut self, mtype: MType) -> Result<(), LoraWanError> {
+        if let MType::Invalid(v) = mtype {
+            return Err(LoraWanError::InvalidPacketType(v));
+        }
+        self.set_mtype(mtype);
+        Ok(())
+    }
+
+    /// Sets the major version. Unlike `set_major` this rejects versions that
+    /// do not fit the 2 bit field instead of truncating them.
+    pub fn try_set_major(&mut self, major: u8) -> Result<(), LoraWanError> {
+        if major > MHDR_MAJOR_MAX {
+            return Err(LoraWanError::InvalidPacketVersion(major));
+        }
+        self.set_major(major);
+        Ok(())
+    }
+
     pub fn read(reader: &mut dyn Buf) -> Result<Self, LoraWanError> {
         if reader.remaining() < MHDR_SIZE {
             return Err(LoraWanError::InvalidPacketSize(
@@ -248,6 +284,24 @@ impl fmt::Debug for Fhdr {
 }
 
 impl Fhdr {
+    /// Constructs a frame header with the given frame options. The options
+    /// length in the frame control is set to match the given options, which
+    /// may be at most `FOPTS_MAX_LEN` bytes.
+    pub fn new(
+        dev_addr: u32,
+        mut fctrl: FCtrl,
+        fcnt: u16,
+        fopts: Bytes,
+    ) -> Result<Self, LoraWanError> {
+        fctrl.try_set_fopts_len(fopts.len())?;
+        Ok(Self {
+            dev_addr,
+            fctrl,
+            fcnt,
+            fopts,
+        })
+    }
+
     pub fn read(
         direction: Direction,
         payload_type: MType,
@@ -280,6 +334,15 @@ impl Fhdr {
         Ok(res)
     }
 
+    /// Parses the MAC commands in the frame options
+    pub fn mac_commands(&self) -> Result<Vec<MacCommand>, LoraWanError> {
+        let direction = match self.fctrl {
+            FCtrl::Uplink(_) => Direction::Uplink,
+            FCtrl::Downlink(_) => Direction::Downlink,
+        };
+        MacCommand::read_all(direction, &mut self.fopts.clone())
+    }
+
     pub fn write(&self, output: &mut dyn BufMut) -> Result<usize, LoraWanError> {
         let mut written = 0;
         output.put_u32_le(self.dev_addr);
@@ -305,8 +368,25 @@ bitfield! {
 }
 
 const FCTRL_UPLINK_SIZE: usize = size_of::<u8>();
+/// Maximum length of the frame options, limited by the 4 bit options length
+/// field in the frame control
+pub const FOPTS_MAX_LEN: usize = 15;
+
+fn check_fopts_len(len: usize) -> Result<u8, LoraWanError> {
+    if len > FOPTS_MAX_LEN {
+        return Err(LoraWanError::InvalidFOptsLen(len));
+    }
+    Ok(len as u8)
+}
 
 impl FCtrlUplink {
+    /// Sets the frame options length. Unlike `set_fopts_len` this rejects
+    /// lengths over `FOPTS_MAX_LEN` instead of truncating them.
+    pub fn try_set_fopts_len(&mut self, len: usize) -> Result<(), LoraWanError> {
+        self.set_fopts_len(check_fopts_len(len)?);
+        Ok(())
+    }
+
     pub fn read(payload_type: MType, reader: &mut dyn Buf) -> Result<Self, LoraWanError> {
         if reader.remaining() < FCTRL_UPLINK_SIZE {
             return Err(LoraWanError::InvalidPacketSize(
@@ -337,6 +417,13 @@ bitfield! {
 const FCTRL_DOWNLINK_SIZE: usize = size_of::<u8>();
 
 impl FCtrlDownlink {
+    /// Sets the frame options length. Unlike `set_fopts_len` this rejects
+    /// lengths over `FOPTS_MAX_LEN` instead of truncating them.
+    pub fn try_set_fopts_len(&mut self, len: usize) -> Result<(), LoraWanError> {
+        self.set_fopts_len(check_fopts_len(len)?);
+        Ok(())
+    }
+
     pub fn read(payload_type: MType, reader: &mut dyn Buf) -> Result<Self, LoraWanError> {
         if reader.remaining() < FCTRL_DOWNLINK_SIZE {
             return Err(LoraWanError::InvalidPacketSize(
@@ -369,6 +456,13 @@ impl FCtrl {
         }
     }
 
+    pub fn try_set_fopts_len(&mut self, len: usize) -> Result<(), LoraWanError> {
+        match self {
+            FCtrl::Uplink(fctrl) => fctrl.try_set_fopts_len(len),
+            FCtrl::Downlink(fctrl) => fctrl.try_set_fopts_len(len),
+        }
+    }
+
     pub fn read(
         direction: Direction,
         payload_type: MType,
@@ -442,6 +536,10 @@ impl MACPayload {
     pub fn dev_addr(&self) -> u32 {
         self.fhdr.dev_addr
     }
+
+    pub fn fport(&self) -> Option<u8> {
+        self.fport
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -583,7 +681,6 @@ impl JoinAccept {
 #[cfg(test)]
 mod test {
     use super::*;
-    use base64;
 
     #[test]
     fn test_read_write_roundtrip() {
@@ -627,6 +724,88 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_mhdr_new() {
+        for mtype in 0..=0b111u8 {
+            for major in 0..=u8::MAX {
+                let result = MHDR::new(MType::from(mtype), major);
+                match (MType::from(mtype), result) {
+                    (MType::Invalid(v), Err(LoraWanError::InvalidPacketType(e))) => {
+                        assert_eq!(v, e)
+                    }
+                    (_, Err(LoraWanError::InvalidPacketVersion(e))) if major > MHDR_MAJOR_MAX => {
+                        assert_eq!(major, e)
+                    }
+                    (expected, Ok(mhdr)) if major <= MHDR_MAJOR_MAX => {
+                        assert_eq!(expected, mhdr.mtype());
+                        assert_eq!(major, mhdr.major());
+                        assert_eq!(0, mhdr.rfu());
+                    }
+                    (expected, result) => {
+                        panic!("unexpected {result:?} for mtype {expected:?} major {major}")
+                    }
+                }
+            }
+        }
+        // Invalid message types that would be truncated to a valid one
+        assert!(matches!(
+            MHDR::new(MType::Invalid(0b1010), 0),
+            Err(LoraWanError::InvalidPacketType(0b1010))
+        ));
+    }
+
+    #[test]
+    fn test_try_set_fopts_len() {
+        for len in 0..=FOPTS_MAX_LEN + 1 {
+            let mut uplink = FCtrl::Uplink(FCtrlUplink(0));
+            let mut downlink = FCtrl::Downlink(FCtrlDownlink(0));
+            for fctrl in [&mut uplink, &mut downlink] {
+                match fctrl.try_set_fopts_len(len) {
+                    Ok(()) if len <= FOPTS_MAX_LEN => assert_eq!(len, fctrl.fopts_len()),
+                    Err(LoraWanError::InvalidFOptsLen(e)) if len > FOPTS_MAX_LEN => {
+                        assert_eq!(len, e);
+                        assert_eq!(0, fctrl.fopts_len());
+                    }
+                    result => panic!("unexpected {result:?} for fopts len {len}"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_fhdr_new() {
+        let fopts = Bytes::from_static(&[1, 2, 3]);
+        let fhdr = Fhdr::new(1, FCtrl::Uplink(FCtrlUplink(0)), 2, fopts.clone()).unwrap();
+        assert_eq!(fopts.len(), fhdr.fctrl.fopts_len());
+        let mut buffer = Vec::new();
+        fhdr.write(&mut buffer).unwrap();
+        let read = Fhdr::read(Direction::Uplink, MType::UnconfirmedUp, &mut &buffer[..]).unwrap();
+        assert_eq!(fhdr, read);
+
+        // LinkADRAns and DevStatusAns
+        let fopts = Bytes::from_static(&[0x03, 0x07, 0x06, 0xFF, 0x05]);
+        let fhdr = Fhdr::new(1, FCtrl::Uplink(FCtrlUplink(0)), 2, fopts).unwrap();
+        assert_eq!(
+            vec![
+                MacCommand::LinkADRAns {
+                    power_ack: true,
+                    data_rate_ack: true,
+                    channel_mask_ack: true,
+                },
+                MacCommand::DevStatusAns {
+                    battery: 255,
+                    margin: 5,
+                },
+            ],
+            fhdr.mac_commands().unwrap()
+        );
+        let fopts = Bytes::from(vec![0; FOPTS_MAX_LEN + 1]);
+        assert!(matches!(
+            Fhdr::new(1, FCtrl::Uplink(FCtrlUplink(0)), 2, fopts),
+            Err(LoraWanError::InvalidFOptsLen(16))
+        ));
+    }
+
     impl TryFrom<&[u8]> for Routing {
         type Error = LoraWanError;
         fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
@@ -646,7 +825,7 @@ mod test {
                         fhdr: Fhdr { dev_addr, .. },
                         ..
                     }) => Ok(dev_addr),
-                    _ => return Err(LoraWanError::InvalidPacketType(mtype.into())),
+                    _ => Err(LoraWanError::InvalidPacketType(mtype.into())),
                 }
             }
 
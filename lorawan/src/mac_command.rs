@@ -0,0 +1,606 @@
+//! LoRaWAN 1.0.x MAC commands.
+//!
+//! MAC commands are carried in the frame options of a data frame, or in the
+//! payload of a frame on port 0. A command is a one byte command identifier
+//! (CID) followed by a payload whose length is fixed per command and
+//! direction. The same CID identifies a request in one direction and its
+//! answer in the other, so commands can only be parsed for a known direction.
+//!
+//! Commands with a proprietary CID (0x80 and up) have no known length and
+//! take the remainder of the options. Reserved bits are ignored when reading
+//! and written as zero.
+
+use super::{Direction, LoraWanError};
+use bytes::{Buf, BufMut, Bytes};
+use std::mem::size_of;
+
+const CID_LINK_CHECK: u8 = 0x02;
+const CID_LINK_ADR: u8 = 0x03;
+const CID_DUTY_CYCLE: u8 = 0x04;
+const CID_RX_PARAM_SETUP: u8 = 0x05;
+const CID_DEV_STATUS: u8 = 0x06;
+const CID_NEW_CHANNEL: u8 = 0x07;
+const CID_RX_TIMING_SETUP: u8 = 0x08;
+const CID_TX_PARAM_SETUP: u8 = 0x09;
+const CID_DL_CHANNEL: u8 = 0x0A;
+const CID_DEVICE_TIME: u8 = 0x0D;
+/// First of the proprietary command identifiers
+const CID_PROPRIETARY: u8 = 0x80;
+
+/// Frequencies are encoded as a 24 bit count of 100 Hz steps
+const FREQUENCY_STEP: u32 = 100;
+const FREQUENCY_SIZE: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MacCommand {
+    /// Requests the link margin of the last uplink from the network
+    LinkCheckReq,
+    LinkCheckAns {
+        /// Link margin in dB above the demodulation floor
+        margin: u8,
+        /// Number of gateways that received the request
+        gw_cnt: u8,
+    },
+    LinkADRReq {
+        data_rate: u8,
+        tx_power: u8,
+        ch_mask: u16,
+        ch_mask_cntl: u8,
+        nb_trans: u8,
+    },
+    LinkADRAns {
+        power_ack: bool,
+        data_rate_ack: bool,
+        channel_mask_ack: bool,
+    },
+    DutyCycleReq {
+        /// The aggregated duty cycle is limited to 1 / 2^max_duty_cycle
+        max_duty_cycle: u8,
+    },
+    DutyCycleAns,
+    RXParamSetupReq {
+        rx1_dr_offset: u8,
+        rx2_data_rate: u8,
+        /// RX2 frequency in Hz
+        frequency: u32,
+    },
+    RXParamSetupAns {
+        rx1_dr_offset_ack: bool,
+        rx2_data_rate_ack: bool,
+        channel_ack: bool,
+    },
+    DevStatusReq,
+    DevStatusAns {
+        /// 0 on external power, 1 to 254 for the battery level, 255 if
+        /// unknown
+        battery: u8,
+        /// SNR in dB of the last received DevStatusReq, from -32 to 31
+        margin: i8,
+    },
+    NewChannelReq {
+        ch_index: u8,
+        /// Channel frequency in Hz, 0 to disable the channel
+        frequency: u32,
+        max_dr: u8,
+        min_dr: u8,
+    },
+    NewChannelAns {
+        data_rate_range_ok: bool,
+        channel_frequency_ok: bool,
+    },
+    RXTimingSetupReq {
+        /// Delay of the first receive window in seconds, 0 meaning 1
+        delay: u8,
+    },
+    RXTimingSetupAns,
+    TxParamSetupReq {
+        downlink_dwell_time: bool,
+        uplink_dwell_time: bool,
+        /// Index into the table of maximum EIRP values
+        max_eirp: u8,
+    },
+    TxParamSetupAns,
+    DlChannelReq {
+        ch_index: u8,
+        /// Downlink frequency in Hz
+        frequency: u32,
+    },
+    DlChannelAns {
+        uplink_frequency_exists: bool,
+        channel_frequency_ok: bool,
+    },
+    DeviceTimeReq,
+    DeviceTimeAns {
+        /// Seconds since the GPS epoch
+        seconds: u32,
+        /// Fraction of the second in 1/256 s steps
+        fractional: u8,
+    },
+    Proprietary {
+        cid: u8,
+        payload: Bytes,
+    },
+}
+
+impl MacCommand {
+    /// The command identifier
+    pub fn cid(&self) -> u8 {
+        match self {
+            Self::LinkCheckReq | Self::LinkCheckAns { .. } => CID_LINK_CHECK,
+            Self::LinkADRReq { .. } | Self::LinkADRAns { .. } => CID_LINK_ADR,
+            Self::DutyCycleReq { .. } | Self::DutyCycleAns => CID_DUTY_CYCLE,
+            Self::RXParamSetupReq { .. } | Self::RXParamSetupAns { .. } => CID_RX_PARAM_SETUP,
+            Self::DevStatusReq | Self::DevStatusAns { .. } => CID_DEV_STATUS,
+            Self::NewChannelReq { .. } | Self::NewChannelAns { .. } => CID_NEW_CHANNEL,
+            Self::RXTimingSetupReq { .. } | Self::RXTimingSetupAns => CID_RX_TIMING_SETUP,
+            Self::TxParamSetupReq { .. } | Self::TxParamSetupAns => CID_TX_PARAM_SETUP,
+            Self::DlChannelReq { .. } | Self::DlChannelAns { .. } => CID_DL_CHANNEL,
+            Self::DeviceTimeReq | Self::DeviceTimeAns { .. } => CID_DEVICE_TIME,
+            Self::Proprietary { cid, .. } => *cid,
+        }
+    }
+
+    /// Reads all commands in the given frame options or port 0 payload sent
+    /// in the given direction
+    pub fn read_all(direction: Direction, reader: &mut dyn Buf) -> Result<Vec<Self>, LoraWanError> {
+        let mut commands = vec![];
+        while reader.has_remaining() {
+            commands.push(Self::read(direction, reader)?);
+        }
+        Ok(commands)
+    }
+
+    /// Writes the given commands one after the other
+    pub fn write_all(commands: &[Self], output: &mut dyn BufMut) -> Result<usize, LoraWanError> {
+        let mut written = 0;
+        for command in commands {
+            written += command.write(output)?;
+        }
+        Ok(written)
+    }
+
+    pub fn read(direction: Direction, reader: &mut dyn Buf) -> Result<Self, LoraWanError> {
+        if !reader.has_remaining() {
+            return Err(LoraWanError::InvalidMacCommandSize(0, 0));
+        }
+        let cid = reader.get_u8();
+        if cid >= CID_PROPRIETARY {
+            return Ok(Self::Proprietary {
+                cid,
+                payload: reader.copy_to_bytes(reader.remaining()),
+            });
+        }
+        let size = payload_size(direction, cid)?;
+        if reader.remaining() < size {
+            return Err(LoraWanError::InvalidMacCommandSize(cid, reader.remaining()));
+        }
+        let command = match (direction, cid) {
+            (Direction::Uplink, CID_LINK_CHECK) => Self::LinkCheckReq,
+            (Direction::Downlink, CID_LINK_CHECK) => Self::LinkCheckAns {
+                margin: reader.get_u8(),
+                gw_cnt: reader.get_u8(),
+            },
+            (Direction::Downlink, CID_LINK_ADR) => {
+                let data_rate_tx_power = reader.get_u8();
+                let ch_mask = reader.get_u16_le();
+                let redundancy = reader.get_u8();
+                Self::LinkADRReq {
+                    data_rate: data_rate_tx_power >> 4,
+                    tx_power: data_rate_tx_power & 0x0F,
+                    ch_mask,
+                    ch_mask_cntl: (redundancy >> 4) & 0x07,
+                    nb_trans: redundancy & 0x0F,
+                }
+            }
+            (Direction::Uplink, CID_LINK_ADR) => {
+                let status = reader.get_u8();
+                Self::LinkADRAns {
+                    power_ack: bit(status, 2),
+                    data_rate_ack: bit(status, 1),
+                    channel_mask_ack: bit(status, 0),
+                }
+            }
+            (Direction::Downlink, CID_DUTY_CYCLE) => Self::DutyCycleReq {
+                max_duty_cycle: reader.get_u8() & 0x0F,
+            },
+            (Direction::Uplink, CID_DUTY_CYCLE) => Self::DutyCycleAns,
+            (Direction::Downlink, CID_RX_PARAM_SETUP) => {
+                let dl_settings = reader.get_u8();
+                Self::RXParamSetupReq {
+                    rx1_dr_offset: (dl_settings >> 4) & 0x07,
+                    rx2_data_rate: dl_settings & 0x0F,
+                    frequency: read_frequency(reader),
+                }
+            }
+            (Direction::Uplink, CID_RX_PARAM_SETUP) => {
+                let status = reader.get_u8();
+                Self::RXParamSetupAns {
+                    rx1_dr_offset_ack: bit(status, 2),
+                    rx2_data_rate_ack: bit(status, 1),
+                    channel_ack: bit(status, 0),
+                }
+            }
+            (Direction::Downlink, CID_DEV_STATUS) => Self::DevStatusReq,
+            (Direction::Uplink, CID_DEV_STATUS) => Self::DevStatusAns {
+                battery: reader.get_u8(),
+                // Sign extend the 6 bit margin
+                margin: ((reader.get_u8() << 2) as i8) >> 2,
+            },
+            (Direction::Downlink, CID_NEW_CHANNEL) => {
+                let ch_index = reader.get_u8();
+                let frequency = read_frequency(reader);
+                let dr_range = reader.get_u8();
+                Self::NewChannelReq {
+                    ch_index,
+                    frequency,
+                    max_dr: dr_range >> 4,
+                    min_dr: dr_range & 0x0F,
+                }
+            }
+            (Direction::Uplink, CID_NEW_CHANNEL) => {
+                let status = reader.get_u8();
+                Self::NewChannelAns {
+                    data_rate_range_ok: bit(status, 1),
+                    channel_frequency_ok: bit(status, 0),
+                }
+            }
+            (Direction::Downlink, CID_RX_TIMING_SETUP) => Self::RXTimingSetupReq {
+                delay: reader.get_u8() & 0x0F,
+            },
+            (Direction::Uplink, CID_RX_TIMING_SETUP) => Self::RXTimingSetupAns,
+            (Direction::Downlink, CID_TX_PARAM_SETUP) => {
+                let eirp_dwell_time = reader.get_u8();
+                Self::TxParamSetupReq {
+                    downlink_dwell_time: bit(eirp_dwell_time, 5),
+                    uplink_dwell_time: bit(eirp_dwell_time, 4),
+                    max_eirp: eirp_dwell_time & 0x0F,
+                }
+            }
+            (Direction::Uplink, CID_TX_PARAM_SETUP) => Self::TxParamSetupAns,
+            (Direction::Downlink, CID_DL_CHANNEL) => Self::DlChannelReq {
+                ch_index: reader.get_u8(),
+                frequency: read_frequency(reader),
+            },
+            (Direction::Uplink, CID_DL_CHANNEL) => {
+                let status = reader.get_u8();
+                Self::DlChannelAns {
+                    uplink_frequency_exists: bit(status, 1),
+                    channel_frequency_ok: bit(status, 0),
+                }
+            }
+            (Direction::Uplink, CID_DEVICE_TIME) => Self::DeviceTimeReq,
+            (Direction::Downlink, CID_DEVICE_TIME) => Self::DeviceTimeAns {
+                seconds: reader.get_u32_le(),
+                fractional: reader.get_u8(),
+            },
+            _ => return Err(LoraWanError::InvalidMacCommand(cid)),
+        };
+        Ok(command)
+    }
+
+    pub fn write(&self, output: &mut dyn BufMut) -> Result<usize, LoraWanError> {
+        output.put_u8(self.cid());
+        let mut written = size_of::<u8>();
+        written += match self {
+            Self::LinkCheckReq
+            | Self::DutyCycleAns
+            | Self::DevStatusReq
+            | Self::RXTimingSetupAns
+            | Self::TxParamSetupAns
+            | Self::DeviceTimeReq => 0,
+            Self::LinkCheckAns { margin, gw_cnt } => {
+                output.put_u8(*margin);
+                output.put_u8(*gw_cnt);
+                2
+            }
+            Self::LinkADRReq {
+                data_rate,
+                tx_power,
+                ch_mask,
+                ch_mask_cntl,
+                nb_trans,
+            } => {
+                output.put_u8(nibbles(*data_rate, *tx_power));
+                output.put_u16_le(*ch_mask);
+                output.put_u8(nibbles(*ch_mask_cntl & 0x07, *nb_trans));
+                4
+            }
+            Self::LinkADRAns {
+                power_ack,
+                data_rate_ack,
+                channel_mask_ack,
+            } => {
+                output.put_u8(bits(&[*power_ack, *data_rate_ack, *channel_mask_ack]));
+                1
+            }
+            Self::DutyCycleReq { max_duty_cycle } => {
+                output.put_u8(max_duty_cycle & 0x0F);
+                1
+            }
+            Self::RXParamSetupReq {
+                rx1_dr_offset,
+                rx2_data_rate,
+                frequency,
+            } => {
+                output.put_u8(nibbles(rx1_dr_offset & 0x07, *rx2_data_rate));
+                write_frequency(*frequency, output)?;
+                1 + FREQUENCY_SIZE
+            }
+            Self::RXParamSetupAns {
+                rx1_dr_offset_ack,
+                rx2_data_rate_ack,
+                channel_ack,
+            } => {
+                output.put_u8(bits(&[
+                    *rx1_dr_offset_ack,
+                    *rx2_data_rate_ack,
+                    *channel_ack,
+                ]));
+                1
+            }
+            Self::DevStatusAns { battery, margin } => {
+                output.put_u8(*battery);
+                output.put_u8(*margin as u8 & 0x3F);
+                2
+            }
+            Self::NewChannelReq {
+                ch_index,
+                frequency,
+                max_dr,
+                min_dr,
+            } => {
+                output.put_u8(*ch_index);
+                write_frequency(*frequency, output)?;
+                output.put_u8(nibbles(*max_dr, *min_dr));
+                2 + FREQUENCY_SIZE
+            }
+            Self::NewChannelAns {
+                data_rate_range_ok,
+                channel_frequency_ok,
+            } => {
+                output.put_u8(bits(&[*data_rate_range_ok, *channel_frequency_ok]));
+                1
+            }
+            Self::RXTimingSetupReq { delay } => {
+                output.put_u8(delay & 0x0F);
+                1
+            }
+            Self::TxParamSetupReq {
+                downlink_dwell_time,
+                uplink_dwell_time,
+                max_eirp,
+            } => {
+                let dwell_time = bits(&[*downlink_dwell_time, *uplink_dwell_time]);
+                output.put_u8(nibbles(dwell_time, *max_eirp));
+                1
+            }
+            Self::DlChannelReq {
+                ch_index,
+                frequency,
+            } => {
+                output.put_u8(*ch_index);
+                write_frequency(*frequency, output)?;
+                1 + FREQUENCY_SIZE
+            }
+            Self::DlChannelAns {
+                uplink_frequency_exists,
+                channel_frequency_ok,
+            } => {
+                output.put_u8(bits(&[*uplink_frequency_exists, *channel_frequency_ok]));
+                1
+            }
+            Self::DeviceTimeAns {
+                seconds,
+                fractional,
+            } => {
+                output.put_u32_le(*seconds);
+                output.put_u8(*fractional);
+                5
+            }
+            Self::Proprietary { payload, .. } => {
+                output.put_slice(payload);
+                payload.len()
+            }
+        };
+        Ok(written)
+    }
+}
+
+/// The payload size of a command with the given identifier sent in the
+/// given direction
+fn payload_size(direction: Direction, cid: u8) -> Result<usize, LoraWanError> {
+    let size = match (direction, cid) {
+        (Direction::Uplink, CID_LINK_CHECK) => 0,
+        (Direction::Downlink, CID_LINK_CHECK) => 2,
+        (Direction::Downlink, CID_LINK_ADR) => 4,
+        (Direction::Uplink, CID_LINK_ADR) => 1,
+        (Direction::Downlink, CID_DUTY_CYCLE) => 1,
+        (Direction::Uplink, CID_DUTY_CYCLE) => 0,
+        (Direction::Downlink, CID_RX_PARAM_SETUP) => 1 + FREQUENCY_SIZE,
+        (Direction::Uplink, CID_RX_PARAM_SETUP) => 1,
+        (Direction::Downlink, CID_DEV_STATUS) => 0,
+        (Direction::Uplink, CID_DEV_STATUS) => 2,
+        (Direction::Downlink, CID_NEW_CHANNEL) => 2 + FREQUENCY_SIZE,
+        (Direction::Uplink, CID_NEW_CHANNEL) => 1,
+        (Direction::Downlink, CID_RX_TIMING_SETUP) => 1,
+        (Direction::Uplink, CID_RX_TIMING_SETUP) => 0,
+        (Direction::Downlink, CID_TX_PARAM_SETUP) => 1,
+        (Direction::Uplink, CID_TX_PARAM_SETUP) => 0,
+        (Direction::Downlink, CID_DL_CHANNEL) => 1 + FREQUENCY_SIZE,
+        (Direction::Uplink, CID_DL_CHANNEL) => 1,
+        (Direction::Uplink, CID_DEVICE_TIME) => 0,
+        (Direction::Downlink, CID_DEVICE_TIME) => 5,
+        _ => return Err(LoraWanError::InvalidMacCommand(cid)),
+    };
+    Ok(size)
+}
+
+fn bit(value: u8, index: u8) -> bool {
+    value & (1 << index) != 0
+}
+
+/// The given flags as the low bits of a byte, the last flag in bit 0
+fn bits(flags: &[bool]) -> u8 {
+    flags
+        .iter()
+        .fold(0, |value, flag| (value << 1) | u8::from(*flag))
+}
+
+fn nibbles(high: u8, low: u8) -> u8 {
+    (high << 4) | (low & 0x0F)
+}
+
+fn read_frequency(reader: &mut dyn Buf) -> u32 {
+    reader.get_uint_le(FREQUENCY_SIZE) as u32 * FREQUENCY_STEP
+}
+
+fn write_frequency(frequency: u32, output: &mut dyn BufMut) -> Result<(), LoraWanError> {
+    let steps = frequency / FREQUENCY_STEP;
+    if steps * FREQUENCY_STEP != frequency || steps >= 1 << (8 * FREQUENCY_SIZE) {
+        return Err(LoraWanError::InvalidMacCommandFrequency(frequency));
+    }
+    output.put_uint_le(steps.into(), FREQUENCY_SIZE);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn roundtrip(direction: Direction, data: &[u8], expected: &[MacCommand]) {
+        let commands = MacCommand::read_all(direction, &mut &data[..]).expect("mac commands");
+        assert_eq!(expected, commands);
+        let mut written = vec![];
+        let len = MacCommand::write_all(&commands, &mut written).expect("written commands");
+        assert_eq!(data, written);
+        assert_eq!(data.len(), len);
+    }
+
+    #[test]
+    fn test_uplink_commands() {
+        roundtrip(
+            Direction::Uplink,
+            &[
+                0x02, 0x03, 0x07, 0x06, 0xFE, 0x3E, 0x04, 0x05, 0x07, 0x07, 0x02, 0x08, 0x09, 0x0A,
+                0x03, 0x0D,
+            ],
+            &[
+                MacCommand::LinkCheckReq,
+                MacCommand::LinkADRAns {
+                    power_ack: true,
+                    data_rate_ack: true,
+                    channel_mask_ack: true,
+                },
+                MacCommand::DevStatusAns {
+                    battery: 254,
+                    margin: -2,
+                },
+                MacCommand::DutyCycleAns,
+                MacCommand::RXParamSetupAns {
+                    rx1_dr_offset_ack: true,
+                    rx2_data_rate_ack: true,
+                    channel_ack: true,
+                },
+                MacCommand::NewChannelAns {
+                    data_rate_range_ok: true,
+                    channel_frequency_ok: false,
+                },
+                MacCommand::RXTimingSetupAns,
+                MacCommand::TxParamSetupAns,
+                MacCommand::DlChannelAns {
+                    uplink_frequency_exists: true,
+                    channel_frequency_ok: true,
+                },
+                MacCommand::DeviceTimeReq,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_downlink_commands() {
+        // 869.525 MHz is 8_695_250 steps of 100 Hz, 0x84ADD2
+        roundtrip(
+            Direction::Downlink,
+            &[
+                0x02, 0x14, 0x03, 0x03, 0x5E, 0xFF, 0x00, 0x01, 0x04, 0x07, 0x05, 0x12, 0xD2, 0xAD,
+                0x84, 0x06, 0x07, 0x03, 0xD2, 0xAD, 0x84, 0x50, 0x08, 0x01, 0x09, 0x3F, 0x0A, 0x03,
+                0xD2, 0xAD, 0x84, 0x0D, 0x80, 0xB1, 0x7F, 0x4D, 0x80,
+            ],
+            &[
+                MacCommand::LinkCheckAns {
+                    margin: 20,
+                    gw_cnt: 3,
+                },
+                MacCommand::LinkADRReq {
+                    data_rate: 5,
+                    tx_power: 14,
+                    ch_mask: 0x00FF,
+                    ch_mask_cntl: 0,
+                    nb_trans: 1,
+                },
+                MacCommand::DutyCycleReq { max_duty_cycle: 7 },
+                MacCommand::RXParamSetupReq {
+                    rx1_dr_offset: 1,
+                    rx2_data_rate: 2,
+                    frequency: 869_525_000,
+                },
+                MacCommand::DevStatusReq,
+                MacCommand::NewChannelReq {
+                    ch_index: 3,
+                    frequency: 869_525_000,
+                    max_dr: 5,
+                    min_dr: 0,
+                },
+                MacCommand::RXTimingSetupReq { delay: 1 },
+                MacCommand::TxParamSetupReq {
+                    downlink_dwell_time: true,
+                    uplink_dwell_time: true,
+                    max_eirp: 15,
+                },
+                MacCommand::DlChannelReq {
+                    ch_index: 3,
+                    frequency: 869_525_000,
+                },
+                MacCommand::DeviceTimeAns {
+                    seconds: 0x4D7F_B180,
+                    fractional: 0x80,
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn test_proprietary_and_invalid_commands() {
+        // Proprietary commands take the rest of the options
+        roundtrip(
+            Direction::Uplink,
+            &[0x06, 0x00, 0x01, 0x80, 0x01, 0x02],
+            &[
+                MacCommand::DevStatusAns {
+                    battery: 0,
+                    margin: 1,
+                },
+                MacCommand::Proprietary {
+                    cid: 0x80,
+                    payload: Bytes::from_static(&[0x01, 0x02]),
+                },
+            ],
+        );
+        assert!(matches!(
+            MacCommand::read_all(Direction::Uplink, &mut &[0x06, 0xFF][..]),
+            Err(LoraWanError::InvalidMacCommandSize(0x06, 1))
+        ));
+        assert!(matches!(
+            MacCommand::read_all(Direction::Uplink, &mut &[0x20][..]),
+            Err(LoraWanError::InvalidMacCommand(0x20))
+        ));
+        let frequency = MacCommand::DlChannelReq {
+            ch_index: 0,
+            frequency: 869_525_050,
+        };
+        assert!(matches!(
+            frequency.write(&mut vec![]),
+            Err(LoraWanError::InvalidMacCommandFrequency(869_525_050))
+        ));
+    }
+}
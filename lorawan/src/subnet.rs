@@ -0,0 +1,118 @@
+//! LoRaWAN NetIDs and the device addresses they assign.
+//!
+//! A device address starts with a prefix of ones, terminated by a zero, that
+//! encodes the NetID type. The prefix is followed by the network id (NwkID),
+//! the least significant bits of the NetID, whose length depends on the type.
+//! The remaining bits are the network address assigned by the network.
+
+use super::LoraWanError;
+use std::{fmt, str::FromStr};
+
+/// Number of NwkID bits in a device address per NetID type
+const NWK_ID_BITS: [u32; 8] = [6, 6, 9, 11, 12, 13, 15, 17];
+/// Highest valid NetID, which is a 24 bit value
+const NET_ID_MAX: u32 = 0xFF_FFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NetId(u32);
+
+impl NetId {
+    pub fn new(net_id: u32) -> Result<Self, LoraWanError> {
+        if net_id > NET_ID_MAX {
+            return Err(LoraWanError::InvalidNetId(format!("{net_id:X}")));
+        }
+        Ok(Self(net_id))
+    }
+
+    /// The NetID that assigned the given device address, or None if the
+    /// device address has an invalid type prefix. Device addresses of NetID
+    /// types 3 to 7 do not carry all bits of the NetID, the missing bits are
+    /// zero in the returned NetID.
+    pub fn from_devaddr(devaddr: u32) -> Option<Self> {
+        let net_type = devaddr_net_type(devaddr)? as u32;
+        let nwk_id = devaddr_nwk_id(devaddr)?;
+        Some(Self((net_type << 21) | nwk_id))
+    }
+
+    /// The NetID type, the 3 most significant bits of the NetID
+    pub fn net_type(&self) -> u8 {
+        (self.0 >> 21) as u8
+    }
+
+    /// The network id carried in the device addresses of this NetID
+    pub fn nwk_id(&self) -> u32 {
+        self.0 & ((1 << NWK_ID_BITS[self.net_type() as usize]) - 1)
+    }
+
+    /// Whether the given device address was assigned by this NetID
+    pub fn contains(&self, devaddr: u32) -> bool {
+        devaddr_net_type(devaddr) == Some(self.net_type())
+            && devaddr_nwk_id(devaddr) == Some(self.nwk_id())
+    }
+}
+
+impl From<NetId> for u32 {
+    fn from(value: NetId) -> Self {
+        value.0
+    }
+}
+
+impl fmt::Display for NetId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:06X}", self.0)
+    }
+}
+
+impl FromStr for NetId {
+    type Err = LoraWanError;
+    /// Parses a NetID in hex, for example "00003C"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let net_id =
+            u32::from_str_radix(s, 16).map_err(|_| LoraWanError::InvalidNetId(s.to_string()))?;
+        Self::new(net_id)
+    }
+}
+
+/// The NetID type encoded in a device address, or None for the invalid all
+/// ones prefix
+pub fn devaddr_net_type(devaddr: u32) -> Option<u8> {
+    let net_type = devaddr.leading_ones();
+    (net_type < 8).then_some(net_type as u8)
+}
+
+/// The network id encoded in a device address, or None if the device address
+/// has an invalid type prefix
+pub fn devaddr_nwk_id(devaddr: u32) -> Option<u32> {
+    let net_type = devaddr_net_type(devaddr)? as u32;
+    let nwk_id_bits = NWK_ID_BITS[net_type as usize];
+    let shift = 32 - (net_type + 1) - nwk_id_bits;
+    Some((devaddr >> shift) & ((1 << nwk_id_bits) - 1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_net_id_contains() {
+        // Helium NetIDs with device addresses they assigned
+        for (net_id, devaddr) in [
+            ("000024", 0x4800_0800),
+            ("60002D", 0xE05A_0008),
+            ("C00053", 0xFC01_4C00),
+        ] {
+            let net_id: NetId = net_id.parse().unwrap();
+            assert!(net_id.contains(devaddr), "{net_id} {devaddr:08X}");
+            assert_eq!(Some(net_id), NetId::from_devaddr(devaddr));
+            assert!(
+                !net_id.contains(devaddr ^ 0x0400_0000),
+                "{net_id} {devaddr:08X}"
+            );
+        }
+        let net_id: NetId = "000024".parse().unwrap();
+        assert_eq!((0, 0x24), (net_id.net_type(), net_id.nwk_id()));
+        assert!(!net_id.contains(u32::MAX));
+        assert!("1000000".parse::<NetId>().is_err());
+        assert!("xyz".parse::<NetId>().is_err());
+    }
+}
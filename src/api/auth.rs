@@ -0,0 +1,298 @@
+//! Authentication of mutating local API requests.
+//!
+//! By default the local API accepts every request, which is fine while it only
+//! listens on the loopback interface. When a token or public keys are set in
+//! the `api_auth` settings, requests that change the gateway, like adding the
+//! gateway, purging the queue, sending pings, reloading settings and pausing
+//! subsystems, have to carry either the token as a bearer token in the
+//! `authorization` header or a signature by one of the keys.
+//!
+//! A signed request carries the b58 encoded public key of the signer, the
+//! unix time in seconds and the base64 encoded signature of
+//! `<gateway key>:<method>:<timestamp>:<request hash>` in headers, where the
+//! request hash is the hex encoded SHA-256 hash of the encoded request
+//! message. The gateway key ties the signature to one gateway and the request
+//! hash to the arguments of the request, like the owner and payer of an added
+//! gateway. The timestamp has to be within a minute of the gateway clock, and
+//! a signature is only accepted once within that time, so captured requests
+//! can not be replayed. The gateway key is always allowed to sign, so the
+//! command line tools on the gateway itself work with signatures only.
+
+use crate::{secret::Secret, settings::ApiAuthSettings, Keypair, PublicKey, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use helium_crypto::Verify;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tonic::{metadata::MetadataMap, Request, Status};
+
+const TOKEN_HEADER: &str = "authorization";
+const KEY_HEADER: &str = "x-gateway-auth-key";
+const TIMESTAMP_HEADER: &str = "x-gateway-auth-timestamp";
+const SIGNATURE_HEADER: &str = "x-gateway-auth-signature";
+/// Largest difference in seconds between the timestamp of a signed request
+/// and the gateway clock
+const MAX_CLOCK_SKEW: u64 = 60;
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// The signed message of a request with the given message
+fn signed_message<T: prost::Message>(
+    gateway: &PublicKey,
+    method: &str,
+    timestamp: u64,
+    message: &T,
+) -> Vec<u8> {
+    let hash: String = Sha256::digest(message.encode_to_vec())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("{gateway}:{method}:{timestamp}:{hash}").into_bytes()
+}
+
+/// Compares in time independent of where the values differ
+fn token_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn header<'a>(metadata: &'a MetadataMap, name: &str) -> Option<&'a str> {
+    metadata.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Who made a request, for the audit log: the key the request claims to be
+/// signed with, "token" for a request with a bearer token, or None for a
+/// request without credentials
+pub fn caller<T>(request: &Request<T>) -> Option<String> {
+    let metadata = request.metadata();
+    if header(metadata, TOKEN_HEADER).is_some_and(|value| value.starts_with("Bearer ")) {
+        return Some("token".to_string());
+    }
+    header(metadata, KEY_HEADER).map(str::to_string)
+}
+
+/// Server side check of mutating requests
+#[derive(Debug)]
+pub struct ApiAuth {
+    token: Option<Secret>,
+    keys: Vec<PublicKey>,
+    gateway: PublicKey,
+    /// Timestamps and signatures of the accepted signed requests whose
+    /// timestamp is still in range
+    seen: Mutex<HashSet<(u64, Vec<u8>)>>,
+}
+
+impl ApiAuth {
+    pub fn new(settings: &ApiAuthSettings, gateway: PublicKey) -> Self {
+        Self {
+            token: settings.token.clone(),
+            keys: settings.keys.clone(),
+            gateway,
+            seen: Mutex::default(),
+        }
+    }
+
+    fn is_required(&self) -> bool {
+        self.token.is_some() || !self.keys.is_empty()
+    }
+
+    /// Checks the credentials of a request to the given method. Always
+    /// succeeds if no token or keys are set.
+    pub fn authorize<T: prost::Message>(
+        &self,
+        request: &Request<T>,
+        method: &str,
+    ) -> std::result::Result<(), Status> {
+        if !self.is_required() {
+            return Ok(());
+        }
+        let metadata = request.metadata();
+        if let (Some(token), Some(bearer)) = (
+            &self.token,
+            header(metadata, TOKEN_HEADER).and_then(|value| value.strip_prefix("Bearer ")),
+        ) {
+            return if token_eq(token.expose().as_bytes(), bearer.as_bytes()) {
+                Ok(())
+            } else {
+                Err(Status::unauthenticated("invalid token"))
+            };
+        }
+        self.verify_signature(metadata, method, request.get_ref())
+    }
+
+    fn verify_signature<T: prost::Message>(
+        &self,
+        metadata: &MetadataMap,
+        method: &str,
+        message: &T,
+    ) -> std::result::Result<(), Status> {
+        let (Some(key), Some(timestamp), Some(signature)) = (
+            header(metadata, KEY_HEADER),
+            header(metadata, TIMESTAMP_HEADER),
+            header(metadata, SIGNATURE_HEADER),
+        ) else {
+            return Err(Status::unauthenticated("missing credentials"));
+        };
+        let key =
+            PublicKey::from_str(key).map_err(|_| Status::unauthenticated("invalid signing key"))?;
+        if key != self.gateway && !self.keys.contains(&key) {
+            return Err(Status::permission_denied("signing key not allowed"));
+        }
+        let timestamp: u64 = timestamp
+            .parse()
+            .map_err(|_| Status::unauthenticated("invalid timestamp"))?;
+        let now = now_secs();
+        if now.abs_diff(timestamp) > MAX_CLOCK_SKEW {
+            return Err(Status::unauthenticated("timestamp out of range"));
+        }
+        let signature = STANDARD
+            .decode(signature)
+            .map_err(|_| Status::unauthenticated("invalid signature"))?;
+        key.verify(
+            &signed_message(&self.gateway, method, timestamp, message),
+            &signature,
+        )
+        .map_err(|_| Status::unauthenticated("invalid signature"))?;
+        // Signatures whose timestamp is out of range are rejected above, so
+        // only the ones still in range are remembered
+        let mut seen = self.seen.lock().unwrap_or_else(|err| err.into_inner());
+        seen.retain(|(seen_at, _)| now.abs_diff(*seen_at) <= MAX_CLOCK_SKEW);
+        if !seen.insert((timestamp, signature)) {
+            return Err(Status::unauthenticated("replayed signature"));
+        }
+        Ok(())
+    }
+}
+
+/// Client side credentials of mutating requests
+#[derive(Debug, Clone)]
+pub enum ClientAuth {
+    Token(Secret),
+    Signature(Arc<Keypair>),
+}
+
+impl ClientAuth {
+    /// The credentials to use with a server with the given settings: the token
+    /// if one is set, otherwise signatures with the given keypair if keys are
+    /// set. None if the server does not require credentials.
+    pub fn new(settings: &ApiAuthSettings, keypair: Arc<Keypair>) -> Option<Self> {
+        match &settings.token {
+            Some(token) => Some(Self::Token(token.clone())),
+            None if !settings.keys.is_empty() => Some(Self::Signature(keypair)),
+            None => None,
+        }
+    }
+
+    /// Adds the credentials for the given method of the gateway with the
+    /// given key to a request
+    pub async fn authorize<T: prost::Message>(
+        &self,
+        request: &mut Request<T>,
+        gateway: &PublicKey,
+        method: &str,
+    ) -> Result {
+        match self {
+            Self::Token(token) => {
+                request.metadata_mut().insert(
+                    TOKEN_HEADER,
+                    metadata_value(&format!("Bearer {}", token.expose()))?,
+                );
+            }
+            Self::Signature(keypair) => {
+                let timestamp = now_secs();
+                let message = signed_message(gateway, method, timestamp, request.get_ref());
+                let signature = crate::sign(keypair.clone(), message).await?;
+                let metadata = request.metadata_mut();
+                metadata.insert(
+                    KEY_HEADER,
+                    metadata_value(&keypair.public_key().to_string())?,
+                );
+                metadata.insert(TIMESTAMP_HEADER, metadata_value(&timestamp.to_string())?);
+                metadata.insert(
+                    SIGNATURE_HEADER,
+                    metadata_value(&STANDARD.encode(signature))?,
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn metadata_value(value: &str) -> Result<tonic::metadata::MetadataValue<tonic::metadata::Ascii>> {
+    value
+        .parse()
+        .map_err(|_| crate::Error::custom("invalid api credentials"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::proto::PingReq;
+
+    #[test]
+    fn test_token_auth() {
+        let gateway =
+            PublicKey::from_str("137oJzq1qZpSbzHawaysTGGsRCYTXG1MiTMQNxYSsQJp4YMDdN8").unwrap();
+        let settings = ApiAuthSettings {
+            token: Some(serde_json::from_str("\"secret\"").unwrap()),
+            keys: vec![],
+        };
+        let auth = ApiAuth::new(&settings, gateway.clone());
+        let with_token = |token: &str| {
+            let mut request = Request::new(());
+            request
+                .metadata_mut()
+                .insert(TOKEN_HEADER, token.parse().unwrap());
+            request
+        };
+        assert!(auth
+            .authorize(&with_token("Bearer secret"), "reload")
+            .is_ok());
+        assert!(auth
+            .authorize(&with_token("Bearer secreT"), "reload")
+            .is_err());
+        assert!(auth.authorize(&Request::new(()), "reload").is_err());
+
+        let open = ApiAuth::new(&ApiAuthSettings::default(), gateway);
+        assert!(open.authorize(&Request::new(()), "reload").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_signature_auth() {
+        let keypair = Arc::new(Keypair::new());
+        let gateway = keypair.public_key().clone();
+        let auth = ApiAuth::new(
+            &ApiAuthSettings {
+                token: None,
+                keys: vec![gateway.clone()],
+            },
+            gateway.clone(),
+        );
+        let client = ClientAuth::Signature(keypair);
+        let mut request = Request::new(PingReq {
+            target: gateway.to_vec(),
+        });
+        client
+            .authorize(&mut request, &gateway, "ping")
+            .await
+            .expect("signed request");
+        assert!(auth.authorize(&request, "reload").is_err());
+
+        // The signature covers the request message
+        let mut tampered = Request::new(PingReq { target: vec![1] });
+        *tampered.metadata_mut() = request.metadata().clone();
+        assert!(auth.authorize(&tampered, "ping").is_err());
+
+        // A signature is accepted once
+        assert!(auth.authorize(&request, "ping").is_ok());
+        assert!(auth.authorize(&request, "ping").is_err());
+    }
+}
@@ -1,35 +1,103 @@
-use super::{AddGatewayReq, GatewayStakingMode, PubkeyReq, RegionReq, RouterReq};
+use super::{
+    auth::ClientAuth,
+    proto::{
+        gateway_client::GatewayClient, AuditLogReq, BeaconsReq, CrcReq, DcReq, DownlinksReq,
+        DutyCycleReq, FiltersReq, ForwarderStatsReq, ForwardersReq, LogsReq, PacketStreamReq,
+        PingReq, PocReq, PurgeQueueReq, QueueReq, ReceivedPingsReq, RegionParamsReq,
+        RegionStreamReq, ReloadReq, SessionsReq, SetSubsystemReq, StatusReq, UptimeReq,
+        WatchStatusReq,
+    },
+    AddGatewayReq, GatewayStakingMode, PubkeyReq, RegionReq, RouterReq, RuntimeStatus, StatusEvent,
+};
 use crate::{
+    audit_log::AuditEntry,
+    beaconer::BeaconerStatus,
+    crc_stats::CrcStatus,
+    dc_stats::DcStatus,
+    downlink_stats::DownlinkStatus,
+    duty_cycle::DutyCycleStatus,
     error::{DecodeError, Error},
-    packet_router::RouterStatus,
+    forwarders::{ForwarderStat, ForwardersStatus},
+    log_buffer::LogRecord,
+    packet_router::{QueueStatus, RouterStatus},
+    packet_trace::PacketEvent,
+    ping::{ReceivedPing, SentPing},
+    poc_history::{BeaconRecord, PocDay},
+    region_watcher::{RegionEvent, RegionPowerStatus},
+    service::session_log::SessionRecord,
     settings::{ListenAddress, StakingMode},
-    PublicKey, Region, Result,
+    subsystems::Subsystem,
+    uplink_filter::FilterStatus,
+    uptime::UptimeStatus,
+    PublicKey, Region, Result, Settings, Stream,
 };
+use futures::{StreamExt, TryStreamExt};
 use helium_proto::{
     services::local::Client, BlockchainTxn, BlockchainTxnAddGatewayV1, Message, Txn,
 };
-use std::convert::TryFrom;
-use tonic::transport::{Channel, Endpoint};
+use std::{convert::TryFrom, path::Path};
+use tonic::{
+    transport::{Channel, Endpoint},
+    Request,
+};
 
 pub struct LocalClient {
     client: Client<Channel>,
+    gateway: GatewayClient<Channel>,
+    /// The gateway and onboarding keys, which are fetched once per client
+    pubkeys: Option<(PublicKey, PublicKey)>,
+    /// Credentials for mutating requests, if the service requires them
+    auth: Option<ClientAuth>,
 }
 
 impl LocalClient {
     pub async fn new(address: &ListenAddress) -> Result<Self> {
-        let uri = http::Uri::try_from(address)?;
-        let endpoint = Endpoint::from_shared(uri.to_string()).unwrap();
-        let client = Client::connect(endpoint)
-            .await
-            .map_err(Error::local_client_connect)?;
-        Ok(Self { client })
+        let channel = match address.unix_path() {
+            Some(path) => connect_unix(path).await?,
+            None => {
+                let uri = http::Uri::try_from(address)?;
+                let endpoint = Endpoint::from_shared(uri.to_string()).unwrap();
+                endpoint
+                    .connect()
+                    .await
+                    .map_err(Error::local_client_connect)?
+            }
+        };
+        Ok(Self {
+            client: Client::new(channel.clone()),
+            gateway: GatewayClient::new(channel),
+            pubkeys: None,
+            auth: None,
+        })
     }
 
+    /// Sends the credentials of the given settings with mutating requests
+    pub fn with_auth(mut self, settings: &Settings) -> Self {
+        self.auth = ClientAuth::new(&settings.api_auth, settings.keypair.clone());
+        self
+    }
+
+    /// A request to the given method with the credentials of the client
+    async fn authorized<T>(&mut self, message: T, method: &str) -> Result<Request<T>> {
+        let mut request = Request::new(message);
+        if let Some(auth) = self.auth.clone() {
+            let (gateway, _) = self.pubkey().await?;
+            auth.authorize(&mut request, &gateway, method).await?;
+        }
+        Ok(request)
+    }
+
+    /// Returns the gateway and onboarding keys. The keys are cached so
+    /// repeated requests on the same client do not go to the service.
     pub async fn pubkey(&mut self) -> Result<(PublicKey, PublicKey)> {
+        if let Some(pubkeys) = &self.pubkeys {
+            return Ok(pubkeys.clone());
+        }
         let response = self.client.pubkey(PubkeyReq {}).await?.into_inner();
 
         let public_key = PublicKey::try_from(response.address)?;
         let onboarding_key = PublicKey::try_from(response.onboarding_address)?;
+        self.pubkeys = Some((public_key.clone(), onboarding_key.clone()));
         Ok((public_key, onboarding_key))
     }
 
@@ -38,25 +106,226 @@ impl LocalClient {
         Ok(Region::from_i32(response.into_inner().region)?)
     }
 
+    pub async fn region_params(&mut self) -> Result<RegionPowerStatus> {
+        let response = self.gateway.region_params(RegionParamsReq {}).await?;
+        Ok(response.into_inner().into())
+    }
+
     pub async fn router(&mut self) -> Result<RouterStatus> {
         let response = self.client.router(RouterReq {}).await?;
         response.into_inner().try_into()
     }
 
+    pub async fn uptime(&mut self) -> Result<UptimeStatus> {
+        let response = self.gateway.uptime(UptimeReq {}).await?;
+        Ok(response.into_inner().into())
+    }
+
+    pub async fn queue(&mut self) -> Result<QueueStatus> {
+        let response = self.gateway.queue(QueueReq {}).await?;
+        Ok(response.into_inner().into())
+    }
+
+    pub async fn purge_queue(&mut self) -> Result<usize> {
+        let request = self.authorized(PurgeQueueReq {}, "purge_queue").await?;
+        let response = self.gateway.purge_queue(request).await?;
+        Ok(response.into_inner().purged as usize)
+    }
+
+    pub async fn ping(&mut self, target: &PublicKey) -> Result<SentPing> {
+        let request = self
+            .authorized(
+                PingReq {
+                    target: target.to_vec(),
+                },
+                "ping",
+            )
+            .await?;
+        let response = self.gateway.ping(request).await?;
+        response.into_inner().try_into()
+    }
+
+    pub async fn received_pings(&mut self) -> Result<Vec<ReceivedPing>> {
+        let response = self.gateway.received_pings(ReceivedPingsReq {}).await?;
+        response
+            .into_inner()
+            .pings
+            .into_iter()
+            .map(ReceivedPing::try_from)
+            .collect()
+    }
+
+    pub async fn poc(&mut self) -> Result<(BeaconerStatus, Vec<PocDay>)> {
+        let response = self.gateway.poc(PocReq {}).await?;
+        Ok(response.into_inner().into())
+    }
+
+    pub async fn downlinks(&mut self) -> Result<DownlinkStatus> {
+        let response = self.gateway.downlinks(DownlinksReq {}).await?;
+        Ok(response.into_inner().into())
+    }
+
+    pub async fn beacons(&mut self) -> Result<Vec<BeaconRecord>> {
+        let response = self.gateway.beacons(BeaconsReq {}).await?;
+        Ok(response
+            .into_inner()
+            .beacons
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    pub async fn dc(&mut self) -> Result<DcStatus> {
+        let response = self.gateway.dc(DcReq {}).await?;
+        response.into_inner().try_into()
+    }
+
+    pub async fn crc(&mut self) -> Result<CrcStatus> {
+        let response = self.gateway.crc(CrcReq {}).await?;
+        Ok(response.into_inner().into())
+    }
+
+    pub async fn duty_cycle(&mut self) -> Result<DutyCycleStatus> {
+        let response = self.gateway.duty_cycle(DutyCycleReq {}).await?;
+        Ok(response.into_inner().into())
+    }
+
+    pub async fn filters(&mut self) -> Result<FilterStatus> {
+        let response = self.gateway.filters(FiltersReq {}).await?;
+        Ok(response.into_inner().into())
+    }
+
+    pub async fn forwarders(&mut self) -> Result<ForwardersStatus> {
+        let response = self.gateway.forwarders(ForwardersReq {}).await?;
+        Ok(response.into_inner().into())
+    }
+
+    pub async fn forwarder_stats(&mut self) -> Result<Vec<ForwarderStat>> {
+        let response = self.gateway.forwarder_stats(ForwarderStatsReq {}).await?;
+        Ok(response
+            .into_inner()
+            .stats
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    pub async fn status(&mut self) -> Result<RuntimeStatus> {
+        let response = self.gateway.status(StatusReq {}).await?;
+        response.into_inner().try_into()
+    }
+
+    /// Subscribes to the uplinks and downlinks handled by the running service
+    pub async fn packet_stream(&mut self) -> Result<Stream<PacketEvent>> {
+        let response = self.gateway.packet_stream(PacketStreamReq {}).await?;
+        let events = response
+            .into_inner()
+            .map_ok(PacketEvent::from)
+            .map_err(Error::from);
+        Ok(events.boxed())
+    }
+
+    /// Subscribes to status changes of the running service. The heartbeat is
+    /// the number of seconds between heartbeat events, or 0 for the default.
+    pub async fn watch_status(&mut self, heartbeat: u32) -> Result<Stream<StatusEvent>> {
+        let response = self
+            .gateway
+            .watch_status(WatchStatusReq { heartbeat })
+            .await?;
+        let events = response
+            .into_inner()
+            .map_err(Error::from)
+            .and_then(|event| async move { StatusEvent::try_from(event) });
+        Ok(events.boxed())
+    }
+
+    /// Subscribes to the region parameter updates of the running service
+    pub async fn region_stream(&mut self) -> Result<Stream<RegionEvent>> {
+        let response = self.gateway.region_stream(RegionStreamReq {}).await?;
+        let events = response
+            .into_inner()
+            .map_ok(RegionEvent::from)
+            .map_err(Error::from);
+        Ok(events.boxed())
+    }
+
+    /// Reloads the settings of the running service, returning the keys of the
+    /// applied settings
+    pub async fn reload(&mut self) -> Result<Vec<String>> {
+        let request = self.authorized(ReloadReq {}, "reload").await?;
+        let response = self.gateway.reload(request).await?;
+        Ok(response.into_inner().applied)
+    }
+
+    pub async fn set_subsystem(
+        &mut self,
+        subsystem: Subsystem,
+        paused: bool,
+        persist: bool,
+    ) -> Result {
+        let request = self
+            .authorized(
+                SetSubsystemReq {
+                    subsystem: subsystem.to_string(),
+                    paused,
+                    persist,
+                },
+                "set_subsystem",
+            )
+            .await?;
+        self.gateway.set_subsystem(request).await?;
+        Ok(())
+    }
+
+    pub async fn logs(&mut self, limit: u32) -> Result<Vec<LogRecord>> {
+        let response = self.gateway.logs(LogsReq { limit }).await?;
+        Ok(response
+            .into_inner()
+            .records
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    /// The given number of most recent audit log entries, all for 0
+    pub async fn audit_log(&mut self, limit: u32) -> Result<Vec<AuditEntry>> {
+        let response = self.gateway.audit_log(AuditLogReq { limit }).await?;
+        response
+            .into_inner()
+            .entries
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect()
+    }
+
+    /// The last established router and ingest sessions
+    pub async fn sessions(&mut self) -> Result<Vec<SessionRecord>> {
+        let response = self.gateway.sessions(SessionsReq {}).await?;
+        Ok(response
+            .into_inner()
+            .sessions
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
     pub async fn add_gateway(
         &mut self,
         owner: &PublicKey,
         payer: &PublicKey,
         mode: &StakingMode,
     ) -> Result<BlockchainTxnAddGatewayV1> {
-        let response = self
-            .client
-            .add_gateway(AddGatewayReq {
-                owner: owner.to_vec(),
-                payer: payer.to_vec(),
-                staking_mode: GatewayStakingMode::from(mode).into(),
-            })
+        let request = self
+            .authorized(
+                AddGatewayReq {
+                    owner: owner.to_vec(),
+                    payer: payer.to_vec(),
+                    staking_mode: GatewayStakingMode::from(mode).into(),
+                },
+                "add_gateway",
+            )
             .await?;
+        let response = self.client.add_gateway(request).await?;
 
         let encoded = response.into_inner().add_gateway_txn;
         let envelope = BlockchainTxn::decode(encoded.as_ref())?;
@@ -66,3 +335,24 @@ impl LocalClient {
         }
     }
 }
+
+#[cfg(unix)]
+async fn connect_unix(path: &Path) -> Result<Channel> {
+    let path = path.to_path_buf();
+    // The uri is not used to connect to a unix socket
+    Endpoint::from_static("http://localhost")
+        .connect_with_connector(tower::service_fn(move |_: http::Uri| {
+            tokio::net::UnixStream::connect(path.clone())
+        }))
+        .await
+        .map_err(Error::local_client_connect)
+}
+
+/// Unix domain sockets are only available on unix platforms
+#[cfg(not(unix))]
+async fn connect_unix(path: &Path) -> Result<Channel> {
+    Err(Error::custom(format!(
+        "unix socket api address {} is not supported on this platform",
+        path.display()
+    )))
+}
@@ -1,5 +1,7 @@
+mod auth;
 mod client;
 mod server;
+mod status_watch;
 
 pub use client::LocalClient;
 pub use helium_proto::{
@@ -10,8 +12,216 @@ pub use helium_proto::{
     GatewayStakingMode,
 };
 pub use server::LocalServer;
+pub use status_watch::{StatusChange, StatusEvent};
 
-use crate::{Error, PublicKey, Result};
+/// Gateway specific local api extensions
+#[allow(non_camel_case_types)]
+pub(crate) mod proto {
+    tonic::include_proto!("helium.gateway");
+}
+
+use crate::{
+    audit_log::{AuditEntry, AuditRecord},
+    beaconer::{BeaconerStatus, PocSubmission},
+    crc_stats::{ChannelCrcCount, CrcCount, CrcStatus},
+    dc_stats::{DcCount, DcDeviceCount, DcNetIdCount, DcStatus, DcVendorCount},
+    downlink_stats::{DownlinkPeriod, DownlinkStatus},
+    duty_cycle::{ChannelUtilization, DutyCycleStatus, SubBandUtilization},
+    forwarders::{ForwarderClient, ForwarderStat, ForwardersStatus},
+    gps::{FixQuality, GpsFix, GpsSource},
+    log_buffer::LogRecord,
+    packet_router::{DeliveryStats, QueueAgeBucket, QueueDevAddrCount, QueueStatus},
+    packet_trace::{PacketDecision, PacketDirection, PacketEvent},
+    ping::{PingId, ReceivedPing, SentPing},
+    poc_history::{BeaconOutcome, BeaconRecord, PocDay, WitnessAlert},
+    region_watcher::{RegionChannel, RegionEvent, RegionPowerStatus},
+    service::session_log::SessionRecord,
+    uplink_filter::{FilterStatus, FportFilterStatus},
+    uptime::{RestartReason, UptimeStatus},
+    witness_queue::WitnessQueueStatus,
+    DecodeError, Error, PublicKey, Result,
+};
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use serde::Serialize;
+
+/// Live runtime status of the gateway service
+#[derive(Debug, Clone, Serialize)]
+pub struct RuntimeStatus {
+    /// The region the gateway operates in, None if not yet known
+    pub region: Option<String>,
+    /// Unix time in seconds of the current region parameters, None if no
+    /// region parameters were received
+    pub region_params_timestamp: Option<u64>,
+    pub poc: BeaconerStatus,
+    /// Whether the packet router session is established
+    pub router_connected: bool,
+    /// Seconds since the packet router session was established
+    pub router_session_age: Option<u64>,
+    /// Number of packet router sessions replaced by a new router offer
+    pub router_session_renewals: u64,
+    /// Number of uplinks queued for the packet router
+    pub router_queue: usize,
+    /// Number of packets queued across all packet forwarders
+    pub forwarders_queued: usize,
+    /// Whether packet processing is saturated
+    pub forwarders_saturated: bool,
+    /// Whether any packet forwarder is silent
+    pub forwarders_silent: bool,
+    /// Why the startup keypair self test failed, None if it passed or did not
+    /// run
+    pub keypair_error: Option<String>,
+    /// The current GPS fix, None without a fix
+    pub gps: Option<GpsFix>,
+}
+
+impl From<RuntimeStatus> for proto::StatusRes {
+    fn from(value: RuntimeStatus) -> Self {
+        Self {
+            region: value.region.unwrap_or_default(),
+            region_params_timestamp: value.region_params_timestamp.unwrap_or_default(),
+            poc: Some((value.poc, vec![]).into()),
+            router_connected: value.router_connected,
+            router_session_age: value.router_session_age.unwrap_or_default(),
+            router_session_renewals: value.router_session_renewals,
+            router_queue: value.router_queue as u32,
+            forwarders_queued: value.forwarders_queued as u32,
+            forwarders_saturated: value.forwarders_saturated,
+            forwarders_silent: value.forwarders_silent,
+            keypair_error: value.keypair_error.unwrap_or_default(),
+            gps: value.gps.map(proto::GpsFix::from),
+        }
+    }
+}
+
+impl TryFrom<proto::StatusRes> for RuntimeStatus {
+    type Error = Error;
+    fn try_from(value: proto::StatusRes) -> Result<Self> {
+        let (poc, _) = value
+            .poc
+            .ok_or_else(|| DecodeError::prost_decode("missing poc status"))?
+            .into();
+        Ok(Self {
+            region: (!value.region.is_empty()).then_some(value.region),
+            region_params_timestamp: (value.region_params_timestamp != 0)
+                .then_some(value.region_params_timestamp),
+            poc,
+            router_connected: value.router_connected,
+            router_session_age: value.router_connected.then_some(value.router_session_age),
+            router_session_renewals: value.router_session_renewals,
+            router_queue: value.router_queue as usize,
+            forwarders_queued: value.forwarders_queued as usize,
+            forwarders_saturated: value.forwarders_saturated,
+            forwarders_silent: value.forwarders_silent,
+            keypair_error: (!value.keypair_error.is_empty()).then_some(value.keypair_error),
+            gps: value.gps.map(GpsFix::from),
+        })
+    }
+}
+
+impl From<GpsFix> for proto::GpsFix {
+    fn from(value: GpsFix) -> Self {
+        let quality = match value.quality {
+            FixQuality::Fix2d => proto::GpsFixQuality::Fix2d,
+            FixQuality::Fix3d => proto::GpsFixQuality::Fix3d,
+        };
+        let source = match value.source {
+            GpsSource::Forwarder => proto::GpsSource::Forwarder,
+            GpsSource::Gpsd => proto::GpsSource::Gpsd,
+        };
+        Self {
+            source: source.into(),
+            quality: quality.into(),
+            lat: value.lat,
+            lon: value.lon,
+            alt: value.alt.unwrap_or_default(),
+            gps_time: value.gps_time.unwrap_or_default(),
+            timestamp: value.timestamp,
+        }
+    }
+}
+
+impl From<proto::GpsFix> for GpsFix {
+    fn from(value: proto::GpsFix) -> Self {
+        let quality = match value.quality() {
+            proto::GpsFixQuality::Fix2d => FixQuality::Fix2d,
+            proto::GpsFixQuality::Fix3d => FixQuality::Fix3d,
+        };
+        let source = match value.source() {
+            proto::GpsSource::Forwarder => GpsSource::Forwarder,
+            proto::GpsSource::Gpsd => GpsSource::Gpsd,
+        };
+        Self {
+            source,
+            quality,
+            lat: value.lat,
+            lon: value.lon,
+            alt: (quality == FixQuality::Fix3d).then_some(value.alt),
+            gps_time: (value.gps_time != 0).then_some(value.gps_time),
+            timestamp: value.timestamp,
+        }
+    }
+}
+
+impl From<StatusChange> for proto::StatusChange {
+    fn from(value: StatusChange) -> Self {
+        match value {
+            StatusChange::Snapshot => Self::Snapshot,
+            StatusChange::Heartbeat => Self::Heartbeat,
+            StatusChange::Region => Self::Region,
+            StatusChange::RouterConnected => Self::RouterConnected,
+            StatusChange::RouterDisconnected => Self::RouterDisconnected,
+            StatusChange::ForwarderConnected => Self::ForwarderConnected,
+            StatusChange::ForwarderDisconnected => Self::ForwarderDisconnected,
+            StatusChange::Saturation => Self::Saturation,
+            StatusChange::ForwarderSilence => Self::ForwarderSilence,
+            StatusChange::BeaconTxHealth => Self::BeaconTxHealth,
+        }
+    }
+}
+
+impl From<proto::StatusChange> for StatusChange {
+    fn from(value: proto::StatusChange) -> Self {
+        match value {
+            proto::StatusChange::Snapshot => Self::Snapshot,
+            proto::StatusChange::Heartbeat => Self::Heartbeat,
+            proto::StatusChange::Region => Self::Region,
+            proto::StatusChange::RouterConnected => Self::RouterConnected,
+            proto::StatusChange::RouterDisconnected => Self::RouterDisconnected,
+            proto::StatusChange::ForwarderConnected => Self::ForwarderConnected,
+            proto::StatusChange::ForwarderDisconnected => Self::ForwarderDisconnected,
+            proto::StatusChange::Saturation => Self::Saturation,
+            proto::StatusChange::ForwarderSilence => Self::ForwarderSilence,
+            proto::StatusChange::BeaconTxHealth => Self::BeaconTxHealth,
+        }
+    }
+}
+
+impl From<StatusEvent> for proto::StatusEvent {
+    fn from(value: StatusEvent) -> Self {
+        Self {
+            timestamp: value.timestamp,
+            change: proto::StatusChange::from(value.change).into(),
+            forwarder: value.forwarder.unwrap_or_default(),
+            status: Some(value.status.into()),
+        }
+    }
+}
+
+impl TryFrom<proto::StatusEvent> for StatusEvent {
+    type Error = Error;
+    fn try_from(value: proto::StatusEvent) -> Result<Self> {
+        let change = value.change().into();
+        Ok(Self {
+            timestamp: value.timestamp,
+            change,
+            forwarder: (!value.forwarder.is_empty()).then_some(value.forwarder),
+            status: value
+                .status
+                .ok_or_else(|| DecodeError::prost_decode("missing status"))?
+                .try_into()?,
+        })
+    }
+}
 
 impl TryFrom<RouterRes> for crate::packet_router::RouterStatus {
     type Error = Error;
@@ -21,6 +231,962 @@ impl TryFrom<RouterRes> for crate::packet_router::RouterStatus {
             uri: http::Uri::from_str(&value.uri)?,
             connected: value.connected,
             session_key: PublicKey::try_from(value.session_key).ok(),
+            session_age: None,
+            session_renewals: None,
+            payload_hash: None,
+            downlink_acks: None,
         })
     }
 }
+
+impl From<RestartReason> for proto::RestartReason {
+    fn from(value: RestartReason) -> Self {
+        match value {
+            RestartReason::FirstStart => Self::FirstStart,
+            RestartReason::Clean => Self::Clean,
+            RestartReason::Crash => Self::Crash,
+            RestartReason::Watchdog => Self::Watchdog,
+        }
+    }
+}
+
+impl From<proto::RestartReason> for RestartReason {
+    fn from(value: proto::RestartReason) -> Self {
+        match value {
+            proto::RestartReason::FirstStart => Self::FirstStart,
+            proto::RestartReason::Clean => Self::Clean,
+            proto::RestartReason::Crash => Self::Crash,
+            proto::RestartReason::Watchdog => Self::Watchdog,
+        }
+    }
+}
+
+impl From<UptimeStatus> for proto::UptimeRes {
+    fn from(value: UptimeStatus) -> Self {
+        Self {
+            uptime: value.uptime,
+            restart_count: value.restart_count,
+            last_restart_reason: proto::RestartReason::from(value.last_restart_reason).into(),
+        }
+    }
+}
+
+impl From<proto::UptimeRes> for UptimeStatus {
+    fn from(value: proto::UptimeRes) -> Self {
+        Self {
+            uptime: value.uptime,
+            restart_count: value.restart_count,
+            last_restart_reason: value.last_restart_reason().into(),
+        }
+    }
+}
+
+impl From<QueueStatus> for proto::QueueRes {
+    fn from(value: QueueStatus) -> Self {
+        Self {
+            count: value.count as u32,
+            bytes: value.bytes as u64,
+            ages: value
+                .ages
+                .into_iter()
+                .map(|bucket| proto::QueueAgeBucket {
+                    min_age: bucket.min_age,
+                    count: bucket.count as u32,
+                })
+                .collect(),
+            top_devaddrs: value
+                .top_devaddrs
+                .into_iter()
+                .map(|entry| proto::QueueDevaddrCount {
+                    devaddr: entry.devaddr,
+                    count: entry.count as u32,
+                })
+                .collect(),
+            delivery: Some(value.delivery.into()),
+        }
+    }
+}
+
+impl From<proto::QueueRes> for QueueStatus {
+    fn from(value: proto::QueueRes) -> Self {
+        Self {
+            count: value.count as usize,
+            bytes: value.bytes as usize,
+            ages: value
+                .ages
+                .into_iter()
+                .map(|bucket| QueueAgeBucket {
+                    min_age: bucket.min_age,
+                    count: bucket.count as usize,
+                })
+                .collect(),
+            top_devaddrs: value
+                .top_devaddrs
+                .into_iter()
+                .map(|entry| QueueDevAddrCount {
+                    devaddr: entry.devaddr,
+                    count: entry.count as usize,
+                })
+                .collect(),
+            delivery: value.delivery.map(Into::into).unwrap_or_default(),
+        }
+    }
+}
+
+impl From<DeliveryStats> for proto::DeliveryStats {
+    fn from(value: DeliveryStats) -> Self {
+        Self {
+            delivered: value.delivered,
+            expired: value.expired,
+            expired_joins: value.expired_joins,
+            evicted: value.evicted,
+            purged: value.purged,
+        }
+    }
+}
+
+impl From<proto::DeliveryStats> for DeliveryStats {
+    fn from(value: proto::DeliveryStats) -> Self {
+        Self {
+            delivered: value.delivered,
+            expired: value.expired,
+            expired_joins: value.expired_joins,
+            evicted: value.evicted,
+            purged: value.purged,
+        }
+    }
+}
+
+fn ping_id(value: &[u8]) -> Result<PingId> {
+    PingId::from_slice(value).ok_or_else(|| DecodeError::prost_decode("invalid ping id"))
+}
+
+impl From<SentPing> for proto::PingRes {
+    fn from(value: SentPing) -> Self {
+        Self {
+            sender: value.sender.as_bytes().to_vec(),
+            target: value.target.as_bytes().to_vec(),
+            seq: value.seq,
+            frequency: value.frequency,
+            datarate: value.datarate,
+            tx_power: value.tx_power,
+        }
+    }
+}
+
+impl TryFrom<proto::PingRes> for SentPing {
+    type Error = Error;
+    fn try_from(value: proto::PingRes) -> Result<Self> {
+        Ok(Self {
+            sender: ping_id(&value.sender)?,
+            target: ping_id(&value.target)?,
+            seq: value.seq,
+            frequency: value.frequency,
+            datarate: value.datarate,
+            tx_power: value.tx_power,
+        })
+    }
+}
+
+impl From<ReceivedPing> for proto::ReceivedPing {
+    fn from(value: ReceivedPing) -> Self {
+        Self {
+            sender: value.sender.as_bytes().to_vec(),
+            seq: value.seq,
+            timestamp: value.timestamp,
+            frequency: value.frequency,
+            datarate: value.datarate,
+            rssi: value.rssi,
+            snr: value.snr,
+        }
+    }
+}
+
+impl TryFrom<proto::ReceivedPing> for ReceivedPing {
+    type Error = Error;
+    fn try_from(value: proto::ReceivedPing) -> Result<Self> {
+        Ok(Self {
+            sender: ping_id(&value.sender)?,
+            seq: value.seq,
+            timestamp: value.timestamp,
+            frequency: value.frequency,
+            datarate: value.datarate,
+            rssi: value.rssi,
+            snr: value.snr,
+        })
+    }
+}
+
+impl From<PocDay> for proto::PocDay {
+    fn from(value: PocDay) -> Self {
+        Self {
+            date: value.date,
+            beacons: value.beacons,
+            witnesses: value.witnesses,
+            unique_beacons: value.unique_beacons,
+            avg_rssi: value.avg_rssi.unwrap_or_default(),
+            avg_snr: value.avg_snr.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<proto::PocDay> for PocDay {
+    fn from(value: proto::PocDay) -> Self {
+        let witnessed = value.witnesses > 0;
+        Self {
+            date: value.date,
+            beacons: value.beacons,
+            witnesses: value.witnesses,
+            unique_beacons: value.unique_beacons,
+            avg_rssi: witnessed.then_some(value.avg_rssi),
+            avg_snr: witnessed.then_some(value.avg_snr),
+        }
+    }
+}
+
+impl From<(BeaconerStatus, Vec<PocDay>)> for proto::PocRes {
+    fn from((status, history): (BeaconerStatus, Vec<PocDay>)) -> Self {
+        Self {
+            disabled: status.disabled,
+            paused: status.paused,
+            tx_failures: status.tx_failures,
+            tx_suppressed: status.tx_suppressed,
+            tx_healthy: status.tx_healthy,
+            history: history.into_iter().map(Into::into).collect(),
+            next_beacon_time: status.next_beacon_time.unwrap_or_default(),
+            last_beacon: status.last_beacon.map(Into::into),
+            last_witness: status.last_witness.map(Into::into),
+            witness_alert: status.witness_alert.map(Into::into),
+            witness_queue: Some(status.witness_queue.into()),
+        }
+    }
+}
+
+impl From<proto::PocRes> for (BeaconerStatus, Vec<PocDay>) {
+    fn from(value: proto::PocRes) -> Self {
+        let status = BeaconerStatus {
+            disabled: value.disabled,
+            paused: value.paused,
+            tx_failures: value.tx_failures,
+            tx_suppressed: value.tx_suppressed,
+            tx_healthy: value.tx_healthy,
+            next_beacon_time: (value.next_beacon_time != 0).then_some(value.next_beacon_time),
+            last_beacon: value.last_beacon.map(Into::into),
+            last_witness: value.last_witness.map(Into::into),
+            witness_alert: value.witness_alert.map(Into::into),
+            witness_queue: value.witness_queue.map(Into::into).unwrap_or_default(),
+        };
+        (status, value.history.into_iter().map(Into::into).collect())
+    }
+}
+
+impl From<WitnessAlert> for proto::WitnessAlert {
+    fn from(value: WitnessAlert) -> Self {
+        Self {
+            date: value.date,
+            witnesses: value.witnesses,
+            baseline: value.baseline,
+        }
+    }
+}
+
+impl From<proto::WitnessAlert> for WitnessAlert {
+    fn from(value: proto::WitnessAlert) -> Self {
+        Self {
+            date: value.date,
+            witnesses: value.witnesses,
+            baseline: value.baseline,
+        }
+    }
+}
+
+impl From<WitnessQueueStatus> for proto::WitnessQueue {
+    fn from(value: WitnessQueueStatus) -> Self {
+        Self {
+            queued: value.queued,
+            failed: value.failed,
+            retried: value.retried,
+            dropped: value.dropped,
+        }
+    }
+}
+
+impl From<proto::WitnessQueue> for WitnessQueueStatus {
+    fn from(value: proto::WitnessQueue) -> Self {
+        Self {
+            queued: value.queued,
+            failed: value.failed,
+            retried: value.retried,
+            dropped: value.dropped,
+        }
+    }
+}
+
+impl From<PocSubmission> for proto::PocSubmission {
+    fn from(value: PocSubmission) -> Self {
+        Self {
+            timestamp: value.timestamp,
+            beacon_id: value.beacon_id.unwrap_or_default(),
+            error: value.error.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<proto::PocSubmission> for PocSubmission {
+    fn from(value: proto::PocSubmission) -> Self {
+        Self {
+            timestamp: value.timestamp,
+            beacon_id: (!value.beacon_id.is_empty()).then_some(value.beacon_id),
+            error: (!value.error.is_empty()).then_some(value.error),
+        }
+    }
+}
+
+impl From<BeaconOutcome> for proto::BeaconOutcome {
+    fn from(value: BeaconOutcome) -> Self {
+        match value {
+            BeaconOutcome::Submitted => Self::Submitted,
+            BeaconOutcome::SubmitFailed => Self::SubmitFailed,
+            BeaconOutcome::TxFailed => Self::TxFailed,
+        }
+    }
+}
+
+impl From<proto::BeaconOutcome> for BeaconOutcome {
+    fn from(value: proto::BeaconOutcome) -> Self {
+        match value {
+            proto::BeaconOutcome::Submitted => Self::Submitted,
+            proto::BeaconOutcome::SubmitFailed => Self::SubmitFailed,
+            proto::BeaconOutcome::TxFailed => Self::TxFailed,
+        }
+    }
+}
+
+impl From<BeaconRecord> for proto::BeaconRecord {
+    fn from(value: BeaconRecord) -> Self {
+        Self {
+            beacon_id: value.beacon_id,
+            timestamp: value.timestamp,
+            tx_power: value.tx_power.unwrap_or_default(),
+            outcome: proto::BeaconOutcome::from(value.outcome).into(),
+            error: value.error.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<proto::BeaconRecord> for BeaconRecord {
+    fn from(value: proto::BeaconRecord) -> Self {
+        let outcome = value.outcome().into();
+        Self {
+            beacon_id: value.beacon_id,
+            timestamp: value.timestamp,
+            tx_power: (outcome != BeaconOutcome::TxFailed).then_some(value.tx_power),
+            outcome,
+            error: (!value.error.is_empty()).then_some(value.error),
+        }
+    }
+}
+
+impl From<DownlinkPeriod> for proto::DownlinkPeriod {
+    fn from(value: DownlinkPeriod) -> Self {
+        Self {
+            accepted: value.accepted,
+            transmitted: value.transmitted,
+            failed: value.failed,
+        }
+    }
+}
+
+impl From<proto::DownlinkPeriod> for DownlinkPeriod {
+    fn from(value: proto::DownlinkPeriod) -> Self {
+        Self {
+            accepted: value.accepted,
+            transmitted: value.transmitted,
+            failed: value.failed,
+        }
+    }
+}
+
+impl From<DownlinkStatus> for proto::DownlinksRes {
+    fn from(value: DownlinkStatus) -> Self {
+        Self {
+            current: Some(value.current.into()),
+            last_hour: value.last_hour.map(Into::into),
+        }
+    }
+}
+
+impl From<proto::DownlinksRes> for DownlinkStatus {
+    fn from(value: proto::DownlinksRes) -> Self {
+        Self {
+            current: value.current.map(Into::into).unwrap_or_default(),
+            last_hour: value.last_hour.map(Into::into),
+        }
+    }
+}
+
+impl From<DcCount> for proto::DcCount {
+    fn from(value: DcCount) -> Self {
+        Self {
+            uplinks: value.uplinks,
+            dc: value.dc,
+        }
+    }
+}
+
+impl From<proto::DcCount> for DcCount {
+    fn from(value: proto::DcCount) -> Self {
+        Self {
+            uplinks: value.uplinks,
+            dc: value.dc,
+        }
+    }
+}
+
+impl From<DcStatus> for proto::DcRes {
+    fn from(value: DcStatus) -> Self {
+        Self {
+            since: value.since,
+            total: Some(value.total.into()),
+            joins: Some(value.joins.into()),
+            net_ids: value
+                .net_ids
+                .into_iter()
+                .map(|count| proto::DcNetIdCount {
+                    net_id: count.net_id.into(),
+                    count: Some(count.count.into()),
+                })
+                .collect(),
+            top_devices: value
+                .top_devices
+                .into_iter()
+                .map(|count| proto::DcDeviceCount {
+                    devaddr: count.devaddr,
+                    count: Some(count.count.into()),
+                })
+                .collect(),
+            join_vendors: value
+                .join_vendors
+                .into_iter()
+                .map(|count| proto::DcVendorCount {
+                    vendor: count.vendor,
+                    count: Some(count.count.into()),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::DcRes> for DcStatus {
+    type Error = Error;
+    fn try_from(value: proto::DcRes) -> Result<Self> {
+        Ok(Self {
+            since: value.since,
+            total: value.total.map(Into::into).unwrap_or_default(),
+            joins: value.joins.map(Into::into).unwrap_or_default(),
+            net_ids: value
+                .net_ids
+                .into_iter()
+                .map(|count| {
+                    Ok(DcNetIdCount {
+                        net_id: lorawan::subnet::NetId::new(count.net_id)
+                            .map_err(DecodeError::from)?,
+                        count: count.count.map(Into::into).unwrap_or_default(),
+                    })
+                })
+                .collect::<Result<_>>()?,
+            top_devices: value
+                .top_devices
+                .into_iter()
+                .map(|count| DcDeviceCount {
+                    devaddr: count.devaddr,
+                    count: count.count.map(Into::into).unwrap_or_default(),
+                })
+                .collect(),
+            join_vendors: value
+                .join_vendors
+                .into_iter()
+                .map(|count| DcVendorCount {
+                    vendor: count.vendor,
+                    count: count.count.map(Into::into).unwrap_or_default(),
+                })
+                .collect(),
+        })
+    }
+}
+
+impl From<CrcCount> for proto::CrcCount {
+    fn from(value: CrcCount) -> Self {
+        Self {
+            ok: value.ok,
+            failed: value.failed,
+        }
+    }
+}
+
+impl From<proto::CrcCount> for CrcCount {
+    fn from(value: proto::CrcCount) -> Self {
+        Self {
+            ok: value.ok,
+            failed: value.failed,
+        }
+    }
+}
+
+impl From<CrcStatus> for proto::CrcRes {
+    fn from(value: CrcStatus) -> Self {
+        Self {
+            since: value.since,
+            total: Some(value.total.into()),
+            failure_rate: value.failure_rate,
+            channels: value
+                .channels
+                .into_iter()
+                .map(|channel| proto::ChannelCrcCount {
+                    frequency: channel.frequency,
+                    count: Some(channel.count.into()),
+                    failure_rate: channel.failure_rate,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<proto::CrcRes> for CrcStatus {
+    fn from(value: proto::CrcRes) -> Self {
+        Self {
+            since: value.since,
+            total: value.total.map(Into::into).unwrap_or_default(),
+            failure_rate: value.failure_rate,
+            channels: value
+                .channels
+                .into_iter()
+                .map(|channel| {
+                    ChannelCrcCount::from((
+                        channel.frequency,
+                        channel.count.map(Into::into).unwrap_or_default(),
+                    ))
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<FilterStatus> for proto::FiltersRes {
+    fn from(value: FilterStatus) -> Self {
+        Self {
+            network_dropped: value.network_dropped,
+            fport_filters: value
+                .fport_filters
+                .into_iter()
+                .map(|filter| proto::FportFilterCount {
+                    subnet: filter.subnet,
+                    dropped: filter.dropped,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<proto::FiltersRes> for FilterStatus {
+    fn from(value: proto::FiltersRes) -> Self {
+        Self {
+            network_dropped: value.network_dropped,
+            fport_filters: value
+                .fport_filters
+                .into_iter()
+                .map(|filter| FportFilterStatus {
+                    subnet: filter.subnet,
+                    dropped: filter.dropped,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A value in dBm or dBi in tenths
+fn to_tenths(value: Decimal) -> u64 {
+    (value * Decimal::TEN).trunc().to_u64().unwrap_or_default()
+}
+
+impl From<RegionPowerStatus> for proto::RegionParamsRes {
+    fn from(value: RegionPowerStatus) -> Self {
+        Self {
+            region: value.region.unwrap_or_default(),
+            max_eirp: value.max_eirp.map(to_tenths).unwrap_or_default(),
+            gain: to_tenths(value.gain),
+            gain_override: value.gain_override,
+            max_conducted_power: value.max_conducted_power.unwrap_or_default(),
+            beacon_tx_power: value.beacon_tx_power.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<proto::RegionParamsRes> for RegionPowerStatus {
+    fn from(value: proto::RegionParamsRes) -> Self {
+        let has_params = value.max_eirp != 0;
+        Self {
+            region: (!value.region.is_empty()).then_some(value.region),
+            max_eirp: has_params.then(|| Decimal::new(value.max_eirp as i64, 1)),
+            gain: Decimal::new(value.gain as i64, 1),
+            gain_override: value.gain_override,
+            max_conducted_power: has_params.then_some(value.max_conducted_power),
+            beacon_tx_power: has_params.then_some(value.beacon_tx_power),
+        }
+    }
+}
+
+impl From<RegionEvent> for proto::RegionEvent {
+    fn from(value: RegionEvent) -> Self {
+        Self {
+            timestamp: value.timestamp.unwrap_or_default(),
+            power: Some(value.power.into()),
+            channels: value
+                .channels
+                .into_iter()
+                .map(|channel| proto::RegionChannel {
+                    frequency: channel.frequency,
+                    bandwidth: channel.bandwidth,
+                    max_eirp: channel.max_eirp,
+                    spreading: channel.spreading,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<proto::RegionEvent> for RegionEvent {
+    fn from(value: proto::RegionEvent) -> Self {
+        Self {
+            timestamp: (value.timestamp != 0).then_some(value.timestamp),
+            power: value.power.unwrap_or_default().into(),
+            channels: value
+                .channels
+                .into_iter()
+                .map(|channel| RegionChannel {
+                    frequency: channel.frequency,
+                    bandwidth: channel.bandwidth,
+                    max_eirp: channel.max_eirp,
+                    spreading: channel.spreading,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<DutyCycleStatus> for proto::DutyCycleRes {
+    fn from(value: DutyCycleStatus) -> Self {
+        Self {
+            enabled: value.enabled,
+            window: value.window,
+            channels: value
+                .channels
+                .into_iter()
+                .map(|channel| proto::ChannelUtilization {
+                    frequency: channel.frequency,
+                    airtime: channel.airtime,
+                    utilization: channel.utilization,
+                })
+                .collect(),
+            sub_bands: value
+                .sub_bands
+                .into_iter()
+                .map(|sub_band| proto::SubBandUtilization {
+                    min_frequency: sub_band.min_frequency,
+                    max_frequency: sub_band.max_frequency,
+                    limit: sub_band.limit,
+                    utilization: sub_band.utilization,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<proto::DutyCycleRes> for DutyCycleStatus {
+    fn from(value: proto::DutyCycleRes) -> Self {
+        Self {
+            enabled: value.enabled,
+            window: value.window,
+            channels: value
+                .channels
+                .into_iter()
+                .map(|channel| ChannelUtilization {
+                    frequency: channel.frequency,
+                    airtime: channel.airtime,
+                    utilization: channel.utilization,
+                })
+                .collect(),
+            sub_bands: value
+                .sub_bands
+                .into_iter()
+                .map(|sub_band| SubBandUtilization {
+                    min_frequency: sub_band.min_frequency,
+                    max_frequency: sub_band.max_frequency,
+                    limit: sub_band.limit,
+                    utilization: sub_band.utilization,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<LogRecord> for proto::LogRecord {
+    fn from(value: LogRecord) -> Self {
+        Self {
+            timestamp: value.timestamp,
+            level: value.level,
+            target: value.target,
+            message: value.message,
+        }
+    }
+}
+
+impl From<proto::LogRecord> for LogRecord {
+    fn from(value: proto::LogRecord) -> Self {
+        Self {
+            timestamp: value.timestamp,
+            level: value.level,
+            target: value.target,
+            message: value.message,
+        }
+    }
+}
+
+impl From<AuditEntry> for proto::AuditEntry {
+    fn from(value: AuditEntry) -> Self {
+        let record = value.record;
+        Self {
+            seq: record.seq,
+            timestamp: record.timestamp,
+            method: record.method,
+            caller: record.caller.unwrap_or_default(),
+            params: record.params.to_string(),
+            error: record.error.unwrap_or_default(),
+            prev_hash: record.prev_hash,
+            hash: value.hash,
+            signature: value.signature,
+        }
+    }
+}
+
+impl TryFrom<proto::AuditEntry> for AuditEntry {
+    type Error = Error;
+    fn try_from(value: proto::AuditEntry) -> Result<Self> {
+        Ok(Self {
+            record: AuditRecord {
+                seq: value.seq,
+                timestamp: value.timestamp,
+                method: value.method,
+                caller: (!value.caller.is_empty()).then_some(value.caller),
+                params: serde_json::from_str(&value.params)?,
+                error: (!value.error.is_empty()).then_some(value.error),
+                prev_hash: value.prev_hash,
+            },
+            hash: value.hash,
+            signature: value.signature,
+        })
+    }
+}
+
+impl From<SessionRecord> for proto::SessionRecord {
+    fn from(value: SessionRecord) -> Self {
+        Self {
+            module: value.module,
+            uri: value.uri,
+            session_key_hash: value.session_key_hash,
+            established: value.established,
+            closed: value.closed.unwrap_or_default(),
+            close_reason: value.close_reason.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<proto::SessionRecord> for SessionRecord {
+    fn from(value: proto::SessionRecord) -> Self {
+        Self {
+            module: value.module,
+            uri: value.uri,
+            session_key_hash: value.session_key_hash,
+            established: value.established,
+            closed: (value.closed != 0).then_some(value.closed),
+            close_reason: (!value.close_reason.is_empty()).then_some(value.close_reason),
+        }
+    }
+}
+
+impl From<ForwardersStatus> for proto::ForwardersRes {
+    fn from(value: ForwardersStatus) -> Self {
+        Self {
+            clients: value.clients.into_iter().map(Into::into).collect(),
+            saturated: value.saturated,
+            queued: value.queued as u32,
+            silent: value.silent,
+        }
+    }
+}
+
+impl From<proto::ForwardersRes> for ForwardersStatus {
+    fn from(value: proto::ForwardersRes) -> Self {
+        Self {
+            saturated: value.saturated,
+            queued: value.queued as usize,
+            silent: value.silent,
+            clients: value.clients.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<ForwarderClient> for proto::ForwarderClient {
+    fn from(value: ForwarderClient) -> Self {
+        Self {
+            mac: value.mac,
+            received: value.received,
+            processed: value.processed,
+            dropped: value.dropped,
+            queued: value.queued as u32,
+            rate: value.rate,
+            connected: value.connected,
+            last_seen: value.last_seen,
+            silent: value.silent,
+            bad_datagrams: value.bad_datagrams,
+            bad_packets: value.bad_packets,
+            duplicates: value.duplicates,
+        }
+    }
+}
+
+impl From<proto::ForwarderClient> for ForwarderClient {
+    fn from(value: proto::ForwarderClient) -> Self {
+        Self {
+            mac: value.mac,
+            received: value.received,
+            processed: value.processed,
+            dropped: value.dropped,
+            queued: value.queued as usize,
+            rate: value.rate,
+            connected: value.connected,
+            last_seen: value.last_seen,
+            silent: value.silent,
+            bad_datagrams: value.bad_datagrams,
+            bad_packets: value.bad_packets,
+            duplicates: value.duplicates,
+        }
+    }
+}
+
+impl From<ForwarderStat> for proto::ForwarderStat {
+    fn from(value: ForwarderStat) -> Self {
+        Self {
+            mac: value.mac,
+            timestamp: value.timestamp,
+            rxnb: value.rxnb,
+            rxok: value.rxok,
+            rxfw: value.rxfw,
+            ackr: value.ackr.unwrap_or(f64::NAN),
+            dwnb: value.dwnb,
+            txnb: value.txnb,
+            temp: value.temp.unwrap_or(f64::NAN),
+        }
+    }
+}
+
+impl From<proto::ForwarderStat> for ForwarderStat {
+    fn from(value: proto::ForwarderStat) -> Self {
+        Self {
+            mac: value.mac,
+            timestamp: value.timestamp,
+            rxnb: value.rxnb,
+            rxok: value.rxok,
+            rxfw: value.rxfw,
+            ackr: (!value.ackr.is_nan()).then_some(value.ackr),
+            dwnb: value.dwnb,
+            txnb: value.txnb,
+            temp: (!value.temp.is_nan()).then_some(value.temp),
+        }
+    }
+}
+
+impl From<PacketDirection> for proto::PacketDirection {
+    fn from(value: PacketDirection) -> Self {
+        match value {
+            PacketDirection::Uplink => Self::Uplink,
+            PacketDirection::Downlink => Self::Downlink,
+        }
+    }
+}
+
+impl From<proto::PacketDirection> for PacketDirection {
+    fn from(value: proto::PacketDirection) -> Self {
+        match value {
+            proto::PacketDirection::Uplink => Self::Uplink,
+            proto::PacketDirection::Downlink => Self::Downlink,
+        }
+    }
+}
+
+impl From<PacketDecision> for proto::PacketDecision {
+    fn from(value: PacketDecision) -> Self {
+        match value {
+            PacketDecision::Routed => Self::Routed,
+            PacketDecision::NoRegion => Self::NoRegion,
+            PacketDecision::FportFiltered => Self::FportFiltered,
+            PacketDecision::Rx1 => Self::Rx1,
+            PacketDecision::Rx2 => Self::Rx2,
+            PacketDecision::Failed => Self::Failed,
+            PacketDecision::Duplicate => Self::Duplicate,
+            PacketDecision::NetworkFiltered => Self::NetworkFiltered,
+        }
+    }
+}
+
+impl From<proto::PacketDecision> for PacketDecision {
+    fn from(value: proto::PacketDecision) -> Self {
+        match value {
+            proto::PacketDecision::Routed => Self::Routed,
+            proto::PacketDecision::NoRegion => Self::NoRegion,
+            proto::PacketDecision::FportFiltered => Self::FportFiltered,
+            proto::PacketDecision::Rx1 => Self::Rx1,
+            proto::PacketDecision::Rx2 => Self::Rx2,
+            proto::PacketDecision::Failed => Self::Failed,
+            proto::PacketDecision::Duplicate => Self::Duplicate,
+            proto::PacketDecision::NetworkFiltered => Self::NetworkFiltered,
+        }
+    }
+}
+
+impl From<PacketEvent> for proto::PacketEvent {
+    fn from(value: PacketEvent) -> Self {
+        Self {
+            timestamp: value.timestamp,
+            direction: proto::PacketDirection::from(value.direction).into(),
+            mac: value.mac,
+            data_frame: value.dev_addr.is_some(),
+            dev_addr: value.dev_addr.unwrap_or_default(),
+            fcnt: value.fcnt.unwrap_or_default() as u32,
+            rssi: value.rssi.unwrap_or_default(),
+            snr: value.snr.unwrap_or_default(),
+            frequency: value.frequency,
+            datarate: value.datarate,
+            decision: proto::PacketDecision::from(value.decision).into(),
+        }
+    }
+}
+
+impl From<proto::PacketEvent> for PacketEvent {
+    fn from(value: proto::PacketEvent) -> Self {
+        let direction = PacketDirection::from(value.direction());
+        let uplink = direction == PacketDirection::Uplink;
+        Self {
+            timestamp: value.timestamp,
+            direction,
+            decision: value.decision().into(),
+            mac: value.mac,
+            dev_addr: value.data_frame.then_some(value.dev_addr),
+            fcnt: value.data_frame.then_some(value.fcnt as u16),
+            rssi: uplink.then_some(value.rssi),
+            snr: uplink.then_some(value.snr),
+            frequency: value.frequency,
+            datarate: value.datarate,
+        }
+    }
+}
@@ -1,50 +1,320 @@
 use super::{
+    auth::{self, ApiAuth},
+    proto::{
+        gateway_server::{Gateway, GatewayServer},
+        AuditLogReq, AuditLogRes, BeaconsReq, BeaconsRes, CrcReq, CrcRes, DcReq, DcRes,
+        DownlinksReq, DownlinksRes, DutyCycleReq, DutyCycleRes, FiltersReq, FiltersRes,
+        ForwarderStatsReq, ForwarderStatsRes, ForwardersReq, ForwardersRes, LogsReq, LogsRes,
+        PacketEvent, PacketStreamReq, PingReq, PingRes, PocReq, PocRes, PurgeQueueReq,
+        PurgeQueueRes, QueueReq, QueueRes, ReceivedPingsReq, ReceivedPingsRes,
+        RegionEvent as ProtoRegionEvent, RegionParamsReq, RegionParamsRes, RegionStreamReq,
+        ReloadReq, ReloadRes, SessionsReq, SessionsRes, SetSubsystemReq, SetSubsystemRes,
+        StatusEvent as ProtoStatusEvent, StatusReq, StatusRes, UptimeReq, UptimeRes,
+        WatchStatusReq,
+    },
+    status_watch::{StatusChange, StatusEvent, StatusSample},
     AddGatewayReq, AddGatewayRes, PubkeyReq, PubkeyRes, RegionReq, RegionRes, RouterReq, RouterRes,
+    RuntimeStatus,
 };
-use crate::{packet_router, region_watcher, Error, Keypair, PublicKey, Result, Settings};
-use futures::TryFutureExt;
-use helium_crypto::Sign;
+#[cfg(feature = "mqtt")]
+use crate::mqtt;
+use crate::{
+    audit_log::{self, AuditLog},
+    beaconer, gateway,
+    keypair::SelfTest,
+    log_buffer, packet_router,
+    region_watcher::{self, RegionEvent},
+    reload,
+    service::session_log,
+    settings::ListenAddress,
+    subsystems::{PausedSubsystems, Subsystem},
+    uptime::Uptime,
+    Error, Keypair, PublicKey, Result, Settings,
+};
+use futures::{Stream, StreamExt, TryFutureExt};
 use helium_proto::services::local::{Api, Server};
 use helium_proto::{BlockchainTxn, BlockchainTxnAddGatewayV1, Message, Txn};
-use std::{net::SocketAddr, sync::Arc};
+use serde_json::json;
+use std::{
+    collections::VecDeque, net::SocketAddr, path::PathBuf, pin::Pin, sync::Arc, time::Duration,
+};
+use tokio::{
+    sync::{broadcast, Mutex},
+    time,
+};
 use tonic::{self, transport::Server as TransportServer, Request, Response, Status};
-use tracing::info;
+use tracing::{info, warn};
 
 pub type ApiResult<T> = std::result::Result<Response<T>, Status>;
+pub type ApiStream<T> = Pin<Box<dyn Stream<Item = std::result::Result<T, Status>> + Send>>;
+
+/// Interval at which the status is sampled for status watchers
+const STATUS_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// Default interval between heartbeat events for status watchers
+const DEFAULT_STATUS_HEARTBEAT: Duration = Duration::from_secs(30);
+/// Permissions of the api unix socket, read and write for owner and group
+#[cfg(unix)]
+const UNIX_SOCKET_MODE: u32 = 0o660;
 
 pub struct LocalServer {
     region_watch: region_watcher::MessageReceiver,
     packet_router: packet_router::MessageSender,
+    gateway: gateway::MessageSender,
+    beaconer: beaconer::MessageSender,
+    reloader: reload::MessageSender,
+    /// The mqtt bridge, if configured
+    #[cfg(feature = "mqtt")]
+    mqtt: Option<mqtt::MessageSender>,
+    /// Why the startup keypair self test failed, if it did
+    keypair_error: Option<String>,
+    uptime: Uptime,
+    /// Ends open streams on shutdown. Set when the server is running
+    shutdown: Option<triggered::Listener>,
     keypair: Arc<Keypair>,
     onboarding_key: PublicKey,
-    listen_addr: SocketAddr,
+    listen: Listen,
+    /// Credentials check of mutating requests
+    auth: ApiAuth,
+    /// Audit log of mutating requests, if enabled
+    audit_log: Option<Arc<Mutex<AuditLog>>>,
+    /// Directory of persisted subsystem pauses
+    data_dir: PathBuf,
+    /// Configured antenna gain in tenths of dBi, if any
+    antenna_gain: Option<u64>,
+    /// Configured beacon transmit power override in dBm, if any
+    beacon_tx_power_override: Option<u32>,
 }
 
 impl LocalServer {
     pub fn new(
         region_watch: region_watcher::MessageReceiver,
         packet_router: packet_router::MessageSender,
+        gateway: gateway::MessageSender,
+        beaconer: beaconer::MessageSender,
+        reloader: reload::MessageSender,
+        uptime: Uptime,
         settings: &Settings,
     ) -> Result<Self> {
         Ok(Self {
             keypair: settings.keypair.clone(),
             onboarding_key: settings.onboarding_key(),
-            listen_addr: (&settings.api).try_into()?,
+            listen: Listen::new(&settings.api)?,
+            auth: ApiAuth::new(&settings.api_auth, settings.keypair.public_key().clone()),
+            audit_log: settings
+                .audit_log
+                .then(|| AuditLog::open(&settings.data_dir, settings.keypair.clone()))
+                .transpose()?
+                .map(|audit_log| Arc::new(Mutex::new(audit_log))),
+            data_dir: settings.data_dir.clone(),
+            antenna_gain: settings.antenna_gain,
+            beacon_tx_power_override: settings.poc.tx_power_override,
             region_watch,
             packet_router,
+            gateway,
+            beaconer,
+            reloader,
+            #[cfg(feature = "mqtt")]
+            mqtt: None,
+            keypair_error: None,
+            uptime,
+            shutdown: None,
         })
     }
 
-    pub async fn run(self, shutdown: &triggered::Listener) -> Result {
-        let listen_addr = self.listen_addr;
-        tracing::Span::current().record("listen", listen_addr.to_string());
-        info!(listen = %listen_addr, "starting");
-        TransportServer::builder()
-            .add_service(Server::new(self))
-            .serve_with_shutdown(listen_addr, shutdown.clone())
-            .map_err(Error::from)
+    /// Lets the server pause and resume the given mqtt bridge
+    #[cfg(feature = "mqtt")]
+    pub fn with_mqtt(self, mqtt: Option<mqtt::MessageSender>) -> Self {
+        Self { mqtt, ..self }
+    }
+
+    /// Reports the given startup keypair self test in the status
+    pub fn with_keypair_test(self, test: Option<SelfTest>) -> Self {
+        Self {
+            keypair_error: test.and_then(|test| test.error),
+            ..self
+        }
+    }
+
+    fn status_source(&self) -> StatusSource {
+        StatusSource {
+            region_watch: self.region_watch.clone(),
+            packet_router: self.packet_router.clone(),
+            gateway: self.gateway.clone(),
+            beaconer: self.beaconer.clone(),
+            keypair_error: self.keypair_error.clone(),
+        }
+    }
+
+    async fn add_gateway_txn(&self, request: Request<AddGatewayReq>) -> ApiResult<AddGatewayRes> {
+        self.auth.authorize(&request, "add_gateway")?;
+        let request = request.into_inner();
+        let _ = PublicKey::from_bytes(&request.owner)
+            .map_err(|_err| Status::invalid_argument("Invalid owner address"))?;
+        let _ = PublicKey::from_bytes(&request.payer)
+            .map_err(|_err| Status::invalid_argument("Invalid payer address"))?;
+
+        let mut txn = BlockchainTxnAddGatewayV1 {
+            gateway: self.keypair.public_key().to_vec(),
+            owner: request.owner.clone(),
+            payer: request.payer,
+            ..Default::default()
+        };
+
+        let signature = crate::sign(self.keypair.clone(), txn.encode_to_vec())
+            .await
+            .map_err(|_err| Status::internal("Failed signing txn"))?;
+        txn.gateway_signature = signature;
+
+        let add_gateway_txn = BlockchainTxn {
+            txn: Some(Txn::AddGateway(txn)),
+        }
+        .encode_to_vec();
+        Ok(Response::new(AddGatewayRes { add_gateway_txn }))
+    }
+
+    async fn change_subsystem(
+        &self,
+        request: Request<SetSubsystemReq>,
+    ) -> ApiResult<SetSubsystemRes> {
+        self.auth.authorize(&request, "set_subsystem")?;
+        let request = request.into_inner();
+        let subsystem: Subsystem = request
+            .subsystem
+            .parse()
+            .map_err(|_err| Status::invalid_argument("Unknown subsystem"))?;
+        match subsystem {
+            Subsystem::Poc => self.beaconer.set_paused(request.paused).await,
+            #[cfg(feature = "mqtt")]
+            Subsystem::Mqtt => match &self.mqtt {
+                Some(mqtt) => mqtt.set_paused(request.paused).await,
+                None => return Err(Status::failed_precondition("Mqtt bridge not configured")),
+            },
+            #[cfg(not(feature = "mqtt"))]
+            Subsystem::Mqtt => {
+                return Err(Status::failed_precondition(
+                    "Mqtt bridge not supported by this build",
+                ))
+            }
+        }
+        if request.persist {
+            PausedSubsystems::store(&self.data_dir, subsystem, request.paused).map_err(|err| {
+                Status::internal(format!("Failed to persist subsystem pause: {err}"))
+            })?;
+        }
+        info!(%subsystem, paused = request.paused, persist = request.persist, "subsystem changed");
+        Ok(Response::new(SetSubsystemRes {}))
+    }
+
+    /// Appends a request and its result to the audit log, if enabled
+    async fn audit<T>(
+        &self,
+        method: &str,
+        caller: Option<String>,
+        params: serde_json::Value,
+        result: &ApiResult<T>,
+    ) {
+        let Some(audit_log) = &self.audit_log else {
+            return;
+        };
+        let error = result
+            .as_ref()
+            .err()
+            .map(|status| status.message().to_string());
+        if let Err(err) = audit_log
+            .lock()
             .await
+            .append(method, caller, params, error)
+            .await
+        {
+            warn!(method, %err, "failed to append to audit log");
+        }
     }
+
+    pub async fn run(mut self, shutdown: &triggered::Listener) -> Result {
+        self.shutdown = Some(shutdown.clone());
+        let listen = self.listen.clone();
+        tracing::Span::current().record("listen", listen.to_string());
+        info!(%listen, "starting");
+        let server = Arc::new(self);
+        let router = TransportServer::builder()
+            .add_service(Server::from_arc(server.clone()))
+            .add_service(GatewayServer::from_arc(server));
+        match listen {
+            Listen::Tcp(addr) => {
+                router
+                    .serve_with_shutdown(addr, shutdown.clone())
+                    .map_err(Error::from)
+                    .await
+            }
+            #[cfg(unix)]
+            Listen::Unix(path) => {
+                let listener = bind_unix(&path)?;
+                let result = router
+                    .serve_with_incoming_shutdown(
+                        tokio_stream::wrappers::UnixListenerStream::new(listener),
+                        shutdown.clone(),
+                    )
+                    .map_err(Error::from)
+                    .await;
+                if let Err(err) = std::fs::remove_file(&path) {
+                    warn!(path = %path.display(), %err, "failed to remove api socket");
+                }
+                result
+            }
+        }
+    }
+}
+
+/// Where the local api listens
+#[derive(Debug, Clone)]
+enum Listen {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Listen {
+    fn new(address: &ListenAddress) -> Result<Self> {
+        match address.unix_path() {
+            #[cfg(unix)]
+            Some(path) => Ok(Self::Unix(path.to_path_buf())),
+            // Unix domain sockets are only available on unix platforms
+            #[cfg(not(unix))]
+            Some(path) => Err(Error::custom(format!(
+                "unix socket api address {} is not supported on this platform",
+                path.display()
+            ))),
+            None => Ok(Self::Tcp(address.try_into()?)),
+        }
+    }
+}
+
+impl std::fmt::Display for Listen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(addr) => addr.fmt(f),
+            #[cfg(unix)]
+            Self::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+/// Binds the unix socket at the given path, replacing the socket of an
+/// earlier run. Only the owner and group of the service can connect, so
+/// access to the api follows the file permissions of the socket.
+#[cfg(unix)]
+fn bind_unix(path: &std::path::Path) -> Result<tokio::net::UnixListener> {
+    use std::{
+        fs,
+        os::unix::fs::{FileTypeExt, PermissionsExt},
+    };
+
+    if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(UNIX_SOCKET_MODE))?;
+    Ok(listener)
 }
 
 #[tonic::async_trait]
@@ -81,29 +351,440 @@ impl Api for LocalServer {
     }
 
     async fn add_gateway(&self, request: Request<AddGatewayReq>) -> ApiResult<AddGatewayRes> {
-        let request = request.into_inner();
-        let _ = PublicKey::from_bytes(&request.owner)
-            .map_err(|_err| Status::invalid_argument("Invalid owner address"))?;
-        let _ = PublicKey::from_bytes(&request.payer)
-            .map_err(|_err| Status::invalid_argument("Invalid payer address"))?;
+        let caller = auth::caller(&request);
+        let key_param = |key: &[u8]| PublicKey::from_bytes(key).map(|key| key.to_string()).ok();
+        let params = json!({
+            "owner": key_param(&request.get_ref().owner),
+            "payer": key_param(&request.get_ref().payer),
+        });
+        let result = self.add_gateway_txn(request).await;
+        self.audit("add_gateway", caller, params, &result).await;
+        result
+    }
+}
 
-        let mut txn = BlockchainTxnAddGatewayV1 {
-            gateway: self.keypair.public_key().to_vec(),
-            owner: request.owner.clone(),
-            payer: request.payer,
-            ..Default::default()
+#[tonic::async_trait]
+impl Gateway for LocalServer {
+    async fn uptime(&self, _request: Request<UptimeReq>) -> ApiResult<UptimeRes> {
+        Ok(Response::new(self.uptime.status().into()))
+    }
+
+    async fn queue(&self, _request: Request<QueueReq>) -> ApiResult<QueueRes> {
+        let queue_status = self
+            .packet_router
+            .queue_status()
+            .map_err(|_err| Status::internal("Failed to get queue status"))
+            .await?;
+        Ok(Response::new(queue_status.into()))
+    }
+
+    async fn purge_queue(&self, request: Request<PurgeQueueReq>) -> ApiResult<PurgeQueueRes> {
+        let caller = auth::caller(&request);
+        let result = async {
+            self.auth.authorize(&request, "purge_queue")?;
+            let purged = self
+                .packet_router
+                .purge_queue()
+                .map_err(|_err| Status::internal("Failed to purge queue"))
+                .await?;
+            Ok(Response::new(PurgeQueueRes {
+                purged: purged as u32,
+            }))
+        }
+        .await;
+        self.audit("purge_queue", caller, json!({}), &result).await;
+        result
+    }
+
+    async fn ping(&self, request: Request<PingReq>) -> ApiResult<PingRes> {
+        let caller = auth::caller(&request);
+        let target = PublicKey::from_bytes(&request.get_ref().target);
+        let params = json!({ "target": target.as_ref().ok().map(ToString::to_string) });
+        let result = async {
+            self.auth.authorize(&request, "ping")?;
+            let target =
+                target.map_err(|_err| Status::invalid_argument("Invalid target address"))?;
+            let sent = self
+                .gateway
+                .transmit_ping(target)
+                .map_err(|err| Status::internal(format!("Failed to send ping: {err}")))
+                .await?;
+            Ok(Response::new(sent.into()))
+        }
+        .await;
+        self.audit("ping", caller, params, &result).await;
+        result
+    }
+
+    async fn received_pings(
+        &self,
+        _request: Request<ReceivedPingsReq>,
+    ) -> ApiResult<ReceivedPingsRes> {
+        let pings = self
+            .gateway
+            .received_pings()
+            .map_err(|_err| Status::internal("Failed to get received pings"))
+            .await?;
+        Ok(Response::new(ReceivedPingsRes {
+            pings: pings.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn poc(&self, _request: Request<PocReq>) -> ApiResult<PocRes> {
+        let status = self
+            .beaconer
+            .status()
+            .map_err(|_err| Status::internal("Failed to get poc status"))
+            .await?;
+        let history = self
+            .beaconer
+            .history()
+            .map_err(|_err| Status::internal("Failed to get poc history"))
+            .await?;
+        Ok(Response::new((status, history).into()))
+    }
+
+    async fn downlinks(&self, _request: Request<DownlinksReq>) -> ApiResult<DownlinksRes> {
+        let status = self
+            .gateway
+            .downlink_status()
+            .map_err(|_err| Status::internal("Failed to get downlink status"))
+            .await?;
+        Ok(Response::new(status.into()))
+    }
+
+    async fn beacons(&self, _request: Request<BeaconsReq>) -> ApiResult<BeaconsRes> {
+        let beacons = self
+            .beaconer
+            .beacons()
+            .map_err(|_err| Status::internal("Failed to get beacon history"))
+            .await?;
+        Ok(Response::new(BeaconsRes {
+            beacons: beacons.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn dc(&self, _request: Request<DcReq>) -> ApiResult<DcRes> {
+        let status = self
+            .gateway
+            .dc_status()
+            .map_err(|_err| Status::internal("Failed to get dc status"))
+            .await?;
+        Ok(Response::new(status.into()))
+    }
+
+    async fn crc(&self, _request: Request<CrcReq>) -> ApiResult<CrcRes> {
+        let status = self
+            .gateway
+            .crc_status()
+            .map_err(|_err| Status::internal("Failed to get crc status"))
+            .await?;
+        Ok(Response::new(status.into()))
+    }
+
+    async fn region_params(
+        &self,
+        _request: Request<RegionParamsReq>,
+    ) -> ApiResult<RegionParamsRes> {
+        let status = region_watcher::RegionPowerStatus::new(
+            &self.region_watch.borrow(),
+            self.antenna_gain,
+            self.beacon_tx_power_override,
+        );
+        Ok(Response::new(status.into()))
+    }
+
+    async fn duty_cycle(&self, _request: Request<DutyCycleReq>) -> ApiResult<DutyCycleRes> {
+        let status = self
+            .gateway
+            .duty_cycle()
+            .map_err(|_err| Status::internal("Failed to get duty cycle status"))
+            .await?;
+        Ok(Response::new(status.into()))
+    }
+
+    async fn filters(&self, _request: Request<FiltersReq>) -> ApiResult<FiltersRes> {
+        let status = self
+            .gateway
+            .filter_status()
+            .map_err(|_err| Status::internal("Failed to get filter status"))
+            .await?;
+        Ok(Response::new(status.into()))
+    }
+
+    async fn forwarders(&self, _request: Request<ForwardersReq>) -> ApiResult<ForwardersRes> {
+        let status = self
+            .gateway
+            .forwarders()
+            .map_err(|_err| Status::internal("Failed to get forwarder status"))
+            .await?;
+        Ok(Response::new(status.into()))
+    }
+
+    async fn forwarder_stats(
+        &self,
+        _request: Request<ForwarderStatsReq>,
+    ) -> ApiResult<ForwarderStatsRes> {
+        let stats = self
+            .gateway
+            .forwarder_stats()
+            .map_err(|_err| Status::internal("Failed to get forwarder stats"))
+            .await?;
+        Ok(Response::new(ForwarderStatsRes {
+            stats: stats.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn status(&self, _request: Request<StatusReq>) -> ApiResult<StatusRes> {
+        let sample = self.status_source().sample().await?;
+        Ok(Response::new(sample.status.into()))
+    }
+
+    type watch_statusStream = ApiStream<ProtoStatusEvent>;
+
+    async fn watch_status(
+        &self,
+        request: Request<WatchStatusReq>,
+    ) -> ApiResult<Self::watch_statusStream> {
+        let shutdown = self
+            .shutdown
+            .clone()
+            .ok_or_else(|| Status::unavailable("Server not running"))?;
+        let heartbeat = match request.into_inner().heartbeat {
+            0 => DEFAULT_STATUS_HEARTBEAT,
+            secs => Duration::from_secs(secs.into()),
+        };
+        let watch = StatusWatch {
+            source: self.status_source(),
+            last: None,
+            events: VecDeque::new(),
+            sample_timer: time::interval(STATUS_SAMPLE_INTERVAL),
+            heartbeat_timer: time::interval_at(time::Instant::now() + heartbeat, heartbeat),
         };
+        let stream = futures::stream::unfold(watch, |mut watch| async move {
+            match watch.next_event().await {
+                Ok(event) => Some((Ok(event.into()), watch)),
+                Err(status) => {
+                    warn!(%status, "ending status watch");
+                    None
+                }
+            }
+        })
+        .take_until(shutdown);
+        Ok(Response::new(Box::pin(stream)))
+    }
 
-        let signature = self
-            .keypair
-            .sign(&txn.encode_to_vec())
-            .map_err(|_err| Status::internal("Failed signing txn"))?;
-        txn.gateway_signature = signature;
+    type region_streamStream = ApiStream<ProtoRegionEvent>;
 
-        let add_gateway_txn = BlockchainTxn {
-            txn: Some(Txn::AddGateway(txn)),
+    async fn region_stream(
+        &self,
+        _request: Request<RegionStreamReq>,
+    ) -> ApiResult<Self::region_streamStream> {
+        let shutdown = self
+            .shutdown
+            .clone()
+            .ok_or_else(|| Status::unavailable("Server not running"))?;
+        let antenna_gain = self.antenna_gain;
+        let beacon_tx_power_override = self.beacon_tx_power_override;
+        // Start with the current region parameters, then every update
+        let state = (self.region_watch.clone(), true);
+        let stream = futures::stream::unfold(state, move |(mut region_watch, first)| async move {
+            if !first {
+                region_watch.changed().await.ok()?;
+            }
+            let event = RegionEvent::new(
+                &region_watch.borrow_and_update(),
+                antenna_gain,
+                beacon_tx_power_override,
+            );
+            Some((Ok(event.into()), (region_watch, false)))
+        })
+        .take_until(shutdown);
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn reload(&self, request: Request<ReloadReq>) -> ApiResult<ReloadRes> {
+        let caller = auth::caller(&request);
+        let result = async {
+            self.auth.authorize(&request, "reload")?;
+            let applied = self
+                .reloader
+                .reload()
+                .map_err(|err| Status::internal(format!("Failed to reload settings: {err}")))
+                .await?;
+            Ok(Response::new(ReloadRes { applied }))
+        }
+        .await;
+        self.audit("reload", caller, json!({}), &result).await;
+        result
+    }
+
+    async fn set_subsystem(&self, request: Request<SetSubsystemReq>) -> ApiResult<SetSubsystemRes> {
+        let caller = auth::caller(&request);
+        let params = json!({
+            "subsystem": request.get_ref().subsystem,
+            "paused": request.get_ref().paused,
+            "persist": request.get_ref().persist,
+        });
+        let result = self.change_subsystem(request).await;
+        self.audit("set_subsystem", caller, params, &result).await;
+        result
+    }
+
+    async fn audit_log(&self, request: Request<AuditLogReq>) -> ApiResult<AuditLogRes> {
+        let limit = request.into_inner().limit as usize;
+        let entries = audit_log::read(&self.data_dir, limit)
+            .map_err(|err| Status::internal(format!("Failed to read audit log: {err}")))?
+            .into_iter()
+            .map(Into::into)
+            .collect();
+        Ok(Response::new(AuditLogRes { entries }))
+    }
+
+    async fn logs(&self, request: Request<LogsReq>) -> ApiResult<LogsRes> {
+        let limit = request.into_inner().limit as usize;
+        let records = log_buffer::records(limit)
+            .into_iter()
+            .map(Into::into)
+            .collect();
+        Ok(Response::new(LogsRes { records }))
+    }
+
+    async fn sessions(&self, _request: Request<SessionsReq>) -> ApiResult<SessionsRes> {
+        let sessions = session_log::load(&self.data_dir)
+            .into_iter()
+            .map(Into::into)
+            .collect();
+        Ok(Response::new(SessionsRes { sessions }))
+    }
+
+    type packet_streamStream = ApiStream<PacketEvent>;
+
+    async fn packet_stream(
+        &self,
+        _request: Request<PacketStreamReq>,
+    ) -> ApiResult<Self::packet_streamStream> {
+        let shutdown = self
+            .shutdown
+            .clone()
+            .ok_or_else(|| Status::unavailable("Server not running"))?;
+        let events = self
+            .gateway
+            .packet_events()
+            .map_err(|_err| Status::internal("Failed to subscribe to packet events"))
+            .await?;
+        let stream = futures::stream::unfold(events, |mut events| async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => return Some((Ok(event.into()), events)),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!(skipped, "packet stream client lagging, skipped events")
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+        .take_until(shutdown);
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// The services the runtime status is collected from
+struct StatusSource {
+    region_watch: region_watcher::MessageReceiver,
+    packet_router: packet_router::MessageSender,
+    gateway: gateway::MessageSender,
+    beaconer: beaconer::MessageSender,
+    keypair_error: Option<String>,
+}
+
+impl StatusSource {
+    async fn sample(&self) -> std::result::Result<StatusSample, Status> {
+        let (region, region_params_timestamp) = {
+            let region_params = self.region_watch.borrow();
+            (
+                (!region_params.region.is_unknown()).then(|| region_params.region.to_string()),
+                (region_params.timestamp != 0).then_some(region_params.timestamp),
+            )
+        };
+        let poc = self
+            .beaconer
+            .status()
+            .map_err(|_err| Status::internal("Failed to get poc status"))
+            .await?;
+        let router = self
+            .packet_router
+            .status()
+            .map_err(|_err| Status::internal("Failed to get router status"))
+            .await?;
+        let queue = self
+            .packet_router
+            .queue_status()
+            .map_err(|_err| Status::internal("Failed to get queue status"))
+            .await?;
+        let forwarders = self
+            .gateway
+            .forwarders()
+            .map_err(|_err| Status::internal("Failed to get forwarder status"))
+            .await?;
+        let gps = self
+            .gateway
+            .gps()
+            .map_err(|_err| Status::internal("Failed to get gps fix"))
+            .await?;
+        let status = RuntimeStatus {
+            region,
+            region_params_timestamp,
+            poc,
+            router_connected: router.connected,
+            router_session_age: router.session_age,
+            router_session_renewals: router.session_renewals.unwrap_or_default(),
+            router_queue: queue.count,
+            forwarders_queued: forwarders.queued,
+            forwarders_saturated: forwarders.saturated,
+            forwarders_silent: forwarders.silent,
+            keypair_error: self.keypair_error.clone(),
+            gps,
+        };
+        Ok(StatusSample::new(status, &forwarders))
+    }
+}
+
+/// The state of a status watch stream
+struct StatusWatch {
+    source: StatusSource,
+    /// The last status sample, None before the snapshot was sent
+    last: Option<StatusSample>,
+    /// Events of the last sample not yet sent
+    events: VecDeque<StatusEvent>,
+    sample_timer: time::Interval,
+    heartbeat_timer: time::Interval,
+}
+
+impl StatusWatch {
+    async fn next_event(&mut self) -> std::result::Result<StatusEvent, Status> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event);
+            }
+            tokio::select! {
+                _ = self.sample_timer.tick() => {
+                    let sample = self.source.sample().await?;
+                    match &self.last {
+                        None => self.events.push_back(sample.event(StatusChange::Snapshot, None)),
+                        Some(last) => self.events.extend(sample.changes(last)),
+                    }
+                    if !self.events.is_empty() {
+                        self.heartbeat_timer.reset();
+                    }
+                    self.last = Some(sample);
+                }
+                _ = self.heartbeat_timer.tick() => {
+                    if let Some(last) = &self.last {
+                        self.events.push_back(last.event(StatusChange::Heartbeat, None));
+                    }
+                }
+            }
         }
-        .encode_to_vec();
-        Ok(Response::new(AddGatewayRes { add_gateway_txn }))
     }
 }
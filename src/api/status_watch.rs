@@ -0,0 +1,214 @@
+//! Status change events for local API clients that watch the gateway status.
+//!
+//! The server samples the runtime status of the gateway and compares it with
+//! the previous sample. Every change of interest to a user interface results
+//! in an event carrying the full status at that time. A watch starts with a
+//! snapshot event and periodic heartbeat events let clients detect a stalled
+//! stream.
+
+use super::RuntimeStatus;
+use crate::forwarders::ForwardersStatus;
+use serde::Serialize;
+use std::{
+    collections::{BTreeSet, HashMap},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// What a status event reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatusChange {
+    /// The status when the watch started
+    Snapshot,
+    /// The periodic status while nothing changed
+    Heartbeat,
+    /// The region or its parameters changed
+    Region,
+    RouterConnected,
+    RouterDisconnected,
+    ForwarderConnected,
+    ForwarderDisconnected,
+    /// Packet processing became saturated or recovered from saturation
+    Saturation,
+    /// A packet forwarder went silent or all forwarders are active again
+    ForwarderSilence,
+    /// Beacon transmissions became unhealthy or recovered
+    BeaconTxHealth,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusEvent {
+    /// Unix time in seconds of the event
+    pub timestamp: u64,
+    pub change: StatusChange,
+    /// The MAC address of the packet forwarder of forwarder events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forwarder: Option<String>,
+    pub status: RuntimeStatus,
+}
+
+/// A status sample with the connection state of each packet forwarder
+#[derive(Debug, Clone)]
+pub struct StatusSample {
+    pub status: RuntimeStatus,
+    pub forwarders: HashMap<String, bool>,
+}
+
+impl StatusSample {
+    pub fn new(status: RuntimeStatus, forwarders: &ForwardersStatus) -> Self {
+        Self {
+            status,
+            forwarders: forwarders
+                .clients
+                .iter()
+                .map(|client| (client.mac.clone(), client.connected))
+                .collect(),
+        }
+    }
+
+    pub fn event(&self, change: StatusChange, forwarder: Option<String>) -> StatusEvent {
+        StatusEvent {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            change,
+            forwarder,
+            status: self.status.clone(),
+        }
+    }
+
+    /// The events for the changes from a previous sample to this one
+    pub fn changes(&self, previous: &Self) -> Vec<StatusEvent> {
+        let (status, previous_status) = (&self.status, &previous.status);
+        let mut events = vec![];
+        if status.region != previous_status.region
+            || status.region_params_timestamp != previous_status.region_params_timestamp
+        {
+            events.push(self.event(StatusChange::Region, None));
+        }
+        if status.router_connected != previous_status.router_connected {
+            let change = if status.router_connected {
+                StatusChange::RouterConnected
+            } else {
+                StatusChange::RouterDisconnected
+            };
+            events.push(self.event(change, None));
+        }
+        let macs: BTreeSet<&String> = self
+            .forwarders
+            .keys()
+            .chain(previous.forwarders.keys())
+            .collect();
+        for mac in macs {
+            let connected = self.forwarders.get(mac).copied().unwrap_or(false);
+            let was_connected = previous.forwarders.get(mac).copied().unwrap_or(false);
+            if connected != was_connected {
+                let change = if connected {
+                    StatusChange::ForwarderConnected
+                } else {
+                    StatusChange::ForwarderDisconnected
+                };
+                events.push(self.event(change, Some(mac.clone())));
+            }
+        }
+        if status.forwarders_saturated != previous_status.forwarders_saturated {
+            events.push(self.event(StatusChange::Saturation, None));
+        }
+        if status.forwarders_silent != previous_status.forwarders_silent {
+            events.push(self.event(StatusChange::ForwarderSilence, None));
+        }
+        if status.poc.tx_healthy != previous_status.poc.tx_healthy {
+            events.push(self.event(StatusChange::BeaconTxHealth, None));
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::beaconer::BeaconerStatus;
+
+    fn mk_sample(region: Option<&str>, router: bool, forwarders: &[(&str, bool)]) -> StatusSample {
+        StatusSample {
+            status: RuntimeStatus {
+                region: region.map(str::to_string),
+                region_params_timestamp: None,
+                poc: BeaconerStatus {
+                    disabled: false,
+                    paused: false,
+                    tx_failures: 0,
+                    tx_suppressed: false,
+                    tx_healthy: true,
+                    next_beacon_time: None,
+                    last_beacon: None,
+                    last_witness: None,
+                    witness_alert: None,
+                    witness_queue: Default::default(),
+                },
+                router_connected: router,
+                router_session_age: None,
+                router_session_renewals: 0,
+                router_queue: 0,
+                forwarders_queued: 0,
+                forwarders_saturated: false,
+                forwarders_silent: false,
+                keypair_error: None,
+                gps: None,
+            },
+            forwarders: forwarders
+                .iter()
+                .map(|(mac, connected)| (mac.to_string(), *connected))
+                .collect(),
+        }
+    }
+
+    fn changes(
+        current: &StatusSample,
+        previous: &StatusSample,
+    ) -> Vec<(StatusChange, Option<String>)> {
+        current
+            .changes(previous)
+            .into_iter()
+            .map(|event| (event.change, event.forwarder))
+            .collect()
+    }
+
+    #[test]
+    fn test_status_changes() {
+        let previous = mk_sample(None, false, &[("a", true), ("b", true)]);
+        assert!(changes(&previous, &previous).is_empty());
+
+        let current = mk_sample(
+            Some("US915"),
+            true,
+            &[("a", true), ("b", false), ("c", true)],
+        );
+        assert_eq!(
+            vec![
+                (StatusChange::Region, None),
+                (StatusChange::RouterConnected, None),
+                (StatusChange::ForwarderDisconnected, Some("b".to_string())),
+                (StatusChange::ForwarderConnected, Some("c".to_string())),
+            ],
+            changes(&current, &previous)
+        );
+        assert_eq!(
+            vec![
+                (StatusChange::Region, None),
+                (StatusChange::RouterDisconnected, None),
+                (StatusChange::ForwarderConnected, Some("b".to_string())),
+                (StatusChange::ForwarderDisconnected, Some("c".to_string())),
+            ],
+            changes(&previous, &current)
+        );
+
+        let mut unhealthy = previous.clone();
+        unhealthy.status.poc.tx_healthy = false;
+        assert_eq!(
+            vec![(StatusChange::BeaconTxHealth, None)],
+            changes(&unhealthy, &previous)
+        );
+    }
+}
@@ -0,0 +1,398 @@
+//! Signed audit log of local API requests that change the gateway.
+//!
+//! Maker fleets with compliance requirements need a record of who changed a
+//! gateway and when. With the `audit_log` setting every local API request
+//! that changes the gateway, like adding the gateway, purging the queue,
+//! sending pings, reloading settings and pausing subsystems, is appended as a
+//! JSON line to a log file in the data directory, whether it succeeded or not.
+//!
+//! Every entry carries the hash of the entry before it and is signed with the
+//! gateway key, so removed, reordered or altered entries are detected when
+//! the log is verified:
+//!
+//! ```text
+//! hash = sha256(json of the entry without hash and signature)
+//! signature = sign(hash) with the gateway key
+//! ```
+//!
+//! The log is rotated once it reaches 1 MiB: the file is renamed to
+//! `audit.log.1`, replacing the previously rotated file, and the chain
+//! continues in a new file. A last entry that was only partly written when
+//! power was lost is truncated when the log is opened again.
+
+use crate::{Base64, Error, Keypair, PublicKey, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use helium_crypto::Verify;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use time::OffsetDateTime;
+use tracing::warn;
+
+/// Name of the audit log file in the data directory
+const AUDIT_LOG_FILE: &str = "audit.log";
+/// Name of the rotated audit log file in the data directory
+const ROTATED_AUDIT_LOG_FILE: &str = "audit.log.1";
+/// Size in bytes at which the audit log is rotated
+const MAX_LOG_SIZE: u64 = 1024 * 1024;
+/// Size in bytes of the blocks the log is read in from its end
+const READ_BLOCK_SIZE: u64 = 8 * 1024;
+
+/// An entry of the audit log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    #[serde(flatten)]
+    pub record: AuditRecord,
+    /// Base64 encoded hash of the record
+    pub hash: String,
+    /// Base64 encoded signature of the hash by the gateway key
+    pub signature: String,
+}
+
+/// The hashed part of an audit log entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Sequence number of the entry, starting at 0
+    pub seq: u64,
+    /// Unix time in seconds of the request
+    pub timestamp: i64,
+    /// The local API method
+    pub method: String,
+    /// Who made the request: the key that signed it, "token" for a request
+    /// with a bearer token, or none for a request without credentials
+    #[serde(default)]
+    pub caller: Option<String>,
+    /// Parameters of the request
+    #[serde(default)]
+    pub params: serde_json::Value,
+    /// Why the request failed, if it did
+    #[serde(default)]
+    pub error: Option<String>,
+    /// Hash of the previous entry, empty for the first entry
+    pub prev_hash: String,
+}
+
+impl AuditRecord {
+    fn hash(&self) -> Result<Vec<u8>> {
+        Ok(Sha256::digest(&serde_json::to_vec(self)?).to_vec())
+    }
+}
+
+pub fn audit_log_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(AUDIT_LOG_FILE)
+}
+
+fn rotated_log_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(ROTATED_AUDIT_LOG_FILE)
+}
+
+/// Reads the given number of most recent entries of the audit log in the
+/// given data directory, all entries for 0, including the rotated log. A
+/// missing log has no entries.
+pub fn read(data_dir: &Path, limit: usize) -> Result<Vec<AuditEntry>> {
+    let limit = if limit == 0 { usize::MAX } else { limit };
+    let mut lines = read_tail(&audit_log_path(data_dir), limit)?;
+    if lines.len() < limit {
+        let mut rotated = read_tail(&rotated_log_path(data_dir), limit - lines.len())?;
+        rotated.append(&mut lines);
+        lines = rotated;
+    }
+    lines
+        .iter()
+        .map(|line| Ok(serde_json::from_slice(line)?))
+        .collect()
+}
+
+/// Reads up to the given number of last complete lines of the given file,
+/// oldest first, reading blocks from the end of the file. A missing file has
+/// no lines.
+fn read_tail(path: &Path, limit: usize) -> io::Result<Vec<Vec<u8>>> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err),
+    };
+    let mut pos = file.metadata()?.len();
+    let mut tail = vec![];
+    let mut newlines = 0;
+    // One more newline than lines are needed to know the first of them is
+    // complete
+    while pos > 0 && newlines <= limit {
+        newlines += read_block_before(&mut file, &mut pos, &mut tail)?;
+    }
+    let mut lines: Vec<&[u8]> = tail.split(|byte| *byte == b'\n').collect();
+    // The part after the last newline is an incomplete line, and the part
+    // before the first newline may be one if the file was not read to its
+    // start
+    lines.pop();
+    if pos > 0 && !lines.is_empty() {
+        lines.remove(0);
+    }
+    let lines: Vec<Vec<u8>> = lines
+        .into_iter()
+        .filter(|line| !line.is_empty())
+        .map(<[u8]>::to_vec)
+        .collect();
+    let skip = lines.len().saturating_sub(limit);
+    Ok(lines.into_iter().skip(skip).collect())
+}
+
+/// Reads the block of the given file before the given position to the front
+/// of the given tail and moves the position to its start. Returns the number
+/// of newlines in the block.
+fn read_block_before(file: &mut fs::File, pos: &mut u64, tail: &mut Vec<u8>) -> io::Result<usize> {
+    let start = pos.saturating_sub(READ_BLOCK_SIZE);
+    let mut block = vec![0; (*pos - start) as usize];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(&mut block)?;
+    let newlines = block.iter().filter(|byte| **byte == b'\n').count();
+    block.append(tail);
+    *tail = block;
+    *pos = start;
+    Ok(newlines)
+}
+
+/// Truncates a last line of the given log that was not completely written,
+/// or that is not a valid entry, as after a power loss during an append
+fn truncate_bad_tail(path: &Path) -> Result {
+    let mut file = match fs::OpenOptions::new().read(true).write(true).open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    let len = file.metadata()?.len();
+    let mut pos = len;
+    let mut tail = vec![];
+    // Read back to the newline before the last line, which is the second
+    // newline from the end if the last line is complete
+    let mut newlines = 0;
+    while pos > 0 && newlines < 2 {
+        newlines += read_block_before(&mut file, &mut pos, &mut tail)?;
+    }
+    let line_start = tail[..tail.len().saturating_sub(1)]
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |newline| newline + 1);
+    let line = &tail[line_start..];
+    let complete = line.last() == Some(&b'\n')
+        && serde_json::from_slice::<AuditEntry>(&line[..line.len() - 1]).is_ok();
+    if line.is_empty() || complete {
+        return Ok(());
+    }
+    let valid_len = pos + line_start as u64;
+    warn!(
+        path = %path.display(),
+        truncated = len - valid_len,
+        "truncating incomplete last audit log entry"
+    );
+    file.set_len(valid_len)?;
+    file.sync_data()?;
+    Ok(())
+}
+
+/// Verifies the hashes, signatures and chaining of consecutive audit log
+/// entries. The first entry is only checked to chain to the previous one if
+/// it is the first entry of the log.
+pub fn verify(entries: &[AuditEntry], key: &PublicKey) -> Result {
+    let mut prev: Option<&AuditEntry> = None;
+    for entry in entries {
+        let seq = entry.record.seq;
+        let invalid = |what: &str| Error::custom(format!("audit log entry {seq}: {what}"));
+        let chained = match prev {
+            Some(prev) => prev.record.seq + 1 == seq && prev.hash == entry.record.prev_hash,
+            None => seq > 0 || entry.record.prev_hash.is_empty(),
+        };
+        if !chained {
+            return Err(invalid("broken chain"));
+        }
+        let hash = entry.record.hash()?;
+        if hash.to_b64() != entry.hash {
+            return Err(invalid("invalid hash"));
+        }
+        let signature = STANDARD
+            .decode(&entry.signature)
+            .map_err(|_| invalid("invalid signature encoding"))?;
+        key.verify(&hash, &signature)
+            .map_err(|_| invalid("invalid signature"))?;
+        prev = Some(entry);
+    }
+    Ok(())
+}
+
+/// Appends signed entries to the audit log
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    rotated_path: PathBuf,
+    /// Size of the log file
+    size: u64,
+    /// Size at which the log is rotated before the next append
+    max_size: u64,
+    keypair: Arc<Keypair>,
+    /// Sequence number of the next entry
+    seq: u64,
+    /// Hash of the last entry
+    last_hash: String,
+}
+
+impl AuditLog {
+    /// Opens the audit log in the given data directory, continuing the chain
+    /// of an existing log. An incomplete last entry is truncated.
+    pub fn open(data_dir: &Path, keypair: Arc<Keypair>) -> Result<Self> {
+        let path = audit_log_path(data_dir);
+        truncate_bad_tail(&path)?;
+        let (seq, last_hash) = match read(data_dir, 1)?.pop() {
+            Some(last) => (last.record.seq + 1, last.hash),
+            None => (0, String::new()),
+        };
+        let size = match fs::metadata(&path) {
+            Ok(metadata) => metadata.len(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self {
+            path,
+            rotated_path: rotated_log_path(data_dir),
+            size,
+            max_size: MAX_LOG_SIZE,
+            keypair,
+            seq,
+            last_hash,
+        })
+    }
+
+    /// Appends a signed entry for a request to the given method
+    pub async fn append(
+        &mut self,
+        method: &str,
+        caller: Option<String>,
+        params: serde_json::Value,
+        error: Option<String>,
+    ) -> Result {
+        let record = AuditRecord {
+            seq: self.seq,
+            timestamp: OffsetDateTime::now_utc().unix_timestamp(),
+            method: method.to_string(),
+            caller,
+            params,
+            error,
+            prev_hash: self.last_hash.clone(),
+        };
+        let hash = record.hash()?;
+        let signature = crate::sign(self.keypair.clone(), hash.clone()).await?;
+        let entry = AuditEntry {
+            record,
+            hash: hash.to_b64(),
+            signature: signature.to_b64(),
+        };
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        if self.size >= self.max_size {
+            fs::rename(&self.path, &self.rotated_path)?;
+            self.size = 0;
+        }
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(&line)?;
+        file.sync_data()?;
+        self.size += line.len() as u64;
+        self.seq += 1;
+        self.last_hash = entry.hash;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn audit_log() {
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        let data_dir = temp_dir.path();
+        let keypair = Arc::new(Keypair::new());
+        let key = keypair.public_key().clone();
+
+        let mut log = AuditLog::open(data_dir, keypair.clone()).expect("audit log");
+        log.append("purge_queue", None, json!({}), None)
+            .await
+            .expect("append");
+        // The chain continues after reopening the log
+        let mut log = AuditLog::open(data_dir, keypair).expect("audit log");
+        log.append(
+            "set_subsystem",
+            Some("token".to_string()),
+            json!({"subsystem": "poc", "paused": true}),
+            Some("denied".to_string()),
+        )
+        .await
+        .expect("append");
+
+        let entries = read(data_dir, 0).expect("entries");
+        assert_eq!(2, entries.len());
+        assert!(verify(&entries, &key).is_ok());
+        // A tail of the log verifies on its own
+        assert!(verify(&read(data_dir, 1).expect("entries"), &key).is_ok());
+
+        let mut tampered = entries.clone();
+        tampered[1].record.params = json!({"subsystem": "mqtt", "paused": true});
+        assert!(verify(&tampered, &key).is_err());
+        let mut reordered = entries;
+        reordered.swap(0, 1);
+        assert!(verify(&reordered, &key).is_err());
+    }
+
+    #[tokio::test]
+    async fn rotate_and_repair() {
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        let data_dir = temp_dir.path();
+        let keypair = Arc::new(Keypair::new());
+        let key = keypair.public_key().clone();
+
+        let mut log = AuditLog::open(data_dir, keypair.clone()).expect("audit log");
+        log.max_size = 1;
+        for _ in 0..3 {
+            log.append("reload", None, json!({}), None)
+                .await
+                .expect("append");
+        }
+        // Only the current and the rotated log are kept
+        let entries = read(data_dir, 0).expect("entries");
+        assert_eq!(
+            vec![1, 2],
+            entries
+                .iter()
+                .map(|entry| entry.record.seq)
+                .collect::<Vec<_>>()
+        );
+        assert!(verify(&entries, &key).is_ok());
+
+        // A partly written entry is truncated and the chain continues
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .open(audit_log_path(data_dir))
+            .expect("log file");
+        file.write_all(b"{\"seq\":3,").expect("partial entry");
+        let mut log = AuditLog::open(data_dir, keypair).expect("repaired audit log");
+        log.append("reload", None, json!({}), None)
+            .await
+            .expect("append");
+        let entries = read(data_dir, 2).expect("entries");
+        assert_eq!(
+            vec![2, 3],
+            entries
+                .iter()
+                .map(|entry| entry.record.seq)
+                .collect::<Vec<_>>()
+        );
+        assert!(verify(&entries, &key).is_ok());
+    }
+}
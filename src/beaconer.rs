@@ -1,24 +1,113 @@
 //! This module provides proof-of-coverage (PoC) beaconing support.
+#[cfg(feature = "webhooks")]
+use crate::http_client;
 use crate::{
+    clock::{self, SharedClock},
     gateway::{self, BeaconResp},
+    gps::GpsFix,
+    hooks::StateHook,
+    local_entropy::LocalEntropy,
     message_cache::MessageCache,
+    poc_history::{BeaconOutcome, BeaconRecord, PocDay, PocHistory, WitnessAlert},
     region_watcher,
-    service::{entropy::EntropyService, poc::PocIotService, Reconnect},
-    settings::Settings,
-    sync, Base64, DecodeError, PacketUp, PublicKey, RegionParams, Result,
+    service::{
+        backhaul::Backhaul, entropy::EntropySources, poc::PocIotService, Reconnect,
+        RECONNECT_BACKOFF_MAX_WAIT, RECONNECT_BACKOFF_MIN_WAIT, RECONNECT_BACKOFF_RETRIES,
+    },
+    settings::{BeaconDatarate, HookState, Settings},
+    sync,
+    witness_queue::{WitnessQueue, WitnessQueueStatus},
+    Base64, DecodeError, PacketUp, PublicKey, RegionParams, Result,
 };
 use futures::TryFutureExt;
-use helium_proto::services::poc_lora::{self, lora_stream_response_v1};
+use helium_proto::{
+    services::poc_lora::{self, lora_stream_response_v1},
+    DataRate, RegionSpreading,
+};
 use http::Uri;
-use std::sync::Arc;
-use std::time::Instant;
+use serde::Serialize;
+use std::{collections::VecDeque, sync::Arc};
 use time::{Duration, OffsetDateTime};
-use tracing::{info, warn};
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+/// Period over which the witness report limit applies
+const WITNESS_LIMIT_PERIOD: std::time::Duration = std::time::Duration::from_secs(3600);
+/// Period at which the daily witnesses are checked for a witness alert
+const WITNESS_ALERT_CHECK: std::time::Duration = std::time::Duration::from_secs(3600);
+/// Time to wait for the witness alert webhook
+#[cfg(feature = "webhooks")]
+const WITNESS_ALERT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+/// Time before a beacon at which entropy is prefetched to keep the cached
+/// entropy recent
+const ENTROPY_PREFETCH_LEAD: std::time::Duration = std::time::Duration::from_secs(60);
 
 /// Message types that can be sent to `Beaconer`'s inbox.
 #[derive(Debug)]
 pub enum Message {
-    ReceivedBeacon(PacketUp),
+    ReceivedBeacon(Box<ReceivedBeacon>),
+    Status(sync::ResponseSender<BeaconerStatus>),
+    History(sync::ResponseSender<Vec<PocDay>>),
+    Beacons(sync::ResponseSender<Vec<BeaconRecord>>),
+    SetInterval(u64),
+    SetPaused(bool),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BeaconerStatus {
+    pub disabled: bool,
+    /// Whether beaconing and witness reports are paused through the local api
+    pub paused: bool,
+    /// Number of consecutive failed beacon transmissions
+    pub tx_failures: u32,
+    /// Whether beaconing is currently suppressed due to transmit failures
+    pub tx_suppressed: bool,
+    /// Whether beacon transmissions are healthy. False from the suppression
+    /// of beacons after repeated transmit failures until the next successful
+    /// transmission, also after the cooldown ended.
+    pub tx_healthy: bool,
+    /// Unix time in seconds of the next scheduled beacon, if scheduled
+    pub next_beacon_time: Option<i64>,
+    /// Result of the last beacon attempt
+    pub last_beacon: Option<PocSubmission>,
+    /// Result of the last witness report submission
+    pub last_witness: Option<PocSubmission>,
+    /// The raised witness alert, if the daily witnesses dropped sharply
+    pub witness_alert: Option<WitnessAlert>,
+    /// Counters of the witness report retry queue
+    pub witness_queue: WitnessQueueStatus,
+}
+
+/// The result of a beacon or witness report submission
+#[derive(Debug, Clone, Serialize)]
+pub struct PocSubmission {
+    /// Unix time in seconds of the submission
+    pub timestamp: i64,
+    /// The id of the beacon. Not known for failed beacon attempts
+    pub beacon_id: Option<String>,
+    /// The error of a failed submission
+    pub error: Option<String>,
+}
+
+impl PocSubmission {
+    fn new<T>(clock: &SharedClock, beacon_id: Option<String>, result: &Result<T>) -> Self {
+        Self {
+            timestamp: clock.now_utc().unix_timestamp(),
+            beacon_id,
+            error: result.as_ref().err().map(|err| err.to_string()),
+        }
+    }
+}
+
+/// A potential beacon received by the gateway, with the beacon data already
+/// parsed from the packet payload
+#[derive(Debug)]
+pub struct ReceivedBeacon {
+    pub packet: PacketUp,
+    pub data: Vec<u8>,
+    /// The GPS fix of the gateway when the beacon was received, if any. The
+    /// witness report has no location, so this is only logged.
+    pub position: Option<GpsFix>,
 }
 
 pub type MessageSender = sync::MessageSender<Message>;
@@ -29,14 +118,39 @@ pub fn message_channel() -> (MessageSender, MessageReceiver) {
 }
 
 impl MessageSender {
-    pub async fn received_beacon(&self, packet: PacketUp) {
-        self.send(Message::ReceivedBeacon(packet)).await
+    pub async fn received_beacon(&self, beacon: ReceivedBeacon) {
+        self.send(Message::ReceivedBeacon(Box::new(beacon))).await
+    }
+
+    pub async fn status(&self) -> Result<BeaconerStatus> {
+        self.request(Message::Status).await
+    }
+
+    pub async fn history(&self) -> Result<Vec<PocDay>> {
+        self.request(Message::History).await
+    }
+
+    /// Returns the most recent beacons, oldest first
+    pub async fn beacons(&self) -> Result<Vec<BeaconRecord>> {
+        self.request(Message::Beacons).await
+    }
+
+    /// Changes the beacon interval to the given number of seconds
+    pub async fn set_interval(&self, interval: u64) {
+        self.send(Message::SetInterval(interval)).await
+    }
+
+    /// Pauses or resumes beaconing and witness reports
+    pub async fn set_paused(&self, paused: bool) {
+        self.send(Message::SetPaused(paused)).await
     }
 }
 
 pub struct Beaconer {
     /// Beacon/Witness handling disabled
     disabled: bool,
+    /// Beacon/Witness handling paused at runtime
+    paused: bool,
     /// gateway packet transmit message queue
     transmit: gateway::MessageSender,
     /// Our receive queue.
@@ -47,15 +161,53 @@ pub struct Beaconer {
     reconnect: Reconnect,
     /// Region change queue
     region_watch: region_watcher::MessageReceiver,
-    /// Beacon interval
-    interval: Duration,
-    // Time next beacon attempt is to be made
-    next_beacon_time: Option<OffsetDateTime>,
+    /// Beacon schedule
+    schedule: BeaconSchedule,
+    /// Time source for beacon scheduling and transmit suppression
+    clock: SharedClock,
     /// Last seen beacons
     last_seen: MessageCache<Vec<u8>>,
+    /// Time a seen beacon suppresses duplicate witnesses, if limited
+    last_seen_ttl: Option<std::time::Duration>,
+    /// Maximum witness reports submitted per hour, or 0 for no limit
+    witness_limit: u32,
+    /// Times of the witness reports submitted in the last hour
+    witness_times: VecDeque<Instant>,
     /// Use for channel plan and FR parameters
     region_params: Arc<RegionParams>,
-    entropy_uri: Uri,
+    /// Remote entropy services for beacons
+    entropy: EntropySources,
+    /// Beacon timer instant the entropy was last prefetched for
+    entropy_prefetched: Option<Instant>,
+    /// Datarate policy for beacons
+    datarate: BeaconDatarate,
+    /// Beacon transmit failures and the resulting suppression of beacons
+    tx_health: TxHealth,
+    /// Result of the last beacon attempt
+    last_beacon: Option<PocSubmission>,
+    /// Result of the last witness report submission
+    last_witness: Option<PocSubmission>,
+    /// Daily beacon and witness rollups
+    history: PocHistory,
+    /// Local entropy generator for beacons
+    local_entropy: LocalEntropy,
+    /// Hooks run when poc becomes healthy or unhealthy
+    health_hook: StateHook,
+    /// Alerts on sharp drops of the daily witnesses
+    witness_alerts: WitnessAlerts,
+    /// Failed witness reports waiting to be retried
+    witness_queue: WitnessQueue,
+}
+
+/// Witness alert settings and the raised alert
+#[derive(Debug)]
+struct WitnessAlerts {
+    drop: u8,
+    baseline_days: u16,
+    min_baseline: u32,
+    webhook: Option<Uri>,
+    alert: Option<WitnessAlert>,
+    next_check: Instant,
 }
 
 impl Beaconer {
@@ -64,15 +216,42 @@ impl Beaconer {
         messages: MessageReceiver,
         region_watch: region_watcher::MessageReceiver,
         transmit: gateway::MessageSender,
+    ) -> Self {
+        Self::with_clock(settings, messages, region_watch, transmit, clock::system())
+    }
+
+    /// Creates a beaconer that uses the given clock for beacon scheduling,
+    /// transmit suppression and reconnect backoff
+    pub fn with_clock(
+        settings: &Settings,
+        messages: MessageReceiver,
+        region_watch: region_watcher::MessageReceiver,
+        transmit: gateway::MessageSender,
+        clock: SharedClock,
     ) -> Self {
         let interval = Duration::seconds(settings.poc.interval as i64);
-        let entropy_uri = settings.poc.entropy_uri.clone();
-        let service = PocIotService::new(
+        let backhaul = Backhaul::from(&settings.network);
+        let entropy = EntropySources::new(
+            std::iter::once(settings.poc.entropy_uri.clone())
+                .chain(settings.poc.entropy_fallback_uris.iter().cloned())
+                .collect(),
+            std::time::Duration::from_secs(settings.poc.entropy_max_age),
+            clock.clone(),
+            &backhaul,
+        );
+        let mut service = PocIotService::new(
             "beaconer",
             settings.poc.ingest_uri.clone(),
             settings.keypair.clone(),
+            backhaul,
+        );
+        service.log_sessions(&settings.data_dir);
+        let reconnect = Reconnect::with_clock(
+            RECONNECT_BACKOFF_RETRIES,
+            RECONNECT_BACKOFF_MIN_WAIT,
+            RECONNECT_BACKOFF_MAX_WAIT,
+            clock.clone(),
         );
-        let reconnect = Reconnect::default();
         let region_params = Arc::new(region_watcher::current_value(&region_watch));
         let disabled = settings.poc.disable;
 
@@ -80,45 +259,75 @@ impl Beaconer {
             transmit,
             messages,
             region_watch,
-            interval,
-            last_seen: MessageCache::new(15),
-            next_beacon_time: None,
+            schedule: BeaconSchedule::new(interval, clock.clone()),
+            last_seen: MessageCache::new(settings.poc.witness_dedup_size),
+            last_seen_ttl: (settings.poc.witness_dedup_ttl > 0)
+                .then(|| std::time::Duration::from_secs(settings.poc.witness_dedup_ttl)),
+            witness_limit: settings.poc.witness_max_per_hour,
+            witness_times: VecDeque::new(),
             region_params,
             service,
-            entropy_uri,
+            entropy,
+            entropy_prefetched: None,
+            datarate: settings.poc.beacon_datarate,
             disabled,
+            paused: false,
             reconnect,
+            tx_health: TxHealth::new(
+                settings.poc.beacon_tx_failures,
+                std::time::Duration::from_secs(settings.poc.beacon_tx_cooldown),
+            ),
+            last_beacon: None,
+            last_witness: None,
+            history: PocHistory::new(settings, clock.clone()),
+            local_entropy: LocalEntropy::new(settings),
+            health_hook: StateHook::new(settings, HookState::Poc),
+            witness_alerts: WitnessAlerts {
+                drop: settings.poc.witness_alert_drop,
+                baseline_days: settings.poc.witness_alert_days,
+                min_baseline: settings.poc.witness_alert_min,
+                webhook: settings.poc.witness_alert_webhook.clone(),
+                alert: None,
+                next_check: clock.now(),
+            },
+            witness_queue: WitnessQueue::new(settings, clock.clone()),
+            clock,
         }
     }
 
     pub async fn run(&mut self, shutdown: &triggered::Listener) -> Result {
         info!(
-            beacon_interval = self.interval.whole_seconds(),
+            beacon_interval = self.schedule.interval.whole_seconds(),
             disabled = self.disabled,
+            paused = self.paused,
             uri = %self.service.uri,
             "starting"
         );
 
-        let mut next_beacon_instant = Instant::now() + self.interval;
-
         loop {
             tokio::select! {
                 _ = shutdown.clone() => {
                     info!("shutting down");
+                    self.history.save();
                     return Ok(())
                 },
-                _ = tokio::time::sleep_until(next_beacon_instant.into()) => {
+                _ = self.schedule.sleep() => {
                     // Check if beaconing is enabled and we have valid region params
-                    if !self.disabled && self.region_params.check_valid().is_ok() {
+                    if self.is_active() && self.region_params.check_valid().is_ok() {
                         self.handle_beacon_tick().await;
                     }
-                    // sleep up to another interval period. A subsequent region
-                    // param update will adjust this back to a random offset in
-                    // the next valid window
-                    next_beacon_instant = Instant::now() + self.interval;
+                    self.schedule.ticked();
                 },
                 message = self.messages.recv() => match message {
-                    Some(Message::ReceivedBeacon(packet)) => self.handle_received_beacon(packet).await,
+                    Some(Message::ReceivedBeacon(beacon)) => self.handle_received_beacon(*beacon).await,
+                    Some(Message::Status(tx_resp)) => tx_resp.send(self.status()),
+                    Some(Message::History(tx_resp)) => tx_resp.send(self.history.days()),
+                    Some(Message::Beacons(tx_resp)) => tx_resp.send(self.history.beacons()),
+                    Some(Message::SetInterval(interval)) => {
+                        self.schedule.set_interval(Duration::seconds(interval as i64));
+                        info!(beacon_interval = interval, "beacon interval changed");
+                    }
+                    Some(Message::SetPaused(paused)) => self.set_paused(paused),
                     None => {
                         warn!("ignoring closed message channel");
                     }
@@ -140,19 +349,7 @@ impl Beaconer {
                             continue;
                         };
 
-                        // Calculate next beacon time
-                        let new_beacon_time = mk_next_beacon_time(
-                            new_timestamp,
-                            self.next_beacon_time,
-                            self.interval,
-                        );
-
-                        // Log next beacon time if changed
-                        if Some(new_beacon_time) != self.next_beacon_time {
-                            info!(beacon_time = %new_beacon_time, "next beacon time");
-                        }
-                        self.next_beacon_time = Some(new_beacon_time);
-                        next_beacon_instant = Instant::now() + (new_beacon_time - new_timestamp);
+                        self.schedule.region_updated(new_timestamp);
 
                         // Reduce noise, log param change if they actually
                         // changed
@@ -170,6 +367,7 @@ impl Beaconer {
                             // (Re)set retry count to max to maximize time to
                             // next disconnect from service
                             self.reconnect.retry_count = self.reconnect.max_retries;
+                            self.witness_queue.retry_now();
                         } else {
                             // Failed to handle session offer, disconnect
                             self.service.disconnect();
@@ -185,8 +383,28 @@ impl Beaconer {
                     let reconnect_result = self.handle_reconnect().await;
                     self.reconnect.update_next_time(reconnect_result.is_err());
                 },
+                _ = tokio::time::sleep_until(self.witness_alerts.next_check) => {
+                    self.witness_alerts.next_check = self.clock.now() + WITNESS_ALERT_CHECK;
+                    self.check_witness_alert();
+                },
+                _ = tokio::time::sleep_until(self.schedule.prefetch_instant()), if self.entropy_prefetch_due() => {
+                    self.entropy_prefetched = Some(self.schedule.next_instant);
+                    if let Err(err) = self.entropy.get_entropy().await {
+                        warn!(%err, "failed to prefetch entropy");
+                    }
+                },
+                _ = tokio::time::sleep_until(self.witness_queue.next_retry()), if self.witness_queue.is_waiting() => {
+                    self.retry_witnesses().await;
+                },
+                _ = tokio::time::sleep_until(self.history.save_at()), if self.history.is_dirty() => {
+                    self.history.save();
+                },
 
             }
+            if self.is_active() {
+                self.health_hook
+                    .set(self.service.is_connected() && self.tx_health.is_ok());
+            }
         }
     }
 
@@ -201,14 +419,27 @@ impl Beaconer {
 
         info!(beacon_id, "transmitting beacon");
 
-        let (powe, tmst) = self
+        let tx_result = self
             .transmit
             .transmit_beacon(beacon.clone())
             .inspect_err(|err| warn!(%err, "transmit beacon"))
             .map_ok(|BeaconResp { powe, tmst }| (powe, tmst))
-            .await?;
+            .await;
+        self.tx_health.record(tx_result.is_ok(), self.clock.now());
+        let (powe, tmst) = match tx_result {
+            Ok(tx) => tx,
+            Err(err) => {
+                self.history.record_beacon(
+                    beacon_id,
+                    None,
+                    BeaconOutcome::TxFailed,
+                    Some(err.to_string()),
+                );
+                return Err(err);
+            }
+        };
 
-        Self::mk_beacon_report(
+        let submit_result = Self::mk_beacon_report(
             beacon.clone(),
             powe,
             tmst,
@@ -217,7 +448,14 @@ impl Beaconer {
         .and_then(|report| self.service.submit_beacon(report))
         .inspect_err(|err| warn!(beacon_id, %err, "submit poc beacon report"))
         .inspect_ok(|_| info!(beacon_id, "poc beacon report submitted"))
-        .await?;
+        .await;
+        let (outcome, error) = match &submit_result {
+            Ok(_) => (BeaconOutcome::Submitted, None),
+            Err(err) => (BeaconOutcome::SubmitFailed, Some(err.to_string())),
+        };
+        self.history
+            .record_beacon(beacon_id, Some(powe), outcome, error);
+        submit_result?;
 
         Ok(beacon)
     }
@@ -239,59 +477,252 @@ impl Beaconer {
             .await
     }
 
+    /// Pauses or resumes beaconing and witness reports
+    pub fn set_paused(&mut self, paused: bool) {
+        if paused != self.paused {
+            info!(paused, "poc pause changed");
+        }
+        self.paused = paused;
+    }
+
+    fn is_active(&self) -> bool {
+        !self.disabled && !self.paused
+    }
+
+    /// Whether entropy is to be prefetched for the next beacon
+    fn entropy_prefetch_due(&self) -> bool {
+        self.is_active()
+            && self.entropy.is_cached()
+            && self.entropy_prefetched != Some(self.schedule.next_instant)
+    }
+
+    pub fn status(&self) -> BeaconerStatus {
+        BeaconerStatus {
+            disabled: self.disabled,
+            paused: self.paused,
+            tx_failures: self.tx_health.failures,
+            tx_suppressed: self.tx_health.is_suppressed(self.clock.now()),
+            tx_healthy: self.tx_health.healthy,
+            next_beacon_time: self
+                .schedule
+                .next_beacon_time
+                .map(OffsetDateTime::unix_timestamp),
+            last_beacon: self.last_beacon.clone(),
+            last_witness: self.last_witness.clone(),
+            witness_alert: self.witness_alerts.alert.clone(),
+            witness_queue: self.witness_queue.status(),
+        }
+    }
+
+    /// Raises or clears the witness alert, posting the change to the webhook
+    /// if configured
+    fn check_witness_alert(&mut self) {
+        let alerts = &mut self.witness_alerts;
+        let alert =
+            self.history
+                .witness_alert(alerts.baseline_days, alerts.min_baseline, alerts.drop);
+        if alert == alerts.alert {
+            return;
+        }
+        match &alert {
+            Some(alert) => warn!(
+                date = alert.date,
+                witnesses = alert.witnesses,
+                baseline = alert.baseline,
+                "witnesses dropped sharply below baseline, check antenna and radio"
+            ),
+            None => info!("witnesses recovered to baseline"),
+        }
+        if let Some(webhook) = &alerts.webhook {
+            let body = serde_json::json!({
+                "gateway": self.service.gateway_key().to_string(),
+                "witness_alert": alert,
+            });
+            post_witness_alert(webhook.clone(), body.to_string());
+        }
+        alerts.alert = alert;
+    }
+
     async fn handle_beacon_tick(&mut self) {
+        if self.tx_health.check_suppressed(self.clock.now()) {
+            warn!(
+                tx_failures = self.tx_health.failures,
+                "beacon suppressed after repeated transmit failures"
+            );
+            return;
+        }
         // Need to clone to allow the subsequence borrow of self for send_beacon.
         // The Arc around the region_params makes this a cheap clone
         let region_params = self.region_params.clone();
-        let last_beacon = Self::mk_beacon(&region_params, self.entropy_uri.clone())
-            .inspect_err(|err| warn!(%err, "construct beacon"))
-            .and_then(|beacon| self.send_beacon(beacon))
-            .map_ok_or_else(|_| None, Some)
-            .await;
+        let local_entropy = match self.local_entropy.generate() {
+            Ok(local_entropy) => local_entropy,
+            Err(err) => {
+                warn!(%err, "construct beacon");
+                return;
+            }
+        };
+        let result = self
+            .entropy
+            .get_entropy()
+            .await
+            .and_then(|remote_entropy| {
+                Self::mk_beacon(&region_params, remote_entropy, local_entropy, self.datarate)
+            })
+            .inspect_err(|err| warn!(%err, "construct beacon"));
+        let result = match result {
+            Ok(beacon) => self.send_beacon(beacon).await,
+            Err(err) => Err(err),
+        };
+        let beacon_id = result.as_ref().ok().map(|beacon| beacon.data.to_b64());
+        self.last_beacon = Some(PocSubmission::new(&self.clock, beacon_id, &result));
 
-        if let Some(data) = last_beacon.beacon_data() {
-            self.last_seen.tag_now(data);
+        if let Some(data) = result.ok().beacon_data() {
+            self.tag_seen(data);
         }
     }
 
-    async fn handle_received_beacon(&mut self, packet: PacketUp) {
-        // Check if poc reporting is disabled
-        if self.disabled {
+    async fn handle_received_beacon(&mut self, beacon: ReceivedBeacon) {
+        // Check if poc reporting is disabled or paused
+        if !self.is_active() {
             return;
         }
 
-        // Check that there is beacon data present
-        let Some(beacon_data) = packet.beacon_data() else {
-            warn!("ignoring invalid received beacon");
-            return;
-        };
-
+        let ReceivedBeacon {
+            packet,
+            data: beacon_data,
+            position,
+        } = beacon;
         let beacon_id = beacon_data.to_b64();
 
         // Check if we've seen this beacon before
-        if self.last_seen.tag_now(beacon_data.clone()) {
+        if self.tag_seen(beacon_data.clone()) {
             info!(%beacon_id, "ignoring duplicate or self beacon witness");
             return;
         }
 
-        let _ = Self::mk_witness_report(packet, beacon_data, self.service.gateway_key().clone())
-            .and_then(|report| self.service.submit_witness(report))
+        if !self.witness_allowed() {
+            info!(%beacon_id, limit = self.witness_limit, "ignoring witness over hourly limit");
+            return;
+        }
+
+        if !packet.antenna_signals().is_empty() {
+            debug!(beacon_id, antenna_signals = ?packet.antenna_signals(), "witness signal");
+        }
+        if let Some(position) = position {
+            info!(
+                beacon_id,
+                lat = position.lat,
+                lon = position.lon,
+                quality = ?position.quality,
+                gps_time = packet.gps_time().is_some(),
+                "witness position"
+            );
+        }
+
+        let (rssi, snr) = (packet.rssi, packet.snr);
+        let report =
+            match Self::mk_witness_report(packet, beacon_data, self.service.gateway_key().clone())
+                .await
+            {
+                Ok(report) => report,
+                Err(err) => {
+                    warn!(beacon_id, %err, "construct poc witness report");
+                    return;
+                }
+            };
+        // Queue behind waiting reports to submit in order and not to flood
+        // a struggling ingest
+        if self.witness_queue.is_waiting() {
+            info!(beacon_id, "poc witness report queued");
+            self.witness_queue.push_back(report);
+            return;
+        }
+        let submitted = self
+            .service
+            .submit_witness(report.clone())
             .inspect_err(|err| warn!(beacon_id, %err, "submit poc witness report"))
             .inspect_ok(|_| info!(beacon_id, "poc witness report submitted"))
             .await;
+        self.last_witness = Some(PocSubmission::new(
+            &self.clock,
+            Some(beacon_id.clone()),
+            &submitted,
+        ));
+        match submitted {
+            Ok(()) => self.history.record_witness(&beacon_id, rssi, snr),
+            Err(_) => self.witness_queue.failed(report),
+        }
+    }
+
+    /// Submits a batch of queued witness reports, stopping at the first
+    /// failure
+    async fn retry_witnesses(&mut self) {
+        for _ in 0..self.witness_queue.batch() {
+            let Some(report) = self.witness_queue.pop_front() else {
+                break;
+            };
+            let beacon_id = report.data.to_b64();
+            let submitted = self
+                .service
+                .submit_witness((*report).clone())
+                .inspect_err(|err| warn!(beacon_id, %err, "retry poc witness report"))
+                .inspect_ok(|_| info!(beacon_id, "queued poc witness report submitted"))
+                .await;
+            if submitted.is_err() {
+                self.witness_queue.retry_failed(report);
+                return;
+            }
+            self.witness_queue.retried();
+            self.history
+                .record_witness(&beacon_id, report.signal / 10, report.snr as f32 / 10.0);
+        }
+    }
+
+    /// Tags beacon data as seen. Returns whether it was seen before, within
+    /// the dedup ttl if one is set.
+    fn tag_seen(&mut self, data: Vec<u8>) -> bool {
+        let now = self.clock.now().into_std();
+        match self.last_seen_ttl {
+            Some(ttl) => self.last_seen.tag_within(data, now, ttl),
+            None => self.last_seen.tag(data, now),
+        }
     }
 
-    pub async fn mk_beacon(
+    /// Whether another witness report may be submitted under the hourly
+    /// limit, counting it if so
+    fn witness_allowed(&mut self) -> bool {
+        if self.witness_limit == 0 {
+            return true;
+        }
+        let now = self.clock.now();
+        while self
+            .witness_times
+            .front()
+            .is_some_and(|time| now.duration_since(*time) >= WITNESS_LIMIT_PERIOD)
+        {
+            self.witness_times.pop_front();
+        }
+        if self.witness_times.len() >= self.witness_limit as usize {
+            return false;
+        }
+        self.witness_times.push_back(now);
+        true
+    }
+
+    pub fn mk_beacon(
         region_params: &RegionParams,
-        entropy_uri: Uri,
+        remote_entropy: beacon::Entropy,
+        local_entropy: beacon::Entropy,
+        datarate: BeaconDatarate,
     ) -> Result<beacon::Beacon> {
         region_params.check_valid()?;
 
-        let mut entropy_service = EntropyService::new(entropy_uri);
-        let remote_entropy = entropy_service.get_entropy().await?;
-        let local_entropy = beacon::Entropy::local()?;
-
-        let beacon = beacon::Beacon::new(remote_entropy, local_entropy, region_params)?;
+        let mut beacon = beacon::Beacon::new(remote_entropy, local_entropy, region_params)?;
+        if datarate == BeaconDatarate::Fastest {
+            if let Some(fastest) = fastest_datarate(region_params, beacon.data.len()) {
+                beacon.datarate = fastest;
+            }
+        }
         Ok(beacon)
     }
 
@@ -320,6 +751,198 @@ impl Beaconer {
     }
 }
 
+/// Tracks consecutive beacon transmit failures. Too many failures in a row
+/// usually indicate a radio or antenna fault, in which case beacons are
+/// suppressed for a cooldown period to avoid fetching entropy for beacons that
+/// can't be transmitted. Once the cooldown ends the failures are counted
+/// anew, while transmissions stay unhealthy until one succeeds.
+#[derive(Debug)]
+struct TxHealth {
+    /// Consecutive failed beacon transmissions
+    failures: u32,
+    /// Consecutive failed transmissions before suppressing beacons, 0 to
+    /// never suppress beacons
+    failure_limit: u32,
+    /// Time to suppress beacons for after repeated transmit failures
+    cooldown: std::time::Duration,
+    /// Time until which beacons are suppressed
+    suppressed_until: Option<Instant>,
+    /// Whether no suppression happened since the last successful transmission
+    healthy: bool,
+}
+
+impl TxHealth {
+    fn new(failure_limit: u32, cooldown: std::time::Duration) -> Self {
+        Self {
+            failures: 0,
+            failure_limit,
+            cooldown,
+            suppressed_until: None,
+            healthy: true,
+        }
+    }
+
+    /// Whether transmissions are healthy and the last one did not fail
+    fn is_ok(&self) -> bool {
+        self.healthy && self.failures == 0
+    }
+
+    fn is_suppressed(&self, now: Instant) -> bool {
+        self.suppressed_until.is_some_and(|until| now < until)
+    }
+
+    /// Returns whether beacons are suppressed at the given time. A cooldown
+    /// that ended resets the failure count, so beacons are suppressed again
+    /// only after another run of failures.
+    fn check_suppressed(&mut self, now: Instant) -> bool {
+        if self.is_suppressed(now) {
+            return true;
+        }
+        if self.suppressed_until.take().is_some() {
+            info!(
+                tx_failures = self.failures,
+                "beacon cooldown ended, resuming beacons"
+            );
+            self.failures = 0;
+        }
+        false
+    }
+
+    /// Records the outcome of a beacon transmission at the given time
+    fn record(&mut self, transmitted: bool, now: Instant) {
+        if transmitted {
+            if !self.is_ok() {
+                info!(tx_failures = self.failures, "beacon transmit recovered");
+            }
+            self.failures = 0;
+            self.suppressed_until = None;
+            self.healthy = true;
+            return;
+        }
+        self.failures += 1;
+        if self.failure_limit > 0 && self.failures >= self.failure_limit {
+            warn!(
+                tx_failures = self.failures,
+                cooldown = self.cooldown.as_secs(),
+                "suppressing beacons after repeated transmit failures"
+            );
+            self.suppressed_until = Some(now + self.cooldown);
+            self.healthy = false;
+        }
+    }
+}
+
+/// The beacon schedule. Beacon times are picked from the timestamps of region
+/// parameter updates and the time until a beacon time is converted to a timer
+/// instant with the clock, so the schedule does not depend on the accuracy of
+/// the local wall clock.
+#[derive(Debug)]
+struct BeaconSchedule {
+    clock: SharedClock,
+    /// Beacon interval
+    interval: Duration,
+    /// Time next beacon attempt is to be made
+    next_beacon_time: Option<OffsetDateTime>,
+    /// Instant the beacon timer fires at
+    next_instant: Instant,
+}
+
+impl BeaconSchedule {
+    fn new(interval: Duration, clock: SharedClock) -> Self {
+        Self {
+            next_instant: clock.now() + interval.unsigned_abs(),
+            next_beacon_time: None,
+            interval,
+            clock,
+        }
+    }
+
+    fn sleep(&self) -> tokio::time::Sleep {
+        tokio::time::sleep_until(self.next_instant)
+    }
+
+    /// The instant to prefetch entropy at for the next beacon
+    fn prefetch_instant(&self) -> Instant {
+        self.next_instant
+            .checked_sub(ENTROPY_PREFETCH_LEAD)
+            .unwrap_or(self.next_instant)
+    }
+
+    /// Sleep up to another interval period after the timer fired. A
+    /// subsequent region param update will adjust this back to a random
+    /// offset in the next valid window
+    fn ticked(&mut self) {
+        self.next_instant = self.clock.now() + self.interval.unsigned_abs();
+    }
+
+    /// Changes the beacon interval. A pending beacon timer is shortened to
+    /// fire within the new interval, the next region parameter update picks
+    /// the next beacon time in the new interval.
+    fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+        self.next_instant = self
+            .next_instant
+            .min(self.clock.now() + interval.unsigned_abs());
+    }
+
+    /// Recalculates the next beacon time for region params with the given
+    /// timestamp
+    fn region_updated(&mut self, timestamp: OffsetDateTime) {
+        let beacon_time = mk_next_beacon_time(timestamp, self.next_beacon_time, self.interval);
+        // Log next beacon time if changed
+        if Some(beacon_time) != self.next_beacon_time {
+            info!(%beacon_time, "next beacon time");
+        }
+        self.next_beacon_time = Some(beacon_time);
+        self.next_instant = self.clock.now() + (beacon_time - timestamp).unsigned_abs();
+    }
+}
+
+/// Returns the fastest datarate of the region spreading table that fits a
+/// payload of the given length
+/// Posts a raised or cleared witness alert to the given webhook in the
+/// background
+#[cfg(feature = "webhooks")]
+fn post_witness_alert(webhook: Uri, body: String) {
+    tokio::spawn(async move {
+        let request = match hyper::Request::post(webhook)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(hyper::Body::from(body))
+        {
+            Ok(request) => request,
+            Err(err) => return warn!(%err, "invalid witness alert webhook request"),
+        };
+        match tokio::time::timeout(WITNESS_ALERT_TIMEOUT, http_client::new().request(request)).await
+        {
+            Ok(Ok(response)) if response.status().is_success() => (),
+            Ok(Ok(response)) => warn!(status = %response.status(), "witness alert webhook failed"),
+            Ok(Err(err)) => warn!(%err, "witness alert webhook failed"),
+            Err(_) => warn!("witness alert webhook timed out"),
+        }
+    });
+}
+
+#[cfg(not(feature = "webhooks"))]
+fn post_witness_alert(webhook: Uri, _body: String) {
+    warn!(%webhook, "witness alert webhooks are not supported by this build");
+}
+
+fn fastest_datarate(region_params: &RegionParams, len: usize) -> Option<DataRate> {
+    // The spreading table and bandwidth are the same for all channels
+    let params = region_params.params.first()?;
+    let spreading = params
+        .spreading
+        .as_ref()?
+        .tagged_spreading
+        .iter()
+        .filter(|tagged| tagged.max_packet_size as usize >= len)
+        .map(|tagged| tagged.region_spreading())
+        .filter(|spreading| *spreading != RegionSpreading::SfInvalid)
+        .min_by_key(|spreading| *spreading as i32)?;
+    let name = format!("{}BW{}", spreading.as_str_name(), params.bandwidth / 1000);
+    DataRate::from_str_name(&name)
+}
+
 fn random_duration(duration: Duration) -> Duration {
     use rand::{rngs::OsRng, Rng};
     Duration::seconds(OsRng.gen_range(0..duration.whole_seconds()))
@@ -375,15 +998,6 @@ trait BeaconData {
     fn beacon_data(&self) -> Option<Vec<u8>>;
 }
 
-impl BeaconData for PacketUp {
-    fn beacon_data(&self) -> Option<Vec<u8>> {
-        match PacketUp::parse_frame(lorawan::Direction::Uplink, self.payload()) {
-            Ok(lorawan::PHYPayloadFrame::Proprietary(payload)) => Some(payload.into()),
-            _ => None,
-        }
-    }
-}
-
 impl BeaconData for beacon::Beacon {
     fn beacon_data(&self) -> Option<Vec<u8>> {
         Some(self.data.clone())
@@ -398,6 +1012,78 @@ impl BeaconData for Option<beacon::Beacon> {
 
 #[cfg(test)]
 mod test {
+    #[test]
+    fn tx_health() {
+        use super::TxHealth;
+        use std::time::Duration;
+        use tokio::time::Instant;
+
+        let cooldown = Duration::from_secs(3600);
+        let mut health = TxHealth::new(2, cooldown);
+        let now = Instant::now();
+        health.record(false, now);
+        assert!(!health.is_ok());
+        assert!(health.healthy);
+        assert!(!health.check_suppressed(now));
+
+        health.record(false, now);
+        assert!(!health.healthy);
+        assert!(health.check_suppressed(now + cooldown / 2));
+
+        // The failures are counted anew after the cooldown while
+        // transmissions stay unhealthy
+        assert!(!health.check_suppressed(now + cooldown));
+        assert_eq!(0, health.failures);
+        assert!(!health.healthy);
+        health.record(false, now + cooldown);
+        assert!(!health.check_suppressed(now + cooldown));
+
+        health.record(true, now + cooldown);
+        assert!(health.is_ok());
+    }
+
+    #[test]
+    fn test_fastest_datarate() {
+        use super::fastest_datarate;
+        use crate::{Region, RegionParams};
+        use helium_proto::{
+            BlockchainRegionParamV1, BlockchainRegionSpreadingV1, DataRate, RegionSpreading,
+            TaggedSpreading,
+        };
+
+        let tagged = |spreading: RegionSpreading, max_packet_size| TaggedSpreading {
+            region_spreading: spreading.into(),
+            max_packet_size,
+        };
+        let mut region_params = RegionParams::from(Region::from(helium_proto::Region::Us915));
+        region_params.params = vec![BlockchainRegionParamV1 {
+            channel_frequency: 903_900_000,
+            bandwidth: 125_000,
+            max_eirp: 360,
+            spreading: Some(BlockchainRegionSpreadingV1 {
+                tagged_spreading: vec![
+                    tagged(RegionSpreading::Sf10, 25),
+                    tagged(RegionSpreading::Sf9, 67),
+                    tagged(RegionSpreading::Sf8, 139),
+                    tagged(RegionSpreading::Sf7, 255),
+                ],
+            }),
+        }];
+
+        assert_eq!(
+            Some(DataRate::Sf7bw125),
+            fastest_datarate(&region_params, 52)
+        );
+        assert_eq!(None, fastest_datarate(&region_params, 256));
+        assert_eq!(
+            None,
+            fastest_datarate(
+                &RegionParams::from(Region::from(helium_proto::Region::Us915)),
+                52
+            )
+        );
+    }
+
     #[test]
     fn test_beacon_roundtrip() {
         use lorawan::PHYPayload;
@@ -409,6 +1095,45 @@ mod test {
         assert_eq!(phy_payload_a, phy_payload_b);
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn test_beacon_schedule() {
+        use super::BeaconSchedule;
+        use crate::clock::TestClock;
+        use time::{macros::datetime, Duration};
+
+        let clock = TestClock::shared(datetime!(2023-09-01 09:20 UTC));
+        let interval = Duration::hours(6);
+        let mut schedule = BeaconSchedule::new(interval, clock.clone());
+
+        // Without region params the timer fires after a full interval
+        let start = clock.now();
+        schedule.sleep().await;
+        assert_eq!(interval.unsigned_abs(), clock.now() - start);
+
+        // A region update schedules the timer for the picked beacon time,
+        // relative to the region params timestamp rather than the local
+        // wall clock
+        let timestamp = datetime!(2023-09-01 12:00 UTC);
+        schedule.region_updated(timestamp);
+        let beacon_time = schedule.next_beacon_time.expect("beacon time");
+        let start = clock.now();
+        schedule.sleep().await;
+        assert_eq!(
+            (beacon_time - timestamp).unsigned_abs(),
+            clock.now() - start
+        );
+
+        // Another update in the same segment keeps the beacon time
+        schedule.region_updated(timestamp + Duration::minutes(1));
+        assert_eq!(Some(beacon_time), schedule.next_beacon_time);
+
+        // After a tick the timer fires a full interval later
+        let start = clock.now();
+        schedule.ticked();
+        schedule.sleep().await;
+        assert_eq!(interval.unsigned_abs(), clock.now() - start);
+    }
+
     #[test]
     fn test_beacon_time() {
         use super::{duration_trunc, mk_next_beacon_time};
@@ -0,0 +1,73 @@
+//! Time source for scheduling code.
+//!
+//! Beacon scheduling, beacon transmit suppression and reconnect backoff read
+//! the current time through a [`Clock`] instead of directly. In production
+//! this is the [`SystemClock`]. Unit tests use a deterministic clock on paused
+//! tokio time so schedules can be verified without waiting in real time.
+
+use std::{fmt, sync::Arc};
+use time::OffsetDateTime;
+use tokio::time::Instant;
+
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// The current monotonic time, used for timers
+    fn now(&self) -> Instant;
+    /// The current wall clock time
+    fn now_utc(&self) -> OffsetDateTime;
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+/// The clock of the host system
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn now_utc(&self) -> OffsetDateTime {
+        OffsetDateTime::now_utc()
+    }
+}
+
+/// Returns a shared system clock
+pub fn system() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+/// A deterministic clock for tests. Monotonic time is tokio time, which only
+/// advances through `tokio::time::advance` or auto-advance when paused. Wall
+/// clock time starts at a fixed time and advances with tokio time.
+#[cfg(test)]
+#[derive(Debug, Clone, Copy)]
+pub struct TestClock {
+    start: Instant,
+    start_utc: OffsetDateTime,
+}
+
+#[cfg(test)]
+impl TestClock {
+    pub fn new(start_utc: OffsetDateTime) -> Self {
+        Self {
+            start: Instant::now(),
+            start_utc,
+        }
+    }
+
+    pub fn shared(start_utc: OffsetDateTime) -> SharedClock {
+        Arc::new(Self::new(start_utc))
+    }
+}
+
+#[cfg(test)]
+impl Clock for TestClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn now_utc(&self) -> OffsetDateTime {
+        self.start_utc + (Instant::now() - self.start)
+    }
+}
@@ -1,49 +1,388 @@
-use crate::{api::LocalClient, cmd::*, settings::StakingMode, Base64, PublicKey, Result, Settings};
+#[cfg(feature = "webhooks")]
+use crate::http_client;
+use crate::{
+    api::LocalClient,
+    cmd::*,
+    settings::{OnboardingServerSettings, StakingMode},
+    state_file, Base64, Error, PublicKey, Result, Settings,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use helium_crypto::Verify;
 use helium_proto::{BlockchainTxn, BlockchainTxnAddGatewayV1, Message, Txn};
-use serde_json::json;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::warn;
+
+/// Time to wait for the onboarding server
+#[cfg(feature = "webhooks")]
+const SUBMIT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Wait before the first retry of a failed submission, doubled on every retry
+const SUBMIT_RETRY_WAIT: Duration = Duration::from_secs(2);
+/// Directory in the data directory that keeps signed transactions whose
+/// submission failed until they are resubmitted
+const SPOOL_DIR: &str = "onboarding_spool";
 
 /// Construct an add gateway transaction for this gateway.
 #[derive(Debug, clap::Args)]
 pub struct Cmd {
     /// The solana address of the target owner for this gateway
-    #[arg(long, value_parser = parse_pubkey)]
-    owner: PublicKey,
+    #[arg(long, value_parser = parse_pubkey, required_unless_present = "resubmit")]
+    owner: Option<PublicKey>,
 
     /// The solana address of the payer account that will pay account for this
     /// addition
-    #[arg(long, value_parser = parse_pubkey)]
-    payer: PublicKey,
+    #[arg(long, value_parser = parse_pubkey, required_unless_present = "resubmit")]
+    payer: Option<PublicKey>,
 
     /// The staking mode for adding the gateway
     #[arg(long, default_value = "dataonly")]
     mode: StakingMode,
+
+    /// Print the transaction without signatures, for the owner wallet to
+    /// sign, without contacting the gateway service
+    #[arg(long, conflicts_with = "owner_signature")]
+    offline: bool,
+
+    /// File with the base64 encoded owner signature of the transaction printed
+    /// with --offline, to include in the transaction signed by the gateway
+    #[arg(long)]
+    owner_signature: Option<PathBuf>,
+
+    /// Submit the signed transaction to the configured onboarding server. A
+    /// transaction that fails to submit, for example without connectivity, is
+    /// spooled in the data directory for --resubmit.
+    #[arg(long, conflicts_with = "offline")]
+    submit: bool,
+
+    /// Submit the spooled transactions of earlier failed submissions to the
+    /// configured onboarding server
+    #[arg(long, conflicts_with_all = ["owner", "payer", "offline", "owner_signature", "submit"])]
+    resubmit: bool,
+}
+
+/// An add gateway transaction with its decoded addresses
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddGateway {
+    /// The staking mode of the transaction
+    pub mode: String,
+    /// The address of the gateway being added
+    pub address: String,
+    /// The solana address of the payer
+    pub payer: String,
+    /// The solana address of the owner
+    pub owner: String,
+    /// The base64 encoded blockchain transaction
+    pub txn: String,
+    /// The base64 encoded add gateway transaction the owner signs. Only
+    /// present for a transaction without signatures.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signing_payload: Option<String>,
+    /// The response of the onboarding server the transaction was submitted
+    /// to, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub submitted: Option<Value>,
+}
+
+/// The outcome of resubmitting a spooled transaction
+#[derive(Debug, Serialize)]
+pub struct Resubmission {
+    #[serde(flatten)]
+    pub txn: AddGateway,
+    /// Why the submission failed, if it did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Whether the transaction stays spooled for another resubmission. Only
+    /// transactions the server could not be reached for stay spooled.
+    pub spooled: bool,
 }
 
 impl Cmd {
     pub async fn run(&self, settings: Settings) -> Result {
-        let mut client = LocalClient::new(&settings.api).await?;
+        if self.resubmit {
+            return resubmit(&settings).await;
+        }
+        let (Some(owner), Some(payer)) = (&self.owner, &self.payer) else {
+            return Err(Error::custom("owner and payer are required"));
+        };
+        let txn = if self.offline {
+            let txn = unsigned_txn(settings.keypair.public_key(), owner, payer);
+            AddGateway::new(txn, &self.mode, true)?
+        } else {
+            let owner_signature = self
+                .owner_signature
+                .as_ref()
+                .map(read_signature)
+                .transpose()?;
+            add_gateway(&settings, owner, payer, &self.mode, owner_signature).await?
+        };
+        if self.submit {
+            let submitted = match submit(onboarding_server(&settings)?, &txn).await {
+                Ok(submitted) => submitted,
+                Err(SubmitError::Rejected(err)) => return Err(err),
+                Err(SubmitError::Failed(err)) => {
+                    let path = spool(&settings.data_dir, &txn)?;
+                    return Err(Error::custom(format!(
+                        "{err}, spooled transaction to {} for --resubmit",
+                        path.display()
+                    )));
+                }
+            };
+            return print_json(&AddGateway {
+                submitted: Some(submitted),
+                ..txn
+            });
+        }
+        print_json(&txn)
+    }
+}
+
+fn onboarding_server(settings: &Settings) -> Result<&OnboardingServerSettings> {
+    let onboarding_server = settings
+        .onboarding_server
+        .as_ref()
+        .ok_or_else(|| Error::custom("no onboarding server configured"))?;
+    if cfg!(not(feature = "webhooks")) {
+        return Err(Error::custom(
+            "onboarding submission is not supported by this build",
+        ));
+    }
+    #[cfg(feature = "webhooks")]
+    if onboarding_server.token.is_some() {
+        http_client::check_token_uri(&onboarding_server.uri)?;
+    }
+    Ok(onboarding_server)
+}
+
+/// Submits the spooled transactions, oldest first. Submitted transactions and
+/// transactions the server rejected are removed from the spool. Fails after
+/// printing the outcomes if any transaction could not be submitted.
+async fn resubmit(settings: &Settings) -> Result {
+    let onboarding_server = onboarding_server(settings)?;
+    let mut resubmissions = vec![];
+    for path in spooled(&settings.data_dir)? {
+        let txn: AddGateway = serde_json::from_slice(&fs::read(&path)?)?;
+        let resubmission = match submit(onboarding_server, &txn).await {
+            Ok(submitted) => Resubmission {
+                txn: AddGateway {
+                    submitted: Some(submitted),
+                    ..txn
+                },
+                error: None,
+                spooled: false,
+            },
+            Err(SubmitError::Rejected(err)) => Resubmission {
+                txn,
+                error: Some(err.to_string()),
+                spooled: false,
+            },
+            Err(SubmitError::Failed(err)) => Resubmission {
+                txn,
+                error: Some(err.to_string()),
+                spooled: true,
+            },
+        };
+        if !resubmission.spooled {
+            fs::remove_file(&path)?;
+        }
+        resubmissions.push(resubmission);
+    }
+    print_json(&resubmissions)?;
+    match resubmissions
+        .iter()
+        .filter(|resubmission| resubmission.error.is_some())
+        .count()
+    {
+        0 => Ok(()),
+        failed => Err(Error::custom(format!(
+            "{failed} of {} spooled transactions failed to submit",
+            resubmissions.len()
+        ))),
+    }
+}
+
+fn spool_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join(SPOOL_DIR)
+}
+
+/// Keeps a transaction whose submission failed in the spool. A transaction
+/// for the same owner and payer replaces the spooled one.
+fn spool(data_dir: &Path, txn: &AddGateway) -> Result<PathBuf> {
+    let dir = spool_dir(data_dir);
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}_{}.json", txn.owner, txn.payer));
+    state_file::write_json(&path, txn)?;
+    Ok(path)
+}
+
+/// The paths of the spooled transactions, oldest first
+fn spooled(data_dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(spool_dir(data_dir)) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err.into()),
+    };
+    let mut paths = vec![];
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            paths.push((entry.metadata()?.modified()?, path));
+        }
+    }
+    paths.sort();
+    Ok(paths.into_iter().map(|(_, path)| path).collect())
+}
+
+impl AddGateway {
+    fn new(txn: BlockchainTxnAddGatewayV1, mode: &StakingMode, unsigned: bool) -> Result<Self> {
+        Ok(Self {
+            mode: mode.to_string(),
+            address: PublicKey::from_bytes(&txn.gateway)?.to_string(),
+            payer: PublicKey::from_bytes(&txn.payer).and_then(solana_pubkey)?,
+            owner: PublicKey::from_bytes(&txn.owner).and_then(solana_pubkey)?,
+            signing_payload: unsigned.then(|| txn.encode_to_vec().to_b64()),
+            submitted: None,
+            txn: BlockchainTxn {
+                txn: Some(Txn::AddGateway(txn)),
+            }
+            .encode_to_vec()
+            .to_b64(),
+        })
+    }
+}
+
+/// Constructs an add gateway transaction for the gateway running with the
+/// given settings, signed by the gateway. A given owner signature is verified
+/// and included in the transaction.
+pub async fn add_gateway(
+    settings: &Settings,
+    owner: &PublicKey,
+    payer: &PublicKey,
+    mode: &StakingMode,
+    owner_signature: Option<Vec<u8>>,
+) -> Result<AddGateway> {
+    let mut client = LocalClient::new(&settings.api).await?.with_auth(settings);
+    let mut txn = client.add_gateway(owner, payer, mode).await?;
+    if let Some(owner_signature) = owner_signature {
+        let payload = unsigned_txn(&PublicKey::from_bytes(&txn.gateway)?, owner, payer);
+        owner
+            .verify(&payload.encode_to_vec(), &owner_signature)
+            .map_err(|_| Error::custom("owner signature does not match the transaction"))?;
+        txn.owner_signature = owner_signature;
+    }
+    AddGateway::new(txn, mode, false)
+}
 
-        let txn = client
-            .add_gateway(&self.owner, &self.payer, &self.mode)
-            .await?;
-        print_txn(&self.mode, txn)
+/// The add gateway transaction without signatures, which is what the gateway
+/// and the owner sign
+fn unsigned_txn(
+    gateway: &PublicKey,
+    owner: &PublicKey,
+    payer: &PublicKey,
+) -> BlockchainTxnAddGatewayV1 {
+    BlockchainTxnAddGatewayV1 {
+        gateway: gateway.to_vec(),
+        owner: owner.to_vec(),
+        payer: payer.to_vec(),
+        ..Default::default()
     }
 }
 
-fn print_txn(mode: &StakingMode, txn: BlockchainTxnAddGatewayV1) -> Result {
-    let table = json!({
-        "mode": mode.to_string(),
-        "address": PublicKey::from_bytes(&txn.gateway)?.to_string(),
-        "payer": PublicKey::from_bytes(&txn.payer).and_then(solana_pubkey)?,
-        "owner": PublicKey::from_bytes(&txn.owner).and_then(solana_pubkey)?,
-        "txn": BlockchainTxn {
-            txn: Some(Txn::AddGateway(txn))
-        }.encode_to_vec().to_b64()
-    });
-    print_json(&table)
+/// Posts a signed add gateway transaction to the onboarding server, retrying
+/// failures the server did not reject the transaction for. Returns the
+/// response of the server, as JSON if it is JSON.
+async fn submit(
+    settings: &OnboardingServerSettings,
+    txn: &AddGateway,
+) -> std::result::Result<Value, SubmitError> {
+    let body = serde_json::to_string(txn).map_err(|err| SubmitError::Rejected(Error::from(err)))?;
+    let mut retry_wait = SUBMIT_RETRY_WAIT;
+    let mut attempt = 0;
+    loop {
+        match post_txn(settings, body.clone()).await {
+            Ok(response) => return Ok(response),
+            Err(err @ SubmitError::Rejected(_)) => return Err(err),
+            Err(err @ SubmitError::Failed(_)) if attempt >= settings.retries => return Err(err),
+            Err(SubmitError::Failed(err)) => {
+                warn!(%err, ?retry_wait, attempt, "onboarding submission failed, retrying");
+                tokio::time::sleep(retry_wait).await;
+                retry_wait *= 2;
+                attempt += 1;
+            }
+        }
+    }
 }
 
-fn parse_pubkey(str: &str) -> Result<PublicKey> {
+/// Why a submission failed
+enum SubmitError {
+    /// The server rejected the transaction, retrying does not help
+    Rejected(Error),
+    /// The server could not be reached or failed to handle the transaction
+    Failed(Error),
+}
+
+#[cfg(feature = "webhooks")]
+async fn post_txn(
+    settings: &OnboardingServerSettings,
+    body: String,
+) -> std::result::Result<Value, SubmitError> {
+    let mut request = hyper::Request::post(settings.uri.clone())
+        .header(http::header::CONTENT_TYPE, "application/json");
+    if let Some(token) = &settings.token {
+        request = request.header(
+            http::header::AUTHORIZATION,
+            format!("Bearer {}", token.expose()),
+        );
+    }
+    let request = request.body(hyper::Body::from(body)).map_err(|err| {
+        SubmitError::Rejected(Error::custom(format!("onboarding request: {err}")))
+    })?;
+    let response = tokio::time::timeout(SUBMIT_TIMEOUT, http_client::new().request(request))
+        .await
+        .map_err(|_| SubmitError::Failed(Error::custom("onboarding server timed out")))?
+        .map_err(|err| SubmitError::Failed(Error::custom(format!("onboarding server: {err}"))))?;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|err| SubmitError::Failed(Error::custom(format!("onboarding response: {err}"))))?;
+    let body = serde_json::from_slice(&body)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&body).into_owned()));
+    if status.is_success() {
+        return Ok(body);
+    }
+    let err = Error::custom(format!("onboarding server returned {status}: {body}"));
+    if status.is_client_error() && status != http::StatusCode::TOO_MANY_REQUESTS {
+        Err(SubmitError::Rejected(err))
+    } else {
+        Err(SubmitError::Failed(err))
+    }
+}
+
+#[cfg(not(feature = "webhooks"))]
+async fn post_txn(
+    _settings: &OnboardingServerSettings,
+    _body: String,
+) -> std::result::Result<Value, SubmitError> {
+    Err(SubmitError::Rejected(Error::custom(
+        "onboarding submission is not supported by this build",
+    )))
+}
+
+/// Reads a base64 encoded signature from a file
+fn read_signature(path: &PathBuf) -> Result<Vec<u8>> {
+    let encoded = fs::read_to_string(path)?;
+    STANDARD
+        .decode(encoded.trim())
+        .map_err(|err| Error::custom(format!("invalid owner signature {}: {err}", path.display())))
+}
+
+/// Parses a helium or solana address into a public key
+pub fn parse_pubkey(str: &str) -> Result<PublicKey> {
     use helium_crypto::{ed25519, ReadFrom};
     use std::{io::Cursor, str::FromStr};
 
@@ -61,3 +400,34 @@ fn solana_pubkey(key: PublicKey) -> std::result::Result<String, helium_crypto::E
     let bytes = &key.to_vec()[1..];
     Ok(bs58::encode(bytes).into_string())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn spool_roundtrip() {
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        let data_dir = temp_dir.path();
+        assert!(spooled(data_dir).expect("empty spool").is_empty());
+
+        let txn = AddGateway {
+            mode: "dataonly".to_string(),
+            address: "gateway".to_string(),
+            payer: "payer".to_string(),
+            owner: "owner".to_string(),
+            txn: "dHhu".to_string(),
+            signing_payload: None,
+            submitted: None,
+        };
+        let path = spool(data_dir, &txn).expect("spool");
+        // Spooling the transaction for the same owner and payer again
+        // replaces it
+        assert_eq!(path, spool(data_dir, &txn).expect("spool again"));
+        assert_eq!(vec![path.clone()], spooled(data_dir).expect("spooled"));
+        let read: AddGateway =
+            serde_json::from_slice(&fs::read(&path).expect("read")).expect("spooled txn");
+        assert_eq!(txn.txn, read.txn);
+        assert_eq!(txn.owner, read.owner);
+    }
+}
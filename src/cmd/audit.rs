@@ -0,0 +1,27 @@
+use crate::{api::LocalClient, audit_log, cmd::*, Result, Settings};
+
+/// Print the audit log of the local API requests that changed the running
+/// service, oldest first.
+///
+/// The log is only kept when the `audit_log` setting is enabled.
+#[derive(Debug, clap::Args)]
+pub struct Cmd {
+    /// Number of most recent entries to print, 0 for all
+    #[arg(long, default_value = "0")]
+    limit: u32,
+    /// Verify the hash chain and the gateway key signatures of the entries
+    #[arg(long)]
+    verify: bool,
+}
+
+impl Cmd {
+    pub async fn run(&self, settings: Settings) -> Result {
+        let mut client = LocalClient::new(&settings.api).await?;
+        let entries = client.audit_log(self.limit).await?;
+        if self.verify {
+            let (gateway, _) = client.pubkey().await?;
+            audit_log::verify(&entries, &gateway)?;
+        }
+        print_json(&entries)
+    }
+}
@@ -0,0 +1,76 @@
+use crate::{
+    api::LocalClient,
+    cmd::print_json,
+    forwarder_config::{self, Concentrator, ForwarderServer, DEFAULT_GATEWAY_ID},
+    Error, Result, Settings,
+};
+use futures::StreamExt;
+use std::{net::SocketAddr, path::PathBuf};
+
+/// Default port of the semtech UDP packet forwarder protocol
+const DEFAULT_PORT: u16 = 1680;
+
+/// Render a Semtech packet forwarder global_conf.json with the channel plan
+/// of the current region of the running service
+#[derive(Debug, clap::Args)]
+pub struct Cmd {
+    /// The concentrator of the gateway
+    #[arg(long, value_enum, default_value = "sx1302")]
+    concentrator: Concentrator,
+
+    /// The gateway ID of the packet forwarder
+    #[arg(long, default_value = DEFAULT_GATEWAY_ID)]
+    gateway_id: String,
+
+    /// File to write the configuration to instead of printing it
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+impl Cmd {
+    pub async fn run(&self, settings: Settings) -> Result {
+        let mut client = LocalClient::new(&settings.api).await?;
+        // The region stream starts with the current region parameters
+        let event = client
+            .region_stream()
+            .await?
+            .next()
+            .await
+            .ok_or_else(|| Error::custom("no region parameters from service"))??;
+        let region = event
+            .power
+            .region
+            .ok_or_else(|| Error::custom("region not yet known"))?;
+        let (address, port) = forwarder_server(&settings);
+        let server = ForwarderServer {
+            gateway_id: self.gateway_id.clone(),
+            address,
+            port,
+        };
+        let config =
+            forwarder_config::render(&region, &event.channels, self.concentrator, &server)?;
+        match &self.output {
+            Some(path) => std::fs::write(path, serde_json::to_vec_pretty(&config)?)?,
+            None => print_json(&config)?,
+        }
+        Ok(())
+    }
+}
+
+/// The address and port the packet forwarder sends to, the first listen
+/// address of the gateway
+fn forwarder_server(settings: &Settings) -> (String, u16) {
+    let Some(listen) = settings
+        .listen
+        .first()
+        .and_then(|listen| listen.parse::<SocketAddr>().ok())
+    else {
+        return ("localhost".to_string(), DEFAULT_PORT);
+    };
+    let address = if listen.ip().is_unspecified() {
+        "localhost".to_string()
+    } else {
+        listen.ip().to_string()
+    };
+    (address, listen.port())
+}
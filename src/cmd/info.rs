@@ -1,13 +1,26 @@
 use crate::{
-    api::LocalClient,
+    api::{LocalClient, RuntimeStatus},
+    beaconer::BeaconerStatus,
     cmd::*,
+    crc_stats::CrcStatus,
+    dc_stats::DcStatus,
+    downlink_stats::{DownlinkPeriod, DownlinkStatus},
+    duty_cycle::DutyCycleStatus,
+    forwarders::{ForwarderStat, ForwardersStatus},
+    packet_router::RouterStatus,
+    poc_history::{BeaconRecord, PocDay},
+    region_watcher::RegionPowerStatus,
+    service::{backhaul::Backhaul, config::ConfigService, session_log::SessionRecord},
     settings::{self, Settings},
-    Result,
+    uplink_filter::FilterStatus,
+    uptime::UptimeStatus,
+    Error, PublicKey, Region, Result,
 };
 use angry_purple_tiger::AnimalName;
+use rust_decimal::Decimal;
 
-use serde_json::json;
-use std::collections::HashMap;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 
 #[derive(Debug, Clone, clap::ValueEnum, PartialOrd, Ord, Copy, PartialEq, Eq)]
@@ -18,6 +31,19 @@ pub enum InfoKey {
     Name,
     Region,
     Router,
+    Uptime,
+    Poc,
+    Downlinks,
+    Dc,
+    Crc,
+    Filters,
+    DutyCycle,
+    Antenna,
+    Forwarders,
+    ForwarderStats,
+    Network,
+    Status,
+    Sessions,
 }
 
 /// Info command. Retrieve all or a subset of information from the running
@@ -27,19 +53,271 @@ pub struct Cmd {
     /// Information keys to fetch
     #[arg(value_enum, required = true)]
     pub keys: Vec<InfoKey>,
+
+    /// Include the daily beacon and witness history with the poc key
+    #[arg(long)]
+    pub history: bool,
+
+    /// Include the most recent beacons with the poc key
+    #[arg(long)]
+    pub beacons: bool,
+
+    /// Include the maximum EIRP, antenna gain and resulting conducted transmit
+    /// power of the region parameters with the region key
+    #[arg(long)]
+    pub region_params: bool,
+
+    /// Fetch the keys again every given number of seconds over the same
+    /// connection, printing one JSON object per line, until interrupted
+    #[arg(long)]
+    pub interval: Option<u64>,
 }
 
 impl Cmd {
-    pub async fn run(&self, settings: Settings) -> Result {
+    pub async fn run(&self, shutdown: &triggered::Listener, settings: Settings) -> Result {
         let mut client = LocalClient::new(&settings.api).await?;
-        let mut info: HashMap<String, serde_json::Value> = HashMap::new();
-        for key in &self.keys {
-            info.insert(key.to_string(), key.to_status(&mut client).await?);
+        let Some(interval) = self.interval else {
+            let info = self.fetch(&settings, &mut client).await?;
+            return print_json(&info);
+        };
+        let mut timer = tokio::time::interval(std::time::Duration::from_secs(interval.max(1)));
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => return Ok(()),
+                _ = timer.tick() => {
+                    let info = self.fetch(&settings, &mut client).await?;
+                    print_json_line(&info)?;
+                }
+            }
+        }
+    }
+
+    async fn fetch(
+        &self,
+        settings: &Settings,
+        client: &mut LocalClient,
+    ) -> Result<HashMap<String, InfoValue>> {
+        Ok(fetch(
+            settings,
+            client,
+            &self.keys,
+            self.history,
+            self.beacons,
+            self.region_params,
+        )
+        .await?
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect())
+    }
+}
+
+/// Poc status with the optional daily beacon and witness history and the
+/// most recent beacons
+#[derive(Debug, Clone, Serialize)]
+pub struct PocInfo {
+    #[serde(flatten)]
+    pub status: BeaconerStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history: Option<Vec<PocDay>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub beacons: Option<Vec<BeaconRecord>>,
+}
+
+/// Downlink counts with the integrity ratio of each period
+#[derive(Debug, Clone, Serialize)]
+pub struct DownlinksInfo {
+    pub current: DownlinkPeriod,
+    pub current_integrity: Option<f32>,
+    pub last_hour: Option<DownlinkPeriod>,
+    pub last_hour_integrity: Option<f32>,
+}
+
+impl From<DownlinkStatus> for DownlinksInfo {
+    fn from(value: DownlinkStatus) -> Self {
+        Self {
+            current: value.current,
+            current_integrity: value.current.integrity(),
+            last_hour: value.last_hour,
+            last_hour_integrity: value.last_hour.and_then(|period| period.integrity()),
+        }
+    }
+}
+
+/// The antenna setup of the gateway as configured in the settings
+#[derive(Debug, Clone, Serialize)]
+pub struct AntennaInfo {
+    /// Antenna gain in dBi, used instead of the gain of the region parameters
+    pub gain: Option<Decimal>,
+    /// Elevation in meters above ground level
+    pub elevation: Option<i32>,
+}
+
+impl From<&Settings> for AntennaInfo {
+    fn from(value: &Settings) -> Self {
+        Self {
+            gain: value.antenna_gain.map(|gain| Decimal::new(gain as i64, 1)),
+            elevation: value.elevation,
+        }
+    }
+}
+
+/// What the network knows about the gateway, with the discrepancies that keep
+/// the gateway from receiving traffic or participating in poc
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkInfo {
+    /// Whether the gateway key is known to the network
+    pub known: bool,
+    /// The staking mode of the gateway, "full" or "dataonly"
+    pub staking_mode: Option<String>,
+    /// The asserted location (h3 index) of the gateway
+    pub location: Option<String>,
+    /// The region of the asserted location
+    pub region: Option<String>,
+    /// Discrepancies between the network and the gateway
+    pub issues: Vec<String>,
+}
+
+impl NetworkInfo {
+    /// Fetches the network view of the gateway from the config service and
+    /// compares it with the region the running gateway operates in, if known
+    async fn fetch(settings: &Settings, current_region: Option<Region>) -> Result<Self> {
+        let mut last_err = None;
+        let mut gateway_info = None;
+        let backhaul = Backhaul::from(&settings.network);
+        for keyed_uri in settings.config.iter() {
+            let mut service = ConfigService::new(keyed_uri, &backhaul);
+            match service.gateway_info(settings.keypair.clone()).await {
+                Ok(info) => {
+                    gateway_info = Some(info);
+                    break;
+                }
+                Err(err) => last_err = Some(err),
+            }
         }
-        print_json(&info)
+        let Some(gateway_info) = gateway_info else {
+            return Err(last_err.unwrap_or_else(|| Error::custom("no config service")));
+        };
+
+        let mut issues = vec![];
+        let Some(gateway_info) = gateway_info else {
+            issues.push(
+                "gateway key is not known to the network, add the gateway to receive traffic"
+                    .to_string(),
+            );
+            return Ok(Self {
+                known: false,
+                staking_mode: None,
+                location: None,
+                region: None,
+                issues,
+            });
+        };
+        if !gateway_info.is_full_hotspot {
+            issues.push("data only gateway, does not participate in poc".to_string());
+        }
+        let metadata = gateway_info.metadata.unwrap_or_default();
+        let location = (!metadata.location.is_empty()).then_some(metadata.location);
+        let region = location
+            .as_ref()
+            .and_then(|_| Region::from_i32(metadata.region).ok());
+        match (region, current_region) {
+            (None, _) => issues.push(
+                "gateway location is not asserted, assert a location to receive traffic in the right region"
+                    .to_string(),
+            ),
+            (Some(_), None) => issues.push(
+                "gateway has not received region parameters for its asserted location".to_string(),
+            ),
+            (Some(asserted), Some(current)) if asserted != current => issues.push(format!(
+                "asserted region {asserted} differs from the region {current} the gateway operates in"
+            )),
+            _ => (),
+        }
+        Ok(Self {
+            known: true,
+            staking_mode: Some(
+                if gateway_info.is_full_hotspot {
+                    "full"
+                } else {
+                    "dataonly"
+                }
+                .to_string(),
+            ),
+            location,
+            region: region.map(|region| region.to_string()),
+            issues,
+        })
     }
 }
 
+/// The value of an information key
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum InfoValue {
+    Fw(String),
+    Key(PublicKey),
+    Onboarding(PublicKey),
+    Name(String),
+    /// The region of the gateway, or None if not yet known
+    Region(Option<String>),
+    /// The region of the gateway with the transmit power limits of its region
+    /// parameters
+    RegionParams(RegionPowerStatus),
+    Router(RouterStatus),
+    Uptime(UptimeStatus),
+    Poc(PocInfo),
+    Downlinks(DownlinksInfo),
+    Dc(DcStatus),
+    Crc(CrcStatus),
+    /// Uplinks dropped by the uplink and fport filters since startup
+    Filters(FilterStatus),
+    DutyCycle(DutyCycleStatus),
+    Antenna(AntennaInfo),
+    Forwarders(ForwardersStatus),
+    /// The latest stat message of each packet forwarder
+    ForwarderStats(Vec<ForwarderStat>),
+    Network(NetworkInfo),
+    Status(RuntimeStatus),
+    /// The last established router and ingest sessions
+    Sessions(Vec<SessionRecord>),
+}
+
+/// Fetches the given information keys from the service running with the given
+/// settings. The history flag includes the daily beacon and witness history
+/// and the beacons flag the most recent beacons with the poc key. The region
+/// params flag includes the transmit power limits with the region key.
+pub async fn info(
+    settings: &Settings,
+    keys: &[InfoKey],
+    history: bool,
+    beacons: bool,
+    region_params: bool,
+) -> Result<BTreeMap<InfoKey, InfoValue>> {
+    let mut client = LocalClient::new(&settings.api).await?;
+    fetch(settings, &mut client, keys, history, beacons, region_params).await
+}
+
+/// Fetches the given information keys with an existing client
+async fn fetch(
+    settings: &Settings,
+    client: &mut LocalClient,
+    keys: &[InfoKey],
+    history: bool,
+    beacons: bool,
+    region_params: bool,
+) -> Result<BTreeMap<InfoKey, InfoValue>> {
+    let mut info = BTreeMap::new();
+    for key in keys {
+        info.insert(
+            *key,
+            key.to_status(settings, client, history, beacons, region_params)
+                .await?,
+        );
+    }
+    Ok(info)
+}
+
 impl fmt::Display for InfoKey {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let s = match self {
@@ -49,34 +327,47 @@ impl fmt::Display for InfoKey {
             Self::Name => "name",
             Self::Region => "region",
             Self::Router => "router",
+            Self::Uptime => "uptime",
+            Self::Poc => "poc",
+            Self::Downlinks => "downlinks",
+            Self::Dc => "dc",
+            Self::Crc => "crc",
+            Self::Filters => "filters",
+            Self::DutyCycle => "duty_cycle",
+            Self::Antenna => "antenna",
+            Self::Forwarders => "forwarders",
+            Self::ForwarderStats => "forwarder_stats",
+            Self::Network => "network",
+            Self::Status => "status",
+            Self::Sessions => "sessions",
         };
         f.write_str(s)
     }
 }
 
 impl InfoKey {
-    async fn to_status(self, client: &mut LocalClient) -> Result<serde_json::Value> {
-        let (public_key, _) = client.pubkey().await?;
+    async fn to_status(
+        self,
+        settings: &Settings,
+        client: &mut LocalClient,
+        history: bool,
+        beacons: bool,
+        region_params: bool,
+    ) -> Result<InfoValue> {
+        let (public_key, onboarding_key) = client.pubkey().await?;
         let v = match self {
-            Self::Fw => {
-                let version = settings::version();
-                json!(format!("{version}"))
-            }
-            Self::Key => {
-                json!(public_key)
-            }
-            Self::Onboarding => {
-                let (_, onboarding_key) = client.pubkey().await?;
-                json!(onboarding_key)
-            }
+            Self::Fw => InfoValue::Fw(settings::version().to_string()),
+            Self::Key => InfoValue::Key(public_key),
+            Self::Onboarding => InfoValue::Onboarding(onboarding_key),
             Self::Name => {
                 let name = public_key
                     .to_string()
                     .parse::<AnimalName>()
                     .unwrap()
                     .to_string();
-                json!(name)
+                InfoValue::Name(name)
             }
+            Self::Region if region_params => InfoValue::RegionParams(client.region_params().await?),
             Self::Region => {
                 let region = client.region().await?;
                 let maybe_region = if region.is_unknown() {
@@ -84,12 +375,38 @@ impl InfoKey {
                 } else {
                     Some(region)
                 };
-                json!(maybe_region.map(|region| region.to_string()))
+                InfoValue::Region(maybe_region.map(|region| region.to_string()))
+            }
+            Self::Router => InfoValue::Router(client.router().await?),
+            Self::Uptime => InfoValue::Uptime(client.uptime().await?),
+            Self::Poc => {
+                let (status, days) = client.poc().await?;
+                let beacons = if beacons {
+                    Some(client.beacons().await?)
+                } else {
+                    None
+                };
+                InfoValue::Poc(PocInfo {
+                    status,
+                    history: history.then_some(days),
+                    beacons,
+                })
             }
-            Self::Router => {
-                let router = client.router().await?;
-                json!(router)
+            Self::Downlinks => InfoValue::Downlinks(client.downlinks().await?.into()),
+            Self::Dc => InfoValue::Dc(client.dc().await?),
+            Self::Crc => InfoValue::Crc(client.crc().await?),
+            Self::Filters => InfoValue::Filters(client.filters().await?),
+            Self::DutyCycle => InfoValue::DutyCycle(client.duty_cycle().await?),
+            Self::Antenna => InfoValue::Antenna(AntennaInfo::from(settings)),
+            Self::Forwarders => InfoValue::Forwarders(client.forwarders().await?),
+            Self::ForwarderStats => InfoValue::ForwarderStats(client.forwarder_stats().await?),
+            Self::Network => {
+                let region = client.region().await?;
+                let current_region = (!region.is_unknown()).then_some(region);
+                InfoValue::Network(NetworkInfo::fetch(settings, current_region).await?)
             }
+            Self::Status => InfoValue::Status(client.status().await?),
+            Self::Sessions => InfoValue::Sessions(client.sessions().await?),
         };
         Ok(v)
     }
@@ -1,7 +1,16 @@
+#[cfg(feature = "sealed-secrets")]
+use crate::{cmd::add::parse_pubkey, secret, PublicKey};
 use crate::{
-    cmd::info::{self, InfoKey},
-    Result, Settings,
+    cmd::{
+        info::{self, InfoKey},
+        print_json,
+    },
+    keypair::{self, RotationProof, SelfTest},
+    state_file, Error, Keypair, Result, Settings,
 };
+use helium_crypto::{KeyTag, KeyType, Network};
+use serde_json::json;
+use std::{collections::HashMap, fs, io, str::FromStr, sync::Arc};
 
 /// Commands on gateway keys
 #[derive(Debug, clap::Args)]
@@ -13,31 +22,234 @@ pub struct Cmd {
 #[derive(Debug, clap::Subcommand)]
 pub enum KeyCmd {
     Info(Info),
+    Test(Test),
+    Gen(Gen),
+    Rotate(Rotate),
+    #[cfg(feature = "sealed-secrets")]
+    Seal(Seal),
 }
 
 /// Commands on gateway keys
 #[derive(Debug, clap::Args)]
 pub struct Info {}
 
+/// Sign and verify a test message with the gateway keypair.
+///
+/// This is the self test the service runs at startup. It fails when a secure
+/// element holding the key is not reachable.
+#[derive(Debug, clap::Args)]
+pub struct Test {}
+
+/// Generate a new gateway key.
+///
+/// By default a new key file is generated, which must not exist yet. With
+/// --tpm a new key is generated in the TPM and made persistent at the given
+/// handle, for the initial provisioning of TPM based gateways. The keypair
+/// setting for the new key is printed. Keys are generated without loading the
+/// settings, so the keypair setting can already refer to the new key.
+#[derive(Debug, clap::Args)]
+pub struct Gen {
+    /// The key file to generate
+    #[arg(long, default_value = "gateway_key.bin")]
+    file: String,
+    /// The network of the key, mainnet or testnet
+    #[arg(long, default_value = "mainnet")]
+    network: Network,
+    /// Generate the key in the TPM instead of a key file. This requires the
+    /// tpm2-tools.
+    #[cfg(feature = "tpm")]
+    #[arg(long, conflicts_with = "file")]
+    tpm: bool,
+    /// The persistent TPM handle for the key
+    #[cfg(feature = "tpm")]
+    #[arg(long, default_value = "0x81000001", value_parser = parse_tpm_handle, requires = "tpm")]
+    handle: u32,
+}
+
+/// Rotate the gateway key to a new key.
+///
+/// Without a target a new key of the same type is generated and replaces the
+/// key file of the gateway, and the old key file is overwritten and removed.
+/// With a target, like another secure element slot, the key in the target is
+/// used and the keypair setting has to be changed to the target. Either way a
+/// proof of the rotation signed by the old, new and onboarding keys is printed
+/// and stored in the data directory. The running service uses the new key
+/// after a restart.
+#[derive(Debug, clap::Args)]
+pub struct Rotate {
+    /// The keypair to rotate to, for example "ecc://i2c-1:96?slot=1". A key
+    /// file target is generated if it does not exist.
+    #[arg(long)]
+    to: Option<String>,
+}
+
+/// Seal a secret settings value to a gateway key.
+///
+/// The output can be used in place of the plain text value of a secret
+/// setting and is only readable by the gateway holding the key. Sealing
+/// requires an ecc_compact gateway key.
+#[cfg(feature = "sealed-secrets")]
+#[derive(Debug, clap::Args)]
+pub struct Seal {
+    /// The value to seal
+    value: String,
+    /// The public key of the gateway to seal the value to. Defaults to the key
+    /// of this gateway.
+    #[arg(long, value_parser = parse_pubkey)]
+    key: Option<PublicKey>,
+}
+
 impl Cmd {
     pub async fn run(&self, settings: Settings) -> Result {
         self.command.run(settings).await
     }
+
+    /// The key generation command, which runs before the settings are loaded
+    pub fn gen(&self) -> Option<&Gen> {
+        match &self.command {
+            KeyCmd::Gen(cmd) => Some(cmd),
+            _ => None,
+        }
+    }
 }
 
 impl KeyCmd {
     pub async fn run(&self, settings: Settings) -> Result {
         match self {
             Self::Info(cmd) => cmd.run(settings).await,
+            Self::Test(cmd) => cmd.run(settings).await,
+            Self::Gen(cmd) => cmd.run(),
+            Self::Rotate(cmd) => cmd.run(settings).await,
+            #[cfg(feature = "sealed-secrets")]
+            Self::Seal(cmd) => cmd.run(settings).await,
         }
     }
 }
 
 impl Info {
     pub async fn run(&self, settings: Settings) -> Result {
-        let cmd = info::Cmd {
-            keys: vec![InfoKey::Name, InfoKey::Key, InfoKey::Onboarding],
+        let keys = [InfoKey::Name, InfoKey::Key, InfoKey::Onboarding];
+        let info: HashMap<String, info::InfoValue> =
+            info::info(&settings, &keys, false, false, false)
+                .await?
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect();
+        print_json(&info)
+    }
+}
+
+impl Test {
+    pub async fn run(&self, settings: Settings) -> Result {
+        let test = SelfTest::run(settings.keypair.clone()).await;
+        print_json(&test)?;
+        match test.error {
+            None => Ok(()),
+            Some(error) => Err(Error::custom(format!("keypair self test failed: {error}"))),
+        }
+    }
+}
+
+impl Gen {
+    pub fn run(&self) -> Result {
+        #[cfg(feature = "tpm")]
+        if self.tpm {
+            let (keypair, uri) = Keypair::generate_tpm(self.network, self.handle)?;
+            return print_json(&json!({
+                "keypair": uri,
+                "key": keypair.public_key().to_string(),
+            }));
+        }
+        if std::path::Path::new(&self.file).exists() {
+            return Err(Error::custom(format!(
+                "key file \"{}\" already exists",
+                self.file
+            )));
+        }
+        let keypair = Keypair::generate(KeyTag {
+            network: self.network,
+            key_type: KeyType::Ed25519,
+        });
+        keypair.save_to_file(&self.file)?;
+        print_json(&json!({
+            "keypair": self.file,
+            "key": keypair.public_key().to_string(),
+        }))
+    }
+}
+
+#[cfg(feature = "tpm")]
+fn parse_tpm_handle(s: &str) -> std::result::Result<u32, String> {
+    u32::from_str_radix(s.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("invalid tpm handle \"{s}\""))
+}
+
+/// Name of the file in the data directory the last rotation proof is stored in
+const ROTATION_PROOF_FILE: &str = "key_rotation.json";
+
+impl Rotate {
+    pub async fn run(&self, settings: Settings) -> Result {
+        let old = settings.keypair.clone();
+        let (new, key_file) = match &self.to {
+            Some(to) => (Arc::new(Keypair::from_str(to)?), None),
+            None => {
+                let uri = settings.source.keypair_uri()?;
+                let path = keypair::key_file_path(&uri).ok_or_else(|| {
+                    Error::custom(format!(
+                        "can not generate a new key for keypair \"{uri}\", use --to with a \
+                         target keypair"
+                    ))
+                })?;
+                (Arc::new(Keypair::generate(old.key_tag())), Some(path))
+            }
         };
-        cmd.run(settings).await
+        if new.public_key() == old.public_key() {
+            return Err(Error::custom("the target keypair is the current keypair"));
+        }
+        let onboarding = settings
+            .onboarding
+            .as_deref()
+            .map(Keypair::from_str)
+            .transpose()?
+            .map(Arc::new);
+        let proof = RotationProof::sign(old, new.clone(), onboarding).await?;
+        // The proof is stored before the key file is replaced, so a replaced
+        // key always has its proof. A failed replacement restores the
+        // previous proof.
+        fs::create_dir_all(&settings.data_dir)?;
+        let proof_path = settings.data_dir.join(ROTATION_PROOF_FILE);
+        let previous_proof = match fs::read(&proof_path) {
+            Ok(data) => Some(data),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+        state_file::write_json(&proof_path, &proof)?;
+        if let Some(path) = &key_file {
+            if let Err(err) = keypair::replace_key_file(path, &new) {
+                match previous_proof {
+                    Some(data) => state_file::write(&proof_path, &data)?,
+                    None => fs::remove_file(&proof_path)?,
+                }
+                return Err(err);
+            }
+            keypair::remove_key_backup(path)?;
+        }
+        print_json(&json!({
+            "proof": proof,
+            "replaced_key_file": key_file,
+            "keypair": self.to,
+        }))
+    }
+}
+
+#[cfg(feature = "sealed-secrets")]
+impl Seal {
+    pub async fn run(&self, settings: Settings) -> Result {
+        let key = self
+            .key
+            .clone()
+            .unwrap_or_else(|| settings.keypair.public_key().to_owned());
+        println!("{}", secret::seal(&self.value, &key)?);
+        Ok(())
     }
 }
@@ -0,0 +1,20 @@
+use crate::{api::LocalClient, cmd::print_json_line, Result, Settings};
+
+/// Print the recent log events kept in memory by the running service, oldest
+/// first, as one JSON object per line
+#[derive(Debug, clap::Args)]
+pub struct Cmd {
+    /// Number of most recent log events to print, 0 for all
+    #[arg(long, default_value = "100")]
+    limit: u32,
+}
+
+impl Cmd {
+    pub async fn run(&self, settings: Settings) -> Result {
+        let mut client = LocalClient::new(&settings.api).await?;
+        for record in client.logs(self.limit).await? {
+            print_json_line(&record)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+use crate::{Error, PublicKey, Result, Settings};
+use futures::TryFutureExt;
+use helium_proto::{
+    services::{
+        poc_entropy::{
+            poc_entropy_server::{PocEntropy, PocEntropyServer},
+            EntropyReqV1,
+        },
+        poc_lora::{
+            lora_stream_request_v1, lora_stream_response_v1,
+            poc_lora_server::{PocLora, PocLoraServer},
+            LoraBeaconReportReqV1, LoraBeaconReportRespV1, LoraStreamRequestV1,
+            LoraStreamResponseV1, LoraStreamSessionOfferV1, LoraWitnessReportReqV1,
+            LoraWitnessReportRespV1,
+        },
+    },
+    EntropyReportV1,
+};
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+use tonic::{transport::Server as TransportServer, Request, Response, Status, Streaming};
+use tracing::{info, warn};
+
+/// Run mock entropy and PoC ingest services.
+///
+/// This allows the beacon and witness path to be exercised on a bench without
+/// internet access. Point the `poc.entropy_uri` and `poc.ingest_uri` settings
+/// of the gateway under test at the listen address of this command.
+#[derive(Debug, clap::Args)]
+pub struct Cmd {
+    /// Address to serve the mock services on
+    #[arg(long, default_value = "127.0.0.1:7080")]
+    listen: SocketAddr,
+
+    /// Latency in milliseconds to add to every response
+    #[arg(long, default_value_t = 0)]
+    latency: u64,
+
+    /// Fraction of requests (0.0 - 1.0) to fail with an unavailable error
+    #[arg(long, default_value_t = 0.0)]
+    failure_rate: f64,
+}
+
+impl Cmd {
+    pub async fn run(&self, shutdown: &triggered::Listener, _settings: Settings) -> Result {
+        if !(0.0..=1.0).contains(&self.failure_rate) {
+            return Err(Error::custom("failure rate must be between 0.0 and 1.0"));
+        }
+        let faults = Arc::new(Faults {
+            latency: Duration::from_millis(self.latency),
+            failure_rate: self.failure_rate,
+        });
+        info!(
+            listen = %self.listen,
+            latency = self.latency,
+            failure_rate = self.failure_rate,
+            "starting mock services"
+        );
+        TransportServer::builder()
+            .add_service(PocEntropyServer::new(MockEntropy {
+                faults: faults.clone(),
+            }))
+            .add_service(PocLoraServer::new(MockIngest { faults }))
+            .serve_with_shutdown(self.listen, shutdown.clone())
+            .map_err(Error::from)
+            .await
+    }
+}
+
+/// Injected latency and failures for mock responses
+#[derive(Debug)]
+struct Faults {
+    latency: Duration,
+    failure_rate: f64,
+}
+
+impl Faults {
+    /// Waits for the configured latency and returns an error for the
+    /// configured fraction of requests.
+    async fn apply(&self, rpc: &'static str) -> std::result::Result<(), Status> {
+        if !self.latency.is_zero() {
+            tokio::time::sleep(self.latency).await;
+        }
+        if self.failure_rate > 0.0 && rand::random::<f64>() < self.failure_rate {
+            warn!(rpc, "injecting failure");
+            return Err(Status::unavailable("injected failure"));
+        }
+        Ok(())
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+fn pubkey_str(bytes: &[u8]) -> String {
+    PublicKey::from_bytes(bytes)
+        .map(|key| key.to_string())
+        .unwrap_or_else(|_| "invalid".to_string())
+}
+
+struct MockEntropy {
+    faults: Arc<Faults>,
+}
+
+#[tonic::async_trait]
+impl PocEntropy for MockEntropy {
+    async fn entropy(
+        &self,
+        _request: Request<EntropyReqV1>,
+    ) -> std::result::Result<Response<EntropyReportV1>, Status> {
+        self.faults.apply("entropy").await?;
+        let report = EntropyReportV1 {
+            data: rand::random::<[u8; 32]>().to_vec(),
+            timestamp: now_secs(),
+            version: 0,
+        };
+        info!("served entropy");
+        Ok(Response::new(report))
+    }
+}
+
+struct MockIngest {
+    faults: Arc<Faults>,
+}
+
+fn log_beacon(report: &LoraBeaconReportReqV1) {
+    info!(
+        pub_key = pubkey_str(&report.pub_key),
+        frequency = report.frequency,
+        datarate = report.datarate,
+        tx_power = report.tx_power,
+        tmst = report.tmst,
+        "received beacon report"
+    );
+}
+
+fn log_witness(report: &LoraWitnessReportReqV1) {
+    info!(
+        pub_key = pubkey_str(&report.pub_key),
+        frequency = report.frequency,
+        datarate = report.datarate,
+        signal = report.signal,
+        snr = report.snr,
+        tmst = report.tmst,
+        "received witness report"
+    );
+}
+
+#[tonic::async_trait]
+impl PocLora for MockIngest {
+    async fn submit_lora_beacon(
+        &self,
+        request: Request<LoraBeaconReportReqV1>,
+    ) -> std::result::Result<Response<LoraBeaconReportRespV1>, Status> {
+        self.faults.apply("submit_lora_beacon").await?;
+        log_beacon(request.get_ref());
+        Ok(Response::new(LoraBeaconReportRespV1 {
+            id: now_secs().to_string(),
+        }))
+    }
+
+    async fn submit_lora_witness(
+        &self,
+        request: Request<LoraWitnessReportReqV1>,
+    ) -> std::result::Result<Response<LoraWitnessReportRespV1>, Status> {
+        self.faults.apply("submit_lora_witness").await?;
+        log_witness(request.get_ref());
+        Ok(Response::new(LoraWitnessReportRespV1 {
+            id: now_secs().to_string(),
+        }))
+    }
+
+    type stream_requestsStream = ReceiverStream<std::result::Result<LoraStreamResponseV1, Status>>;
+
+    async fn stream_requests(
+        &self,
+        request: Request<Streaming<LoraStreamRequestV1>>,
+    ) -> std::result::Result<Response<Self::stream_requestsStream>, Status> {
+        self.faults.apply("stream_requests").await?;
+        let mut requests = request.into_inner();
+        let (tx, rx) = mpsc::channel(10);
+        let faults = self.faults.clone();
+        tokio::spawn(async move {
+            let offer = LoraStreamResponseV1 {
+                response: Some(lora_stream_response_v1::Response::Offer(
+                    LoraStreamSessionOfferV1 {
+                        nonce: rand::random::<[u8; 32]>().to_vec(),
+                    },
+                )),
+            };
+            if tx.send(Ok(offer)).await.is_err() {
+                return;
+            }
+            while let Some(request) = requests.next().await {
+                let request = match request {
+                    Ok(LoraStreamRequestV1 {
+                        request: Some(request),
+                    }) => request,
+                    Ok(_) => continue,
+                    Err(err) => {
+                        warn!(%err, "stream request error");
+                        break;
+                    }
+                };
+                // Failures close the stream, like a remote ingest going away
+                if let Err(status) = faults.apply("stream_requests").await {
+                    let _ = tx.send(Err(status)).await;
+                    break;
+                }
+                match request {
+                    lora_stream_request_v1::Request::SessionInit(init) => info!(
+                        pub_key = pubkey_str(&init.pub_key),
+                        session_key = pubkey_str(&init.session_key),
+                        "received session init"
+                    ),
+                    lora_stream_request_v1::Request::BeaconReport(report) => log_beacon(&report),
+                    lora_stream_request_v1::Request::WitnessReport(report) => log_witness(&report),
+                }
+            }
+            info!("stream closed");
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
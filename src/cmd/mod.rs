@@ -1,11 +1,143 @@
 pub mod add;
+pub mod audit;
+pub mod forwarder_config;
 pub mod info;
 pub mod key;
+pub mod logs;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod ping;
+pub mod queue;
+pub mod region;
 pub mod server;
+pub mod settings;
+pub mod subsystem;
+pub mod trace;
+pub mod watch;
 
-use crate::Result;
+use crate::{Error, Result};
+use serde_json::Value;
+use std::sync::OnceLock;
+
+/// How commands print their JSON output
+#[derive(Debug, Clone, Default)]
+pub struct JsonOutput {
+    /// Print compact JSON with sorted object keys
+    pub canonical: bool,
+    /// Print only the value of this field, with nested fields separated by
+    /// dots. String values are printed without quotes.
+    pub field: Option<String>,
+}
+
+static JSON_OUTPUT: OnceLock<JsonOutput> = OnceLock::new();
+
+/// Sets how commands print their JSON output. Only the first call has effect.
+pub fn set_json_output(output: JsonOutput) {
+    let _ = JSON_OUTPUT.set(output);
+}
 
 pub(crate) fn print_json<T: ?Sized + serde::Serialize>(value: &T) -> Result {
-    println!("{}", serde_json::to_string_pretty(value)?);
+    let output = JSON_OUTPUT.get().cloned().unwrap_or_default();
+    println!("{}", format_json(value, &output, !output.canonical)?);
     Ok(())
 }
+
+/// Prints a value as a single line of JSON, for output that is printed
+/// repeatedly
+pub(crate) fn print_json_line<T: ?Sized + serde::Serialize>(value: &T) -> Result {
+    let output = JSON_OUTPUT.get().cloned().unwrap_or_default();
+    println!("{}", format_json(value, &output, false)?);
+    Ok(())
+}
+
+fn format_json<T: ?Sized + serde::Serialize>(
+    value: &T,
+    output: &JsonOutput,
+    pretty: bool,
+) -> Result<String> {
+    let mut value = serde_json::to_value(value)?;
+    if let Some(field) = &output.field {
+        value = select_field(value, field)?;
+        if let Value::String(value) = value {
+            return Ok(value);
+        }
+    }
+    if output.canonical {
+        sort_keys(&mut value);
+    }
+    let formatted = if pretty {
+        serde_json::to_string_pretty(&value)?
+    } else {
+        serde_json::to_string(&value)?
+    };
+    Ok(formatted)
+}
+
+/// The value of a dot separated field path. Array elements are selected by
+/// their index.
+fn select_field(mut value: Value, field: &str) -> Result<Value> {
+    for name in field.split('.') {
+        let selected = match &mut value {
+            Value::Object(map) => map.remove(name),
+            Value::Array(values) => name
+                .parse::<usize>()
+                .ok()
+                .filter(|index| *index < values.len())
+                .map(|index| values.swap_remove(index)),
+            _ => None,
+        };
+        value = selected.ok_or_else(|| Error::custom(format!("no field {field}")))?;
+    }
+    Ok(value)
+}
+
+fn sort_keys(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<(String, Value)> = std::mem::take(map).into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (key, mut value) in entries {
+                sort_keys(&mut value);
+                map.insert(key, value);
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(sort_keys),
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn json_output() {
+        let value = json!({
+            "name": "gateway",
+            "poc": {"disabled": false, "history": [{"witnesses": 3}]},
+            "key": "1abc",
+        });
+        let format = |canonical, field: Option<&str>| {
+            let output = JsonOutput {
+                canonical,
+                field: field.map(str::to_string),
+            };
+            format_json(&value, &output, false).unwrap()
+        };
+        assert_eq!(
+            r#"{"key":"1abc","name":"gateway","poc":{"disabled":false,"history":[{"witnesses":3}]}}"#,
+            format(true, None)
+        );
+        assert_eq!("1abc", format(false, Some("key")));
+        assert_eq!("false", format(false, Some("poc.disabled")));
+        assert_eq!("3", format(false, Some("poc.history.0.witnesses")));
+        assert_eq!(r#"{"witnesses":3}"#, format(true, Some("poc.history.0")));
+
+        let output = JsonOutput {
+            canonical: false,
+            field: Some("poc.history.1".to_string()),
+        };
+        assert!(format_json(&value, &output, false).is_err());
+    }
+}
@@ -0,0 +1,60 @@
+use crate::{api::LocalClient, cmd::*, PublicKey, Result, Settings};
+
+/// Test the RF link to another gateway owned by you.
+///
+/// Both gateways need pings enabled in their settings. A ping sent by one
+/// gateway and received by the other is listed on the receiving gateway with
+/// its signal metadata.
+#[derive(Debug, clap::Args)]
+pub struct Cmd {
+    #[command(subcommand)]
+    command: PingCmd,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum PingCmd {
+    Send(Send),
+    List(List),
+}
+
+/// Transmit a ping addressed to another gateway
+#[derive(Debug, clap::Args)]
+pub struct Send {
+    /// The public key of the gateway to ping
+    target: PublicKey,
+}
+
+/// List the most recent pings received by this gateway
+#[derive(Debug, clap::Args)]
+pub struct List {}
+
+impl Cmd {
+    pub async fn run(&self, settings: Settings) -> Result {
+        self.command.run(settings).await
+    }
+}
+
+impl PingCmd {
+    pub async fn run(&self, settings: Settings) -> Result {
+        match self {
+            Self::Send(cmd) => cmd.run(settings).await,
+            Self::List(cmd) => cmd.run(settings).await,
+        }
+    }
+}
+
+impl Send {
+    pub async fn run(&self, settings: Settings) -> Result {
+        let mut client = LocalClient::new(&settings.api).await?.with_auth(&settings);
+        let sent = client.ping(&self.target).await?;
+        print_json(&sent)
+    }
+}
+
+impl List {
+    pub async fn run(&self, settings: Settings) -> Result {
+        let mut client = LocalClient::new(&settings.api).await?;
+        let pings = client.received_pings().await?;
+        print_json(&pings)
+    }
+}
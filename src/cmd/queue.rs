@@ -0,0 +1,54 @@
+use crate::{api::LocalClient, cmd::*, Result, Settings};
+use serde_json::json;
+
+/// Commands on the packet router uplink queue of the running service
+#[derive(Debug, clap::Args)]
+pub struct Cmd {
+    #[command(subcommand)]
+    command: QueueCmd,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum QueueCmd {
+    Info(Info),
+    Purge(Purge),
+}
+
+/// Show a summary of the queued uplinks
+#[derive(Debug, clap::Args)]
+pub struct Info {}
+
+/// Remove all queued uplinks
+#[derive(Debug, clap::Args)]
+pub struct Purge {}
+
+impl Cmd {
+    pub async fn run(&self, settings: Settings) -> Result {
+        self.command.run(settings).await
+    }
+}
+
+impl QueueCmd {
+    pub async fn run(&self, settings: Settings) -> Result {
+        match self {
+            Self::Info(cmd) => cmd.run(settings).await,
+            Self::Purge(cmd) => cmd.run(settings).await,
+        }
+    }
+}
+
+impl Info {
+    pub async fn run(&self, settings: Settings) -> Result {
+        let mut client = LocalClient::new(&settings.api).await?;
+        let queue = client.queue().await?;
+        print_json(&queue)
+    }
+}
+
+impl Purge {
+    pub async fn run(&self, settings: Settings) -> Result {
+        let mut client = LocalClient::new(&settings.api).await?.with_auth(&settings);
+        let purged = client.purge_queue().await?;
+        print_json(&json!({ "purged": purged }))
+    }
+}
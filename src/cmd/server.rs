@@ -1,11 +1,28 @@
-use crate::*;
+use crate::{sim::SimOptions, *};
 
 /// Run the gateway service
 #[derive(Debug, clap::Args)]
-pub struct Cmd {}
+pub struct Cmd {
+    /// Inject synthetic uplinks and beacons at the configured intervals,
+    /// without a radio or packet forwarder
+    #[arg(long)]
+    pub sim: bool,
+
+    /// Seconds between simulated uplinks, 0 to disable
+    #[arg(long, default_value_t = 10, requires = "sim")]
+    pub sim_uplink_interval: u64,
+
+    /// Seconds between simulated beacons, 0 to disable
+    #[arg(long, default_value_t = 300, requires = "sim")]
+    pub sim_beacon_interval: u64,
+}
 
 impl Cmd {
     pub async fn run(&self, shutdown: &triggered::Listener, settings: Settings) -> Result {
-        server::run(shutdown, &settings).await
+        let sim = self.sim.then_some(SimOptions {
+            uplink_interval: self.sim_uplink_interval,
+            beacon_interval: self.sim_beacon_interval,
+        });
+        server::run(shutdown, &settings, sim).await
     }
 }
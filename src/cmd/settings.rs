@@ -0,0 +1,55 @@
+use crate::{api::LocalClient, cmd::*, Result, Settings};
+use serde_json::json;
+
+/// Commands on the settings of the gateway
+#[derive(Debug, clap::Args)]
+pub struct Cmd {
+    #[command(subcommand)]
+    command: SettingsCmd,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum SettingsCmd {
+    Check(Check),
+    Reload(Reload),
+}
+
+/// List legacy settings keys of older releases in the settings and how to
+/// migrate them
+#[derive(Debug, clap::Args)]
+pub struct Check {}
+
+/// Reload the settings of the running service. Changes to the log level, poc
+/// interval, listen address and router queue settings are applied, all other
+/// settings require a restart. Sending SIGHUP to the service does the same.
+#[derive(Debug, clap::Args)]
+pub struct Reload {}
+
+impl Cmd {
+    pub async fn run(&self, settings: Settings) -> Result {
+        self.command.run(settings).await
+    }
+}
+
+impl SettingsCmd {
+    pub async fn run(&self, settings: Settings) -> Result {
+        match self {
+            Self::Check(cmd) => cmd.run(settings).await,
+            Self::Reload(cmd) => cmd.run(settings).await,
+        }
+    }
+}
+
+impl Check {
+    pub async fn run(&self, settings: Settings) -> Result {
+        print_json(&settings.deprecated)
+    }
+}
+
+impl Reload {
+    pub async fn run(&self, settings: Settings) -> Result {
+        let mut client = LocalClient::new(&settings.api).await?.with_auth(&settings);
+        let applied = client.reload().await?;
+        print_json(&json!({ "applied": applied }))
+    }
+}
@@ -0,0 +1,68 @@
+use crate::{api::LocalClient, cmd::*, subsystems::Subsystem, Result, Settings};
+use serde_json::json;
+
+/// Pause or resume subsystems of the running service.
+///
+/// A paused subsystem stays paused until it is resumed or the service
+/// restarts. Pausing only silences subsystems that are enabled in the
+/// settings, resuming does not enable a subsystem disabled in the settings.
+#[derive(Debug, clap::Args)]
+pub struct Cmd {
+    #[command(subcommand)]
+    command: SubsystemCmd,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum SubsystemCmd {
+    Pause(Pause),
+    Resume(Resume),
+}
+
+/// Pause a subsystem, poc for beacons and witness reports or mqtt for the
+/// mqtt bridge
+#[derive(Debug, clap::Args)]
+pub struct Pause {
+    subsystem: Subsystem,
+    /// Keep the subsystem paused across service restarts
+    #[arg(long)]
+    persist: bool,
+}
+
+/// Resume a paused subsystem
+#[derive(Debug, clap::Args)]
+pub struct Resume {
+    subsystem: Subsystem,
+    /// Also remove a persisted pause of the subsystem
+    #[arg(long)]
+    persist: bool,
+}
+
+impl Cmd {
+    pub async fn run(&self, settings: Settings) -> Result {
+        self.command.run(settings).await
+    }
+}
+
+impl SubsystemCmd {
+    pub async fn run(&self, settings: Settings) -> Result {
+        match self {
+            Self::Pause(cmd) => set_subsystem(&settings, cmd.subsystem, true, cmd.persist).await,
+            Self::Resume(cmd) => set_subsystem(&settings, cmd.subsystem, false, cmd.persist).await,
+        }
+    }
+}
+
+async fn set_subsystem(
+    settings: &Settings,
+    subsystem: Subsystem,
+    paused: bool,
+    persist: bool,
+) -> Result {
+    let mut client = LocalClient::new(&settings.api).await?.with_auth(settings);
+    client.set_subsystem(subsystem, paused, persist).await?;
+    print_json(&json!({
+        "subsystem": subsystem.to_string(),
+        "paused": paused,
+        "persisted": persist,
+    }))
+}
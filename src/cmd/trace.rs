@@ -0,0 +1,18 @@
+use crate::{api::LocalClient, cmd::print_json_line, Result, Settings};
+use futures::StreamExt;
+
+/// Stream the uplinks and downlinks handled by the running service, with the
+/// routing decision for each packet, as one JSON object per line
+#[derive(Debug, clap::Args)]
+pub struct Cmd {}
+
+impl Cmd {
+    pub async fn run(&self, shutdown: &triggered::Listener, settings: Settings) -> Result {
+        let mut client = LocalClient::new(&settings.api).await?;
+        let mut events = client.packet_stream().await?.take_until(shutdown.clone());
+        while let Some(event) = events.next().await {
+            print_json_line(&event?)?;
+        }
+        Ok(())
+    }
+}
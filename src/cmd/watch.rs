@@ -0,0 +1,25 @@
+use crate::{api::LocalClient, cmd::print_json_line, Result, Settings};
+use futures::StreamExt;
+
+/// Stream status changes of the running service, starting with the current
+/// status, as one JSON object per line
+#[derive(Debug, clap::Args)]
+pub struct Cmd {
+    /// Seconds between heartbeat events while the status does not change
+    #[arg(long, default_value = "30")]
+    heartbeat: u32,
+}
+
+impl Cmd {
+    pub async fn run(&self, shutdown: &triggered::Listener, settings: Settings) -> Result {
+        let mut client = LocalClient::new(&settings.api).await?;
+        let mut events = client
+            .watch_status(self.heartbeat)
+            .await?
+            .take_until(shutdown.clone());
+        while let Some(event) = events.next().await {
+            print_json_line(&event?)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+//! Payload CRC statistics of received packets.
+//!
+//! Packets that fail their payload CRC are not forwarded and do not count
+//! towards the uplink or witness statistics of the gateway. They are counted
+//! per channel here instead, since a rising CRC failure rate is the earliest
+//! sign of a degrading antenna or LNA, well before fewer witnesses show it.
+
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Maximum number of channels counted individually. Packets on other
+/// channels are only counted in the total.
+const MAX_CHANNELS: usize = 64;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct CrcCount {
+    /// Packets with a valid payload CRC
+    pub ok: u64,
+    /// Packets with an invalid payload CRC
+    pub failed: u64,
+}
+
+impl CrcCount {
+    fn add(&mut self, ok: bool) {
+        if ok {
+            self.ok += 1;
+        } else {
+            self.failed += 1;
+        }
+    }
+
+    /// Share of packets with an invalid CRC in percent
+    pub fn failure_rate(&self) -> f32 {
+        match self.ok + self.failed {
+            0 => 0.0,
+            total => (100.0 * self.failed as f64 / total as f64) as f32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelCrcCount {
+    /// Frequency in Hz
+    pub frequency: u32,
+    #[serde(flatten)]
+    pub count: CrcCount,
+    /// Share of packets with an invalid CRC in percent
+    pub failure_rate: f32,
+}
+
+impl From<(u32, CrcCount)> for ChannelCrcCount {
+    fn from((frequency, count): (u32, CrcCount)) -> Self {
+        Self {
+            frequency,
+            count,
+            failure_rate: count.failure_rate(),
+        }
+    }
+}
+
+/// Payload CRC results of the packets received since startup
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CrcStatus {
+    /// Unix time in seconds since which packets are counted
+    pub since: u64,
+    pub total: CrcCount,
+    /// Share of all packets with an invalid CRC in percent
+    pub failure_rate: f32,
+    /// Counts per channel, lowest frequency first
+    pub channels: Vec<ChannelCrcCount>,
+}
+
+#[derive(Debug)]
+pub struct CrcStats {
+    since: u64,
+    total: CrcCount,
+    channels: BTreeMap<u32, CrcCount>,
+}
+
+impl Default for CrcStats {
+    fn default() -> Self {
+        Self {
+            since: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            total: CrcCount::default(),
+            channels: BTreeMap::new(),
+        }
+    }
+}
+
+impl CrcStats {
+    /// Counts a received packet on the given frequency in MHz with a valid or
+    /// invalid payload CRC
+    pub fn record(&mut self, frequency: f64, ok: bool) {
+        self.total.add(ok);
+        let frequency = (frequency * 1_000_000.0).round() as u32;
+        if self.channels.len() < MAX_CHANNELS || self.channels.contains_key(&frequency) {
+            self.channels.entry(frequency).or_default().add(ok);
+        }
+    }
+
+    pub fn status(&self) -> CrcStatus {
+        CrcStatus {
+            since: self.since,
+            total: self.total,
+            failure_rate: self.total.failure_rate(),
+            channels: self
+                .channels
+                .iter()
+                .map(|(frequency, count)| ChannelCrcCount::from((*frequency, *count)))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc_stats() {
+        let mut stats = CrcStats::default();
+        stats.record(868.1, true);
+        stats.record(868.1, false);
+        stats.record(868.1, true);
+        stats.record(868.1, true);
+        stats.record(867.5, false);
+        let status = stats.status();
+        assert_eq!(CrcCount { ok: 3, failed: 2 }, status.total);
+        assert!((status.failure_rate - 40.0).abs() < 0.001);
+        let channels: Vec<(u32, u64, u64)> = status
+            .channels
+            .iter()
+            .map(|channel| (channel.frequency, channel.count.ok, channel.count.failed))
+            .collect();
+        assert_eq!(vec![(867_500_000, 0, 1), (868_100_000, 3, 1)], channels);
+        assert!((status.channels[1].failure_rate - 25.0).abs() < 0.001);
+    }
+}
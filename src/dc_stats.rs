@@ -0,0 +1,278 @@
+//! Data credit cost estimation of forwarded uplinks.
+//!
+//! The network charges one data credit (DC) for every started 24 bytes of an
+//! uplink delivered to the packet routers. The gateway estimates the cost of
+//! each uplink it forwards and aggregates it per NetID and per device address
+//! since startup, so operators of private fleets can predict their data credit
+//! use from the gateway. Which OUI pays for an uplink is only known to the
+//! packet router, the NetID of the device address is the closest the gateway
+//! can get.
+
+use crate::PacketUp;
+use lorawan::subnet::NetId;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Payload bytes covered by one data credit
+pub const DC_PAYLOAD_SIZE: usize = 24;
+/// Maximum number of device addresses counted individually. Uplinks of
+/// devices beyond this are only counted in the totals.
+const MAX_DEVICES: usize = 1024;
+/// Number of devices with the highest cost reported in the status
+const TOP_DEVICES: usize = 10;
+/// Maximum number of join request vendors counted individually
+const MAX_VENDORS: usize = 256;
+
+/// The estimated data credit cost of an uplink with a payload of the given
+/// size in bytes
+pub fn dc_cost(payload_size: usize) -> u64 {
+    payload_size.div_ceil(DC_PAYLOAD_SIZE).max(1) as u64
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct DcCount {
+    pub uplinks: u64,
+    pub dc: u64,
+}
+
+impl DcCount {
+    fn add(&mut self, dc: u64) {
+        self.uplinks += 1;
+        self.dc += dc;
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DcNetIdCount {
+    #[serde(serialize_with = "serialize_net_id")]
+    pub net_id: NetId,
+    #[serde(flatten)]
+    pub count: DcCount,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DcDeviceCount {
+    #[serde(serialize_with = "serialize_devaddr")]
+    pub devaddr: u32,
+    #[serde(flatten)]
+    pub count: DcCount,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DcVendorCount {
+    pub vendor: String,
+    #[serde(flatten)]
+    pub count: DcCount,
+}
+
+fn serialize_net_id<S: serde::Serializer>(
+    net_id: &NetId,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_str(net_id)
+}
+
+fn serialize_devaddr<S: serde::Serializer>(
+    devaddr: &u32,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{devaddr:08X}"))
+}
+
+/// Estimated data credit cost of the uplinks forwarded since startup
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DcStatus {
+    /// Unix time in seconds since which uplinks are counted
+    pub since: u64,
+    pub total: DcCount,
+    /// Join requests, which have no device address
+    pub joins: DcCount,
+    /// Cost per NetID, highest first
+    pub net_ids: Vec<DcNetIdCount>,
+    /// The devices with the highest cost, highest first
+    pub top_devices: Vec<DcDeviceCount>,
+    /// Join requests per likely device vendor, most first. Only counted when
+    /// the vendor lookup is enabled.
+    pub join_vendors: Vec<DcVendorCount>,
+}
+
+#[derive(Debug)]
+pub struct DcStats {
+    since: u64,
+    total: DcCount,
+    joins: DcCount,
+    net_ids: HashMap<NetId, DcCount>,
+    devices: HashMap<u32, DcCount>,
+    join_vendors: HashMap<String, DcCount>,
+}
+
+impl Default for DcStats {
+    fn default() -> Self {
+        Self {
+            since: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            total: DcCount::default(),
+            joins: DcCount::default(),
+            net_ids: HashMap::new(),
+            devices: HashMap::new(),
+            join_vendors: HashMap::new(),
+        }
+    }
+}
+
+impl DcStats {
+    /// Counts a forwarded uplink, with the likely device vendor of join
+    /// requests
+    pub fn record(&mut self, packet: &PacketUp, vendor: Option<&str>) {
+        let dc = dc_cost(packet.payload().len());
+        self.total.add(dc);
+        let Some(devaddr) = packet.dev_addr() else {
+            if packet.join_eui().is_some() {
+                self.joins.add(dc);
+                if let Some(vendor) = vendor {
+                    if self.join_vendors.len() < MAX_VENDORS
+                        || self.join_vendors.contains_key(vendor)
+                    {
+                        self.join_vendors
+                            .entry(vendor.to_string())
+                            .or_default()
+                            .add(dc);
+                    }
+                }
+            }
+            return;
+        };
+        if let Some(net_id) = NetId::from_devaddr(devaddr) {
+            self.net_ids.entry(net_id).or_default().add(dc);
+        }
+        if self.devices.len() < MAX_DEVICES || self.devices.contains_key(&devaddr) {
+            self.devices.entry(devaddr).or_default().add(dc);
+        }
+    }
+
+    pub fn status(&self) -> DcStatus {
+        let mut net_ids: Vec<DcNetIdCount> = self
+            .net_ids
+            .iter()
+            .map(|(net_id, count)| DcNetIdCount {
+                net_id: *net_id,
+                count: *count,
+            })
+            .collect();
+        net_ids.sort_by(|a, b| {
+            b.count
+                .dc
+                .cmp(&a.count.dc)
+                .then_with(|| u32::from(a.net_id).cmp(&u32::from(b.net_id)))
+        });
+        let mut top_devices: Vec<DcDeviceCount> = self
+            .devices
+            .iter()
+            .map(|(devaddr, count)| DcDeviceCount {
+                devaddr: *devaddr,
+                count: *count,
+            })
+            .collect();
+        top_devices.sort_by(|a, b| {
+            b.count
+                .dc
+                .cmp(&a.count.dc)
+                .then_with(|| a.devaddr.cmp(&b.devaddr))
+        });
+        top_devices.truncate(TOP_DEVICES);
+        let mut join_vendors: Vec<DcVendorCount> = self
+            .join_vendors
+            .iter()
+            .map(|(vendor, count)| DcVendorCount {
+                vendor: vendor.clone(),
+                count: *count,
+            })
+            .collect();
+        join_vendors.sort_by(|a, b| {
+            b.count
+                .uplinks
+                .cmp(&a.count.uplinks)
+                .then_with(|| a.vendor.cmp(&b.vendor))
+        });
+        DcStatus {
+            since: self.since,
+            total: self.total,
+            joins: self.joins,
+            net_ids,
+            top_devices,
+            join_vendors,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message_cache::Persist;
+    use helium_proto::{services::router::PacketRouterPacketUpV1, Message};
+
+    fn mk_uplink(payload: Vec<u8>) -> PacketUp {
+        let packet = PacketRouterPacketUpV1 {
+            payload,
+            ..Default::default()
+        };
+        PacketUp::from_bytes(&packet.encode_to_vec()).unwrap()
+    }
+
+    fn mk_data(devaddr: u32, size: usize) -> PacketUp {
+        let mut payload = vec![0x40];
+        payload.extend_from_slice(&devaddr.to_le_bytes());
+        payload.extend_from_slice(&[0, 0, 0]);
+        payload.resize(size, 0);
+        mk_uplink(payload)
+    }
+
+    #[test]
+    fn test_dc_cost() {
+        assert_eq!(1, dc_cost(0));
+        assert_eq!(1, dc_cost(24));
+        assert_eq!(2, dc_cost(25));
+        assert_eq!(3, dc_cost(51));
+    }
+
+    #[test]
+    fn test_dc_stats() {
+        let mut stats = DcStats::default();
+        stats.record(&mk_data(0x4800_0800, 20), None);
+        stats.record(&mk_data(0x4800_0800, 30), None);
+        stats.record(&mk_data(0x4800_0801, 60), None);
+        stats.record(&mk_data(0x2600_0001, 12), None);
+        let mut join = vec![0u8; 23];
+        join[1] = 1;
+        stats.record(&mk_uplink(join), Some("Dragino"));
+
+        let status = stats.status();
+        assert_eq!((5, 8), (status.total.uplinks, status.total.dc));
+        assert_eq!((1, 1), (status.joins.uplinks, status.joins.dc));
+        assert_eq!("Dragino", status.join_vendors[0].vendor);
+        assert_eq!(1, status.join_vendors[0].count.uplinks);
+        let net_ids: Vec<(String, u64)> = status
+            .net_ids
+            .iter()
+            .map(|count| (count.net_id.to_string(), count.count.dc))
+            .collect();
+        assert_eq!(
+            vec![("000024".to_string(), 6), ("000013".to_string(), 1)],
+            net_ids
+        );
+        let devices: Vec<(u32, u64)> = status
+            .top_devices
+            .iter()
+            .map(|count| (count.devaddr, count.count.dc))
+            .collect();
+        assert_eq!(
+            vec![(0x4800_0800, 3), (0x4800_0801, 3), (0x2600_0001, 1)],
+            devices
+        );
+    }
+}
@@ -0,0 +1,337 @@
+//! Prioritized dispatch of downlinks to the packet forwarders.
+//!
+//! Downlinks received from the packet routers are queued and dispatched in
+//! priority order, join accepts first, since a missed join accept costs the
+//! device a full join procedure. Downlinks of the same priority are
+//! dispatched in the order of their rx1 transmit time. The airtime of the receive windows sent to
+//! each packet forwarder is tracked so that a data downlink whose first
+//! receive window would overlap a scheduled join accept on the same
+//! forwarder is deferred to its second receive window instead of colliding
+//! with the join accept.
+//!
+//! Beacons and pings are sent immediately and have the lowest priority. The
+//! concentrator clock of each forwarder is estimated from the timestamps of
+//! its uplinks, so a beacon can be deferred until the class A receive windows
+//! scheduled around it have passed.
+
+use crate::{packet_router, PacketDown};
+use lorawan::MType;
+use semtech_udp::MacAddress;
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+    time::{Duration, Instant},
+};
+
+/// Time after which a scheduled window is forgotten. This is well past the
+/// longest receive delay of a downlink.
+const SCHEDULED_TTL: Duration = Duration::from_secs(20);
+/// Margin kept between a beacon and a scheduled window, covering the error of
+/// the concentrator clock estimate
+const BEACON_GUARD: Duration = Duration::from_millis(250);
+
+/// Transmit priority of a downlink, lowest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DownlinkPriority {
+    Beacon,
+    Data,
+    JoinAccept,
+}
+
+impl From<&PacketDown> for DownlinkPriority {
+    fn from(packet: &PacketDown) -> Self {
+        match packet.mtype() {
+            Some(MType::JoinAccept) => Self::JoinAccept,
+            _ => Self::Data,
+        }
+    }
+}
+
+/// A transmit window in concentrator time of a packet forwarder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxWindow {
+    /// Concentrator timestamp in microseconds of the start of the window
+    pub tmst: u32,
+    pub airtime: Duration,
+}
+
+impl TxWindow {
+    /// Whether two windows overlap, taking the wrapping of the concentrator
+    /// timestamp counter into account
+    fn overlaps(&self, other: &Self) -> bool {
+        let (first, second) = if (other.tmst.wrapping_sub(self.tmst) as i32) >= 0 {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        (second.tmst.wrapping_sub(first.tmst) as u128) < first.airtime.as_micros()
+    }
+}
+
+#[derive(Debug)]
+struct Pending {
+    priority: DownlinkPriority,
+    /// Arrival order, to dispatch downlinks of the same priority first in
+    /// first out
+    seq: u64,
+    /// Concentrator timestamp of the rx1 window, None for immediate downlinks
+    deadline: Option<u32>,
+    packet: PacketDown,
+    /// The router to report the outcome of the downlink to
+    router: Option<packet_router::MessageSender>,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| cmp_deadline(other.deadline, self.deadline))
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// Orders transmit deadlines earliest first, taking the wrapping of the
+/// concentrator timestamp counter into account. Immediate downlinks come last.
+fn cmp_deadline(a: Option<u32>, b: Option<u32>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => (a.wrapping_sub(b) as i32).cmp(&0),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+#[derive(Debug)]
+struct Scheduled {
+    mac: MacAddress,
+    window: TxWindow,
+    priority: DownlinkPriority,
+    at: Instant,
+    /// Estimated start of the window, if the clock of the forwarder is known
+    start: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+pub struct DownlinkArbiter {
+    pending: BinaryHeap<Pending>,
+    seq: u64,
+    scheduled: Vec<Scheduled>,
+    /// Concentrator timestamp and receive time of the last uplink of each
+    /// forwarder
+    clocks: HashMap<MacAddress, (u32, Instant)>,
+}
+
+impl DownlinkArbiter {
+    /// Queues a downlink, and the router it came from, for dispatch
+    pub fn push(&mut self, packet: PacketDown, router: Option<packet_router::MessageSender>) {
+        self.seq += 1;
+        self.pending.push(Pending {
+            priority: DownlinkPriority::from(&packet),
+            seq: self.seq,
+            deadline: packet.rx1_timestamp(),
+            packet,
+            router,
+        });
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Returns the queued downlink with the highest priority and its router
+    pub fn pop(&mut self) -> Option<(PacketDown, Option<packet_router::MessageSender>)> {
+        self.pending
+            .pop()
+            .map(|pending| (pending.packet, pending.router))
+    }
+
+    /// Records the concentrator timestamp of an uplink received from the
+    /// given forwarder to estimate its clock
+    pub fn sync_clock(&mut self, mac: MacAddress, tmst: u32, received: Instant) {
+        self.clocks.insert(mac, (tmst, received));
+    }
+
+    /// The estimated time the concentrator clock of the given forwarder
+    /// reaches the given timestamp
+    fn tx_start(&self, mac: MacAddress, tmst: u32) -> Option<Instant> {
+        let (sync_tmst, synced) = self.clocks.get(&mac)?;
+        let offset = tmst.wrapping_sub(*sync_tmst) as i32;
+        let micros = Duration::from_micros(offset.unsigned_abs() as u64);
+        if offset >= 0 {
+            synced.checked_add(micros)
+        } else {
+            synced.checked_sub(micros)
+        }
+    }
+
+    /// Schedules a transmit window on the given forwarder. Returns false, and
+    /// does not schedule the window, when it overlaps a scheduled window of a
+    /// higher priority downlink on the same forwarder.
+    pub fn schedule(
+        &mut self,
+        mac: MacAddress,
+        window: TxWindow,
+        priority: DownlinkPriority,
+    ) -> bool {
+        let now = Instant::now();
+        self.expire_scheduled(now);
+        let conflict = self.scheduled.iter().any(|scheduled| {
+            scheduled.mac == mac
+                && scheduled.priority > priority
+                && scheduled.window.overlaps(&window)
+        });
+        if !conflict {
+            self.scheduled.push(Scheduled {
+                mac,
+                window,
+                priority,
+                at: now,
+                start: self.tx_start(mac, window.tmst),
+            });
+        }
+        !conflict
+    }
+
+    /// Returns how long to defer a beacon with the given airtime on the given
+    /// forwarder so it does not overlap a scheduled window
+    pub fn beacon_delay(&mut self, mac: MacAddress, airtime: Duration, now: Instant) -> Duration {
+        self.expire_scheduled(now);
+        let mut busy: Vec<(Instant, Instant)> = self
+            .scheduled
+            .iter()
+            .filter(|scheduled| scheduled.mac == mac)
+            .filter_map(|scheduled| {
+                let start = scheduled.start?;
+                Some((
+                    start.checked_sub(BEACON_GUARD).unwrap_or(start),
+                    start + scheduled.window.airtime + BEACON_GUARD,
+                ))
+            })
+            .collect();
+        busy.sort_unstable();
+        let mut start = now;
+        for (from, to) in busy {
+            if from < start + airtime && to > start {
+                start = to;
+            }
+        }
+        start - now
+    }
+
+    fn expire_scheduled(&mut self, now: Instant) {
+        self.scheduled
+            .retain(|scheduled| now.duration_since(scheduled.at) < SCHEDULED_TTL);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use helium_proto::services::router::{PacketRouterPacketDownV1, WindowV1};
+
+    fn mk_downlink(mtype: MType, timestamp: u64) -> PacketDown {
+        PacketDown::from(PacketRouterPacketDownV1 {
+            payload: vec![u8::from(mtype) << 5, 0, 0, 0, 0],
+            rx1: Some(WindowV1 {
+                timestamp,
+                frequency: 923_300_000,
+                datarate: helium_proto::DataRate::Sf10bw500 as i32,
+                immediate: false,
+            }),
+            rx2: None,
+        })
+    }
+
+    fn window(tmst: u32, airtime_ms: u64) -> TxWindow {
+        TxWindow {
+            tmst,
+            airtime: Duration::from_millis(airtime_ms),
+        }
+    }
+
+    #[test]
+    fn test_join_accept_first() {
+        let mut arbiter = DownlinkArbiter::default();
+        arbiter.push(mk_downlink(MType::UnconfirmedDown, 1), None);
+        arbiter.push(mk_downlink(MType::JoinAccept, 2), None);
+        arbiter.push(mk_downlink(MType::ConfirmedDown, 3), None);
+        let order: Vec<u64> = std::iter::from_fn(|| arbiter.pop())
+            .map(|(packet, _)| packet.rx1.as_ref().unwrap().timestamp)
+            .collect();
+        assert_eq!(vec![2, 1, 3], order);
+        assert!(!arbiter.has_pending());
+
+        // Downlinks of the same priority go by rx1 deadline, across the wrap
+        // of the timestamp counter
+        arbiter.push(mk_downlink(MType::UnconfirmedDown, 5_000), None);
+        arbiter.push(mk_downlink(MType::UnconfirmedDown, 1_000), None);
+        arbiter.push(mk_downlink(MType::UnconfirmedDown, u32::MAX as u64), None);
+        let order: Vec<u64> = std::iter::from_fn(|| arbiter.pop())
+            .map(|(packet, _)| packet.rx1.as_ref().unwrap().timestamp)
+            .collect();
+        assert_eq!(vec![u32::MAX as u64, 1_000, 5_000], order);
+    }
+
+    #[test]
+    fn test_beacon_delay() {
+        let mac = MacAddress::from([1, 0, 0, 0, 0, 0, 0, 1]);
+        let other_mac = MacAddress::from([2, 0, 0, 0, 0, 0, 0, 2]);
+        let now = Instant::now();
+        let beacon_airtime = Duration::from_millis(200);
+        let mut arbiter = DownlinkArbiter::default();
+        // Without a clock estimate beacons are not deferred
+        assert!(arbiter.schedule(mac, window(1_000_000, 100), DownlinkPriority::Data));
+        assert!(arbiter.beacon_delay(mac, beacon_airtime, now).is_zero());
+
+        // A window starting in one second leaves room for the beacon
+        arbiter.sync_clock(mac, 0, now);
+        assert!(arbiter.schedule(mac, window(1_000_000, 100), DownlinkPriority::Data));
+        assert!(arbiter.beacon_delay(mac, beacon_airtime, now).is_zero());
+        // Windows starting within the beacon defer it past their end
+        assert!(arbiter.schedule(mac, window(300_000, 100), DownlinkPriority::JoinAccept));
+        assert!(arbiter.schedule(mac, window(600_000, 100), DownlinkPriority::Data));
+        assert_eq!(
+            Duration::from_millis(1_350),
+            arbiter.beacon_delay(mac, beacon_airtime, now)
+        );
+        assert!(arbiter
+            .beacon_delay(other_mac, beacon_airtime, now)
+            .is_zero());
+    }
+
+    #[test]
+    fn test_schedule_conflicts() {
+        let mac = MacAddress::from([1, 0, 0, 0, 0, 0, 0, 1]);
+        let other_mac = MacAddress::from([2, 0, 0, 0, 0, 0, 0, 2]);
+        let mut arbiter = DownlinkArbiter::default();
+        assert!(arbiter.schedule(mac, window(1_000_000, 100), DownlinkPriority::JoinAccept));
+        // Overlapping data downlinks are deferred, others are not
+        assert!(!arbiter.schedule(mac, window(1_050_000, 100), DownlinkPriority::Data));
+        assert!(!arbiter.schedule(mac, window(950_000, 100), DownlinkPriority::Data));
+        assert!(arbiter.schedule(mac, window(1_100_000, 100), DownlinkPriority::Data));
+        assert!(arbiter.schedule(other_mac, window(1_050_000, 100), DownlinkPriority::Data));
+        // Join accepts are never deferred
+        assert!(arbiter.schedule(mac, window(1_150_000, 100), DownlinkPriority::JoinAccept));
+        // Windows across the wrap of the timestamp counter
+        assert!(arbiter.schedule(
+            mac,
+            window(u32::MAX - 10_000, 100),
+            DownlinkPriority::JoinAccept
+        ));
+        assert!(!arbiter.schedule(mac, window(10_000, 100), DownlinkPriority::Data));
+    }
+}
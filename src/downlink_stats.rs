@@ -0,0 +1,82 @@
+//! Downlink delivery integrity.
+//!
+//! Counts downlinks accepted from the packet router and correlates them with
+//! the packet forwarder transmit acks for those downlinks. The counters are
+//! rolled up every hour so a gateway that accepts downlinks but fails to
+//! transmit them can be detected from the logs and the local api.
+
+use serde::Serialize;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// Downlink counts over a period of time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct DownlinkPeriod {
+    /// Number of downlinks accepted from the packet router
+    pub accepted: u64,
+    /// Number of accepted downlinks acknowledged as transmitted by the packet
+    /// forwarder
+    pub transmitted: u64,
+    /// Number of accepted downlinks that could not be transmitted
+    pub failed: u64,
+}
+
+impl DownlinkPeriod {
+    /// Ratio of transmitted to accepted downlinks, or None if no downlinks
+    /// were accepted
+    pub fn integrity(&self) -> Option<f32> {
+        (self.accepted > 0).then(|| self.transmitted as f32 / self.accepted as f32)
+    }
+}
+
+/// Downlink counts of the current hour and of the last full hour
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DownlinkStatus {
+    pub current: DownlinkPeriod,
+    pub last_hour: Option<DownlinkPeriod>,
+}
+
+/// Shared downlink counters. Clones share the same counts so the counters can
+/// be updated from spawned downlink dispatch tasks.
+#[derive(Debug, Clone, Default)]
+pub struct DownlinkCounters(Arc<Counters>);
+
+#[derive(Debug, Default)]
+struct Counters {
+    accepted: AtomicU64,
+    transmitted: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl DownlinkCounters {
+    pub fn accepted(&self) {
+        self.0.accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn transmitted(&self) {
+        self.0.transmitted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn failed(&self) {
+        self.0.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> DownlinkPeriod {
+        DownlinkPeriod {
+            accepted: self.0.accepted.load(Ordering::Relaxed),
+            transmitted: self.0.transmitted.load(Ordering::Relaxed),
+            failed: self.0.failed.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the current counts and resets them for the next period
+    pub fn take(&self) -> DownlinkPeriod {
+        DownlinkPeriod {
+            accepted: self.0.accepted.swap(0, Ordering::Relaxed),
+            transmitted: self.0.transmitted.swap(0, Ordering::Relaxed),
+            failed: self.0.failed.swap(0, Ordering::Relaxed),
+        }
+    }
+}
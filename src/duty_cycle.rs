@@ -0,0 +1,215 @@
+//! Duty-cycle accounting of transmissions.
+//!
+//! Regions like EU868 limit the share of time a transmitter may be on air per
+//! sub-band, for most of EU868 to 1%. The airtime of every downlink, beacon
+//! and ping is recorded per channel over a sliding window, and a transmission
+//! that would take its sub-band over the limit is not sent. Data downlinks
+//! whose first receive window is over the limit are sent in their second
+//! window, which often lies in a sub-band with a higher limit.
+
+use crate::{settings::DutyCycleSettings, Region};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// Sub-bands of EU868 as minimum and maximum frequency in Hz and the duty
+/// cycle limit as a fraction of time
+const EU868_SUB_BANDS: &[(u32, u32, f64)] = &[
+    (863_000_000, 865_000_000, 0.001),
+    (865_000_000, 868_000_000, 0.01),
+    (868_000_000, 868_600_000, 0.01),
+    (868_700_000, 869_200_000, 0.001),
+    (869_400_000, 869_650_000, 0.1),
+    (869_700_000, 870_000_000, 0.01),
+];
+/// Sub-bands of EU433
+const EU433_SUB_BANDS: &[(u32, u32, f64)] = &[(433_050_000, 434_790_000, 0.1)];
+
+/// The duty cycle limited sub-bands of a region
+fn sub_bands(region: Region) -> &'static [(u32, u32, f64)] {
+    if region == Region::from(helium_proto::Region::Eu868) {
+        EU868_SUB_BANDS
+    } else if region == Region::from(helium_proto::Region::Eu433) {
+        EU433_SUB_BANDS
+    } else {
+        &[]
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelUtilization {
+    /// Frequency in Hz
+    pub frequency: u32,
+    /// Airtime in milliseconds in the window
+    pub airtime: u64,
+    /// Share of the window in percent
+    pub utilization: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SubBandUtilization {
+    /// Lowest frequency in Hz
+    pub min_frequency: u32,
+    /// Highest frequency in Hz
+    pub max_frequency: u32,
+    /// Duty cycle limit in percent
+    pub limit: f32,
+    /// Share of the window in percent
+    pub utilization: f32,
+}
+
+/// Transmit utilization over the accounting window
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DutyCycleStatus {
+    pub enabled: bool,
+    /// Length of the window in seconds
+    pub window: u64,
+    pub channels: Vec<ChannelUtilization>,
+    /// The duty cycle limited sub-bands of the region
+    pub sub_bands: Vec<SubBandUtilization>,
+}
+
+#[derive(Debug)]
+struct Transmission {
+    at: Instant,
+    frequency: u32,
+    airtime: Duration,
+}
+
+#[derive(Debug)]
+pub struct DutyCycle {
+    /// Whether transmissions over the limit are rejected
+    enabled: bool,
+    window: Duration,
+    region: Region,
+    /// Transmissions in the window, oldest first
+    transmissions: VecDeque<Transmission>,
+}
+
+impl DutyCycle {
+    pub fn new(settings: &DutyCycleSettings, region: Region) -> Self {
+        Self {
+            enabled: settings.enabled,
+            window: Duration::from_secs(settings.window.max(1)),
+            region,
+            transmissions: VecDeque::new(),
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while self
+            .transmissions
+            .front()
+            .is_some_and(|tx| now.saturating_duration_since(tx.at) >= self.window)
+        {
+            self.transmissions.pop_front();
+        }
+    }
+
+    /// Airtime in the window on the frequencies between the given bounds
+    fn airtime(&self, min_frequency: u32, max_frequency: u32) -> Duration {
+        self.transmissions
+            .iter()
+            .filter(|tx| (min_frequency..max_frequency).contains(&tx.frequency))
+            .map(|tx| tx.airtime)
+            .sum()
+    }
+
+    /// Whether a transmission on the given frequency in Hz fits the duty cycle
+    /// limit of its sub-band. Always true when duty cycle limits are disabled.
+    pub fn allows(&mut self, frequency: u32, airtime: Duration, now: Instant) -> bool {
+        self.expire(now);
+        if !self.enabled {
+            return true;
+        }
+        sub_bands(self.region)
+            .iter()
+            .find(|(min, max, _)| (*min..*max).contains(&frequency))
+            .is_none_or(|(min, max, limit)| {
+                (self.airtime(*min, *max) + airtime).as_secs_f64()
+                    <= limit * self.window.as_secs_f64()
+            })
+    }
+
+    /// Records a transmission on the given frequency in Hz
+    pub fn record(&mut self, frequency: u32, airtime: Duration, now: Instant) {
+        self.expire(now);
+        self.transmissions.push_back(Transmission {
+            at: now,
+            frequency,
+            airtime,
+        });
+    }
+
+    pub fn status(&mut self, now: Instant) -> DutyCycleStatus {
+        self.expire(now);
+        let window = self.window.as_secs_f64();
+        let percent = |airtime: Duration| (100.0 * airtime.as_secs_f64() / window) as f32;
+        let mut channels: BTreeMap<u32, Duration> = BTreeMap::new();
+        for tx in &self.transmissions {
+            *channels.entry(tx.frequency).or_default() += tx.airtime;
+        }
+        DutyCycleStatus {
+            enabled: self.enabled,
+            window: self.window.as_secs(),
+            channels: channels
+                .into_iter()
+                .map(|(frequency, airtime)| ChannelUtilization {
+                    frequency,
+                    airtime: airtime.as_millis() as u64,
+                    utilization: percent(airtime),
+                })
+                .collect(),
+            sub_bands: sub_bands(self.region)
+                .iter()
+                .map(|(min, max, limit)| SubBandUtilization {
+                    min_frequency: *min,
+                    max_frequency: *max,
+                    limit: (100.0 * limit) as f32,
+                    utilization: percent(self.airtime(*min, *max)),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_duty_cycle() {
+        let settings = DutyCycleSettings {
+            enabled: true,
+            window: 100,
+        };
+        let mut duty_cycle = DutyCycle::new(&settings, Region::from(helium_proto::Region::Eu868));
+        let now = Instant::now();
+        let airtime = Duration::from_millis(400);
+        // 1% of 100 seconds is one second in the 865-868 MHz sub-band
+        assert!(duty_cycle.allows(867_100_000, airtime, now));
+        duty_cycle.record(867_100_000, airtime, now);
+        duty_cycle.record(867_300_000, airtime, now);
+        assert!(!duty_cycle.allows(867_500_000, airtime, now));
+        // Other sub-bands have their own limit
+        assert!(duty_cycle.allows(869_525_000, airtime, now));
+
+        let status = duty_cycle.status(now);
+        assert_eq!(2, status.channels.len());
+        assert_eq!(400, status.channels[0].airtime);
+        assert!((status.sub_bands[1].utilization - 0.8).abs() < 0.001);
+
+        // Transmissions leave the window
+        assert!(duty_cycle.allows(867_500_000, airtime, now + Duration::from_secs(100)));
+
+        // Regions without limits allow anything
+        duty_cycle.set_region(Region::from(helium_proto::Region::Us915));
+        assert!(duty_cycle.allows(867_500_000, airtime, now));
+    }
+}
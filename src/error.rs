@@ -67,6 +67,12 @@ pub enum DecodeError {
     NotBeacon,
     #[error("invalid datarate: {0}")]
     InvalidDataRate(String),
+    #[error("payload size {size} exceeds max {max} for datarate {datarate}")]
+    PayloadTooLarge {
+        size: usize,
+        max: usize,
+        datarate: String,
+    },
 }
 
 #[derive(Error, Debug)]
@@ -81,6 +87,8 @@ pub enum ServiceError {
     Channel,
     #[error("no active session")]
     NoSession,
+    #[error("session expired after {age}s")]
+    SessionExpired { age: u64 },
     #[error("age {age}s > {max_age}s")]
     Check { age: u64, max_age: u64 },
     #[error("Unable to connect to local server. Check that `helium_gateway` is running.")]
@@ -135,7 +143,7 @@ impl DecodeError {
     }
 
     pub fn crc_disabled() -> Error {
-        Error::Decode(DecodeError::CrcInvalid)
+        Error::Decode(DecodeError::CrcDisabled)
     }
 
     pub fn prost_decode(msg: &'static str) -> Error {
@@ -157,6 +165,14 @@ impl DecodeError {
     pub fn not_beacon() -> Error {
         Error::Decode(DecodeError::NotBeacon)
     }
+
+    pub fn payload_too_large(size: usize, max: usize, datarate: String) -> Error {
+        Error::Decode(DecodeError::PayloadTooLarge {
+            size,
+            max,
+            datarate,
+        })
+    }
 }
 
 impl RegionError {
@@ -180,6 +196,10 @@ impl Error {
         Error::Service(ServiceError::NoSession)
     }
 
+    pub fn session_expired(age: u64) -> Error {
+        Error::Service(ServiceError::SessionExpired { age })
+    }
+
     pub fn no_stream() -> Error {
         Error::Service(ServiceError::Stream)
     }
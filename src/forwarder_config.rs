@@ -0,0 +1,307 @@
+//! Semtech packet forwarder configuration for the current region.
+//!
+//! The packet forwarder and the gateway are configured separately, so the
+//! channel plan of the forwarder can drift from the region the gateway
+//! operates in. A `global_conf.json` is rendered from the channel plan of the
+//! region parameters for a SX1301 or SX1302 concentrator:
+//!
+//! * The 125 kHz channels are the multi spreading factor channels, split over
+//!   the two radios of the concentrator with the radio centered on its
+//!   channels.
+//! * The first wider channel is the single spreading factor LoRa channel,
+//!   with the first spreading factor allowed on it.
+//! * Transmissions are allowed in the band of the region.
+//!
+//! Board specific settings like the TX gain table, RSSI calibration and the
+//! SPI device are not rendered and need to be merged from the configuration
+//! of the board vendor.
+
+use crate::{region_watcher::RegionChannel, Error, Result};
+use serde_json::{json, Map, Value};
+
+/// Maximum offset in Hz of a channel from the center frequency of its radio
+const MAX_IF: i64 = 400_000;
+/// Maximum number of multi spreading factor channels of a concentrator
+const MAX_MULTI_SF_CHANNELS: usize = 8;
+/// Bandwidth in Hz of the multi spreading factor channels
+const MULTI_SF_BANDWIDTH: u64 = 125_000;
+/// Gateway ID placeholder used by the Semtech reference configurations
+pub const DEFAULT_GATEWAY_ID: &str = "AA555A0000000000";
+
+/// Minimum and maximum transmit frequency in Hz of the regions
+const REGION_BANDS: &[(helium_proto::Region, u64, u64)] = &[
+    (helium_proto::Region::Us915, 902_000_000, 928_000_000),
+    (helium_proto::Region::Eu868, 863_000_000, 870_000_000),
+    (helium_proto::Region::Eu868A, 863_000_000, 870_000_000),
+    (helium_proto::Region::Eu433, 433_050_000, 434_790_000),
+    (helium_proto::Region::Cn470, 470_000_000, 510_000_000),
+    (helium_proto::Region::Cn779, 779_000_000, 787_000_000),
+    (helium_proto::Region::Au915, 915_000_000, 928_000_000),
+    (helium_proto::Region::As9231, 915_000_000, 928_000_000),
+    (helium_proto::Region::As9231b, 915_000_000, 928_000_000),
+    (helium_proto::Region::As9232, 915_000_000, 928_000_000),
+    (helium_proto::Region::As9233, 915_000_000, 928_000_000),
+    (helium_proto::Region::As9234, 915_000_000, 928_000_000),
+    (helium_proto::Region::Kr920, 920_900_000, 923_300_000),
+    (helium_proto::Region::In865, 865_000_000, 867_000_000),
+    (helium_proto::Region::Ru864, 864_000_000, 870_000_000),
+];
+
+/// The concentrator the configuration is rendered for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Concentrator {
+    Sx1301,
+    Sx1302,
+}
+
+impl Concentrator {
+    fn conf_key(&self) -> &'static str {
+        match self {
+            Self::Sx1301 => "SX1301_conf",
+            Self::Sx1302 => "SX130x_conf",
+        }
+    }
+
+    fn radio_type(&self) -> &'static str {
+        match self {
+            Self::Sx1301 => "SX1257",
+            Self::Sx1302 => "SX1250",
+        }
+    }
+
+    /// The radio providing the clock of the reference designs
+    fn clock_source(&self) -> u8 {
+        match self {
+            Self::Sx1301 => 1,
+            Self::Sx1302 => 0,
+        }
+    }
+
+    fn rssi_offset(&self) -> f64 {
+        match self {
+            Self::Sx1301 => -166.0,
+            Self::Sx1302 => -215.4,
+        }
+    }
+}
+
+/// Where the packet forwarder sends its packets
+#[derive(Debug, Clone)]
+pub struct ForwarderServer {
+    pub gateway_id: String,
+    pub address: String,
+    pub port: u16,
+}
+
+/// A radio of the concentrator with the channels received on it
+struct Radio {
+    center: u64,
+    channels: Vec<u64>,
+}
+
+impl Radio {
+    fn new(channels: &[u64]) -> Option<Self> {
+        let (min, max) = (channels.first()?, channels.last()?);
+        Some(Self {
+            center: (min + max) / 2,
+            channels: channels.to_vec(),
+        })
+    }
+
+    fn offset(&self, frequency: u64) -> i64 {
+        frequency as i64 - self.center as i64
+    }
+}
+
+/// Renders the packet forwarder configuration for the given region and
+/// channel plan
+pub fn render(
+    region: &str,
+    channels: &[RegionChannel],
+    concentrator: Concentrator,
+    server: &ForwarderServer,
+) -> Result<Value> {
+    let mut multi_sf: Vec<u64> = channels
+        .iter()
+        .filter(|channel| channel.bandwidth == MULTI_SF_BANDWIDTH)
+        .map(|channel| channel.frequency)
+        .collect();
+    multi_sf.sort_unstable();
+    multi_sf.dedup();
+    if multi_sf.is_empty() {
+        return Err(Error::custom(format!(
+            "no 125 kHz channels in region {region}"
+        )));
+    }
+    if multi_sf.len() > MAX_MULTI_SF_CHANNELS {
+        return Err(Error::custom(format!(
+            "{} channels in region {region}, a concentrator supports {MAX_MULTI_SF_CHANNELS}",
+            multi_sf.len()
+        )));
+    }
+    let (first, second) = multi_sf.split_at(multi_sf.len().div_ceil(2));
+    let radios: Vec<Radio> = [first, second].into_iter().filter_map(Radio::new).collect();
+    if radios.iter().any(|radio| {
+        radio
+            .channels
+            .iter()
+            .any(|f| radio.offset(*f).abs() > MAX_IF)
+    }) {
+        return Err(Error::custom(format!(
+            "channels of region {region} do not fit the two radios of a concentrator"
+        )));
+    }
+
+    let (tx_min, tx_max) = tx_band(region).unwrap_or((multi_sf[0], multi_sf[multi_sf.len() - 1]));
+    let mut conf = Map::new();
+    if concentrator == Concentrator::Sx1302 {
+        conf.insert("com_type".into(), json!("SPI"));
+        conf.insert("com_path".into(), json!("/dev/spidev0.0"));
+    }
+    conf.insert("lorawan_public".into(), json!(true));
+    conf.insert("clksrc".into(), json!(concentrator.clock_source()));
+    conf.insert("antenna_gain".into(), json!(0));
+    for index in 0..2 {
+        let radio = match radios.get(index) {
+            Some(radio) => json!({
+                "enable": true,
+                "type": concentrator.radio_type(),
+                "freq": radio.center,
+                "rssi_offset": concentrator.rssi_offset(),
+                "tx_enable": index == 0,
+                "tx_freq_min": tx_min,
+                "tx_freq_max": tx_max,
+            }),
+            None => json!({"enable": false}),
+        };
+        conf.insert(format!("radio_{index}"), radio);
+    }
+    let multi_sf_channels = radios.iter().enumerate().flat_map(|(index, radio)| {
+        radio
+            .channels
+            .iter()
+            .map(move |frequency| json!({"enable": true, "radio": index, "if": radio.offset(*frequency)}))
+    });
+    for (index, channel) in multi_sf_channels.enumerate() {
+        conf.insert(format!("chan_multiSF_{index}"), channel);
+    }
+    for index in multi_sf.len()..MAX_MULTI_SF_CHANNELS {
+        conf.insert(format!("chan_multiSF_{index}"), json!({"enable": false}));
+    }
+    conf.insert("chan_Lora_std".into(), lora_std(channels, &radios));
+    conf.insert("chan_FSK".into(), json!({"enable": false}));
+
+    Ok(json!({
+        concentrator.conf_key(): conf,
+        "gateway_conf": {
+            "gateway_ID": server.gateway_id,
+            "server_address": server.address,
+            "serv_port_up": server.port,
+            "serv_port_down": server.port,
+            "keepalive_interval": 10,
+            "stat_interval": 30,
+            "push_timeout_ms": 100,
+            "forward_crc_valid": true,
+            "forward_crc_error": false,
+            "forward_crc_disabled": false,
+        },
+    }))
+}
+
+/// The single spreading factor channel, the first channel wider than 125 kHz
+/// that fits one of the radios
+fn lora_std(channels: &[RegionChannel], radios: &[Radio]) -> Value {
+    let channel = channels
+        .iter()
+        .filter(|channel| channel.bandwidth > MULTI_SF_BANDWIDTH)
+        .find_map(|channel| {
+            let (index, radio) = radios
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, radio)| radio.offset(channel.frequency).abs())?;
+            let spread_factor = channel
+                .spreading
+                .iter()
+                .find_map(|spreading| spreading.strip_prefix("SF")?.parse::<u8>().ok())?;
+            (radio.offset(channel.frequency).abs() <= MAX_IF).then(|| {
+                json!({
+                    "enable": true,
+                    "radio": index,
+                    "if": radio.offset(channel.frequency),
+                    "bandwidth": channel.bandwidth,
+                    "spread_factor": spread_factor,
+                })
+            })
+        });
+    channel.unwrap_or_else(|| json!({"enable": false}))
+}
+
+/// The transmit band of a region, if known
+fn tx_band(region: &str) -> Option<(u64, u64)> {
+    let region = helium_proto::Region::from_str_name(region)?;
+    REGION_BANDS
+        .iter()
+        .find(|(band_region, _, _)| *band_region == region)
+        .map(|(_, min, max)| (*min, *max))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn channel(frequency: u64, bandwidth: u64, spreading: &[&str]) -> RegionChannel {
+        RegionChannel {
+            frequency,
+            bandwidth,
+            max_eirp: 160,
+            spreading: spreading.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn eu868_config() {
+        let channels: Vec<RegionChannel> = [
+            868_100_000,
+            868_300_000,
+            868_500_000,
+            867_100_000,
+            867_300_000,
+            867_500_000,
+            867_700_000,
+            867_900_000,
+        ]
+        .into_iter()
+        .map(|frequency| channel(frequency, 125_000, &["SF12", "SF7"]))
+        .chain([channel(868_300_000, 250_000, &["SF7"])])
+        .collect();
+        let server = ForwarderServer {
+            gateway_id: DEFAULT_GATEWAY_ID.to_string(),
+            address: "localhost".to_string(),
+            port: 1680,
+        };
+        let conf = render("EU868", &channels, Concentrator::Sx1301, &server).expect("config");
+        let sx1301 = &conf["SX1301_conf"];
+        assert_eq!(867_400_000, sx1301["radio_0"]["freq"]);
+        assert_eq!(868_200_000, sx1301["radio_1"]["freq"]);
+        assert_eq!(863_000_000, sx1301["radio_0"]["tx_freq_min"]);
+        assert_eq!(
+            json!({"enable": true, "radio": 0, "if": -300_000}),
+            sx1301["chan_multiSF_0"]
+        );
+        assert_eq!(
+            json!({"enable": true, "radio": 1, "if": 300_000}),
+            sx1301["chan_multiSF_7"]
+        );
+        assert_eq!(7, sx1301["chan_Lora_std"]["spread_factor"]);
+        assert_eq!(1, sx1301["chan_Lora_std"]["radio"]);
+        assert_eq!(1680, conf["gateway_conf"]["serv_port_up"]);
+
+        // Channels too far apart for two radios
+        let channels = vec![
+            channel(867_100_000, 125_000, &[]),
+            channel(868_100_000, 125_000, &[]),
+            channel(869_100_000, 125_000, &[]),
+        ];
+        assert!(render("EU868", &channels, Concentrator::Sx1302, &server).is_err());
+    }
+}
@@ -0,0 +1,705 @@
+//! Per packet forwarder client accounting, fair dequeuing and downlink
+//! routing.
+//!
+//! Packets received from packet forwarder clients are queued per client MAC
+//! and dequeued round-robin across clients. With several forwarders connected
+//! this keeps a chatty forwarder from starving the others in the single
+//! gateway event loop.
+//!
+//! When packets arrive faster than the gateway can process them the queued
+//! packets across all forwarders pass a threshold and the gateway reports a
+//! saturated state. Under saturation non-join uplinks can optionally be
+//! dropped first, to preserve join latency during overload.
+//!
+//! A forwarder that neither polls for downlinks nor sends packets or stats
+//! for longer than the configured silence period is reported as silent by
+//! the forwarder watchdog, since a hung packet forwarder often keeps its
+//! process alive without any traffic.
+//!
+//! Gateways with several concentrators run a packet forwarder per
+//! concentrator. Each concentrator has its own timestamp counter, so a
+//! downlink scheduled relative to an uplink has to be sent to the forwarder
+//! that received that uplink.
+//!
+//! Some forwarders send rxpk batches larger than a UDP datagram, which arrive
+//! truncated, or batches with a single malformed entry. Instead of dropping
+//! such a datagram wholesale the valid packets in it are salvaged, and the
+//! failed datagrams and the packets lost in them are counted per client.
+//!
+//! Copies of an uplink heard by several concentrators are dropped by the
+//! uplink deduplication of the gateway and counted per client as duplicates.
+//!
+//! The latest stat message of each client is kept, so the radio counters and
+//! concentrator temperature the forwarder reports can be inspected remotely.
+
+use crate::{
+    settings::{BackpressureSettings, DownlinkRouting},
+    PacketUp,
+};
+use semtech_udp::{
+    push_data::{RxPk, Stat},
+    MacAddress,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, warn};
+
+/// Maximum number of packets queued per forwarder client. The oldest packet
+/// is dropped when a full queue receives a new packet.
+const CLIENT_QUEUE_SIZE: usize = 64;
+/// Period over which the packet rate of a client is measured
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+/// Number of recent uplinks remembered for routing downlinks
+const RECENT_UPLINKS: usize = 128;
+/// Longest delay in microseconds between an uplink and a downlink scheduled
+/// relative to it. This covers the longest LoRaWAN receive delay of 15s plus
+/// the second receive window.
+const MAX_DOWNLINK_DELAY_US: u32 = 16_000_000;
+/// Length of the header of a semtech udp datagram up to and including the
+/// MAC of a PUSH_DATA datagram
+const PUSH_DATA_HEADER_LEN: usize = 12;
+/// Identifier of a semtech udp PUSH_DATA datagram
+const PUSH_DATA_ID: u8 = 0x00;
+
+/// Packet counts of a packet forwarder client
+#[derive(Debug, Clone, Serialize)]
+pub struct ForwarderClient {
+    /// The MAC address of the forwarder
+    pub mac: String,
+    /// Whether the forwarder is connected
+    pub connected: bool,
+    /// Number of packets received from the forwarder
+    pub received: u64,
+    /// Number of received packets that were processed
+    pub processed: u64,
+    /// Number of received packets dropped because the queue of the forwarder
+    /// was full, or because the gateway was saturated
+    pub dropped: u64,
+    /// Number of packets currently queued
+    pub queued: usize,
+    /// Packets received in the last full minute
+    pub rate: u64,
+    /// Seconds since the last poll, packet or stat from the forwarder
+    pub last_seen: u64,
+    /// Whether the forwarder was silent for longer than the watchdog silence
+    /// period
+    pub silent: bool,
+    /// Number of datagrams from the forwarder that failed to parse. Valid
+    /// packets in these datagrams are still processed.
+    pub bad_datagrams: u64,
+    /// Number of invalid or truncated packets in datagrams that failed to
+    /// parse
+    pub bad_packets: u64,
+    /// Number of processed packets dropped as copies of an uplink received
+    /// with a better signal by another forwarder
+    pub duplicates: u64,
+}
+
+/// The latest stat message of a packet forwarder client. The packet counts
+/// are those of the stat interval of the forwarder, 30 seconds by default.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ForwarderStat {
+    /// The MAC address of the forwarder
+    pub mac: String,
+    /// Unix time in seconds the stat was received
+    pub timestamp: u64,
+    /// Number of radio packets received
+    pub rxnb: u64,
+    /// Number of radio packets received with a valid CRC
+    pub rxok: u64,
+    /// Number of radio packets forwarded
+    pub rxfw: u64,
+    /// Percentage of upstream datagrams that were acknowledged, if reported
+    pub ackr: Option<f64>,
+    /// Number of downlink datagrams received
+    pub dwnb: u64,
+    /// Number of packets transmitted
+    pub txnb: u64,
+    /// Concentrator temperature in degrees Celsius, if reported
+    pub temp: Option<f64>,
+}
+
+impl ForwarderStat {
+    pub fn new(mac: MacAddress, stat: &Stat, timestamp: u64) -> Self {
+        Self {
+            mac: mac.to_string(),
+            timestamp,
+            rxnb: stat.rxnb,
+            rxok: stat.rxok,
+            rxfw: stat.rxfw,
+            ackr: stat.ackr,
+            dwnb: stat.dwnb,
+            txnb: stat.txnb,
+            temp: stat.temp,
+        }
+    }
+}
+
+/// Queue status of all packet forwarder clients
+#[derive(Debug, Clone, Serialize)]
+pub struct ForwardersStatus {
+    /// Whether the packets queued across all forwarders passed the
+    /// backpressure threshold
+    pub saturated: bool,
+    /// Number of packets queued across all forwarders
+    pub queued: usize,
+    /// Whether any forwarder is silent
+    pub silent: bool,
+    pub clients: Vec<ForwarderClient>,
+}
+
+#[derive(Debug)]
+struct Client {
+    connected: bool,
+    /// Index of the udp listener the forwarder connected through
+    listener: usize,
+    queue: VecDeque<(RxPk, Instant)>,
+    received: u64,
+    processed: u64,
+    dropped: u64,
+    window_start: Instant,
+    window_received: u64,
+    rate: u64,
+    last_seen: Instant,
+    silent: bool,
+    bad_datagrams: u64,
+    bad_packets: u64,
+    duplicates: u64,
+    stat: Option<ForwarderStat>,
+}
+
+impl Client {
+    fn new() -> Self {
+        Self {
+            connected: false,
+            listener: 0,
+            queue: VecDeque::with_capacity(CLIENT_QUEUE_SIZE),
+            received: 0,
+            processed: 0,
+            dropped: 0,
+            window_start: Instant::now(),
+            window_received: 0,
+            rate: 0,
+            last_seen: Instant::now(),
+            silent: false,
+            bad_datagrams: 0,
+            bad_packets: 0,
+            duplicates: 0,
+            stat: None,
+        }
+    }
+
+    fn update_rate(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed < RATE_WINDOW {
+            return;
+        }
+        // A client that was silent for more than a full window has a rate of 0
+        self.rate = if elapsed < 2 * RATE_WINDOW {
+            self.window_received
+        } else {
+            0
+        };
+        self.window_received = 0;
+        self.window_start = now;
+    }
+}
+
+#[derive(Debug)]
+pub struct Forwarders {
+    /// Queued packets across all clients at which the gateway is saturated
+    saturation_threshold: usize,
+    /// Whether non-join uplinks are dropped while saturated
+    drop_non_join: bool,
+    /// Number of packets queued across all clients
+    queued: usize,
+    saturated: bool,
+    clients: HashMap<MacAddress, Client>,
+    /// Clients with queued packets in the order they are served
+    ready: VecDeque<MacAddress>,
+    /// The most recently connected client, used for downlinks that can not be
+    /// routed to the client that received the triggering uplink, and for
+    /// beacons and pings
+    default_mac: Option<MacAddress>,
+    /// Recent uplink timestamps by client, newest last
+    recent_uplinks: VecDeque<(MacAddress, u32)>,
+}
+
+impl Forwarders {
+    pub fn new(settings: &BackpressureSettings) -> Self {
+        Self {
+            saturation_threshold: settings.threshold,
+            drop_non_join: settings.drop_non_join,
+            queued: 0,
+            saturated: false,
+            clients: HashMap::new(),
+            ready: VecDeque::new(),
+            default_mac: None,
+            recent_uplinks: VecDeque::new(),
+        }
+    }
+
+    /// Marks the given forwarder as connected through the udp listener with
+    /// the given index. The most recently connected forwarder becomes the
+    /// default downlink forwarder.
+    pub fn connected(&mut self, mac: MacAddress, listener: usize) {
+        let client = self.clients.entry(mac).or_insert_with(Client::new);
+        client.connected = true;
+        client.listener = listener;
+        client.last_seen = Instant::now();
+        self.default_mac = Some(mac);
+    }
+
+    /// Records other activity of the given forwarder, like a stat report
+    pub fn seen(&mut self, mac: MacAddress) {
+        self.clients
+            .entry(mac)
+            .or_insert_with(Client::new)
+            .last_seen = Instant::now();
+    }
+
+    /// Keeps the given stat message as the latest of the given forwarder
+    pub fn stat(&mut self, mac: MacAddress, stat: &Stat, timestamp: u64) {
+        let client = self.clients.entry(mac).or_insert_with(Client::new);
+        client.last_seen = Instant::now();
+        client.stat = Some(ForwarderStat::new(mac, stat, timestamp));
+    }
+
+    /// Returns the latest stat message of each forwarder that sent one
+    pub fn stats(&self) -> Vec<ForwarderStat> {
+        let mut stats: Vec<ForwarderStat> = self
+            .clients
+            .values()
+            .filter_map(|client| client.stat.clone())
+            .collect();
+        stats.sort_unstable_by(|a, b| a.mac.cmp(&b.mac));
+        stats
+    }
+
+    /// Updates which forwarders were silent for longer than the given period
+    /// and returns whether any is silent, or None if no forwarder was seen
+    /// yet.
+    pub fn check_silence(&mut self, now: Instant, silence: Duration) -> Option<bool> {
+        for (mac, client) in self.clients.iter_mut() {
+            let quiet = now.saturating_duration_since(client.last_seen);
+            let silent = quiet > silence;
+            if silent && !client.silent {
+                warn!(%mac, quiet = quiet.as_secs(), "packet forwarder silent");
+            } else if !silent && client.silent {
+                info!(%mac, "packet forwarder active again");
+            }
+            client.silent = silent;
+        }
+        (!self.clients.is_empty()).then(|| self.is_silent())
+    }
+
+    fn is_silent(&self) -> bool {
+        self.clients.values().any(|client| client.silent)
+    }
+
+    /// Marks the given forwarder as disconnected. If it was the default
+    /// downlink forwarder another connected forwarder takes over.
+    pub fn disconnected(&mut self, mac: MacAddress) {
+        if let Some(client) = self.clients.get_mut(&mac) {
+            client.connected = false;
+        }
+        if self.default_mac == Some(mac) {
+            self.default_mac = self
+                .clients
+                .iter()
+                .find(|(_, client)| client.connected)
+                .map(|(mac, _)| *mac);
+        }
+    }
+
+    /// Marks all forwarders connected through the udp listener with the given
+    /// index as disconnected, for example when the listener is rebuilt
+    pub fn disconnect_listener(&mut self, listener: usize) {
+        let macs: Vec<MacAddress> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| client.connected && client.listener == listener)
+            .map(|(mac, _)| *mac)
+            .collect();
+        for mac in macs {
+            self.disconnected(mac);
+        }
+    }
+
+    /// The index of the udp listener the given forwarder connected through
+    pub fn listener(&self, mac: MacAddress) -> usize {
+        self.clients
+            .get(&mac)
+            .map(|client| client.listener)
+            .unwrap_or_default()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.clients.values().any(|client| client.connected)
+    }
+
+    /// The number of connected forwarders
+    pub fn connected_count(&self) -> usize {
+        self.clients
+            .values()
+            .filter(|client| client.connected)
+            .count()
+    }
+
+    /// The forwarder for beacons, pings and downlinks that can not be routed
+    /// by uplink
+    pub fn default_mac(&self) -> MacAddress {
+        self.default_mac.unwrap_or_default()
+    }
+
+    /// Returns the forwarder to send a downlink with the given concentrator
+    /// timestamp to. With uplink routing this is the connected forwarder that
+    /// most recently received an uplink the downlink timestamp can be relative
+    /// to.
+    pub fn downlink_mac(&self, timestamp: Option<u32>, routing: DownlinkRouting) -> MacAddress {
+        let routed = match (routing, timestamp) {
+            (DownlinkRouting::Uplink, Some(timestamp)) => self
+                .recent_uplinks
+                .iter()
+                .rev()
+                .find(|(mac, uplink)| {
+                    timestamp.wrapping_sub(*uplink) <= MAX_DOWNLINK_DELAY_US
+                        && self
+                            .clients
+                            .get(mac)
+                            .map(|client| client.connected)
+                            .unwrap_or(false)
+                })
+                .map(|(mac, _)| *mac),
+            _ => None,
+        };
+        routed.unwrap_or_else(|| self.default_mac())
+    }
+
+    /// Queues a packet received from the given forwarder
+    pub fn push(&mut self, mac: MacAddress, rxpk: RxPk, received: Instant) {
+        let drop_non_join = self.drop_non_join && self.saturated;
+        let client = self.clients.entry(mac).or_insert_with(Client::new);
+        client.update_rate(received);
+        client.last_seen = received;
+        client.received += 1;
+        client.window_received += 1;
+        if drop_non_join && !is_join(&rxpk) {
+            client.dropped += 1;
+            debug!(%mac, dropped = client.dropped, "saturated, dropped non-join packet");
+            return;
+        }
+        if client.queue.len() >= CLIENT_QUEUE_SIZE {
+            // Prefer dropping the oldest non-join packet when saturated
+            let index = drop_non_join
+                .then(|| client.queue.iter().position(|(rxpk, _)| !is_join(rxpk)))
+                .flatten()
+                .unwrap_or(0);
+            client.queue.remove(index);
+            client.dropped += 1;
+            self.queued -= 1;
+            warn!(%mac, dropped = client.dropped, "forwarder queue full, dropped packet");
+        }
+        client.queue.push_back((rxpk, received));
+        self.queued += 1;
+        if !self.ready.contains(&mac) {
+            self.ready.push_back(mac);
+        }
+        self.update_saturated();
+    }
+
+    /// Accounts a datagram from the given forwarder that failed to parse,
+    /// with the number of invalid or truncated packets in it
+    pub fn bad_datagram(&mut self, mac: MacAddress, bad_packets: u64) {
+        let client = self.clients.entry(mac).or_insert_with(Client::new);
+        client.bad_datagrams += 1;
+        client.bad_packets += bad_packets;
+    }
+
+    /// Accounts a packet from the given forwarder dropped as a duplicate
+    pub fn duplicate(&mut self, mac: MacAddress) {
+        if let Some(client) = self.clients.get_mut(&mac) {
+            client.duplicates += 1;
+        }
+    }
+
+    fn update_saturated(&mut self) {
+        let saturated = self.saturation_threshold > 0 && self.queued >= self.saturation_threshold;
+        if saturated == self.saturated {
+            return;
+        }
+        self.saturated = saturated;
+        if saturated {
+            warn!(queued = self.queued, "packet processing saturated");
+        } else {
+            info!(queued = self.queued, "packet processing recovered");
+        }
+    }
+
+    pub fn has_queued(&self) -> bool {
+        !self.ready.is_empty()
+    }
+
+    /// Returns the next packet of the next forwarder in round-robin order
+    pub fn pop(&mut self) -> Option<(MacAddress, RxPk, Instant)> {
+        while let Some(mac) = self.ready.pop_front() {
+            let Some(client) = self.clients.get_mut(&mac) else {
+                continue;
+            };
+            let Some((rxpk, received)) = client.queue.pop_front() else {
+                continue;
+            };
+            client.processed += 1;
+            self.queued -= 1;
+            if !client.queue.is_empty() {
+                self.ready.push_back(mac);
+            }
+            if self.recent_uplinks.len() >= RECENT_UPLINKS {
+                self.recent_uplinks.pop_front();
+            }
+            self.recent_uplinks.push_back((mac, *rxpk.get_timestamp()));
+            // Recover only once the queue drained well below the threshold to
+            // avoid flapping around it
+            if self.saturated && self.queued <= self.saturation_threshold / 2 {
+                self.update_saturated();
+            }
+            return Some((mac, rxpk, received));
+        }
+        None
+    }
+
+    pub fn status(&mut self) -> ForwardersStatus {
+        let now = Instant::now();
+        let mut clients: Vec<ForwarderClient> = self
+            .clients
+            .iter_mut()
+            .map(|(mac, client)| {
+                client.update_rate(now);
+                ForwarderClient {
+                    mac: mac.to_string(),
+                    connected: client.connected,
+                    received: client.received,
+                    processed: client.processed,
+                    dropped: client.dropped,
+                    queued: client.queue.len(),
+                    rate: client.rate,
+                    last_seen: now.saturating_duration_since(client.last_seen).as_secs(),
+                    silent: client.silent,
+                    bad_datagrams: client.bad_datagrams,
+                    bad_packets: client.bad_packets,
+                    duplicates: client.duplicates,
+                }
+            })
+            .collect();
+        clients.sort_unstable_by(|a, b| a.mac.cmp(&b.mac));
+        ForwardersStatus {
+            saturated: self.saturated,
+            queued: self.queued,
+            silent: self.is_silent(),
+            clients,
+        }
+    }
+}
+
+/// The packets salvaged from a PUSH_DATA datagram that failed to parse
+#[derive(Debug)]
+pub struct SalvagedDatagram {
+    pub mac: MacAddress,
+    pub packets: Vec<RxPk>,
+    /// Number of invalid or truncated packets in the datagram
+    pub bad_packets: u64,
+}
+
+/// Salvages the valid packets of a PUSH_DATA datagram that failed to parse,
+/// or returns None if the datagram is not a PUSH_DATA datagram
+pub fn salvage_push_data(buf: &[u8]) -> Option<SalvagedDatagram> {
+    if buf.len() < PUSH_DATA_HEADER_LEN || buf[3] != PUSH_DATA_ID {
+        return None;
+    }
+    let mac: [u8; 8] = buf[4..PUSH_DATA_HEADER_LEN].try_into().ok()?;
+    let (packets, bad_packets) = salvage_rxpk(&buf[PUSH_DATA_HEADER_LEN..]);
+    Some(SalvagedDatagram {
+        mac: MacAddress::from(mac),
+        packets,
+        bad_packets,
+    })
+}
+
+/// Parses the entries of the rxpk array of a possibly truncated or otherwise
+/// invalid PUSH_DATA json object one at a time. Returns the valid entries
+/// and the number of invalid ones, including a truncated last entry.
+fn salvage_rxpk<T: DeserializeOwned>(json: &[u8]) -> (Vec<T>, u64) {
+    let mut packets = vec![];
+    let mut bad_packets = 0;
+    let Some(mut pos) = rxpk_array_start(json) else {
+        return (packets, bad_packets);
+    };
+    loop {
+        while json
+            .get(pos)
+            .is_some_and(|b| b.is_ascii_whitespace() || *b == b',')
+        {
+            pos += 1;
+        }
+        if matches!(json.get(pos), None | Some(b']')) {
+            break;
+        }
+        let mut entries =
+            serde_json::Deserializer::from_slice(&json[pos..]).into_iter::<serde_json::Value>();
+        match entries.next() {
+            Some(Ok(entry)) => {
+                pos += entries.byte_offset();
+                match serde_json::from_value(entry) {
+                    Ok(packet) => packets.push(packet),
+                    Err(_) => bad_packets += 1,
+                }
+            }
+            // A truncated entry ends the datagram, any other syntax error
+            // leaves no way to find the next entry
+            _ => {
+                bad_packets += 1;
+                break;
+            }
+        }
+    }
+    (packets, bad_packets)
+}
+
+/// The position right after the opening bracket of the rxpk array
+fn rxpk_array_start(json: &[u8]) -> Option<usize> {
+    const KEY: &[u8] = b"\"rxpk\"";
+    let mut pos = json.windows(KEY.len()).position(|window| window == KEY)? + KEY.len();
+    for expected in [b':', b'['] {
+        while json.get(pos)?.is_ascii_whitespace() {
+            pos += 1;
+        }
+        if json[pos] != expected {
+            return None;
+        }
+        pos += 1;
+    }
+    Some(pos)
+}
+
+/// Whether the packet is a join request
+fn is_join(rxpk: &RxPk) -> bool {
+    PacketUp::parse_header(rxpk.get_data())
+        .map(|header| header.mtype() == lorawan::MType::JoinRequest)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn downlink_routing() {
+        let mac_a = MacAddress::from([1, 0, 0, 0, 0, 0, 0, 1]);
+        let mac_b = MacAddress::from([2, 0, 0, 0, 0, 0, 0, 2]);
+        let mut forwarders = Forwarders::new(&BackpressureSettings::default());
+        forwarders.connected(mac_a, 0);
+        forwarders.connected(mac_b, 1);
+        forwarders.recent_uplinks.push_back((mac_a, 50_000_000));
+        forwarders
+            .recent_uplinks
+            .push_back((mac_b, u32::MAX - 500_000));
+
+        // Downlinks go to the forwarder that received the uplink, including
+        // across a wrap of the concentrator timestamp
+        let routing = DownlinkRouting::Uplink;
+        assert_eq!(mac_a, forwarders.downlink_mac(Some(51_000_000), routing));
+        assert_eq!(mac_b, forwarders.downlink_mac(Some(500_000), routing));
+        // Unmatched and immediate downlinks go to the latest forwarder
+        assert_eq!(mac_b, forwarders.downlink_mac(Some(100_000_000), routing));
+        assert_eq!(mac_b, forwarders.downlink_mac(None, routing));
+        assert_eq!(
+            mac_b,
+            forwarders.downlink_mac(Some(51_000_000), DownlinkRouting::Latest)
+        );
+
+        // Downlinks are sent through the listener the forwarder connected on
+        assert_eq!(0, forwarders.listener(mac_a));
+        assert_eq!(1, forwarders.listener(mac_b));
+
+        // Disconnected forwarders are skipped
+        forwarders.disconnect_listener(1);
+        assert_eq!(mac_a, forwarders.downlink_mac(Some(500_000), routing));
+        assert_eq!(mac_a, forwarders.default_mac());
+        assert!(forwarders.is_connected());
+        forwarders.disconnected(mac_a);
+        assert!(!forwarders.is_connected());
+    }
+
+    #[test]
+    fn silence_watchdog() {
+        let mac_a = MacAddress::from([1, 0, 0, 0, 0, 0, 0, 1]);
+        let mac_b = MacAddress::from([2, 0, 0, 0, 0, 0, 0, 2]);
+        let silence = Duration::from_secs(60);
+        let mut forwarders = Forwarders::new(&BackpressureSettings::default());
+        assert_eq!(None, forwarders.check_silence(Instant::now(), silence));
+
+        forwarders.connected(mac_a, 0);
+        forwarders.connected(mac_b, 0);
+        let now = Instant::now();
+        assert_eq!(Some(false), forwarders.check_silence(now, silence));
+
+        // Only the forwarder without activity goes silent
+        forwarders.seen(mac_b);
+        forwarders.clients.get_mut(&mac_a).unwrap().last_seen = now - 2 * silence;
+        assert_eq!(Some(true), forwarders.check_silence(now, silence));
+        let status = forwarders.status();
+        assert!(status.silent);
+        assert_eq!(
+            vec![true, false],
+            status
+                .clients
+                .iter()
+                .map(|client| client.silent)
+                .collect::<Vec<_>>()
+        );
+
+        // Any activity ends the silence
+        forwarders.seen(mac_a);
+        assert_eq!(Some(false), forwarders.check_silence(now, silence));
+    }
+
+    #[test]
+    fn salvage_datagram() {
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Entry {
+            tmst: u32,
+        }
+        let salvage = |json: &str| {
+            let (entries, bad_packets) = salvage_rxpk::<Entry>(json.as_bytes());
+            let tmsts: Vec<u32> = entries.into_iter().map(|entry| entry.tmst).collect();
+            (tmsts, bad_packets)
+        };
+
+        // Valid entries around an invalid one are kept
+        assert_eq!(
+            (vec![1, 3], 1),
+            salvage(r#"{"rxpk": [{"tmst": 1}, {"tmst": "bad"}, {"tmst": 3}]}"#)
+        );
+        // A datagram truncated in the middle of an entry
+        assert_eq!(
+            (vec![1, 2], 1),
+            salvage(r#"{"rxpk":[{"tmst":1},{"tmst":2},{"tmst":3,"da"#)
+        );
+        // A datagram truncated between entries
+        assert_eq!((vec![1], 0), salvage(r#"{"rxpk":[{"tmst":1},"#));
+        assert_eq!((vec![], 0), salvage(r#"{"stat":{"rxnb":1"#));
+
+        // Only PUSH_DATA datagrams are salvaged
+        let mut buf = vec![2, 0, 1, PUSH_DATA_ID, 1, 2, 3, 4, 5, 6, 7, 8];
+        buf.extend_from_slice(br#"{"rxpk":[{"tmst":1"#);
+        let salvaged = salvage_push_data(&buf).expect("salvaged datagram");
+        assert_eq!(MacAddress::from([1, 2, 3, 4, 5, 6, 7, 8]), salvaged.mac);
+        assert_eq!((0, 1), (salvaged.packets.len(), salvaged.bad_packets));
+        buf[3] = 0x02;
+        assert!(salvage_push_data(&buf).is_none());
+        assert!(salvage_push_data(&buf[..8]).is_none());
+    }
+}
@@ -1,20 +1,55 @@
+#[cfg(feature = "mqtt")]
+use crate::mqtt;
 use crate::{
-    beaconer, packet, packet_router, region_watcher, sync, DecodeError, Error, PacketDown,
-    PacketUp, PublicKey, RegionParams, Result, Settings,
+    beaconer,
+    crc_stats::{CrcStats, CrcStatus},
+    dc_stats::{DcStats, DcStatus},
+    downlink_arbiter::{DownlinkArbiter, DownlinkPriority},
+    downlink_stats::{DownlinkCounters, DownlinkPeriod, DownlinkStatus},
+    duty_cycle::{DutyCycle, DutyCycleStatus},
+    forwarders::{self, ForwarderStat, Forwarders, ForwardersStatus},
+    gps::{self, GpsFix, GpsState},
+    gps_region,
+    hooks::StateHook,
+    join_vendors::JoinVendors,
+    packet::{self, TxIntent, TxPkBuilder},
+    packet_broker,
+    packet_router::{self, DownlinkAck},
+    packet_trace::{PacketDecision, PacketEvent, PacketTrace},
+    ping::{PingFrame, PingId, ReceivedPing, SentPing},
+    region_watcher,
+    settings::{DownlinkRouting, HookState, Rx2Settings, TxPowerLimit},
+    sync,
+    udp_listener::UdpListener,
+    uplink_dedup::UplinkDedup,
+    uplink_filter::{FilterStatus, FportFilters, UplinkFilter},
+    DecodeError, Error, PacketDown, PacketUp, PublicKey, Region, RegionParams, Result, Settings,
 };
-use beacon::Beacon;
+use beacon::{Beacon, Entropy};
+use helium_proto::services::router::WindowV1;
 use lorawan::PHYPayload;
 use semtech_udp::{
-    pull_resp::{self, Time},
-    server_runtime::{Error as SemtechError, Event, UdpRuntime},
+    pull_resp,
+    push_data::{RxPk, CRC},
+    server_runtime::{Downlink, Error as SemtechError, Event, UdpRuntime},
     tx_ack,
     tx_ack::Error as TxAckErr,
-    CodingRate, MacAddress, Modulation,
+    MacAddress,
 };
-use std::time::{Duration, Instant};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast;
 use tracing::{debug, info, warn};
 
 pub const DOWNLINK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Number of most recently received pings to keep
+const PING_HISTORY: usize = 20;
+/// Period over which downlink delivery integrity is rolled up
+const DOWNLINK_STATS_PERIOD: Duration = Duration::from_secs(3600);
+/// Period at which the forwarder watchdog checks for silent forwarders
+const FORWARDER_WATCHDOG_PERIOD: Duration = Duration::from_secs(5);
 
 #[derive(Debug)]
 pub struct BeaconResp {
@@ -24,8 +59,27 @@ pub struct BeaconResp {
 
 #[derive(Debug)]
 pub enum Message {
-    Downlink(PacketDown),
+    /// A downlink and the router to report its outcome to
+    Downlink(PacketDown, Option<packet_router::MessageSender>),
     TransmitBeacon(Beacon, sync::ResponseSender<Result<BeaconResp>>),
+    TransmitPing(PublicKey, sync::ResponseSender<Result<SentPing>>),
+    ReceivedPings(sync::ResponseSender<Vec<ReceivedPing>>),
+    DownlinkStatus(sync::ResponseSender<DownlinkStatus>),
+    DcStatus(sync::ResponseSender<DcStatus>),
+    CrcStatus(sync::ResponseSender<CrcStatus>),
+    DutyCycle(sync::ResponseSender<DutyCycleStatus>),
+    Forwarders(sync::ResponseSender<ForwardersStatus>),
+    ForwarderStats(sync::ResponseSender<Vec<ForwarderStat>>),
+    FilterStatus(sync::ResponseSender<FilterStatus>),
+    SetListen {
+        listen: Vec<String>,
+        interface: Option<String>,
+    },
+    PacketEvents(sync::ResponseSender<broadcast::Receiver<PacketEvent>>),
+    GpsFix(GpsFix),
+    Gps(sync::ResponseSender<Option<GpsFix>>),
+    /// A synthetic packet of a simulated packet forwarder
+    Simulated(MacAddress, RxPk),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -34,6 +88,14 @@ pub enum GatewayError {
     NoBeaconTxPower,
     #[error("beacon transmit failed")]
     BeaconTxFailure,
+    #[error("pings are disabled")]
+    PingDisabled,
+    #[error("ping transmit failed")]
+    PingTxFailure,
+    #[error("duty cycle limit exceeded")]
+    DutyCycleExceeded,
+    #[error("no bound udp listener")]
+    NoUdpListener,
 }
 
 pub type MessageSender = sync::MessageSender<Message>;
@@ -45,7 +107,13 @@ pub fn message_channel() -> (MessageSender, MessageReceiver) {
 
 impl MessageSender {
     pub async fn downlink(&self, packet: PacketDown) {
-        self.send(Message::Downlink(packet)).await
+        self.send(Message::Downlink(packet, None)).await
+    }
+
+    /// Queues a downlink of a router, which is told the outcome of the
+    /// transmission
+    pub async fn router_downlink(&self, packet: PacketDown, router: packet_router::MessageSender) {
+        self.send(Message::Downlink(packet, Some(router))).await
     }
 
     /// Send a non-inverted (`ipol = false`) beacon packet that is receivable by
@@ -57,18 +125,172 @@ impl MessageSender {
         self.request(move |tx| Message::TransmitBeacon(beacon, tx))
             .await?
     }
+
+    /// Send a ping frame addressed to the gateway with the given public key.
+    /// Like beacons, ping frames are sent as non-inverted proprietary frames
+    /// so they are receivable by other gateways.
+    pub async fn transmit_ping(&self, target: PublicKey) -> Result<SentPing> {
+        self.request(move |tx| Message::TransmitPing(target, tx))
+            .await?
+    }
+
+    /// Returns the most recently received pings addressed to this gateway
+    pub async fn received_pings(&self) -> Result<Vec<ReceivedPing>> {
+        self.request(Message::ReceivedPings).await
+    }
+
+    /// Returns the number of downlinks accepted from the packet router and
+    /// how many of those were transmitted by the packet forwarder
+    pub async fn downlink_status(&self) -> Result<DownlinkStatus> {
+        self.request(Message::DownlinkStatus).await
+    }
+
+    /// Returns the estimated data credit cost of the uplinks forwarded since
+    /// startup
+    pub async fn dc_status(&self) -> Result<DcStatus> {
+        self.request(Message::DcStatus).await
+    }
+
+    /// Returns the payload CRC results per channel of the packets received
+    /// since startup
+    pub async fn crc_status(&self) -> Result<CrcStatus> {
+        self.request(Message::CrcStatus).await
+    }
+
+    /// Returns the transmit utilization per channel and duty cycle limited
+    /// sub-band
+    pub async fn duty_cycle(&self) -> Result<DutyCycleStatus> {
+        self.request(Message::DutyCycle).await
+    }
+
+    /// Returns the packet counts of the connected packet forwarders
+    pub async fn forwarders(&self) -> Result<ForwardersStatus> {
+        self.request(Message::Forwarders).await
+    }
+
+    /// Returns the latest stat message of each packet forwarder
+    pub async fn forwarder_stats(&self) -> Result<Vec<ForwarderStat>> {
+        self.request(Message::ForwarderStats).await
+    }
+
+    /// Returns the number of uplinks dropped by the uplink and fport filters
+    /// since startup
+    pub async fn filter_status(&self) -> Result<FilterStatus> {
+        self.request(Message::FilterStatus).await
+    }
+
+    /// Reports a fix read from gpsd
+    pub async fn gps_fix(&self, fix: GpsFix) {
+        self.send(Message::GpsFix(fix)).await
+    }
+
+    /// Injects a synthetic packet as received from the given packet forwarder
+    pub async fn simulated(&self, mac: MacAddress, rxpk: RxPk) {
+        self.send(Message::Simulated(mac, rxpk)).await
+    }
+
+    /// Returns the current GPS fix, if any
+    pub async fn gps(&self) -> Result<Option<GpsFix>> {
+        self.request(Message::Gps).await
+    }
+
+    /// Subscribes to the live trace of the uplinks and downlinks handled by
+    /// the gateway
+    pub async fn packet_events(&self) -> Result<broadcast::Receiver<PacketEvent>> {
+        self.request(Message::PacketEvents).await
+    }
+
+    /// Changes the packet forwarder listen address and interface, which binds
+    /// the udp listener again
+    pub async fn set_listen(&self, listen: Vec<String>, interface: Option<String>) {
+        self.send(Message::SetListen { listen, interface }).await
+    }
+}
+
+/// Receives the next event of any of the given udp listeners, with the index
+/// of the listener it was received on
+async fn recv_udp(udp: &mut [UdpListener]) -> (usize, Event) {
+    // A single listener, the common setup, is received from without boxing
+    // its future for every packet
+    if let [udp] = udp {
+        return (0, udp.recv().await);
+    }
+    let (event, index, _) =
+        futures::future::select_all(udp.iter_mut().map(|udp| Box::pin(udp.recv()))).await;
+    (index, event)
 }
 
 pub struct Gateway {
     public_key: PublicKey,
     messages: MessageReceiver,
-    uplinks: packet_router::MessageSender,
+    /// Packet routers to deliver uplinks to
+    uplinks: Vec<packet_router::UplinkRoute>,
+    /// MQTT bridge to also publish uplinks to, if enabled
+    #[cfg(feature = "mqtt")]
+    mqtt: Option<mqtt::MessageSender>,
+    /// Packet Broker to also publish uplinks to, if peering is enabled
+    packet_broker: Option<packet_broker::MessageSender>,
+    /// Region detection to report forwarder GPS positions to, if enabled
+    gps_positions: Option<gps_region::MessageSender>,
+    /// GPS fixes of the forwarders and gpsd
+    gps: GpsState,
     beacons: beaconer::MessageSender,
-    downlink_mac: MacAddress,
-    udp_runtime: UdpRuntime,
-    listen_address: String,
+    /// A udp listener for each configured listen address, never empty
+    udp: Vec<UdpListener>,
+    /// Network interface the udp listeners are bound to
+    listen_interface: Option<String>,
+    /// Time without packet forwarder traffic after which the socket of a udp
+    /// listener is checked and rebuilt if dead, or None to never check
+    udp_idle_timeout: Option<Duration>,
+    /// DSCP value to mark packet forwarder traffic with
+    radio_dscp: u8,
     region_watch: region_watcher::MessageReceiver,
     region_params: RegionParams,
+    /// Beacon transmit power to use instead of the regional maximum
+    beacon_tx_power_override: Option<u32>,
+    /// Maximum EIRP per frequency range
+    tx_power_limits: Vec<TxPowerLimit>,
+    /// Whether pings can be sent and received
+    ping_enabled: bool,
+    /// Sequence number of the last transmitted ping
+    ping_seq: u32,
+    /// Most recently received pings addressed to this gateway
+    received_pings: VecDeque<ReceivedPing>,
+    /// Permitted uplink frame ports per device address subnet
+    fport_filters: FportFilters,
+    /// Allowlist of the networks whose uplinks are forwarded
+    uplink_filter: UplinkFilter,
+    /// Downlinks accepted and transmitted in the current period
+    downlinks: DownlinkCounters,
+    /// Downlink counts of the last full period
+    last_downlinks: Option<DownlinkPeriod>,
+    /// Estimated data credit cost of forwarded uplinks
+    dc_stats: DcStats,
+    /// Device vendor lookup of join requests, if enabled
+    join_vendors: Option<JoinVendors>,
+    /// Payload CRC results per channel of received packets
+    crc_stats: CrcStats,
+    /// Hooks run when a packet forwarder connects or disconnects
+    forwarder_hook: StateHook,
+    /// Silence after which a packet forwarder is reported silent, if the
+    /// watchdog is enabled
+    forwarder_silence: Option<Duration>,
+    /// Hooks run when a packet forwarder goes silent or becomes active again
+    forwarder_activity_hook: StateHook,
+    /// Packets received per packet forwarder, waiting to be processed
+    forwarders: Forwarders,
+    /// Policy for the forwarder downlinks are sent to
+    downlink_routing: DownlinkRouting,
+    /// The rx2 window for downlinks the router sent without one, if any
+    default_rx2: Option<Rx2Settings>,
+    /// Live trace of handled uplinks and downlinks
+    packet_trace: PacketTrace,
+    /// Downlinks waiting to be sent and the windows scheduled per forwarder
+    downlink_arbiter: DownlinkArbiter,
+    /// Transmit airtime per channel for the duty cycle limits of the region
+    duty_cycle: DutyCycle,
+    /// Uplinks held for copies from other forwarders
+    uplink_dedup: UplinkDedup,
 }
 
 impl Gateway {
@@ -76,35 +298,139 @@ impl Gateway {
         settings: &Settings,
         messages: MessageReceiver,
         region_watch: region_watcher::MessageReceiver,
-        uplinks: packet_router::MessageSender,
+        uplinks: Vec<packet_router::UplinkRoute>,
+        gps_positions: Option<gps_region::MessageSender>,
         beacons: beaconer::MessageSender,
     ) -> Result<Self> {
         let region_params = region_watcher::current_value(&region_watch);
+        let duty_cycle = DutyCycle::new(&settings.duty_cycle, region_params.region);
         let public_key = settings.keypair.public_key().clone();
+        let listen_interface = settings.listen_interface.clone();
+        let mut udp = Vec::with_capacity(settings.listen.len());
+        for listen in &settings.listen {
+            udp.push(
+                UdpListener::bind(
+                    listen.clone(),
+                    listen_interface.as_deref(),
+                    settings.network.radio_dscp,
+                )
+                .await?,
+            );
+        }
         let gateway = Gateway {
             public_key,
             messages,
             uplinks,
+            #[cfg(feature = "mqtt")]
+            mqtt: None,
+            packet_broker: None,
+            gps_positions,
+            gps: GpsState::default(),
             beacons,
-            downlink_mac: Default::default(),
-            listen_address: settings.listen.clone(),
-            udp_runtime: UdpRuntime::new(&settings.listen).await.map_err(Box::new)?,
+            udp,
+            listen_interface,
+            udp_idle_timeout: (settings.listen_idle_timeout > 0)
+                .then(|| Duration::from_secs(settings.listen_idle_timeout)),
+            radio_dscp: settings.network.radio_dscp,
             region_watch,
             region_params,
+            beacon_tx_power_override: settings.poc.tx_power_override,
+            tx_power_limits: settings.tx_power_limits.clone(),
+            ping_enabled: settings.ping.enabled,
+            ping_seq: 0,
+            received_pings: VecDeque::with_capacity(PING_HISTORY),
+            fport_filters: FportFilters::new(&settings.fport_filters),
+            uplink_filter: UplinkFilter::new(&settings.uplink_filter),
+            downlinks: DownlinkCounters::default(),
+            last_downlinks: None,
+            dc_stats: DcStats::default(),
+            join_vendors: JoinVendors::new(&settings.join_vendors)?,
+            crc_stats: CrcStats::default(),
+            forwarder_hook: StateHook::new(settings, HookState::Forwarder),
+            forwarder_silence: (settings.forwarder_watchdog.silence > 0)
+                .then(|| Duration::from_secs(settings.forwarder_watchdog.silence)),
+            forwarder_activity_hook: StateHook::new(settings, HookState::ForwarderActivity),
+            forwarders: Forwarders::new(&settings.backpressure),
+            downlink_routing: settings.downlink_routing,
+            default_rx2: settings.rx2,
+            packet_trace: PacketTrace::default(),
+            downlink_arbiter: DownlinkArbiter::default(),
+            duty_cycle,
+            uplink_dedup: UplinkDedup::new(Duration::from_millis(settings.uplink_dedup.window)),
         };
         Ok(gateway)
     }
 
+    /// Publishes forwarded uplinks to Packet Broker as well
+    pub fn with_packet_broker(
+        mut self,
+        packet_broker: Option<packet_broker::MessageSender>,
+    ) -> Self {
+        self.packet_broker = packet_broker;
+        self
+    }
+
+    /// Publishes forwarded uplinks to the MQTT bridge as well
+    #[cfg(feature = "mqtt")]
+    pub fn with_mqtt(mut self, mqtt: Option<mqtt::MessageSender>) -> Self {
+        self.mqtt = mqtt;
+        self
+    }
+
     pub async fn run(&mut self, shutdown: &triggered::Listener) -> Result {
-        info!(listen = &self.listen_address, "starting");
+        info!(
+            listen = ?self.listen_addresses(),
+            interface = ?self.listen_interface,
+            "starting"
+        );
+        for index in 0..self.udp.len() {
+            self.reset_udp_idle(index);
+        }
+        let mut downlink_stats_timer = tokio::time::interval_at(
+            tokio::time::Instant::now() + DOWNLINK_STATS_PERIOD,
+            DOWNLINK_STATS_PERIOD,
+        );
+        let mut watchdog_timer = tokio::time::interval(FORWARDER_WATCHDOG_PERIOD);
         loop {
+            let udp_check_at = self.next_udp_check();
+            // Failed rebuilds are retried even without idle checks
+            let checks_udp =
+                self.udp_idle_timeout.is_some() || self.udp.iter().any(UdpListener::rebuild_failed);
+            let uplink_release_at = self.uplink_dedup.next_release();
+            let holds_uplinks = uplink_release_at.is_some();
+            let uplink_release_at = uplink_release_at.unwrap_or_else(tokio::time::Instant::now);
             tokio::select! {
                 _ = shutdown.clone() => {
                     info!( "shutting down");
                     return Ok(())
                 },
-                event = self.udp_runtime.recv() =>
-                    self.handle_udp_event(event).await?,
+                (index, event) = recv_udp(&mut self.udp) => {
+                    self.reset_udp_idle(index);
+                    self.handle_udp_event(index, event).await?
+                },
+                _ = tokio::time::sleep_until(udp_check_at), if checks_udp => {
+                    self.check_idle_udp().await
+                },
+                _ = tokio::time::sleep_until(uplink_release_at), if holds_uplinks => {
+                    self.release_uplinks().await
+                },
+                _ = downlink_stats_timer.tick() => self.roll_downlink_stats(),
+                _ = watchdog_timer.tick(), if self.forwarder_silence.is_some() => {
+                    self.check_forwarder_silence()
+                },
+                // Received packets are processed one at a time, round-robin
+                // across forwarders, interleaved with receiving new events
+                _ = std::future::ready(()), if self.forwarders.has_queued() => {
+                    if let Some((mac, rxpk, received)) = self.forwarders.pop() {
+                        self.handle_rxpk(rxpk, mac, received).await
+                    }
+                },
+                // Queued downlinks are sent highest priority first
+                _ = std::future::ready(()), if self.downlink_arbiter.has_pending() => {
+                    if let Some((downlink, router)) = self.downlink_arbiter.pop() {
+                        self.handle_downlink(downlink, router).await
+                    }
+                },
                 message = self.messages.recv() => match message {
                     Some(message) => self.handle_message(message).await,
                     None => {
@@ -120,7 +446,9 @@ impl Gateway {
                         if self.region_params != new_region_params {
                             info!(region = RegionParams::to_string(&new_region_params), "region updated");
                         }
+                        self.duty_cycle.set_region(new_region_params.region);
                         self.region_params = new_region_params;
+                        self.check_tx_power_limits();
                     }
                     Err(_) => warn!("region watch disconnected")
                 },
@@ -128,90 +456,464 @@ impl Gateway {
         }
     }
 
-    async fn handle_udp_event(&mut self, event: Event) -> Result {
-        match event {
-            Event::UnableToParseUdpFrame(e, buf) => {
-                warn!(raw_bytes = ?buf, "ignoring semtech udp parsing error {e}");
+    fn listen_addresses(&self) -> Vec<&str> {
+        self.udp.iter().map(|udp| udp.listen.as_str()).collect()
+    }
+
+    /// The udp runtime the given forwarder connected through, or the first
+    /// runtime if that listener was removed
+    fn udp_runtime(&self, mac: MacAddress) -> Option<&UdpRuntime> {
+        self.udp
+            .get(self.forwarders.listener(mac))
+            .or_else(|| self.udp.first())
+            .map(UdpListener::runtime)
+    }
+
+    /// Closes the current downlink integrity period. Downlinks that were
+    /// accepted from the packet router but never transmitted are reported as
+    /// a warning.
+    fn roll_downlink_stats(&mut self) {
+        let period = self.downlinks.take();
+        let integrity = period.integrity();
+        if period.failed > 0 {
+            warn!(
+                accepted = period.accepted,
+                transmitted = period.transmitted,
+                failed = period.failed,
+                ?integrity,
+                "downlinks accepted but not transmitted"
+            );
+        } else {
+            info!(
+                accepted = period.accepted,
+                transmitted = period.transmitted,
+                ?integrity,
+                "downlink integrity"
+            );
+        }
+        self.last_downlinks = Some(period);
+    }
+
+    /// Reports forwarders silent for longer than the watchdog silence period
+    fn check_forwarder_silence(&mut self) {
+        let Some(silence) = self.forwarder_silence else {
+            return;
+        };
+        if let Some(silent) = self.forwarders.check_silence(Instant::now(), silence) {
+            self.forwarder_activity_hook.set(!silent);
+        }
+    }
+
+    fn reset_udp_idle(&mut self, index: usize) {
+        if let Some(timeout) = self.udp_idle_timeout {
+            self.udp[index].reset_idle(timeout);
+        }
+    }
+
+    /// The earliest time at which the socket of a udp listener is checked
+    fn next_udp_check(&self) -> tokio::time::Instant {
+        self.udp
+            .iter()
+            .map(|udp| udp.check_at)
+            .min()
+            .unwrap_or_else(tokio::time::Instant::now)
+    }
+
+    /// Checks the sockets of the udp listeners that had no packet forwarder
+    /// traffic for the idle timeout and rebuilds the dead ones
+    async fn check_idle_udp(&mut self) {
+        let now = tokio::time::Instant::now();
+        for index in 0..self.udp.len() {
+            if self.udp[index].check_at > now {
+                continue;
+            }
+            if self.udp[index].needs_rebuild() {
+                info!(
+                    listen = self.udp[index].listen,
+                    "no packet forwarder traffic on dead socket, rebuilding udp runtime"
+                );
+                self.rebuild_udp_runtime(index).await
+            } else {
+                debug!(
+                    listen = self.udp[index].listen,
+                    "no packet forwarder traffic, udp socket is alive"
+                );
+                self.reset_udp_idle(index);
+            }
+        }
+    }
+
+    /// Applies changed listen addresses from a settings reload. Listeners are
+    /// rebuilt on the address at the same position in the list, added for
+    /// new addresses and dropped for removed ones. With an unchanged listen
+    /// interface listeners whose address did not change are kept.
+    async fn set_listen(&mut self, listen: Vec<String>, interface: Option<String>) {
+        let interface_changed = interface != self.listen_interface;
+        self.listen_interface = interface;
+        for index in listen.len()..self.udp.len() {
+            self.forwarders.disconnect_listener(index);
+        }
+        self.udp.truncate(listen.len().max(1));
+        for (index, listen) in listen.into_iter().enumerate() {
+            match self.udp.get_mut(index) {
+                Some(udp) if udp.listen == listen && !interface_changed => (),
+                Some(udp) => {
+                    udp.listen = listen;
+                    self.rebuild_udp_runtime(index).await
+                }
+                None => match UdpListener::bind(
+                    listen,
+                    self.listen_interface.as_deref(),
+                    self.radio_dscp,
+                )
+                .await
+                {
+                    Ok(listener) => {
+                        info!(listen = listener.listen, "udp runtime added");
+                        self.udp.push(listener);
+                        self.reset_udp_idle(self.udp.len() - 1);
+                    }
+                    Err(err) => warn!(%err, "failed to add udp runtime"),
+                },
             }
+        }
+        self.forwarder_hook.set(self.forwarders.is_connected());
+    }
+
+    /// Binds the udp listener with the given index again, after its socket
+    /// was found dead or its listen address changed. Forwarders connected
+    /// through the listener are disconnected, and reconnect with their next
+    /// pull request. A failed bind keeps the current runtime in use and is
+    /// retried with a backoff on the next check.
+    async fn rebuild_udp_runtime(&mut self, index: usize) {
+        self.forwarders.disconnect_listener(index);
+        self.forwarder_hook.set(self.forwarders.is_connected());
+        let udp = &mut self.udp[index];
+        match udp.rebuild(self.listen_interface.as_deref()).await {
+            Ok(addr) => {
+                self.reset_udp_idle(index);
+                info!(listen = %addr, "udp runtime rebuilt");
+            }
+            Err(err) => debug!(
+                listen = udp.listen,
+                %err,
+                retry_at = ?udp.check_at,
+                "failed to rebuild udp runtime"
+            ),
+        }
+    }
+
+    /// Handles an event of the udp runtime with the given index
+    async fn handle_udp_event(&mut self, index: usize, event: Event) -> Result {
+        match event {
+            Event::UnableToParseUdpFrame(e, buf) => match forwarders::salvage_push_data(&buf) {
+                Some(salvaged) => {
+                    let mac = salvaged.mac;
+                    warn!(
+                        %mac,
+                        salvaged = salvaged.packets.len(),
+                        bad_packets = salvaged.bad_packets,
+                        "salvaging semtech udp datagram with parsing error {e}"
+                    );
+                    self.forwarders.bad_datagram(mac, salvaged.bad_packets);
+                    let received = Instant::now();
+                    for rxpk in salvaged.packets {
+                        self.forwarders.push(mac, rxpk, received);
+                    }
+                }
+                None => warn!(raw_bytes = ?buf, "ignoring semtech udp parsing error {e}"),
+            },
             Event::NewClient((mac, addr)) => {
                 info!(%mac, %addr, "new packet forwarder client");
-                self.downlink_mac = mac;
+                self.forwarders.connected(mac, index);
+                self.forwarder_hook.set(true);
             }
             Event::UpdateClient((mac, addr)) => {
-                info!(%mac, %addr, "mac existed, but IP updated")
+                info!(%mac, %addr, "mac existed, but IP updated");
+                self.forwarders.connected(mac, index);
+                self.forwarder_hook.set(true);
             }
             Event::ClientDisconnected((mac, addr)) => {
-                info!(%mac, %addr, "disconnected packet forwarder")
+                info!(%mac, %addr, "disconnected packet forwarder");
+                self.forwarders.disconnected(mac);
+                self.forwarder_hook.set(self.forwarders.is_connected());
             }
-            Event::PacketReceived(rxpk, _gateway_mac) => {
-                match PacketUp::from_rxpk(rxpk, &self.public_key, self.region_params.region) {
-                    Ok(packet) if packet.is_potential_beacon() => {
-                        self.handle_potential_beacon(packet).await;
-                    }
-                    Ok(packet) if packet.is_uplink() => {
-                        self.handle_uplink(packet, Instant::now()).await
-                    }
-                    Ok(packet) => {
-                        info!(%packet, "ignoring non-uplink packet");
-                    }
-                    Err(Error::Decode(DecodeError::CrcDisabled)) => {
-                        debug!("ignoring packet with disabled crc");
-                    }
-                    Err(Error::Decode(DecodeError::InvalidDataRate(datarate))) => {
-                        debug!(%datarate, "ignoring packet with invalid datarate");
-                    }
-                    Err(err) => {
-                        warn!(%err, "ignoring push_data");
-                    }
-                }
+            Event::PacketReceived(rxpk, mac) => {
+                self.forwarders.push(mac, rxpk, Instant::now());
             }
             Event::NoClientWithMac(_packet, mac) => {
                 info!(%mac, "ignoring send to client with unknown MAC")
             }
             Event::StatReceived(stat, mac) => {
-                debug!(%mac, ?stat, "received stat")
+                debug!(%mac, ?stat, "received stat");
+                self.forwarders.stat(mac, &stat, gps::unix_now());
+                if let Some(fix) = GpsFix::from_stat(&stat) {
+                    self.handle_gps_fix(fix);
+                }
             }
         };
         Ok(())
     }
 
-    async fn handle_potential_beacon(&mut self, packet: PacketUp) {
+    async fn handle_rxpk(&mut self, rxpk: RxPk, mac: MacAddress, received: Instant) {
+        self.downlink_arbiter
+            .sync_clock(mac, *rxpk.get_timestamp(), received);
+        match rxpk.get_crc_status() {
+            CRC::OK => self.crc_stats.record(*rxpk.get_frequency(), true),
+            CRC::Fail => self.crc_stats.record(*rxpk.get_frequency(), false),
+            CRC::Disabled => (),
+        }
+        match PacketUp::from_rxpk(rxpk, &self.public_key, self.region_params.region) {
+            // Ping frames are proprietary frames like beacons and are never
+            // handed to the beaconer, even with pings disabled
+            Ok(packet) => match PingFrame::from_packet(&packet) {
+                Some(frame) => self.handle_ping(frame, packet),
+                None => match packet.beacon_data() {
+                    Some(data) => self.handle_potential_beacon(packet, data, mac).await,
+                    None if packet.is_uplink() => self.handle_uplink(packet, received, mac).await,
+                    None => info!(%packet, "ignoring non-uplink packet"),
+                },
+            },
+            Err(Error::Decode(DecodeError::CrcDisabled)) => {
+                debug!("ignoring packet with disabled crc");
+            }
+            Err(Error::Decode(DecodeError::CrcInvalid)) => {
+                debug!(%mac, "ignoring packet with invalid crc");
+            }
+            Err(Error::Decode(DecodeError::InvalidDataRate(datarate))) => {
+                debug!(%datarate, "ignoring packet with invalid datarate");
+            }
+            Err(err) => {
+                warn!(%err, "ignoring push_data");
+            }
+        }
+    }
+
+    async fn handle_potential_beacon(&mut self, packet: PacketUp, data: Vec<u8>, mac: MacAddress) {
         if self.region_params.is_unknown() {
-            info!(downlink_mac = %self.downlink_mac, uplink = %packet, "ignored potential beacon, no region");
+            info!(%mac, uplink = %packet, "ignored potential beacon, no region");
+            return;
+        }
+        info!(%mac, uplink = %packet, "received potential beacon");
+        let position = self.gps.current(gps::unix_now()).cloned();
+        self.beacons
+            .received_beacon(beaconer::ReceivedBeacon {
+                packet,
+                data,
+                position,
+            })
+            .await
+    }
+
+    fn handle_ping(&mut self, frame: PingFrame, packet: PacketUp) {
+        if !self.ping_enabled {
+            debug!(sender = %frame.sender, target = %frame.target, "ignoring ping, pings are disabled");
+            return;
+        }
+        if frame.target != PingId::from(&self.public_key) {
+            debug!(sender = %frame.sender, target = %frame.target, "ignoring ping for other gateway");
             return;
         }
-        info!(downlink_mac = %self.downlink_mac, uplink = %packet, "received potential beacon");
-        self.beacons.received_beacon(packet).await
+        info!(sender = %frame.sender, seq = frame.seq, uplink = %packet, "received ping");
+        if self.received_pings.len() >= PING_HISTORY {
+            self.received_pings.pop_front();
+        }
+        self.received_pings
+            .push_back(ReceivedPing::new(&frame, &packet));
     }
 
-    async fn handle_uplink(&mut self, packet: PacketUp, received: Instant) {
+    async fn handle_uplink(&mut self, packet: PacketUp, received: Instant, mac: MacAddress) {
         if self.region_params.is_unknown() {
             info!(
-                downlink_mac = %self.downlink_mac,
+                %mac,
                 uplink = %packet,
                 region = %self.region_params,
                 "ignored uplink");
+            self.trace_uplink(&packet, mac, PacketDecision::NoRegion);
             return;
         }
+        if self.uplink_filter.is_filtered(&packet) {
+            debug!(
+                %mac,
+                uplink = %packet,
+                dropped = self.uplink_filter.dropped(),
+                "dropped uplink of filtered network"
+            );
+            self.trace_uplink(&packet, mac, PacketDecision::NetworkFiltered);
+            return;
+        }
+        if self.fport_filters.is_filtered(&packet) {
+            self.trace_uplink(&packet, mac, PacketDecision::FportFiltered);
+            return;
+        }
+        if self.uplink_dedup.is_enabled() && self.forwarders.connected_count() > 1 {
+            let now = tokio::time::Instant::now();
+            if let Some(dropped) = self.uplink_dedup.push(packet, received, mac, now) {
+                debug!(mac = %dropped.mac, uplink = %dropped.packet, "dropped duplicate uplink");
+                self.trace_uplink(&dropped.packet, dropped.mac, PacketDecision::Duplicate);
+                self.forwarders.duplicate(dropped.mac);
+            }
+            return;
+        }
+        self.forward_uplink(packet, received, mac).await
+    }
+
+    /// Forwards the held uplinks whose deduplication window passed
+    async fn release_uplinks(&mut self) {
+        for held in self.uplink_dedup.release(tokio::time::Instant::now()) {
+            self.forward_uplink(held.packet, held.received, held.mac)
+                .await
+        }
+    }
+
+    /// Delivers an uplink to the packet routers, Packet Broker and mqtt
+    async fn forward_uplink(&mut self, packet: PacketUp, received: Instant, mac: MacAddress) {
         info!(
-            downlink_mac = %self.downlink_mac,
+            %mac,
             uplink = %packet,
             region = %self.region_params,
             "received uplink");
-        self.uplinks.uplink(packet, received).await;
+        self.trace_uplink(&packet, mac, PacketDecision::Routed);
+        let vendor = self
+            .join_vendors
+            .as_ref()
+            .zip(packet.join_eui())
+            .and_then(|(join_vendors, join_eui)| join_vendors.lookup(join_eui))
+            .map(str::to_string);
+        if let Some(vendor) = &vendor {
+            debug!(%mac, vendor, "join request vendor");
+        }
+        self.dc_stats.record(&packet, vendor.as_deref());
+        for route in packet_router::select_routes(&self.uplinks, &packet) {
+            route.router().uplink(packet.clone(), received).await;
+        }
+        if let Some(packet_broker) = &self.packet_broker {
+            packet_broker.uplink(packet.clone(), received).await;
+        }
+        #[cfg(feature = "mqtt")]
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.uplink(packet, vendor).await;
+        }
+    }
+
+    fn trace_uplink(&self, packet: &PacketUp, mac: MacAddress, decision: PacketDecision) {
+        if self.packet_trace.is_traced() {
+            self.packet_trace
+                .send(PacketEvent::uplink(packet, mac, decision));
+        }
     }
 
     async fn handle_message(&mut self, message: Message) {
         match message {
-            Message::Downlink(packet) => self.handle_downlink(packet).await,
+            Message::Downlink(packet, router) => self.downlink_arbiter.push(packet, router),
             Message::TransmitBeacon(beacon, tx_resp) => {
                 self.handle_transmit_beacon(beacon, tx_resp).await
             }
+            Message::TransmitPing(target, tx_resp) => {
+                self.handle_transmit_ping(target, tx_resp).await
+            }
+            Message::ReceivedPings(tx_resp) => {
+                tx_resp.send(self.received_pings.iter().cloned().collect())
+            }
+            Message::DownlinkStatus(tx_resp) => tx_resp.send(DownlinkStatus {
+                current: self.downlinks.snapshot(),
+                last_hour: self.last_downlinks,
+            }),
+            Message::DcStatus(tx_resp) => tx_resp.send(self.dc_stats.status()),
+            Message::CrcStatus(tx_resp) => tx_resp.send(self.crc_stats.status()),
+            Message::DutyCycle(tx_resp) => tx_resp.send(self.duty_cycle.status(Instant::now())),
+            Message::Forwarders(tx_resp) => tx_resp.send(self.forwarders.status()),
+            Message::ForwarderStats(tx_resp) => tx_resp.send(self.forwarders.stats()),
+            Message::FilterStatus(tx_resp) => tx_resp.send(FilterStatus {
+                network_dropped: self.uplink_filter.dropped(),
+                fport_filters: self.fport_filters.status(),
+            }),
+            Message::SetListen { listen, interface } => {
+                info!(
+                    ?listen,
+                    ?interface,
+                    "listen address changed, rebuilding udp runtime"
+                );
+                self.set_listen(listen, interface).await
+            }
+            Message::PacketEvents(tx_resp) => tx_resp.send(self.packet_trace.subscribe()),
+            Message::GpsFix(fix) => self.handle_gps_fix(fix),
+            Message::Gps(tx_resp) => tx_resp.send(self.gps.current(gps::unix_now()).cloned()),
+            Message::Simulated(mac, rxpk) => self.forwarders.push(mac, rxpk, Instant::now()),
         }
     }
 
-    fn max_tx_power(&mut self) -> Result<u32> {
-        Ok(self.region_params.max_conducted_power()?)
+    /// Records a GPS fix and passes its position on to region detection
+    fn handle_gps_fix(&mut self, fix: GpsFix) {
+        if let Some(gps_positions) = &self.gps_positions {
+            gps_positions.position(fix.lat, fix.lon)
+        }
+        self.gps.update(fix);
+    }
+
+    /// Records a transmission on the given frequency in Hz if it fits the
+    /// duty cycle limit of its sub-band. Returns false if it does not.
+    fn take_duty_cycle(&mut self, frequency: u32, airtime: Duration) -> bool {
+        let now = Instant::now();
+        if !self.duty_cycle.allows(frequency, airtime, now) {
+            return false;
+        }
+        self.duty_cycle.record(frequency, airtime, now);
+        true
+    }
+
+    /// Returns the maximum conducted transmit power on the given frequency in
+    /// Hz, the regional maximum capped by the configured limit for the
+    /// frequency, if any
+    fn max_tx_power(&self, frequency: Option<u32>) -> Result<u32> {
+        let max_tx_power = self.region_params.max_conducted_power()?;
+        let limit = frequency.and_then(|frequency| {
+            self.tx_power_limits
+                .iter()
+                .find(|limit| limit.contains(frequency))
+        });
+        Ok(limit.map_or(max_tx_power, |limit| {
+            limit
+                .max_conducted_power(self.region_params.gain)
+                .min(max_tx_power)
+        }))
+    }
+
+    /// Warns about transmit power limits above the maximum EIRP of the
+    /// region parameters, which are capped at the regional maximum
+    fn check_tx_power_limits(&self) {
+        let Some(max_eirp) = self.region_params.max_eirp() else {
+            return;
+        };
+        for limit in &self.tx_power_limits {
+            if limit.max_eirp() > max_eirp {
+                warn!(
+                    min_frequency = limit.min_frequency,
+                    max_frequency = limit.max_frequency,
+                    max_eirp = %limit.max_eirp(),
+                    region_max_eirp = %max_eirp,
+                    "tx power limit above regional maximum, capped"
+                );
+            }
+        }
+    }
+
+    /// Returns the beacon transmit power on the given frequency in Hz, which
+    /// is the configured override capped at the maximum transmit power, or the
+    /// maximum transmit power if no override is configured.
+    fn beacon_tx_power(&self, frequency: u64) -> Result<u32> {
+        let max_tx_power = self.max_tx_power(Some(frequency as u32))?;
+        match self.beacon_tx_power_override {
+            Some(tx_power) if tx_power > max_tx_power => {
+                warn!(
+                    tx_power,
+                    max_tx_power, "beacon tx power override capped at regional max"
+                );
+                Ok(max_tx_power)
+            }
+            Some(tx_power) => Ok(tx_power),
+            None => Ok(max_tx_power),
+        }
     }
 
     async fn handle_transmit_beacon(
@@ -219,7 +921,7 @@ impl Gateway {
         beacon: Beacon,
         responder: sync::ResponseSender<Result<BeaconResp>>,
     ) {
-        let tx_power = match self.max_tx_power() {
+        let tx_power = match self.beacon_tx_power(beacon.frequency) {
             Ok(tx_power) => tx_power,
             Err(err) => {
                 warn!(%err, "beacon transmit");
@@ -228,7 +930,7 @@ impl Gateway {
             }
         };
 
-        let packet = match beacon_to_pull_resp(&beacon, tx_power as u64) {
+        let packet = match beacon_to_pull_resp(&beacon, TxIntent::Beacon, tx_power) {
             Ok(packet) => packet,
             Err(err) => {
                 warn!(%err, "failed to construct beacon pull resp");
@@ -237,10 +939,36 @@ impl Gateway {
             }
         };
 
-        let beacon_tx = self.udp_runtime.prepare_downlink(packet, self.downlink_mac);
+        let airtime = beacon_airtime(&beacon).unwrap_or_default();
+        if !self.take_duty_cycle(beacon.frequency as u32, airtime) {
+            warn!(
+                beacon_id = beacon.beacon_id(),
+                "beacon exceeds duty cycle limit"
+            );
+            responder.send(Err(GatewayError::DutyCycleExceeded.into()));
+            return;
+        }
+
+        let mac = self.forwarders.default_mac();
+        let Some(udp_runtime) = self.udp_runtime(mac) else {
+            warn!(
+                beacon_id = beacon.beacon_id(),
+                "no bound udp listener for beacon"
+            );
+            responder.send(Err(GatewayError::NoUdpListener.into()));
+            return;
+        };
+        let beacon_tx = udp_runtime.prepare_downlink(packet, mac);
+        let delay = self
+            .downlink_arbiter
+            .beacon_delay(mac, airtime, Instant::now());
 
         tokio::spawn(async move {
             let beacon_id = beacon.beacon_id();
+            if !delay.is_zero() {
+                info!(beacon_id, ?delay, "deferring beacon for class A window");
+                tokio::time::sleep(delay).await;
+            }
             match beacon_tx.dispatch(Some(DOWNLINK_TIMEOUT)).await {
                 Ok(tmst) => {
                     info!(
@@ -289,75 +1017,326 @@ impl Gateway {
         });
     }
 
-    async fn handle_downlink(&mut self, downlink: PacketDown) {
-        let tx_power = match self.max_tx_power() {
+    async fn handle_transmit_ping(
+        &mut self,
+        target: PublicKey,
+        responder: sync::ResponseSender<Result<SentPing>>,
+    ) {
+        if !self.ping_enabled {
+            responder.send(Err(GatewayError::PingDisabled.into()));
+            return;
+        }
+        self.ping_seq = self.ping_seq.wrapping_add(1);
+        let frame = PingFrame {
+            target: PingId::from(&target),
+            sender: PingId::from(&self.public_key),
+            seq: self.ping_seq,
+        };
+        let (ping, packet, tx_power) = match mk_ping(&self.region_params, &frame).and_then(|ping| {
+            // Pings are sent at beacon power so they test the link as beacons
+            // would see it
+            let tx_power = self.beacon_tx_power(ping.frequency)?;
+            beacon_to_pull_resp(&ping, TxIntent::Test, tx_power).map(|p| (ping, p, tx_power))
+        }) {
+            Ok(result) => result,
+            Err(err) => {
+                warn!(%err, "failed to construct ping pull resp");
+                responder.send(Err(err));
+                return;
+            }
+        };
+
+        let airtime = beacon_airtime(&ping).unwrap_or_default();
+        if !self.take_duty_cycle(ping.frequency as u32, airtime) {
+            warn!(target = %frame.target, seq = frame.seq, "ping exceeds duty cycle limit");
+            responder.send(Err(GatewayError::DutyCycleExceeded.into()));
+            return;
+        }
+
+        let mac = self.forwarders.default_mac();
+        let Some(udp_runtime) = self.udp_runtime(mac) else {
+            warn!(target = %frame.target, seq = frame.seq, "no bound udp listener for ping");
+            responder.send(Err(GatewayError::NoUdpListener.into()));
+            return;
+        };
+        let ping_tx = udp_runtime.prepare_downlink(packet, mac);
+        let delay = self
+            .downlink_arbiter
+            .beacon_delay(mac, airtime, Instant::now());
+
+        tokio::spawn(async move {
+            if !delay.is_zero() {
+                info!(target = %frame.target, ?delay, "deferring ping for class A window");
+                tokio::time::sleep(delay).await;
+            }
+            let tx_power = match ping_tx.dispatch(Some(DOWNLINK_TIMEOUT)).await {
+                Ok(_) => tx_power as i32,
+                Err(SemtechError::Ack(TxAckErr::AdjustedTransmitPower(Some(actual_power), _))) => {
+                    actual_power
+                }
+                Err(err) => {
+                    warn!(target = %frame.target, seq = frame.seq, %err, "failed to transmit ping");
+                    responder.send(Err(GatewayError::PingTxFailure.into()));
+                    return;
+                }
+            };
+            info!(target = %frame.target, seq = frame.seq, tx_power, "ping transmitted");
+            responder.send(Ok(SentPing {
+                sender: frame.sender,
+                target: frame.target,
+                seq: frame.seq,
+                frequency: ping.frequency,
+                datarate: ping.datarate.as_str_name().to_string(),
+                tx_power,
+            }));
+        });
+    }
+
+    async fn handle_downlink(
+        &mut self,
+        downlink: PacketDown,
+        router: Option<packet_router::MessageSender>,
+    ) {
+        let downlink = match self.default_rx2 {
+            Some(rx2) => downlink.with_default_rx2(rx2.frequency, rx2.datarate),
+            None => downlink,
+        };
+        self.downlinks.accepted();
+        let window_tx_power =
+            |window: Option<&WindowV1>| self.max_tx_power(window.map(|window| window.frequency));
+        let tx_power = match window_tx_power(downlink.rx1.as_ref())
+            .and_then(|rx1| Ok((rx1, window_tx_power(downlink.rx2.as_ref())?)))
+        {
             Ok(tx_power) => tx_power,
             Err(err) => {
                 warn!(%err, "downlink transmit");
+                self.downlinks.failed();
+                if let Some(router) = router {
+                    router.downlink_ack(DownlinkAck::Failed).await;
+                }
                 return;
             }
         };
 
-        let (mut downlink_rx1, mut downlink_rx2) = (
+        // Both windows are relative to the same uplink, so they go to the same
+        // forwarder
+        let downlink_mac = self
+            .forwarders
+            .downlink_mac(downlink.rx1_timestamp(), self.downlink_routing);
+        let Some(udp_runtime) = self.udp_runtime(downlink_mac) else {
+            warn!(%downlink_mac, "no bound udp listener for downlink");
+            self.downlinks.failed();
+            if let Some(router) = router {
+                router.downlink_ack(DownlinkAck::Failed).await;
+            }
+            return;
+        };
+        let (downlink_rx1, downlink_rx2) = (
             // first downlink
-            self.udp_runtime.prepare_empty_downlink(self.downlink_mac),
+            udp_runtime.prepare_empty_downlink(downlink_mac),
             // 2nd downlink window if requested by the router response
-            self.udp_runtime.prepare_empty_downlink(self.downlink_mac),
+            udp_runtime.prepare_empty_downlink(downlink_mac),
         );
 
-        let downlink_mac = self.downlink_mac;
+        // A downlink whose rx1 window overlaps a higher priority downlink on
+        // the same forwarder, or exceeds the duty cycle limit, is sent in its
+        // rx2 window instead
+        let now = Instant::now();
+        let rx1_airtime = downlink
+            .rx1
+            .as_ref()
+            .and_then(|rx1| Some((rx1.frequency, downlink.airtime(rx1)?)));
+        let rx2_airtime = downlink
+            .rx2
+            .as_ref()
+            .and_then(|rx2| Some((rx2.frequency, downlink.airtime(rx2)?)));
+        let rx1_over_limit = rx1_airtime
+            .is_some_and(|(frequency, airtime)| !self.duty_cycle.allows(frequency, airtime, now));
+        if rx1_over_limit {
+            info!(%downlink_mac, "rx1 window exceeds duty cycle limit");
+        }
+        let priority = DownlinkPriority::from(&downlink);
+        let rx1_deferred = rx1_over_limit
+            || downlink.rx1_tx_window().is_some_and(|window| {
+                !self
+                    .downlink_arbiter
+                    .schedule(downlink_mac, window, priority)
+            });
+        let rx2_available = downlink.rx2_tx_window().is_some_and(|window| {
+            !rx1_deferred
+                || (rx2_airtime.is_none_or(|(frequency, airtime)| {
+                    self.duty_cycle.allows(frequency, airtime, now)
+                }) && self
+                    .downlink_arbiter
+                    .schedule(downlink_mac, window, priority))
+        });
+        let transmitted_airtime = if !rx1_deferred {
+            rx1_airtime
+        } else if rx2_available {
+            rx2_airtime
+        } else {
+            None
+        };
+        if let Some((frequency, airtime)) = transmitted_airtime {
+            self.duty_cycle.record(frequency, airtime, now);
+        }
+
+        let downlinks = self.downlinks.clone();
+        let packet_trace = self.packet_trace.clone();
+        let region = self.region_params.region;
 
         tokio::spawn(async move {
-            if let Ok(txpk) = downlink.to_rx1_pull_resp(tx_power) {
-                info!(%downlink_mac, "rx1 downlink {txpk}",);
-
-                downlink_rx1.set_packet(txpk);
-                match downlink_rx1.dispatch(Some(DOWNLINK_TIMEOUT)).await {
-                    // On a too early or too late error retry on the rx2 slot if available.
-                    Err(SemtechError::Ack(TxAckErr::TooEarly | TxAckErr::TooLate)) => {
-                        if let Ok(Some(txpk)) = downlink.to_rx2_pull_resp(tx_power) {
-                            info!(%downlink_mac, "rx2 downlink {txpk}");
-
-                            downlink_rx2.set_packet(txpk);
-                            match downlink_rx2.dispatch(Some(DOWNLINK_TIMEOUT)).await {
-                                Err(SemtechError::Ack(TxAckErr::AdjustedTransmitPower(_, _))) => {
-                                    warn!("rx2 downlink sent with adjusted transmit power");
-                                }
-                                Err(err) => warn!(%err, "ignoring rx2 downlink error"),
-                                _ => (),
-                            }
-                        }
-                    }
-                    Err(SemtechError::Ack(TxAckErr::AdjustedTransmitPower(_, _))) => {
-                        warn!("rx1 downlink sent with adjusted transmit power");
-                    }
-                    Err(err) => {
-                        warn!(%err, "ignoring rx1 downlink error");
-                    }
-                    Ok(_) => (),
+            let (decision, ack) = if rx1_deferred {
+                if rx2_available {
+                    info!(%downlink_mac, ?priority, "rx1 window unavailable, deferring to rx2");
+                    dispatch_rx2(&downlink, downlink_mac, downlink_rx2, region, tx_power.1).await
+                } else {
+                    warn!(%downlink_mac, ?priority, "rx1 window unavailable and no rx2 window");
+                    (PacketDecision::Failed, DownlinkAck::Failed)
                 }
+            } else {
+                dispatch_rx1(
+                    &downlink,
+                    downlink_mac,
+                    downlink_rx1,
+                    downlink_rx2,
+                    region,
+                    tx_power,
+                )
+                .await
+            };
+            if decision == PacketDecision::Failed {
+                downlinks.failed();
+            } else {
+                downlinks.transmitted();
+            }
+            if packet_trace.is_traced() {
+                packet_trace.send(PacketEvent::downlink(&downlink, downlink_mac, decision));
+            }
+            if let Some(router) = router {
+                router.downlink_ack(ack).await;
             }
         });
     }
 }
 
-pub fn beacon_to_pull_resp(beacon: &Beacon, tx_power: u64) -> Result<pull_resp::TxPk> {
-    let datr = packet::datarate::from_proto(beacon.datarate)?;
-    let freq = packet::to_mhz(beacon.frequency as f64);
-    let data: Vec<u8> = PHYPayload::proprietary(beacon.data.as_slice()).try_into()?;
+/// The downlink outcome reported to the router for a transmit error
+fn downlink_ack(err: &SemtechError) -> DownlinkAck {
+    match err {
+        SemtechError::Ack(TxAckErr::AdjustedTransmitPower(_, _)) => DownlinkAck::AdjustedPower,
+        SemtechError::Ack(TxAckErr::TooEarly) => DownlinkAck::TooEarly,
+        SemtechError::Ack(TxAckErr::TooLate) => DownlinkAck::TooLate,
+        _ => DownlinkAck::Failed,
+    }
+}
+
+/// Sends a downlink in its rx1 window, falling back to its rx2 window when
+/// the rx1 window is missed. The transmit power is given for each window, the
+/// region limits the payload size.
+async fn dispatch_rx1(
+    downlink: &PacketDown,
+    downlink_mac: MacAddress,
+    mut downlink_rx1: Downlink,
+    downlink_rx2: Downlink,
+    region: Region,
+    tx_power: (u32, u32),
+) -> (PacketDecision, DownlinkAck) {
+    let (rx1_tx_power, rx2_tx_power) = tx_power;
+    let txpk = match downlink.to_rx1_pull_resp(region, rx1_tx_power) {
+        Ok(txpk) => txpk,
+        Err(err) => {
+            warn!(%downlink_mac, %err, "rejected rx1 downlink");
+            return (PacketDecision::Failed, DownlinkAck::Failed);
+        }
+    };
+    info!(%downlink_mac, "rx1 downlink {txpk}",);
+
+    downlink_rx1.set_packet(txpk);
+    match downlink_rx1.dispatch(Some(DOWNLINK_TIMEOUT)).await {
+        // On a too early or too late error retry on the rx2 slot if available.
+        // Without an rx2 window the rx1 error is reported.
+        Err(err @ SemtechError::Ack(TxAckErr::TooEarly | TxAckErr::TooLate)) => {
+            match dispatch_rx2(downlink, downlink_mac, downlink_rx2, region, rx2_tx_power).await {
+                (PacketDecision::Failed, DownlinkAck::Failed) => {
+                    (PacketDecision::Failed, downlink_ack(&err))
+                }
+                result => result,
+            }
+        }
+        Err(err @ SemtechError::Ack(TxAckErr::AdjustedTransmitPower(_, _))) => {
+            warn!("rx1 downlink sent with adjusted transmit power");
+            (PacketDecision::Rx1, downlink_ack(&err))
+        }
+        Err(err) => {
+            warn!(%err, "ignoring rx1 downlink error");
+            (PacketDecision::Failed, downlink_ack(&err))
+        }
+        Ok(_) => (PacketDecision::Rx1, DownlinkAck::Rx1),
+    }
+}
+
+/// Sends a downlink in its rx2 window, if it has one
+async fn dispatch_rx2(
+    downlink: &PacketDown,
+    downlink_mac: MacAddress,
+    mut downlink_rx2: Downlink,
+    region: Region,
+    tx_power: u32,
+) -> (PacketDecision, DownlinkAck) {
+    match downlink.to_rx2_pull_resp(region, tx_power) {
+        Ok(Some(txpk)) => {
+            info!(%downlink_mac, "rx2 downlink {txpk}");
 
-    Ok(pull_resp::TxPk {
-        time: Time::immediate(),
-        ipol: false,
-        modu: Modulation::LORA,
-        codr: CodingRate::_4_5,
-        datr,
-        freq,
-        data: pull_resp::PhyData::new(data),
-        powe: tx_power,
-        rfch: 0,
-        fdev: None,
-        prea: None,
-        ncrc: None,
-    })
+            downlink_rx2.set_packet(txpk);
+            match downlink_rx2.dispatch(Some(DOWNLINK_TIMEOUT)).await {
+                Err(err @ SemtechError::Ack(TxAckErr::AdjustedTransmitPower(_, _))) => {
+                    warn!("rx2 downlink sent with adjusted transmit power");
+                    (PacketDecision::Rx2, downlink_ack(&err))
+                }
+                Err(err) => {
+                    warn!(%err, "ignoring rx2 downlink error");
+                    (PacketDecision::Failed, downlink_ack(&err))
+                }
+                Ok(_) => (PacketDecision::Rx2, DownlinkAck::Rx2),
+            }
+        }
+        Ok(None) => (PacketDecision::Failed, DownlinkAck::Failed),
+        Err(err) => {
+            warn!(%downlink_mac, %err, "rejected rx2 downlink");
+            (PacketDecision::Failed, DownlinkAck::Failed)
+        }
+    }
+}
+
+/// Constructs a ping frame transmission. The channel and datarate are selected
+/// from the region parameters the same way they are for beacons.
+fn mk_ping(region_params: &RegionParams, frame: &PingFrame) -> Result<Beacon> {
+    region_params.check_valid()?;
+    let mut ping = Beacon::new(Entropy::local()?, Entropy::local()?, region_params)?;
+    ping.data = frame.to_vec();
+    Ok(ping)
+}
+
+/// The airtime of a beacon or ping, which is sent as a proprietary frame
+fn beacon_airtime(beacon: &Beacon) -> Result<Duration> {
+    let datarate = packet::datarate::from_proto(beacon.datarate)?;
+    // The payload follows the one byte MAC header without a MIC
+    Ok(packet::datarate::airtime(&datarate, beacon.data.len() + 1))
+}
+
+/// The transmit packet of a beacon, or of a ping with the given intent
+pub fn beacon_to_pull_resp(
+    beacon: &Beacon,
+    intent: TxIntent,
+    tx_power: u32,
+) -> Result<pull_resp::TxPk> {
+    let data: Vec<u8> = PHYPayload::proprietary(beacon.data.as_slice()).try_into()?;
+    TxPkBuilder::new(
+        intent,
+        beacon.frequency as u32,
+        packet::datarate::from_proto(beacon.datarate)?,
+        data,
+    )
+    .tx_power(tx_power)
+    .build()
 }
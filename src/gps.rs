@@ -0,0 +1,288 @@
+//! GPS position of the gateway.
+//!
+//! The position is taken from two sources:
+//!
+//! * The stat messages of packet forwarders with a GPS receiver. The Semtech
+//!   protocol does not report the fix quality, so a reported position counts
+//!   as a 2D fix, or a 3D fix when it has an altitude.
+//! * A local gpsd, if configured, read through its JSON socket protocol. gpsd
+//!   reports the fix mode and the GPS time of the fix.
+//!
+//! A gpsd fix is preferred over a forwarder fix while it is current. A fix
+//! that is not updated for [`FIX_TTL`] is considered lost.
+//!
+//! Fine timestamps of received packets are not taken from here but from the
+//! packet forwarder, which stamps packets with GPS time while its GPS is
+//! locked.
+
+use crate::{gateway, service::Reconnect, settings::GpsSettings, Result};
+use semtech_udp::push_data::Stat;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use tracing::{debug, info, warn};
+
+/// Time after which a fix that was not updated is lost. Packet forwarders
+/// send stat messages every 30 seconds by default.
+pub const FIX_TTL: Duration = Duration::from_secs(90);
+
+/// Request to gpsd to stream reports as JSON objects
+const GPSD_WATCH: &[u8] = b"?WATCH={\"enable\":true,\"json\":true}\n";
+
+/// Minimum and maximum wait between gpsd reconnects
+const GPSD_RECONNECT_MIN_WAIT: Duration = Duration::from_secs(5);
+const GPSD_RECONNECT_MAX_WAIT: Duration = Duration::from_secs(300);
+const GPSD_RECONNECT_RETRIES: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FixQuality {
+    Fix2d,
+    Fix3d,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GpsSource {
+    Forwarder,
+    Gpsd,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GpsFix {
+    pub source: GpsSource,
+    pub quality: FixQuality,
+    pub lat: f64,
+    pub lon: f64,
+    /// Altitude in meters, if known
+    pub alt: Option<f64>,
+    /// Unix time in seconds of the GPS time of the fix, if reported
+    pub gps_time: Option<u64>,
+    /// Unix time in seconds the fix was received
+    pub timestamp: u64,
+}
+
+impl GpsFix {
+    /// The fix in a packet forwarder stat message. Forwarders without a GPS
+    /// fix report no or a zero position.
+    pub fn from_stat(stat: &Stat) -> Option<Self> {
+        let (lat, lon) = (stat.lati?, stat.long?);
+        if lat == 0.0 && lon == 0.0 {
+            return None;
+        }
+        Some(Self {
+            source: GpsSource::Forwarder,
+            quality: if stat.alti.is_some() {
+                FixQuality::Fix3d
+            } else {
+                FixQuality::Fix2d
+            },
+            lat,
+            lon,
+            alt: stat.alti.map(|alt| alt as f64),
+            gps_time: None,
+            timestamp: unix_now(),
+        })
+    }
+}
+
+/// The fixes of each source
+#[derive(Debug, Default)]
+pub struct GpsState {
+    forwarder: Option<GpsFix>,
+    gpsd: Option<GpsFix>,
+}
+
+impl GpsState {
+    pub fn update(&mut self, fix: GpsFix) {
+        match fix.source {
+            GpsSource::Forwarder => self.forwarder = Some(fix),
+            GpsSource::Gpsd => self.gpsd = Some(fix),
+        }
+    }
+
+    /// The current fix at the given unix time in seconds, preferring gpsd
+    pub fn current(&self, now: u64) -> Option<&GpsFix> {
+        [&self.gpsd, &self.forwarder]
+            .into_iter()
+            .flatten()
+            .find(|fix| now.saturating_sub(fix.timestamp) < FIX_TTL.as_secs())
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// A gpsd report. Only time position velocity (TPV) reports carry a fix.
+#[derive(Debug, Deserialize)]
+struct GpsdReport {
+    class: String,
+    /// 0 or 1 without a fix, 2 for a 2D fix and 3 for a 3D fix
+    #[serde(default)]
+    mode: u8,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    /// Altitude above the ellipsoid, reported by gpsd 3.20 and later
+    #[serde(rename = "altHAE")]
+    alt_hae: Option<f64>,
+    alt: Option<f64>,
+    time: Option<String>,
+}
+
+/// The fix in a line of the gpsd JSON protocol received at the given unix
+/// time, if it is a report with a fix
+fn parse_gpsd_report(line: &str, timestamp: u64) -> Option<GpsFix> {
+    let report: GpsdReport = serde_json::from_str(line).ok()?;
+    if report.class != "TPV" {
+        return None;
+    }
+    let quality = match report.mode {
+        2 => FixQuality::Fix2d,
+        3 => FixQuality::Fix3d,
+        _ => return None,
+    };
+    Some(GpsFix {
+        source: GpsSource::Gpsd,
+        quality,
+        lat: report.lat?,
+        lon: report.lon?,
+        alt: (quality == FixQuality::Fix3d)
+            .then_some(report.alt_hae.or(report.alt))
+            .flatten(),
+        gps_time: report
+            .time
+            .and_then(|time| OffsetDateTime::parse(&time, &Rfc3339).ok())
+            .map(|time| time.unix_timestamp() as u64),
+        timestamp,
+    })
+}
+
+/// Reads fixes from a local gpsd and passes them to the gateway
+pub struct Gpsd {
+    address: String,
+    gateway: gateway::MessageSender,
+    reconnect: Reconnect,
+}
+
+impl Gpsd {
+    /// A gpsd reader if a gpsd address is configured
+    pub fn new(settings: &GpsSettings, gateway: gateway::MessageSender) -> Option<Self> {
+        Some(Self {
+            address: settings.gpsd.clone()?,
+            gateway,
+            reconnect: Reconnect::new(
+                GPSD_RECONNECT_RETRIES,
+                GPSD_RECONNECT_MIN_WAIT,
+                GPSD_RECONNECT_MAX_WAIT,
+            ),
+        })
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn run(&mut self, shutdown: &triggered::Listener) -> Result {
+        info!(address = %self.address, "starting");
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => {
+                    info!("shutting down");
+                    return Ok(())
+                },
+                result = self.watch() => match result {
+                    Ok(()) => warn!("gpsd closed the connection"),
+                    Err(err) => warn!(%err, "gpsd connection failed"),
+                },
+            }
+            self.reconnect.update_next_time(true);
+            tokio::select! {
+                _ = shutdown.clone() => {
+                    info!("shutting down");
+                    return Ok(())
+                },
+                _ = self.reconnect.wait() => (),
+            }
+        }
+    }
+
+    async fn watch(&mut self) -> Result {
+        let mut stream = TcpStream::connect(&self.address).await?;
+        stream.write_all(GPSD_WATCH).await?;
+        info!("connected to gpsd");
+        let mut lines = BufReader::new(stream).lines();
+        while let Some(line) = lines.next_line().await? {
+            if let Some(fix) = parse_gpsd_report(&line, unix_now()) {
+                debug!(?fix, "gpsd fix");
+                self.reconnect.retry_count = 0;
+                self.gateway.gps_fix(fix).await;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn gpsd_report() {
+        let fix = parse_gpsd_report(
+            r#"{"class":"TPV","device":"/dev/ttyS0","mode":3,"time":"2024-03-01T12:00:00.000Z","lat":52.37,"lon":4.89,"altHAE":42.5,"alt":40.1}"#,
+            100,
+        )
+        .expect("fix");
+        assert_eq!(FixQuality::Fix3d, fix.quality);
+        assert_eq!((52.37, 4.89, Some(42.5)), (fix.lat, fix.lon, fix.alt));
+        assert_eq!(Some(1_709_294_400), fix.gps_time);
+
+        let fix = parse_gpsd_report(
+            r#"{"class":"TPV","mode":2,"lat":1.5,"lon":2.5,"alt":9}"#,
+            100,
+        )
+        .expect("fix");
+        assert_eq!((FixQuality::Fix2d, None), (fix.quality, fix.alt));
+
+        // Reports without a fix, of other classes and invalid lines
+        assert!(parse_gpsd_report(r#"{"class":"TPV","mode":1}"#, 100).is_none());
+        assert!(parse_gpsd_report(r#"{"class":"SKY","mode":3}"#, 100).is_none());
+        assert!(parse_gpsd_report("garbage", 100).is_none());
+    }
+
+    #[test]
+    fn gps_state() {
+        let fix = |source, timestamp| GpsFix {
+            source,
+            quality: FixQuality::Fix2d,
+            lat: 1.0,
+            lon: 2.0,
+            alt: None,
+            gps_time: None,
+            timestamp,
+        };
+        let mut state = GpsState::default();
+        assert!(state.current(100).is_none());
+        state.update(fix(GpsSource::Forwarder, 100));
+        assert_eq!(
+            Some(GpsSource::Forwarder),
+            state.current(100).map(|fix| fix.source)
+        );
+        // gpsd is preferred while current
+        state.update(fix(GpsSource::Gpsd, 50));
+        assert_eq!(
+            Some(GpsSource::Gpsd),
+            state.current(100).map(|fix| fix.source)
+        );
+        assert_eq!(
+            Some(GpsSource::Forwarder),
+            state.current(150).map(|fix| fix.source)
+        );
+        assert!(state.current(200).is_none());
+    }
+}
@@ -0,0 +1,182 @@
+//! Region detection from the GPS position of the gateway.
+//!
+//! Mobile gateways, on ships or vehicles, cross region borders. When enabled,
+//! the GPS position reported by the packet forwarder in its stat messages is
+//! mapped to a region using a list of areas, and the region watcher requests
+//! the region parameters of the detected region from the config service.
+//!
+//! Areas are latitude/longitude boxes checked in order, so smaller areas
+//! inside larger ones have to come first. The built in areas are coarse boxes
+//! around the main land masses of common regions. Configured areas are checked
+//! before them.
+//!
+//! To avoid flapping at borders the current region is kept until positions
+//! are more than a configured distance outside of it, and a new region is
+//! only selected once a configured number of consecutive positions lie at
+//! least that distance inside its area.
+
+use crate::{
+    settings::{GpsRegionSettings, RegionArea},
+    sync, Region, Result,
+};
+
+/// Kilometers per degree of latitude
+const KM_PER_DEGREE: f64 = 111.2;
+
+/// Built in areas as region name, minimum and maximum latitude and minimum
+/// and maximum longitude, in the order they are checked
+const BUILTIN_AREAS: &[(&str, f64, f64, f64, f64)] = &[
+    ("KR920", 33.0, 38.7, 124.5, 131.0),
+    ("AS923_1", 24.0, 46.0, 128.0, 154.0),
+    ("IN865", 6.0, 35.5, 68.0, 97.5),
+    ("CN470", 18.0, 53.5, 73.5, 135.0),
+    ("AU915", -48.0, -10.0, 112.0, 179.0),
+    ("AU915", -34.0, 5.5, -74.0, -34.0),
+    ("US915", 14.0, 72.0, -170.0, -52.0),
+    ("EU868", 35.0, 72.0, -25.0, 45.0),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Message {
+    /// A position fix in decimal degrees
+    Position { lat: f64, lon: f64 },
+}
+
+pub type MessageSender = sync::MessageSender<Message>;
+pub type MessageReceiver = sync::MessageReceiver<Message>;
+
+pub fn message_channel() -> (MessageSender, MessageReceiver) {
+    sync::message_channel(10)
+}
+
+impl MessageSender {
+    /// Reports a position without waiting. Positions are dropped while the
+    /// region watcher is busy fetching region parameters.
+    pub fn position(&self, lat: f64, lon: f64) {
+        _ = self.0.try_send(Message::Position { lat, lon });
+    }
+}
+
+impl RegionArea {
+    /// Distance in km from the position to the nearest edge of the area,
+    /// negative if the position is outside the area
+    fn inset_km(&self, lat: f64, lon: f64) -> f64 {
+        let lat_inset = (lat - self.min_lat).min(self.max_lat - lat) * KM_PER_DEGREE;
+        let lon_inset =
+            (lon - self.min_lon).min(self.max_lon - lon) * KM_PER_DEGREE * lat.to_radians().cos();
+        lat_inset.min(lon_inset)
+    }
+}
+
+#[derive(Debug)]
+pub struct RegionDetector {
+    areas: Vec<RegionArea>,
+    /// Consecutive positions in a new region before it is selected
+    confirm: u32,
+    /// Distance in km a position has to be inside the area of a new region
+    margin_km: f64,
+    current: Option<Region>,
+    /// A new region and the number of consecutive positions in it
+    candidate: Option<(Region, u32)>,
+}
+
+impl RegionDetector {
+    pub fn new(settings: &GpsRegionSettings) -> Result<Self> {
+        let mut areas = settings.areas.clone();
+        for (region, min_lat, max_lat, min_lon, max_lon) in BUILTIN_AREAS {
+            areas.push(RegionArea {
+                region: region.parse()?,
+                min_lat: *min_lat,
+                max_lat: *max_lat,
+                min_lon: *min_lon,
+                max_lon: *max_lon,
+            });
+        }
+        Ok(Self {
+            areas,
+            confirm: settings.confirm.max(1),
+            margin_km: settings.margin_km,
+            current: None,
+            candidate: None,
+        })
+    }
+
+    /// The region of the first area the position is at least the given
+    /// distance inside of. A negative distance extends the areas.
+    fn region_at(&self, lat: f64, lon: f64, margin_km: f64) -> Option<Region> {
+        self.areas
+            .iter()
+            .find(|area| area.inset_km(lat, lon) >= margin_km)
+            .map(|area| area.region)
+    }
+
+    /// Processes a position fix. Returns the newly detected region once a
+    /// region change is confirmed. Positions outside all areas keep the
+    /// current region.
+    pub fn update(&mut self, lat: f64, lon: f64) -> Option<Region> {
+        // The first region needs no margin since there is no border crossed
+        let margin_km = self.current.map_or(0.0, |_| self.margin_km);
+        let region = match (
+            self.region_at(lat, lon, -margin_km),
+            self.region_at(lat, lon, margin_km),
+        ) {
+            (near, Some(region)) if near != self.current && Some(region) != self.current => region,
+            _ => {
+                self.candidate = None;
+                return None;
+            }
+        };
+        let count = match self.candidate {
+            Some((candidate, count)) if candidate == region => count + 1,
+            _ => 1,
+        };
+        if count < self.confirm {
+            self.candidate = Some((region, count));
+            return None;
+        }
+        self.candidate = None;
+        self.current = Some(region);
+        Some(region)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn region(name: &str) -> Region {
+        name.parse().unwrap()
+    }
+
+    #[test]
+    fn test_region_detection() {
+        let settings = GpsRegionSettings {
+            enabled: true,
+            confirm: 2,
+            margin_km: 10.0,
+            areas: vec![],
+        };
+        let mut detector = RegionDetector::new(&settings).unwrap();
+        // Seoul is in the Korean area, which is checked before the Chinese one
+        assert_eq!(None, detector.update(37.5, 127.0));
+        assert_eq!(Some(region("KR920")), detector.update(37.5, 127.0));
+        assert_eq!(None, detector.update(37.5, 127.0));
+
+        // Just across the border of the Korean area is not far enough outside
+        // of it
+        assert_eq!(None, detector.update(38.75, 127.0));
+        assert_eq!(None, detector.update(38.75, 127.0));
+        // Further inside it is, but only after consecutive positions
+        assert_eq!(None, detector.update(40.0, 127.0));
+        assert_eq!(None, detector.update(37.5, 127.0));
+        assert_eq!(None, detector.update(40.0, 127.0));
+        assert_eq!(Some(region("CN470")), detector.update(40.0, 127.0));
+        // Just inside the Korean area is not far enough inside of it
+        assert_eq!(None, detector.update(38.65, 127.0));
+        assert_eq!(None, detector.update(38.65, 127.0));
+
+        // Positions at sea keep the current region
+        assert_eq!(None, detector.update(0.0, -150.0));
+        assert_eq!(None, detector.update(0.0, -150.0));
+    }
+}
@@ -0,0 +1,94 @@
+//! Service state change hooks.
+//!
+//! Hooks run on transitions of a service state, like the packet forwarder
+//! connecting, so that for example enclosure LEDs can reflect the actual
+//! state of the gateway. A hook either executes a script or writes a value to
+//! a (sysfs) file. Script hooks need the `hook-commands` feature.
+
+use crate::{
+    settings::{HookSettings, HookState},
+    Settings,
+};
+use tracing::{debug, warn};
+
+/// Runs the configured hooks of a single service state on transitions of that
+/// state.
+#[derive(Debug)]
+pub struct StateHook {
+    state: HookState,
+    hooks: Vec<HookSettings>,
+    /// The last reported state, or None if no state was reported yet
+    current: Option<bool>,
+}
+
+impl StateHook {
+    pub fn new(settings: &Settings, state: HookState) -> Self {
+        Self {
+            state,
+            hooks: settings
+                .hooks
+                .iter()
+                .filter(|hook| hook.state == state)
+                .cloned()
+                .collect(),
+            current: None,
+        }
+    }
+
+    /// Reports the current state. The hooks run when the state differs from
+    /// the last reported state, and for the first reported state.
+    pub fn set(&mut self, up: bool) {
+        if self.current == Some(up) {
+            return;
+        }
+        self.current = Some(up);
+        debug!(state = %self.state, up, "service state changed");
+        for hook in &self.hooks {
+            run_hook(self.state, hook, up);
+        }
+    }
+}
+
+fn run_hook(state: HookState, hook: &HookSettings, up: bool) {
+    if let Some(path) = &hook.path {
+        let value = if up { &hook.up_value } else { &hook.down_value };
+        if let Err(err) = std::fs::write(path, value) {
+            warn!(%state, path = %path.display(), %err, "failed to write hook value");
+        }
+    }
+    if let Some(command) = &hook.command {
+        run_command(state, command, up);
+    }
+}
+
+#[cfg(feature = "hook-commands")]
+fn run_command(state: HookState, command: &std::path::Path, up: bool) {
+    let child = tokio::process::Command::new(command)
+        .arg(state.to_string())
+        .arg(if up { "up" } else { "down" })
+        .spawn();
+    let command = command.to_path_buf();
+    match child {
+        Ok(mut child) => {
+            tokio::spawn(async move {
+                match child.wait().await {
+                    Ok(status) if status.success() => (),
+                    Ok(status) => {
+                        warn!(%state, command = %command.display(), %status, "hook command failed")
+                    }
+                    Err(err) => {
+                        warn!(%state, command = %command.display(), %err, "hook command failed")
+                    }
+                }
+            });
+        }
+        Err(err) => {
+            warn!(%state, command = %command.display(), %err, "failed to run hook command")
+        }
+    }
+}
+
+#[cfg(not(feature = "hook-commands"))]
+fn run_command(state: HookState, command: &std::path::Path, _up: bool) {
+    warn!(%state, command = %command.display(), "hook commands are not supported by this build");
+}
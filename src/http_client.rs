@@ -0,0 +1,37 @@
+//! HTTP client for the onboarding server, the remote configuration and
+//! webhooks.
+//!
+//! Requests to `https` URIs are sent over TLS, with the server certificate
+//! verified against the Mozilla root certificates built into the gateway.
+//! Plain `http` URIs are still accepted, but bearer tokens are only sent over
+//! `https`.
+
+#[cfg(feature = "webhooks")]
+use crate::{Error, Result};
+#[cfg(feature = "webhooks")]
+use http::Uri;
+use hyper::client::HttpConnector;
+use hyper_rustls::HttpsConnector;
+
+pub type HttpClient = hyper::Client<HttpsConnector<HttpConnector>>;
+
+/// A client for `http` and `https` URIs
+pub fn new() -> HttpClient {
+    let connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_webpki_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    hyper::Client::builder().build(connector)
+}
+
+/// Checks that a bearer token for the given URI is sent over TLS
+#[cfg(feature = "webhooks")]
+pub fn check_token_uri(uri: &Uri) -> Result {
+    if uri.scheme() != Some(&http::uri::Scheme::HTTPS) {
+        return Err(Error::custom(format!(
+            "refusing to send a token to {uri} without https"
+        )));
+    }
+    Ok(())
+}
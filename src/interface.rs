@@ -0,0 +1,103 @@
+use socket2::SockRef;
+#[cfg(unix)]
+use std::{
+    ffi::CStr,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+};
+
+/// Binds the given socket to the network interface with the given name, so
+/// its traffic is only sent and received through that interface regardless
+/// of the addresses of the interface. Before Linux 5.7 this requires the
+/// `CAP_NET_RAW` capability.
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+pub fn bind_device(socket: SockRef, name: &str) -> io::Result<()> {
+    socket.bind_device(Some(name.as_bytes())).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("failed to bind to interface {name}: {err}"),
+        )
+    })
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+pub fn bind_device(_socket: SockRef, name: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("binding to interface {name} is not supported on this platform"),
+    ))
+}
+
+/// The addresses currently assigned to the network interface with the given
+/// name, empty if there is no such interface
+#[cfg(unix)]
+pub fn addresses(name: &str) -> io::Result<Vec<IpAddr>> {
+    let mut ifaddrs = std::ptr::null_mut();
+    // SAFETY: getifaddrs allocates the list of interface addresses, which is
+    // freed below after its entries were copied
+    if unsafe { libc::getifaddrs(&mut ifaddrs) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut addresses = Vec::new();
+    let mut next = ifaddrs;
+    while !next.is_null() {
+        // SAFETY: the entry and the name and address it points to are valid
+        // until the list is freed. The address is a sockaddr_in or
+        // sockaddr_in6 for the AF_INET and AF_INET6 families.
+        unsafe {
+            let ifaddr = &*next;
+            next = ifaddr.ifa_next;
+            if ifaddr.ifa_addr.is_null()
+                || CStr::from_ptr(ifaddr.ifa_name).to_bytes() != name.as_bytes()
+            {
+                continue;
+            }
+            match i32::from((*ifaddr.ifa_addr).sa_family) {
+                libc::AF_INET => {
+                    let addr = &*(ifaddr.ifa_addr as *const libc::sockaddr_in);
+                    addresses.push(IpAddr::from(Ipv4Addr::from(u32::from_be(
+                        addr.sin_addr.s_addr,
+                    ))));
+                }
+                libc::AF_INET6 => {
+                    let addr = &*(ifaddr.ifa_addr as *const libc::sockaddr_in6);
+                    addresses.push(IpAddr::from(Ipv6Addr::from(addr.sin6_addr.s6_addr)));
+                }
+                _ => (),
+            }
+        }
+    }
+    // SAFETY: the list was allocated by getifaddrs and is no longer used
+    unsafe { libc::freeifaddrs(ifaddrs) };
+    Ok(addresses)
+}
+
+#[cfg(not(unix))]
+pub fn addresses(name: &str) -> io::Result<Vec<IpAddr>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("listing the addresses of interface {name} is not supported on this platform"),
+    ))
+}
+
+/// The address of the network interface with the given name to bind to for
+/// the address family of the given address. IPv6 link local addresses are
+/// skipped since they can not be bound without a scope.
+pub fn bind_address(name: &str, family: &SocketAddr) -> io::Result<IpAddr> {
+    addresses(name)?
+        .into_iter()
+        .find(|ip| match (ip, family) {
+            (IpAddr::V4(_), SocketAddr::V4(_)) => true,
+            (IpAddr::V6(ip), SocketAddr::V6(_)) => ip.segments()[0] & 0xffc0 != 0xfe80,
+            _ => false,
+        })
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                format!("no address to bind to on interface {name}"),
+            )
+        })
+}
@@ -0,0 +1,120 @@
+//! Device vendor lookup by join EUI.
+//!
+//! Device makers commonly derive the join EUI of their devices from their
+//! IEEE OUI or use the join server of a known provider, so the join EUI of a
+//! join request hints at the vendor of the device. When enabled, join requests
+//! are annotated with the likely vendor in the data credit statistics and the
+//! uplinks published to MQTT, which helps to find the devices behind a join
+//! storm.
+//!
+//! A small table of prefixes is built in. Additional prefixes are read from a
+//! file with one `<eui>/<prefix length> <vendor>` entry per line, for example
+//! `70B3D57ED0000000/36 The Things Industries`. Empty lines and lines
+//! starting with `#` are skipped. The longest matching prefix wins, with
+//! prefixes from the file winning over built in ones of the same length.
+
+use crate::{matcher::JoinEuiPrefix, settings::JoinVendorSettings, Error, Result};
+use std::{fs, path::Path};
+
+/// Built in join EUI prefixes and the vendor they belong to
+const BUILTIN_VENDORS: &[(&str, &str)] = &[
+    ("70B3D57ED0000000/36", "The Things Industries"),
+    ("0016C00000000000/24", "Semtech"),
+    ("00137A0000000000/24", "Netvox"),
+    ("24E1240000000000/24", "Milesight"),
+    ("2CF7F10000000000/24", "Seeed"),
+    ("58A0CB0000000000/24", "TrackNet"),
+    ("647FDA0000000000/24", "Tektelic"),
+    ("A817580000000000/24", "Elsys"),
+    ("A840410000000000/24", "Dragino"),
+];
+
+#[derive(Debug)]
+pub struct JoinVendors {
+    /// Prefixes and vendors, longest prefix first
+    vendors: Vec<(JoinEuiPrefix, String)>,
+}
+
+impl JoinVendors {
+    /// The vendor table of the given settings, or None if the lookup is
+    /// disabled
+    pub fn new(settings: &JoinVendorSettings) -> Result<Option<Self>> {
+        if !settings.enabled {
+            return Ok(None);
+        }
+        let vendors = match &settings.file {
+            Some(path) => read_vendors(path)?,
+            None => vec![],
+        };
+        Self::with_builtin(vendors).map(Some)
+    }
+
+    /// The given vendors followed by the built in ones
+    fn with_builtin(mut vendors: Vec<(JoinEuiPrefix, String)>) -> Result<Self> {
+        for (prefix, vendor) in BUILTIN_VENDORS {
+            vendors.push((prefix.parse()?, vendor.to_string()));
+        }
+        // Stable, so given vendors stay ahead of built in ones
+        vendors.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.prefix_len()));
+        Ok(Self { vendors })
+    }
+
+    /// The likely vendor of devices with the given join EUI
+    pub fn lookup(&self, join_eui: u64) -> Option<&str> {
+        self.vendors
+            .iter()
+            .find(|(prefix, _)| prefix.contains(join_eui))
+            .map(|(_, vendor)| vendor.as_str())
+    }
+}
+
+fn read_vendors(path: &Path) -> Result<Vec<(JoinEuiPrefix, String)>> {
+    let contents = fs::read_to_string(path).map_err(|err| {
+        Error::custom(format!(
+            "failed to read join vendors {}: {err}",
+            path.display()
+        ))
+    })?;
+    parse_vendors(&contents)
+}
+
+fn parse_vendors(contents: &str) -> Result<Vec<(JoinEuiPrefix, String)>> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (prefix, vendor) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| Error::custom(format!("invalid join vendor \"{line}\"")))?;
+            Ok((prefix.parse()?, vendor.trim().to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_join_vendors() {
+        let vendors = parse_vendors(
+            "# comment\n\n70B3D57ED0000000/40  Example Join Server\n0016C00000000000/24 Other\n",
+        )
+        .unwrap();
+        let vendors = JoinVendors::with_builtin(vendors).unwrap();
+
+        assert_eq!(
+            Some("Example Join Server"),
+            vendors.lookup(0x70B3_D57E_D000_0001)
+        );
+        assert_eq!(
+            Some("The Things Industries"),
+            vendors.lookup(0x70B3_D57E_D100_0001)
+        );
+        assert_eq!(Some("Other"), vendors.lookup(0x0016_C001_0000_0000));
+        assert_eq!(Some("Dragino"), vendors.lookup(0xA840_4100_0000_0001));
+        assert_eq!(None, vendors.lookup(0));
+        assert!(parse_vendors("70B3D57ED0000000/40").is_err());
+    }
+}
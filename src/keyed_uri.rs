@@ -1,21 +1,126 @@
 use crate::{PublicKey, Result};
 use http::Uri;
-use serde::Deserialize;
+use serde::{de, Deserialize, Deserializer};
 use std::{
+    collections::HashMap,
     fmt,
     hash::{Hash, Hasher},
+    ops::Deref,
     str::FromStr,
     sync::Arc,
 };
 
 /// A URI that has an associated public key
-#[derive(Clone, Deserialize, Eq)]
+#[derive(Clone, Eq)]
 pub struct KeyedUri {
-    #[serde(with = "http_serde::uri")]
     pub uri: Uri,
     pub pubkey: Arc<PublicKey>,
 }
 
+/// The unvalidated form of a keyed uri in settings
+#[derive(Deserialize)]
+struct KeyedUriEntry {
+    uri: String,
+    pubkey: String,
+}
+
+impl TryFrom<KeyedUriEntry> for KeyedUri {
+    type Error = String;
+    fn try_from(value: KeyedUriEntry) -> std::result::Result<Self, Self::Error> {
+        let uri = Uri::from_str(&value.uri)
+            .map_err(|err| format!("invalid uri \"{}\": {err}", value.uri))?;
+        if uri.host().is_none() {
+            return Err(format!("invalid uri \"{}\": missing host", value.uri));
+        }
+        let pubkey = PublicKey::from_str(&value.pubkey).map_err(|err| {
+            format!(
+                "invalid pubkey \"{}\" for uri \"{}\": {err}",
+                value.pubkey, value.uri
+            )
+        })?;
+        Ok(Self {
+            uri,
+            pubkey: Arc::new(pubkey),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyedUri {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        KeyedUriEntry::deserialize(deserializer)?
+            .try_into()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// A non-empty list of keyed uris without duplicate uris. In settings this is
+/// either a single keyed uri or a list of keyed uris. Every entry is
+/// validated when the settings are loaded and errors name the offending entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyedUris(Vec<KeyedUri>);
+
+impl Deref for KeyedUris {
+    type Target = [KeyedUri];
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl From<KeyedUri> for KeyedUris {
+    fn from(value: KeyedUri) -> Self {
+        Self(vec![value])
+    }
+}
+
+impl TryFrom<Vec<KeyedUri>> for KeyedUris {
+    type Error = String;
+    fn try_from(value: Vec<KeyedUri>) -> std::result::Result<Self, Self::Error> {
+        if value.is_empty() {
+            return Err("empty keyed uri list".to_string());
+        }
+        let mut seen: HashMap<&Uri, usize> = HashMap::new();
+        for (index, keyed_uri) in value.iter().enumerate() {
+            if let Some(first) = seen.insert(&keyed_uri.uri, index) {
+                return Err(format!(
+                    "entry {index}: duplicate uri \"{}\" of entry {first}",
+                    keyed_uri.uri
+                ));
+            }
+        }
+        Ok(Self(value))
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyedUris {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum OneOrMany {
+            One(KeyedUriEntry),
+            Many(Vec<KeyedUriEntry>),
+        }
+
+        let keyed_uris = match OneOrMany::deserialize(deserializer)? {
+            OneOrMany::One(entry) => vec![KeyedUri::try_from(entry).map_err(de::Error::custom)?],
+            OneOrMany::Many(entries) => entries
+                .into_iter()
+                .enumerate()
+                .map(|(index, entry)| {
+                    KeyedUri::try_from(entry)
+                        .map_err(|err| de::Error::custom(format!("entry {index}: {err}")))
+                })
+                .collect::<std::result::Result<Vec<_>, _>>()?,
+        };
+        keyed_uris.try_into().map_err(de::Error::custom)
+    }
+}
+
 impl PartialEq for KeyedUri {
     fn eq(&self, other: &Self) -> bool {
         self.uri.eq(&other.uri) && self.pubkey.eq(&other.pubkey)
@@ -68,3 +173,44 @@ impl TryFrom<helium_proto::RoutingAddress> for KeyedUri {
         Ok(result)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PUBKEY: &str = "137oJzq1qZpSbzHawaysTGGsRCYTXG1MiTMQNxYSsQJp4YMDdN8";
+
+    fn parse(value: serde_json::Value) -> std::result::Result<KeyedUris, String> {
+        serde_json::from_value(value).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn keyed_uris() {
+        let single = parse(serde_json::json!({"uri": "http://a.io:6080", "pubkey": PUBKEY}))
+            .expect("single keyed uri");
+        assert_eq!(1, single.len());
+
+        let list = parse(serde_json::json!([
+            {"uri": "http://a.io:6080", "pubkey": PUBKEY},
+            {"uri": "http://b.io:6080", "pubkey": PUBKEY},
+        ]))
+        .expect("keyed uri list");
+        assert_eq!(2, list.len());
+
+        let err = parse(serde_json::json!([
+            {"uri": "http://a.io:6080", "pubkey": PUBKEY},
+            {"uri": "not a uri", "pubkey": PUBKEY},
+        ]))
+        .expect_err("invalid uri");
+        assert!(err.contains("entry 1"), "{err}");
+
+        let err = parse(serde_json::json!([
+            {"uri": "http://a.io:6080", "pubkey": PUBKEY},
+            {"uri": "http://a.io:6080", "pubkey": PUBKEY},
+        ]))
+        .expect_err("duplicate uri");
+        assert!(err.contains("duplicate"), "{err}");
+
+        parse(serde_json::json!([])).expect_err("empty list");
+    }
+}
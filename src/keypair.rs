@@ -1,24 +1,40 @@
-use crate::{DecodeError, Error, Result};
+use crate::{base64::Base64, DecodeError, Error, Result};
 #[cfg(feature = "ecc608")]
 use helium_crypto::ecc608;
 #[cfg(feature = "tpm")]
 use helium_crypto::tpm;
 use helium_crypto::{KeyTag, KeyType, Network};
 use http::Uri;
-use rand::rngs::OsRng;
-use serde::{de, Deserializer};
+use rand::{rngs::OsRng, RngCore};
+use serde::{de, Deserializer, Serialize};
 #[cfg(feature = "ecc608")]
 use std::path::Path;
-use std::{collections::HashMap, convert::TryFrom, fmt, fs, io, path, str::FromStr};
-use tonic::async_trait;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fmt, fs, io, path,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 #[derive(Debug)]
-pub struct Keypair(helium_crypto::Keypair);
+pub struct Keypair(KeypairKind);
 pub type PublicKey = helium_crypto::PublicKey;
 
-#[async_trait]
+#[derive(Debug)]
+enum KeypairKind {
+    /// A key file or secure element keypair
+    Crypto(helium_crypto::Keypair),
+    /// A keypair in a hardware security module
+    #[cfg(feature = "pkcs11")]
+    Pkcs11(pkcs11::Keypair),
+}
+
+/// Signs a message in place. The signing future is returned as is instead of
+/// boxed, since uplinks are signed per packet.
 pub trait Sign {
-    async fn sign<K>(&mut self, keypair: K) -> Result
+    fn sign<K>(&mut self, keypair: K) -> impl std::future::Future<Output = Result> + Send
     where
         K: AsRef<Keypair> + std::marker::Send + 'static;
 }
@@ -36,42 +52,32 @@ macro_rules! uri_error {
     };
 }
 
+#[cfg(feature = "pkcs11")]
+mod pkcs11;
+
 impl From<helium_crypto::Keypair> for Keypair {
     fn from(v: helium_crypto::Keypair) -> Self {
-        Self(v)
+        Self(KeypairKind::Crypto(v))
     }
 }
 
 impl FromStr for Keypair {
     type Err = Error;
     fn from_str(str: &str) -> Result<Self> {
+        // Plain relative or non unix file paths (like on Windows) are not valid
+        // uris but are accepted as key files to ease local development
+        if !str.contains("://") && !str.starts_with('/') {
+            return Self::load_or_generate(str, Network::MainNet);
+        }
         let url: Uri = str
             .parse()
             .map_err(|err| uri_error!("invalid keypair url \"{str}\": {err:?}"))?;
         match url.scheme_str() {
-            Some("file") | None => match Self::load_from_file(url.path()) {
-                Ok(k) => Ok(k),
-                Err(Error::IO(io_error)) if io_error.kind() == std::io::ErrorKind::NotFound => {
-                    let args = KeypairArgs::from_uri(&url)?;
-                    let network = args.get::<Network>("network", Network::MainNet)?;
-                    let new_key: Keypair = helium_crypto::Keypair::generate(
-                        KeyTag {
-                            network,
-                            key_type: KeyType::Ed25519,
-                        },
-                        &mut OsRng,
-                    )
-                    .into();
-                    new_key.save_to_file(url.path()).map_err(|err| {
-                        uri_error!("unable to save key file \"{}\": {err:?}", url.path())
-                    })?;
-                    Ok(new_key)
-                }
-                Err(err) => Err(uri_error!(
-                    "unable to load key file \"{}\": {err:?}",
-                    url.path()
-                )),
-            },
+            Some("file") | None => {
+                let args = KeypairArgs::from_uri(&url)?;
+                let network = args.get::<Network>("network", Network::MainNet)?;
+                Self::load_or_generate(url.path(), network)
+            }
             #[cfg(feature = "ecc608")]
             Some("ecc") => {
                 let args = KeypairArgs::from_uri(&url).map_err(DecodeError::keypair_uri)?;
@@ -120,19 +126,55 @@ impl FromStr for Keypair {
 
                 Ok(keypair.into())
             }
+            #[cfg(feature = "pkcs11")]
+            Some("pkcs11") => {
+                let args = KeypairArgs::from_uri(&url)?;
+                let keypair = pkcs11::Keypair::from_uri(&url, &args)?;
+                Ok(Self(KeypairKind::Pkcs11(keypair)))
+            }
             Some(unknown) => Err(uri_error!("unkown keypair scheme: \"{unknown}\"")),
         }
     }
 }
 
-impl std::ops::Deref for Keypair {
-    type Target = helium_crypto::Keypair;
-    fn deref(&self) -> &Self::Target {
-        &self.0
+impl Keypair {
+    pub fn public_key(&self) -> &PublicKey {
+        match &self.0 {
+            KeypairKind::Crypto(keypair) => keypair.public_key(),
+            #[cfg(feature = "pkcs11")]
+            KeypairKind::Pkcs11(keypair) => keypair.public_key(),
+        }
+    }
+
+    pub fn key_tag(&self) -> KeyTag {
+        match &self.0 {
+            KeypairKind::Crypto(keypair) => keypair.key_tag(),
+            #[cfg(feature = "pkcs11")]
+            KeypairKind::Pkcs11(keypair) => keypair.key_tag(),
+        }
+    }
+
+    /// Signs the given message. Signing can block on a secure element or
+    /// hardware security module, async code signs through `crate::sign`.
+    pub fn sign(&self, msg: &[u8]) -> Result<Vec<u8>> {
+        use helium_crypto::Sign;
+        match &self.0 {
+            KeypairKind::Crypto(keypair) => Ok(keypair.sign(msg)?),
+            #[cfg(feature = "pkcs11")]
+            KeypairKind::Pkcs11(keypair) => keypair.sign(msg),
+        }
+    }
+
+    /// The ECDH shared secret with the given public key. Keys in a hardware
+    /// security module do not support key agreement.
+    pub fn ecdh(&self, public_key: &PublicKey) -> Result<helium_crypto::ecc_compact::SharedSecret> {
+        match &self.0 {
+            KeypairKind::Crypto(keypair) => Ok(keypair.ecdh(public_key)?),
+            #[cfg(feature = "pkcs11")]
+            KeypairKind::Pkcs11(_) => Err(Error::custom("pkcs11 keys do not support ecdh")),
+        }
     }
-}
 
-impl Keypair {
     pub fn new() -> Self {
         let keypair = helium_crypto::Keypair::generate(
             KeyTag {
@@ -144,17 +186,204 @@ impl Keypair {
         keypair.into()
     }
 
+    /// Loads the keypair from the given file, generating and saving a new
+    /// keypair for the given network if the file does not exist
+    pub fn load_or_generate(path: &str, network: Network) -> Result<Self> {
+        match Self::load_from_file(path) {
+            Ok(k) => Ok(k),
+            Err(Error::IO(io_error)) if io_error.kind() == std::io::ErrorKind::NotFound => {
+                let new_key: Keypair = helium_crypto::Keypair::generate(
+                    KeyTag {
+                        network,
+                        key_type: KeyType::Ed25519,
+                    },
+                    &mut OsRng,
+                )
+                .into();
+                new_key
+                    .save_to_file(path)
+                    .map_err(|err| uri_error!("unable to save key file \"{path}\": {err:?}"))?;
+                Ok(new_key)
+            }
+            Err(err) => Err(uri_error!("unable to load key file \"{path}\": {err:?}")),
+        }
+    }
+
+    /// Generates a new keypair with the network and key type of the given tag
+    pub fn generate(key_tag: KeyTag) -> Self {
+        helium_crypto::Keypair::generate(key_tag, &mut OsRng).into()
+    }
+
     pub fn load_from_file(path: &str) -> Result<Self> {
         let data = fs::read(path)?;
         Ok(helium_crypto::Keypair::try_from(&data[..])?.into())
     }
 
     pub fn save_to_file(&self, path: &str) -> io::Result<()> {
-        if let Some(parent) = path::PathBuf::from(path).parent() {
-            fs::create_dir_all(parent)?;
+        match &self.0 {
+            KeypairKind::Crypto(keypair) => {
+                if let Some(parent) = path::PathBuf::from(path).parent() {
+                    fs::create_dir_all(parent)?;
+                };
+                fs::write(path, keypair.to_vec())
+            }
+            #[cfg(feature = "pkcs11")]
+            KeypairKind::Pkcs11(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "pkcs11 keys can not be saved to a file",
+            )),
+        }
+    }
+}
+
+/// Attributes of a key generated in a TPM, a non restricted key for signing
+/// and key agreement that never leaves the TPM
+#[cfg(feature = "tpm")]
+const TPM_KEY_ATTRIBUTES: &str =
+    "fixedtpm|fixedparent|sensitivedataorigin|userwithauth|sign|decrypt";
+/// Number of keys generated in a TPM before giving up on a compact key
+#[cfg(feature = "tpm")]
+const TPM_GENERATE_ATTEMPTS: usize = 16;
+
+#[cfg(feature = "tpm")]
+impl Keypair {
+    /// Generates a new key in the TPM and makes it persistent at the given
+    /// handle. Returns the keypair and the keypair uri to load it with.
+    ///
+    /// Generation uses the tpm2-tools, which have to be installed. Only about
+    /// half of all P-256 keys are compact keys as used by Helium, so a key
+    /// that does not load is evicted again and generation is retried.
+    pub fn generate_tpm(network: Network, handle: u32) -> Result<(Self, String)> {
+        let handle_str = format!("{handle:#010x}");
+        if tpm2("tpm2_readpublic", &["-c", &handle_str]).is_ok() {
+            return Err(Error::custom(format!(
+                "tpm handle {handle_str} is already in use"
+            )));
+        }
+        let dir = std::env::temp_dir().join(format!("helium_gateway_tpm_{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let keypair = Self::generate_tpm_in(&dir, network, handle);
+        let _ = fs::remove_dir_all(&dir);
+        let uri = match network {
+            Network::MainNet => format!("tpm://esys/{handle_str}"),
+            _ => format!("tpm://esys/{handle_str}?network={network}"),
         };
-        fs::write(path, self.0.to_vec())?;
-        Ok(())
+        Ok((keypair?, uri))
+    }
+
+    /// Generates keys under a new primary key, with the key contexts in the
+    /// given directory, until a key loads as a Helium keypair
+    fn generate_tpm_in(dir: &path::Path, network: Network, handle: u32) -> Result<Self> {
+        let handle_str = format!("{handle:#010x}");
+        let ctx_path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let primary = ctx_path("primary.ctx");
+        let public = ctx_path("key.pub");
+        let private = ctx_path("key.priv");
+        let key = ctx_path("key.ctx");
+        tpm2(
+            "tpm2_createprimary",
+            &["-C", "o", "-g", "sha256", "-G", "ecc256", "-c", &primary],
+        )?;
+        for _ in 0..TPM_GENERATE_ATTEMPTS {
+            tpm2(
+                "tpm2_create",
+                &[
+                    "-C",
+                    &primary,
+                    "-g",
+                    "sha256",
+                    "-G",
+                    "ecc256:null",
+                    "-a",
+                    TPM_KEY_ATTRIBUTES,
+                    "-u",
+                    &public,
+                    "-r",
+                    &private,
+                ],
+            )?;
+            tpm2(
+                "tpm2_load",
+                &["-C", &primary, "-u", &public, "-r", &private, "-c", &key],
+            )?;
+            tpm2("tpm2_evictcontrol", &["-C", "o", "-c", &key, &handle_str])?;
+            match tpm::KeypairHandle::from_key_handle(network, handle) {
+                Ok(keypair) => return Ok(helium_crypto::Keypair::from(keypair).into()),
+                Err(_) => tpm2("tpm2_evictcontrol", &["-C", "o", "-c", &handle_str])?,
+            }
+        }
+        Err(Error::custom(format!(
+            "no compact tpm key after {TPM_GENERATE_ATTEMPTS} attempts"
+        )))
+    }
+}
+
+/// Runs the given tpm2-tools command, failing with its error output
+#[cfg(feature = "tpm")]
+fn tpm2(tool: &str, args: &[&str]) -> Result {
+    let output = std::process::Command::new(tool)
+        .args(args)
+        .output()
+        .map_err(|err| Error::custom(format!("could not run {tool}: {err}")))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(Error::custom(format!(
+        "{tool} failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    )))
+}
+
+/// Longest time a self test waits for a signature, since an unreachable
+/// secure element can block instead of failing
+const SELF_TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outcome of a sign and verify round trip with the gateway keypair
+#[derive(Debug, Clone, Serialize)]
+pub struct SelfTest {
+    /// Key type of the keypair
+    pub key_type: String,
+    /// Milliseconds the signature took
+    pub sign_ms: u64,
+    /// Why the self test failed, None if it passed
+    pub error: Option<String>,
+}
+
+impl SelfTest {
+    /// Signs a random message with the given keypair and verifies the
+    /// signature with its public key
+    pub async fn run(keypair: Arc<Keypair>) -> Self {
+        let key_type = format!("{:?}", keypair.key_tag().key_type);
+        let mut message = vec![0u8; 32];
+        OsRng.fill_bytes(&mut message);
+        let public_key = keypair.public_key().clone();
+        let started = Instant::now();
+        let signed =
+            tokio::time::timeout(SELF_TEST_TIMEOUT, crate::sign(keypair, message.clone())).await;
+        let sign_ms = started.elapsed().as_millis() as u64;
+        let error = match signed {
+            Err(_) => Some(format!(
+                "signing timed out after {}s",
+                SELF_TEST_TIMEOUT.as_secs()
+            )),
+            Ok(Err(err)) => Some(format!("signing failed: {err}")),
+            Ok(Ok(signature)) => {
+                use helium_crypto::Verify;
+                public_key
+                    .verify(&message, &signature)
+                    .err()
+                    .map(|err| format!("signature verification failed: {err}"))
+            }
+        };
+        Self {
+            key_type,
+            sign_ms,
+            error,
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
     }
 }
 
@@ -164,6 +393,113 @@ impl Default for Keypair {
     }
 }
 
+/// Proof that the holder of a gateway key rotated it to a new key. The old
+/// key authorizes the rotation and the new key proves it is held by the same
+/// gateway, both by signing the rotation message.
+#[derive(Debug, Clone, Serialize)]
+pub struct RotationProof {
+    pub old_key: String,
+    pub new_key: String,
+    /// Unix time in seconds of the rotation
+    pub timestamp: u64,
+    /// Base64 signature of the rotation message by the old key
+    pub old_signature: String,
+    /// Base64 signature of the rotation message by the new key
+    pub new_signature: String,
+    /// The onboarding key, if it differs from the old key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub onboarding_key: Option<String>,
+    /// Base64 signature of the rotation message by the onboarding key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub onboarding_signature: Option<String>,
+}
+
+impl RotationProof {
+    /// The message signed for a rotation from the old to the new key at the
+    /// given unix time
+    pub fn message(old_key: &PublicKey, new_key: &PublicKey, timestamp: u64) -> Vec<u8> {
+        format!("helium_gateway key rotate {old_key} {new_key} {timestamp}").into_bytes()
+    }
+
+    /// Signs the rotation from the old to the new keypair, and with the
+    /// onboarding keypair if it differs from the old keypair
+    pub async fn sign(
+        old: Arc<Keypair>,
+        new: Arc<Keypair>,
+        onboarding: Option<Arc<Keypair>>,
+    ) -> Result<Self> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let old_key = old.public_key().clone();
+        let new_key = new.public_key().clone();
+        let message = Self::message(&old_key, &new_key, timestamp);
+        let onboarding = onboarding.filter(|onboarding| onboarding.public_key() != &old_key);
+        let (onboarding_key, onboarding_signature) = match onboarding {
+            Some(onboarding) => (
+                Some(onboarding.public_key().to_string()),
+                Some(crate::sign(onboarding, message.clone()).await?.to_b64()),
+            ),
+            None => (None, None),
+        };
+        Ok(Self {
+            old_signature: crate::sign(old, message.clone()).await?.to_b64(),
+            new_signature: crate::sign(new, message).await?.to_b64(),
+            old_key: old_key.to_string(),
+            new_key: new_key.to_string(),
+            timestamp,
+            onboarding_key,
+            onboarding_signature,
+        })
+    }
+}
+
+/// Replaces the key file at the given path with the given keypair. The new key
+/// is written next to the key file and renamed over it, keeping the old key
+/// file with a ".bak" extension until [`remove_key_backup`]. If the new key
+/// file does not load back as the given keypair the old key file is restored.
+pub fn replace_key_file(path: &str, keypair: &Keypair) -> Result {
+    let new_path = format!("{path}.new");
+    let backup_path = key_backup_path(path);
+    keypair.save_to_file(&new_path)?;
+    fs::copy(path, &backup_path)?;
+    fs::rename(&new_path, path)?;
+    match Keypair::load_from_file(path) {
+        Ok(loaded) if loaded.public_key() == keypair.public_key() => Ok(()),
+        Ok(_) | Err(_) => {
+            fs::rename(&backup_path, path)?;
+            Err(Error::custom(format!(
+                "new key file \"{path}\" did not load back, restored the old key"
+            )))
+        }
+    }
+}
+
+/// Overwrites the old key file kept by [`replace_key_file`] with zeros before
+/// removing it, so the old key can not be recovered from the freed blocks
+pub fn remove_key_backup(path: &str) -> Result {
+    let backup_path = key_backup_path(path);
+    let mut file = fs::OpenOptions::new().write(true).open(&backup_path)?;
+    let len = file.metadata()?.len();
+    io::copy(&mut io::Read::take(io::repeat(0), len), &mut file)?;
+    file.sync_all()?;
+    drop(file);
+    fs::remove_file(&backup_path)?;
+    Ok(())
+}
+
+fn key_backup_path(path: &str) -> String {
+    format!("{path}.bak")
+}
+
+/// The path of a key file keypair uri, or None for other keypairs like
+/// secure elements
+pub fn key_file_path(uri: &str) -> Option<String> {
+    if !uri.contains("://") && !uri.starts_with('/') {
+        return Some(uri.to_string());
+    }
+    let url: Uri = uri.parse().ok()?;
+    matches!(url.scheme_str(), Some("file") | None).then(|| url.path().to_string())
+}
+
 #[derive(Debug)]
 struct KeypairArgs(HashMap<String, String>);
 
@@ -232,4 +568,17 @@ mod tests {
                 .expect("network")
         );
     }
+
+    #[test]
+    fn key_file_paths() {
+        assert_eq!(
+            Some("gateway_key.bin"),
+            key_file_path("gateway_key.bin").as_deref()
+        );
+        assert_eq!(
+            Some("/etc/helium_gateway/gateway_key.bin"),
+            key_file_path("/etc/helium_gateway/gateway_key.bin").as_deref()
+        );
+        assert_eq!(None, key_file_path("ecc://i2c-1:96?slot=0"));
+    }
 }
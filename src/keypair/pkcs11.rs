@@ -0,0 +1,453 @@
+//! Keypairs in a hardware security module or secure enclave that exposes a
+//! PKCS#11 module.
+//!
+//! The keypair uri names the slot, the module and the key:
+//!
+//! ```text
+//! pkcs11://0?module=/usr/lib/softhsm/libsofthsm2.so&label=gateway&pin=1234
+//! ```
+//!
+//! The key is found by its `label` or hex encoded `id`, and the user `pin` is
+//! optional for tokens that do not require a login. Keys have to be NIST P-256
+//! keys whose public key is compact, as used by Helium ecc_compact keys.
+//! Signing uses CKM_ECDSA over the SHA-256 digest of the message.
+//!
+//! The module is loaded at runtime, so no PKCS#11 library is needed to build.
+//! Calls into the module are serialized and can block, which is why async code
+//! signs through `crate::sign` on the blocking thread pool.
+
+use super::{KeyTag, KeyType, KeypairArgs, Network};
+use crate::{DecodeError, Error, PublicKey, Result};
+use http::Uri;
+use sha2::{Digest, Sha256};
+use std::{
+    ffi::{c_void, CStr, CString},
+    fmt,
+    os::raw::c_ulong,
+    ptr,
+    sync::Mutex,
+};
+
+type CkUlong = c_ulong;
+type CkRv = CkUlong;
+
+const CKR_OK: CkRv = 0x000;
+const CKR_USER_ALREADY_LOGGED_IN: CkRv = 0x100;
+const CKR_CRYPTOKI_ALREADY_INITIALIZED: CkRv = 0x191;
+const CKF_SERIAL_SESSION: CkUlong = 0x4;
+const CKU_USER: CkUlong = 1;
+const CKA_CLASS: CkUlong = 0x000;
+const CKA_LABEL: CkUlong = 0x003;
+const CKA_ID: CkUlong = 0x102;
+const CKA_EC_POINT: CkUlong = 0x181;
+const CKO_PUBLIC_KEY: CkUlong = 2;
+const CKO_PRIVATE_KEY: CkUlong = 3;
+const CKM_ECDSA: CkUlong = 0x1041;
+
+/// Length of an uncompressed P-256 point
+const EC_POINT_LEN: usize = 65;
+/// Length of a raw P-256 ECDSA signature
+const SIGNATURE_LEN: usize = 64;
+/// The P-256 field prime, big endian
+const P256_PRIME: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+];
+
+#[repr(C)]
+struct CkVersion {
+    major: u8,
+    minor: u8,
+}
+
+#[repr(C)]
+struct CkAttribute {
+    kind: CkUlong,
+    value: *mut c_void,
+    len: CkUlong,
+}
+
+#[repr(C)]
+struct CkMechanism {
+    mechanism: CkUlong,
+    parameter: *mut c_void,
+    len: CkUlong,
+}
+
+type Unused = Option<unsafe extern "C" fn()>;
+
+/// The leading part of the PKCS#11 function list, up to the functions used
+#[repr(C)]
+struct FunctionList {
+    version: CkVersion,
+    initialize: unsafe extern "C" fn(*mut c_void) -> CkRv,
+    // C_Finalize through C_SetPIN
+    _unused_1: [Unused; 11],
+    open_session:
+        unsafe extern "C" fn(CkUlong, CkUlong, *mut c_void, *mut c_void, *mut CkUlong) -> CkRv,
+    // C_CloseSession through C_SetOperationState
+    _unused_2: [Unused; 5],
+    login: unsafe extern "C" fn(CkUlong, CkUlong, *const u8, CkUlong) -> CkRv,
+    // C_Logout through C_GetObjectSize
+    _unused_3: [Unused; 5],
+    get_attribute_value: unsafe extern "C" fn(CkUlong, CkUlong, *mut CkAttribute, CkUlong) -> CkRv,
+    // C_SetAttributeValue
+    _unused_4: [Unused; 1],
+    find_objects_init: unsafe extern "C" fn(CkUlong, *mut CkAttribute, CkUlong) -> CkRv,
+    find_objects: unsafe extern "C" fn(CkUlong, *mut CkUlong, CkUlong, *mut CkUlong) -> CkRv,
+    find_objects_final: unsafe extern "C" fn(CkUlong) -> CkRv,
+    // C_EncryptInit through C_DigestFinal
+    _unused_5: [Unused; 13],
+    sign_init: unsafe extern "C" fn(CkUlong, *mut CkMechanism, CkUlong) -> CkRv,
+    sign: unsafe extern "C" fn(CkUlong, *const u8, CkUlong, *mut u8, *mut CkUlong) -> CkRv,
+}
+
+type GetFunctionList = unsafe extern "C" fn(*mut *const FunctionList) -> CkRv;
+
+/// How the key is found on the token
+enum KeySelector {
+    Label(String),
+    Id(Vec<u8>),
+}
+
+impl KeySelector {
+    fn attribute(&mut self) -> CkAttribute {
+        let (kind, value) = match self {
+            Self::Label(label) => (CKA_LABEL, label.as_bytes()),
+            Self::Id(id) => (CKA_ID, id.as_slice()),
+        };
+        CkAttribute {
+            kind,
+            value: value.as_ptr() as *mut c_void,
+            len: value.len() as CkUlong,
+        }
+    }
+}
+
+/// A session with the token holding the key
+struct Session {
+    functions: *const FunctionList,
+    handle: CkUlong,
+}
+
+// The function list is static for the lifetime of the process, since the
+// module is never unloaded, and all calls through a session are serialized
+// by the mutex of the keypair that owns it.
+unsafe impl Send for Session {}
+
+pub struct Keypair {
+    network: Network,
+    public_key: PublicKey,
+    session: Mutex<Session>,
+    key: CkUlong,
+}
+
+impl fmt::Debug for Keypair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pkcs11Keypair")
+            .field("public_key", &self.public_key)
+            .finish()
+    }
+}
+
+macro_rules! check {
+    ($call:literal, $rv:expr) => {
+        match $rv {
+            CKR_OK => Ok(()),
+            rv => Err(Error::custom(format!("pkcs11 {} failed: {rv:#x}", $call))),
+        }
+    };
+}
+
+impl Keypair {
+    pub fn from_uri(url: &Uri, args: &KeypairArgs) -> Result<Self> {
+        let uri_error = |msg: String| DecodeError::keypair_uri(msg);
+        let slot = url
+            .host()
+            .and_then(|slot| slot.parse::<CkUlong>().ok())
+            .ok_or_else(|| uri_error(format!("missing or invalid pkcs11 slot in \"{url}\"")))?;
+        let module = args
+            .0
+            .get("module")
+            .ok_or_else(|| uri_error("missing pkcs11 module".to_string()))?;
+        let mut selector = match (args.0.get("label"), args.0.get("id")) {
+            (Some(label), _) => KeySelector::Label(label.clone()),
+            (None, Some(id)) => KeySelector::Id(
+                hex_decode(id).ok_or_else(|| uri_error(format!("invalid pkcs11 key id {id}")))?,
+            ),
+            (None, None) => return Err(uri_error("missing pkcs11 key label or id".to_string())),
+        };
+        let network = args.get("network", Network::MainNet)?;
+
+        let functions = load_module(module)?;
+        let session = Session::open(functions, slot, args.0.get("pin"))?;
+        let key = session.find_object(CKO_PRIVATE_KEY, &mut selector)?;
+        let public = session.find_object(CKO_PUBLIC_KEY, &mut selector)?;
+        let point = session.attribute(public, CKA_EC_POINT)?;
+        let public_key = compact_public_key(&point, network)?;
+        Ok(Self {
+            network,
+            public_key,
+            session: Mutex::new(session),
+            key,
+        })
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    pub fn key_tag(&self) -> KeyTag {
+        KeyTag {
+            network: self.network,
+            key_type: KeyType::EccCompact,
+        }
+    }
+
+    /// Signs the SHA-256 digest of the message, returning a DER encoded
+    /// signature like ecc_compact keys do
+    pub fn sign(&self, msg: &[u8]) -> Result<Vec<u8>> {
+        let digest = Sha256::digest(msg);
+        let session = self
+            .session
+            .lock()
+            .map_err(|_| Error::custom("pkcs11 session poisoned"))?;
+        let mut mechanism = CkMechanism {
+            mechanism: CKM_ECDSA,
+            parameter: ptr::null_mut(),
+            len: 0,
+        };
+        let mut signature = [0u8; SIGNATURE_LEN];
+        let mut signature_len = SIGNATURE_LEN as CkUlong;
+        unsafe {
+            let functions = &*session.functions;
+            check!(
+                "C_SignInit",
+                (functions.sign_init)(session.handle, &mut mechanism, self.key)
+            )?;
+            check!(
+                "C_Sign",
+                (functions.sign)(
+                    session.handle,
+                    digest.as_ptr(),
+                    digest.len() as CkUlong,
+                    signature.as_mut_ptr(),
+                    &mut signature_len,
+                )
+            )?;
+        }
+        if signature_len as usize != SIGNATURE_LEN {
+            return Err(Error::custom("invalid pkcs11 signature length"));
+        }
+        Ok(der_signature(&signature))
+    }
+}
+
+impl Session {
+    fn open(functions: *const FunctionList, slot: CkUlong, pin: Option<&String>) -> Result<Self> {
+        let mut handle = 0;
+        unsafe {
+            let list = &*functions;
+            match (list.initialize)(ptr::null_mut()) {
+                CKR_OK | CKR_CRYPTOKI_ALREADY_INITIALIZED => (),
+                rv => check!("C_Initialize", rv)?,
+            }
+            check!(
+                "C_OpenSession",
+                (list.open_session)(
+                    slot,
+                    CKF_SERIAL_SESSION,
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                    &mut handle,
+                )
+            )?;
+            if let Some(pin) = pin {
+                match (list.login)(handle, CKU_USER, pin.as_ptr(), pin.len() as CkUlong) {
+                    CKR_OK | CKR_USER_ALREADY_LOGGED_IN => (),
+                    rv => check!("C_Login", rv)?,
+                }
+            }
+        }
+        Ok(Self { functions, handle })
+    }
+
+    fn find_object(&self, class: CkUlong, selector: &mut KeySelector) -> Result<CkUlong> {
+        let mut class = class;
+        let mut template = [
+            CkAttribute {
+                kind: CKA_CLASS,
+                value: &mut class as *mut CkUlong as *mut c_void,
+                len: std::mem::size_of::<CkUlong>() as CkUlong,
+            },
+            selector.attribute(),
+        ];
+        let mut object = 0;
+        let mut count = 0;
+        unsafe {
+            let list = &*self.functions;
+            check!(
+                "C_FindObjectsInit",
+                (list.find_objects_init)(
+                    self.handle,
+                    template.as_mut_ptr(),
+                    template.len() as CkUlong
+                )
+            )?;
+            let found = check!(
+                "C_FindObjects",
+                (list.find_objects)(self.handle, &mut object, 1, &mut count)
+            );
+            check!("C_FindObjectsFinal", (list.find_objects_final)(self.handle))?;
+            found?;
+        }
+        if count == 0 {
+            return Err(Error::custom("pkcs11 key not found"));
+        }
+        Ok(object)
+    }
+
+    fn attribute(&self, object: CkUlong, kind: CkUlong) -> Result<Vec<u8>> {
+        let mut template = CkAttribute {
+            kind,
+            value: ptr::null_mut(),
+            len: 0,
+        };
+        unsafe {
+            let list = &*self.functions;
+            check!(
+                "C_GetAttributeValue",
+                (list.get_attribute_value)(self.handle, object, &mut template, 1)
+            )?;
+            let mut value = vec![0u8; template.len as usize];
+            template.value = value.as_mut_ptr() as *mut c_void;
+            check!(
+                "C_GetAttributeValue",
+                (list.get_attribute_value)(self.handle, object, &mut template, 1)
+            )?;
+            value.truncate(template.len as usize);
+            Ok(value)
+        }
+    }
+}
+
+/// Loads the module at the given path and returns its function list. The
+/// module stays loaded for the lifetime of the process.
+fn load_module(path: &str) -> Result<*const FunctionList> {
+    let module_error = |msg: &str| Error::custom(format!("pkcs11 module \"{path}\": {msg}"));
+    let c_path = CString::new(path).map_err(|_| module_error("invalid path"))?;
+    unsafe {
+        let handle = libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW);
+        if handle.is_null() {
+            let err = libc::dlerror();
+            let msg = if err.is_null() {
+                "could not load".into()
+            } else {
+                CStr::from_ptr(err).to_string_lossy()
+            };
+            return Err(module_error(&msg));
+        }
+        let symbol = libc::dlsym(handle, c"C_GetFunctionList".as_ptr());
+        if symbol.is_null() {
+            return Err(module_error("missing C_GetFunctionList"));
+        }
+        let get_function_list: GetFunctionList = std::mem::transmute(symbol);
+        let mut functions = ptr::null();
+        check!("C_GetFunctionList", get_function_list(&mut functions))?;
+        if functions.is_null() {
+            return Err(module_error("no function list"));
+        }
+        Ok(functions)
+    }
+}
+
+/// The Helium public key of a CKA_EC_POINT value, which is an uncompressed
+/// point either DER wrapped in an octet string or raw. Fails for points that
+/// are not compact.
+fn compact_public_key(point: &[u8], network: Network) -> Result<PublicKey> {
+    let point = match point {
+        [0x04, len, rest @ ..] if *len as usize == EC_POINT_LEN && rest.len() == EC_POINT_LEN => {
+            rest
+        }
+        raw => raw,
+    };
+    if point.len() != EC_POINT_LEN || point[0] != 0x04 {
+        return Err(Error::custom("unsupported pkcs11 key, not a P-256 key"));
+    }
+    let (x, y) = point[1..].split_at(32);
+    // A compact point has the smaller of the two possible y coordinates
+    let mut other_y = [0u8; 32];
+    let mut borrow = 0i16;
+    for i in (0..32).rev() {
+        let diff = i16::from(P256_PRIME[i]) - i16::from(y[i]) - borrow;
+        borrow = i16::from(diff < 0);
+        other_y[i] = diff.rem_euclid(256) as u8;
+    }
+    if y > &other_y[..] {
+        return Err(Error::custom("unsupported pkcs11 key, not a compact key"));
+    }
+    let tag = match network {
+        Network::MainNet => 0x00,
+        Network::TestNet => 0x10,
+    };
+    let mut bytes = vec![tag];
+    bytes.extend_from_slice(x);
+    Ok(PublicKey::from_bytes(bytes)?)
+}
+
+/// DER encodes a raw ECDSA signature of concatenated r and s values
+fn der_signature(raw: &[u8]) -> Vec<u8> {
+    let integer = |value: &[u8]| {
+        let start = value
+            .iter()
+            .position(|b| *b != 0)
+            .unwrap_or(value.len() - 1);
+        let value = &value[start..];
+        let pad = value[0] & 0x80 != 0;
+        let mut der = vec![0x02, (value.len() + usize::from(pad)) as u8];
+        if pad {
+            der.push(0);
+        }
+        der.extend_from_slice(value);
+        der
+    };
+    let (r, s) = raw.split_at(raw.len() / 2);
+    let body = [integer(r), integer(s)].concat();
+    let mut der = vec![0x30, body.len() as u8];
+    der.extend(body);
+    der
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn der_signatures() {
+        let mut raw = [0u8; SIGNATURE_LEN];
+        raw[0] = 0x80;
+        raw[63] = 0x01;
+        let der = der_signature(&raw);
+        // r gets a leading zero to stay positive, s is trimmed to one byte
+        assert_eq!(&[0x30, 0x26, 0x02, 0x21, 0x00, 0x80], &der[..6]);
+        assert_eq!(&[0x02, 0x01, 0x01], &der[der.len() - 3..]);
+        assert_eq!(der.len(), 2 + 0x26);
+    }
+
+    #[test]
+    fn hex_ids() {
+        assert_eq!(Some(vec![0x01, 0xab]), hex_decode("01ab"));
+        assert_eq!(None, hex_decode("1ab"));
+        assert_eq!(None, hex_decode("zz"));
+    }
+}
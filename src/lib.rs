@@ -1,21 +1,64 @@
+#[cfg(all(feature = "console", not(tokio_unstable)))]
+compile_error!("the console feature requires RUSTFLAGS=\"--cfg tokio_unstable\"");
+
+pub mod audit_log;
 pub mod beaconer;
+pub mod clock;
 pub mod cmd;
+pub mod crc_stats;
+pub mod dc_stats;
+pub mod downlink_arbiter;
+pub mod downlink_stats;
+pub mod duty_cycle;
 pub mod error;
+pub mod forwarder_config;
+pub mod forwarders;
 pub mod gateway;
+pub mod gps;
+pub mod gps_region;
+pub mod hooks;
+pub mod join_vendors;
 pub mod keyed_uri;
 pub mod keypair;
+pub mod local_entropy;
+pub mod log_buffer;
+pub mod matcher;
 pub mod message_cache;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
 pub mod packet;
+pub mod packet_broker;
 
 pub mod packet_router;
+pub mod packet_trace;
+pub mod ping;
+pub mod poc_history;
 pub mod region_watcher;
+pub mod reload;
+#[cfg(feature = "remote-config")]
+pub mod remote_config;
+pub mod secret;
 pub mod server;
 pub mod service;
 pub mod settings;
+pub mod sim;
+pub mod subsystems;
 pub mod sync;
+pub mod uplink_dedup;
+pub mod uplink_filter;
+pub mod uptime;
+pub mod witness_queue;
 
 mod api;
 mod base64;
+#[cfg(any(feature = "webhooks", feature = "remote-config"))]
+mod http_client;
+mod interface;
+mod qos;
+mod state_file;
+mod udp_listener;
 
 pub(crate) use crate::base64::Base64;
 pub use beacon::{Region, RegionParams};
@@ -39,11 +82,8 @@ where
     K: AsRef<Keypair> + std::marker::Send + 'static,
 {
     use futures::TryFutureExt;
-    use helium_crypto::Sign;
     let join_handle: tokio::task::JoinHandle<Result<Vec<u8>>> =
-        tokio::task::spawn_blocking(move || {
-            keypair.as_ref().sign(&data).map_err(crate::Error::from)
-        });
+        tokio::task::spawn_blocking(move || keypair.as_ref().sign(&data));
     join_handle
         .map_err(|err| helium_crypto::Error::from(signature::Error::from_source(err)))
         .await?
@@ -51,7 +91,6 @@ where
 
 macro_rules! impl_sign {
     ($type: ty) => {
-        #[tonic::async_trait]
         impl Sign for $type {
             async fn sign<K>(&mut self, keypair: K) -> Result
             where
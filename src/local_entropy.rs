@@ -0,0 +1,94 @@
+//! Local entropy for beacons.
+//!
+//! The local entropy of a beacon comes from OS randomness. On devices whose OS
+//! entropy pool is weak at boot, hardware random number generators configured
+//! in the settings are mixed into the local entropy. A hardware source is only
+//! mixed in while it passes basic health checks, so a failed source can never
+//! make the local entropy worse than OS randomness alone.
+
+use crate::{Error, Result, Settings};
+use beacon::Entropy;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+/// Number of bytes read from a hardware source for every local entropy
+const SAMPLE_SIZE: usize = 32;
+
+#[derive(Debug)]
+pub struct LocalEntropy {
+    /// Hardware random number generator devices to mix in
+    devices: Vec<PathBuf>,
+    /// Last sample read from each device, used to detect stuck sources
+    last_samples: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl LocalEntropy {
+    /// Creates the local entropy generator and checks the health of the
+    /// configured hardware sources.
+    pub fn new(settings: &Settings) -> Self {
+        let mut local_entropy = Self {
+            devices: settings.poc.entropy_devices.clone(),
+            last_samples: HashMap::new(),
+        };
+        for device in local_entropy.devices.clone() {
+            match local_entropy.sample(&device) {
+                Ok(_) => info!(device = %device.display(), "entropy source healthy"),
+                Err(err) => warn!(device = %device.display(), %err, "entropy source unhealthy"),
+            }
+        }
+        local_entropy
+    }
+
+    /// Generates local entropy from OS randomness, mixed with samples from
+    /// all healthy hardware sources.
+    pub fn generate(&mut self) -> Result<Entropy> {
+        let mut entropy = Entropy::local()?;
+        if self.devices.is_empty() {
+            return Ok(entropy);
+        }
+        let mut hasher = Sha256::new();
+        let mut mixed = 0;
+        for device in self.devices.clone() {
+            match self.sample(&device) {
+                Ok(sample) => {
+                    hasher.update(&sample);
+                    mixed += 1;
+                }
+                Err(err) => {
+                    warn!(device = %device.display(), %err, "skipping unhealthy entropy source")
+                }
+            }
+        }
+        if mixed > 0 {
+            // XOR with an independent source never reduces the entropy of the
+            // OS randomness
+            let digest = hasher.finalize();
+            for (i, byte) in entropy.data.iter_mut().enumerate() {
+                *byte ^= digest[i % digest.len()];
+            }
+        }
+        Ok(entropy)
+    }
+
+    /// Reads a sample from the given device and checks it for the failure
+    /// modes of a broken source: a constant output or a repeated sample.
+    fn sample(&mut self, device: &Path) -> Result<Vec<u8>> {
+        let mut sample = vec![0u8; SAMPLE_SIZE];
+        File::open(device)?.read_exact(&mut sample)?;
+        if sample.iter().all(|byte| *byte == sample[0]) {
+            return Err(Error::custom("constant output"));
+        }
+        if self.last_samples.get(device) == Some(&sample) {
+            return Err(Error::custom("repeated sample"));
+        }
+        self.last_samples
+            .insert(device.to_path_buf(), sample.clone());
+        Ok(sample)
+    }
+}
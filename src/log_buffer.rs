@@ -0,0 +1,162 @@
+//! Memory ring buffer of recent log events.
+//!
+//! The binary adds the layer of a [`LogBuffer`] to its log subscriber, which
+//! keeps the most recent log events that pass the configured log level in
+//! memory. The local API serves them, so the recent history of a gateway can
+//! be retrieved after an incident on systems without a persistent journal.
+
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    fmt::{self, Write},
+    sync::{Arc, Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{
+    field::{Field, Visit},
+    Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+static LOG_BUFFER: OnceLock<LogBuffer> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LogRecord {
+    /// Unix time in milliseconds of the event
+    pub timestamp: u64,
+    pub level: String,
+    pub target: String,
+    /// The message followed by the other fields of the event
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+struct Records {
+    records: VecDeque<LogRecord>,
+    capacity: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LogBuffer(Arc<Mutex<Records>>);
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self(Arc::new(Mutex::new(Records {
+            records: VecDeque::with_capacity(capacity),
+            capacity,
+        })))
+    }
+
+    fn push(&self, record: LogRecord) {
+        let mut records = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        if records.capacity == 0 {
+            return;
+        }
+        while records.records.len() >= records.capacity {
+            records.records.pop_front();
+        }
+        records.records.push_back(record);
+    }
+
+    /// The given number of most recent records, or all records for 0, oldest
+    /// first
+    pub fn records(&self, limit: usize) -> Vec<LogRecord> {
+        let records = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        let skip = match limit {
+            0 => 0,
+            limit => records.records.len().saturating_sub(limit),
+        };
+        records.records.iter().skip(skip).cloned().collect()
+    }
+}
+
+/// Creates the buffer of the process holding the given number of records and
+/// returns the layer to add to the log subscriber, or None if the capacity
+/// is 0
+pub fn layer(capacity: usize) -> Option<LogBuffer> {
+    if capacity == 0 {
+        return None;
+    }
+    Some(LOG_BUFFER.get_or_init(|| LogBuffer::new(capacity)).clone())
+}
+
+/// The given number of most recent records of the process buffer, or all
+/// records for 0, oldest first. Empty if there is no buffer.
+pub fn records(limit: usize) -> Vec<LogRecord> {
+    LOG_BUFFER
+        .get()
+        .map(|buffer| buffer.records(limit))
+        .unwrap_or_default()
+}
+
+/// Formats the message and the other fields of an event
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            _ = write!(self.fields, " {}={value}", field.name());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            _ = write!(self.message, "{value:?}");
+        } else {
+            _ = write!(self.fields, " {}={value:?}", field.name());
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for LogBuffer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let metadata = event.metadata();
+        self.push(LogRecord {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_millis() as u64)
+                .unwrap_or_default(),
+            level: metadata.level().to_string(),
+            target: metadata.target().to_string(),
+            message: visitor.message + &visitor.fields,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tracing_subscriber::prelude::*;
+
+    #[test]
+    fn test_log_buffer() {
+        let buffer = LogBuffer::new(2);
+        let subscriber = tracing_subscriber::registry().with(buffer.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("first");
+            tracing::warn!(count = 2, name = "b", "second");
+            tracing::info!("third");
+        });
+        let messages: Vec<(String, String)> = buffer
+            .records(0)
+            .into_iter()
+            .map(|record| (record.level, record.message))
+            .collect();
+        assert_eq!(
+            vec![
+                ("WARN".to_string(), "second count=2 name=b".to_string()),
+                ("INFO".to_string(), "third".to_string()),
+            ],
+            messages
+        );
+        assert_eq!(1, buffer.records(1).len());
+    }
+}
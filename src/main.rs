@@ -1,9 +1,14 @@
 use clap::Parser;
-use gateway_rs::{cmd, error::Result, settings::Settings};
+use gateway_rs::{
+    cmd,
+    error::{Error, Result},
+    log_buffer, reload,
+    settings::{log_level, Settings},
+};
 use std::path::PathBuf;
 use tokio::{io::AsyncReadExt, signal, time::Duration};
 use tracing::{debug, error, Level};
-use tracing_subscriber::prelude::*;
+use tracing_subscriber::{filter::Targets, prelude::*};
 
 #[derive(Debug, Parser)]
 #[command(version = env!("CARGO_PKG_VERSION"))]
@@ -18,6 +23,22 @@ pub struct Cli {
     #[arg(long)]
     stdin: bool,
 
+    /// Run with local development settings. This uses a key file and data
+    /// directory in the current directory, the US915 region and the default
+    /// service endpoints, all of which can be overridden by the configuration
+    /// file or environment.
+    #[arg(long)]
+    dev: bool,
+
+    /// Print JSON output compact and with sorted keys, for scripting
+    #[arg(long, global = true)]
+    canonical: bool,
+
+    /// Print only the given field of the JSON output, with nested fields
+    /// separated by dots. Strings are printed without quotes
+    #[arg(long, global = true)]
+    field: Option<String>,
+
     #[command(subcommand)]
     cmd: Cmd,
 }
@@ -28,31 +49,75 @@ pub enum Cmd {
     Info(cmd::info::Cmd),
     Server(cmd::server::Cmd),
     Add(Box<cmd::add::Cmd>),
+    Queue(cmd::queue::Cmd),
+    Ping(cmd::ping::Cmd),
+    Settings(cmd::settings::Cmd),
+    Subsystem(cmd::subsystem::Cmd),
+    Trace(cmd::trace::Cmd),
+    Logs(cmd::logs::Cmd),
+    Watch(cmd::watch::Cmd),
+    Region(cmd::region::Cmd),
+    ForwarderConfig(cmd::forwarder_config::Cmd),
+    Audit(cmd::audit::Cmd),
+    #[cfg(feature = "mock")]
+    Mock(cmd::mock::Cmd),
+}
+
+fn log_filter(level: log_level::Level) -> Targets {
+    Targets::new()
+        .with_target(env!("CARGO_BIN_NAME"), level)
+        .with_target("gateway_rs", level)
+        .with_default(Level::INFO)
 }
 
 fn setup_tracing(settings: &Settings) -> tracing_appender::non_blocking::WorkerGuard {
     let (non_blocking, guard) = tracing_appender::non_blocking(std::io::stdout());
-    let filter = tracing_subscriber::filter::Targets::new()
-        .with_target(env!("CARGO_BIN_NAME"), settings.log.level)
-        .with_target("gateway_rs", settings.log.level)
-        .with_default(Level::INFO);
+    // The filter can be replaced to change the log level on a settings reload
+    let (filter, filter_handle) =
+        tracing_subscriber::reload::Layer::new(log_filter(settings.log.level));
+    reload::set_log_level_handler(move |level| {
+        filter_handle
+            .reload(log_filter(level))
+            .map_err(|err| Error::custom(format!("failed to change log level: {err}")))
+    });
 
     let stdout_log = tracing_subscriber::fmt::layer()
         .compact()
         .with_timer(settings.log.time_formatter())
         .with_writer(non_blocking);
 
-    tracing_subscriber::registry()
-        .with(stdout_log)
-        .with(filter)
-        .init();
+    let logs = stdout_log
+        .and_then(log_buffer::layer(settings.log.buffer as usize))
+        .with_filter(filter);
+    let registry = tracing_subscriber::registry().with(logs);
+    // The console layer sees the task instrumentation regardless of the log
+    // level, and serves it to tokio-console on 127.0.0.1:6669
+    #[cfg(feature = "console")]
+    let registry = registry.with(console_subscriber::spawn());
+    registry.init();
     guard
 }
 
 pub fn main() -> Result {
     let cli = Cli::parse();
+    cmd::set_json_output(cmd::JsonOutput {
+        canonical: cli.canonical,
+        field: cli.field.clone(),
+    });
+
+    // Keys are generated before loading the settings, which fail to load with
+    // a keypair that is not provisioned yet
+    if let Cmd::Key(cmd) = &cli.cmd {
+        if let Some(gen) = cmd.gen() {
+            return gen.run();
+        }
+    }
 
-    let settings = Settings::new(&cli.config)?;
+    let settings = if cli.dev {
+        Settings::dev(&cli.config)?
+    } else {
+        Settings::new(&cli.config)?
+    };
 
     // This `main()` returns a result only for errors we can't easily
     // intercept and log. An example is config file parsing. The
@@ -78,6 +143,7 @@ pub fn main() -> Result {
                 loop {
                     tokio::select!(
                         _ = signal::ctrl_c() => break,
+                        _ = terminate() => break,
                         read = stdin.read(&mut in_buf), if cli.stdin => if let Ok(0) = read { break },
                     )
                 }
@@ -99,12 +165,38 @@ pub fn main() -> Result {
     std::process::exit(retcode);
 }
 
+/// Completes when the process is asked to terminate (SIGTERM)
+#[cfg(unix)]
+async fn terminate() {
+    let mut sigterm =
+        signal::unix::signal(signal::unix::SignalKind::terminate()).expect("sigterm handler");
+    sigterm.recv().await;
+}
+
+/// There is no SIGTERM equivalent on non unix platforms, only ctrl-c is used
+#[cfg(not(unix))]
+async fn terminate() {
+    futures::future::pending::<()>().await
+}
+
 pub async fn run(cli: Cli, settings: Settings, shutdown_listener: &triggered::Listener) -> Result {
     debug!(settings = %cli.config.display(), "starting");
     match cli.cmd {
         Cmd::Key(cmd) => cmd.run(settings).await,
-        Cmd::Info(cmd) => cmd.run(settings).await,
+        Cmd::Info(cmd) => cmd.run(shutdown_listener, settings).await,
         Cmd::Add(cmd) => cmd.run(settings).await,
+        Cmd::Queue(cmd) => cmd.run(settings).await,
+        Cmd::Ping(cmd) => cmd.run(settings).await,
+        Cmd::Settings(cmd) => cmd.run(settings).await,
+        Cmd::Subsystem(cmd) => cmd.run(settings).await,
         Cmd::Server(cmd) => cmd.run(shutdown_listener, settings).await,
+        Cmd::Trace(cmd) => cmd.run(shutdown_listener, settings).await,
+        Cmd::Logs(cmd) => cmd.run(settings).await,
+        Cmd::Audit(cmd) => cmd.run(settings).await,
+        Cmd::Watch(cmd) => cmd.run(shutdown_listener, settings).await,
+        Cmd::Region(cmd) => cmd.run(shutdown_listener, settings).await,
+        Cmd::ForwarderConfig(cmd) => cmd.run(settings).await,
+        #[cfg(feature = "mock")]
+        Cmd::Mock(cmd) => cmd.run(shutdown_listener, settings).await,
     }
 }
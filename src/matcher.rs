@@ -0,0 +1,216 @@
+//! Matching of uplinks by the network of their device.
+//!
+//! Data frames are matched by the NetID or subnet of their device address,
+//! join requests by the prefix of their join EUI. The uplink filter, the fport
+//! filters and the routing rules of the packet routers share this matching.
+
+use crate::Result;
+use lorawan::subnet::NetId;
+use serde::Deserialize;
+use std::{fmt, str::FromStr};
+
+/// A device address subnet in `<devaddr>/<prefix length>` form, with the
+/// device address in hex, for example "48000800/25".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DevAddrSubnet {
+    addr: u32,
+    prefix_len: u32,
+}
+
+impl DevAddrSubnet {
+    fn mask(&self) -> u32 {
+        u32::MAX.checked_shl(32 - self.prefix_len).unwrap_or(0)
+    }
+
+    pub fn contains(&self, devaddr: u32) -> bool {
+        devaddr & self.mask() == self.addr & self.mask()
+    }
+}
+
+impl fmt::Display for DevAddrSubnet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:08X}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl FromStr for DevAddrSubnet {
+    type Err = crate::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || crate::Error::custom(format!("invalid devaddr subnet \"{s}\""));
+        let (addr, prefix_len) = s.split_once('/').ok_or_else(invalid)?;
+        let addr = u32::from_str_radix(addr, 16).map_err(|_| invalid())?;
+        let prefix_len = prefix_len.parse::<u32>().map_err(|_| invalid())?;
+        if prefix_len > 32 {
+            return Err(invalid());
+        }
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+impl<'de> Deserialize<'de> for DevAddrSubnet {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A join EUI prefix in `<eui>/<prefix length>` form, with the EUI in hex, for
+/// example "70B3D57ED0000000/32".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JoinEuiPrefix {
+    eui: u64,
+    prefix_len: u32,
+}
+
+impl JoinEuiPrefix {
+    fn mask(&self) -> u64 {
+        u64::MAX.checked_shl(64 - self.prefix_len).unwrap_or(0)
+    }
+
+    pub fn contains(&self, eui: u64) -> bool {
+        eui & self.mask() == self.eui & self.mask()
+    }
+
+    pub fn prefix_len(&self) -> u32 {
+        self.prefix_len
+    }
+}
+
+impl fmt::Display for JoinEuiPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016X}/{}", self.eui, self.prefix_len)
+    }
+}
+
+impl FromStr for JoinEuiPrefix {
+    type Err = crate::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || crate::Error::custom(format!("invalid join eui prefix \"{s}\""));
+        let (eui, prefix_len) = s.split_once('/').ok_or_else(invalid)?;
+        let eui = u64::from_str_radix(eui, 16).map_err(|_| invalid())?;
+        let prefix_len = prefix_len.parse::<u32>().map_err(|_| invalid())?;
+        if prefix_len > 64 {
+            return Err(invalid());
+        }
+        Ok(Self { eui, prefix_len })
+    }
+}
+
+impl<'de> Deserialize<'de> for JoinEuiPrefix {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// NetIDs, device address subnets and join EUI prefixes to match uplinks by
+#[derive(Debug, Clone, Default)]
+pub struct DevAddrJoinEuiMatcher {
+    net_ids: Vec<NetId>,
+    devaddrs: Vec<DevAddrSubnet>,
+    join_euis: Vec<JoinEuiPrefix>,
+}
+
+impl DevAddrJoinEuiMatcher {
+    pub fn new(net_ids: &[NetId], devaddrs: &[DevAddrSubnet], join_euis: &[JoinEuiPrefix]) -> Self {
+        Self {
+            net_ids: net_ids.to_vec(),
+            devaddrs: devaddrs.to_vec(),
+            join_euis: join_euis.to_vec(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.has_devaddrs() && !self.has_join_euis()
+    }
+
+    /// Whether there are NetIDs or device address subnets to match data
+    /// frames by
+    pub fn has_devaddrs(&self) -> bool {
+        !(self.net_ids.is_empty() && self.devaddrs.is_empty())
+    }
+
+    /// Whether there are join EUI prefixes to match join requests by
+    pub fn has_join_euis(&self) -> bool {
+        !self.join_euis.is_empty()
+    }
+
+    pub fn matches_devaddr(&self, devaddr: u32) -> bool {
+        self.net_ids.iter().any(|net_id| net_id.contains(devaddr))
+            || self.devaddrs.iter().any(|subnet| subnet.contains(devaddr))
+    }
+
+    pub fn matches_join_eui(&self, join_eui: u64) -> bool {
+        self.join_euis
+            .iter()
+            .any(|prefix| prefix.contains(join_eui))
+    }
+
+    /// Whether the device address of a data frame or the join EUI of a join
+    /// request matches
+    pub fn matches(&self, devaddr: Option<u32>, join_eui: Option<u64>) -> bool {
+        devaddr.is_some_and(|devaddr| self.matches_devaddr(devaddr))
+            || join_eui.is_some_and(|join_eui| self.matches_join_eui(join_eui))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn mk_matcher(
+        net_ids: &[&str],
+        devaddrs: &[&str],
+        join_euis: &[&str],
+    ) -> DevAddrJoinEuiMatcher {
+        DevAddrJoinEuiMatcher {
+            net_ids: net_ids.iter().map(|s| s.parse().unwrap()).collect(),
+            devaddrs: devaddrs.iter().map(|s| s.parse().unwrap()).collect(),
+            join_euis: join_euis.iter().map(|s| s.parse().unwrap()).collect(),
+        }
+    }
+
+    #[test]
+    fn devaddr_subnet() {
+        let subnet: DevAddrSubnet = "48000800/25".parse().expect("devaddr subnet");
+        assert!(subnet.contains(0x48000800));
+        assert!(subnet.contains(0x4800087F));
+        assert!(!subnet.contains(0x48000880));
+        assert!(!subnet.contains(0x49000800));
+
+        let all: DevAddrSubnet = "00000000/0".parse().expect("devaddr subnet");
+        assert!(all.contains(0xFFFFFFFF));
+
+        assert!("48000800".parse::<DevAddrSubnet>().is_err());
+        assert!("48000800/33".parse::<DevAddrSubnet>().is_err());
+        assert!("nothex/8".parse::<DevAddrSubnet>().is_err());
+    }
+
+    #[test]
+    fn devaddr_join_eui_matcher() {
+        let empty = DevAddrJoinEuiMatcher::default();
+        assert!(empty.is_empty());
+        assert!(!empty.matches(Some(0x2600_0001), Some(0x0011_2233_4400_0000)));
+
+        let helium = mk_matcher(&["000024", "60002D"], &[], &[]);
+        assert!(helium.has_devaddrs());
+        assert!(!helium.has_join_euis());
+        assert!(helium.matches(Some(0x4800_0800), None));
+        assert!(!helium.matches(Some(0x2600_0001), None));
+
+        let private = mk_matcher(&["000013"], &["26000000/8"], &["0011223344000000/40"]);
+        // NetID 000013 assigns device addresses starting with 0x26
+        assert!(private.matches(Some(0x2700_0001), None));
+        assert!(private.matches(Some(0x2600_0001), None));
+        assert!(!private.matches(Some(0x4800_0800), None));
+        assert!(private.matches(None, Some(0x0011_2233_4400_00FF)));
+        assert!(!private.matches(None, Some(0x0011_2233_4500_0000)));
+        assert!(!private.matches(None, None));
+    }
+}
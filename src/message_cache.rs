@@ -1,13 +1,61 @@
+//! Bounded message queue with LRU tagging.
+//!
+//! The cache is bounded by a number of messages and optionally by the total
+//! size of the cached messages in bytes.
+//!
+//! The cache is held in memory and can optionally be backed by a journal file
+//! so that cached messages survive restarts of the gateway, for example
+//! uplinks queued during a long packet router outage.
+
+use crate::Result;
 use std::{
     collections::VecDeque,
     ops::Deref,
-    time::{Duration, Instant},
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
+use tracing::{info, warn};
+
+mod journal;
+use journal::{Journal, Op};
+
+/// Messages that can be kept in a persistent message cache
+pub trait Persist: Sized {
+    fn to_bytes(&self) -> Vec<u8>;
+    fn from_bytes(data: &[u8]) -> Result<Self>;
+}
+
+impl Persist for Vec<u8> {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Self> {
+        Ok(data.to_vec())
+    }
+}
+
+/// Messages with a known size, for caches limited to a number of bytes
+pub trait MessageSize {
+    /// The approximate memory used by the message in bytes
+    fn message_size(&self) -> usize;
+}
+
+impl MessageSize for Vec<u8> {
+    fn message_size(&self) -> usize {
+        self.len()
+    }
+}
 
 #[derive(Debug)]
 pub struct MessageCache<T: PartialEq> {
     cache: VecDeque<CacheMessage<T>>,
     max_messages: u16,
+    /// Maximum total size of the cached messages, if limited
+    max_bytes: Option<usize>,
+    /// Total size of the cached messages
+    bytes: usize,
+    journal: Option<Journal>,
 }
 
 #[derive(Debug, Clone)]
@@ -34,28 +82,176 @@ impl<T: PartialEq> Deref for CacheMessage<T> {
     }
 }
 
-impl<T: PartialEq> MessageCache<T> {
+impl<T: PartialEq + Persist + MessageSize> MessageCache<T> {
     pub fn new(max_messages: u16) -> Self {
         let waiting = VecDeque::new();
         Self {
             cache: waiting,
             max_messages,
+            max_bytes: None,
+            bytes: 0,
+            journal: None,
+        }
+    }
+
+    /// Creates a cache backed by the journal file in the given path. Messages
+    /// in an existing journal are loaded, except for messages held longer than
+    /// the given maximum hold time. The journal is compacted when it reaches
+    /// the given maximum size in bytes, dropping the oldest messages if the
+    /// cached messages take more than half of that size.
+    pub fn persistent(
+        max_messages: u16,
+        path: &Path,
+        max_size: u64,
+        max_hold_time: Duration,
+    ) -> Result<Self> {
+        let (journal, entries) = Journal::open(path, max_size)?;
+        let mut cache = Self::new(max_messages);
+        for (received, data) in entries {
+            let Some(received) = received_instant(received, max_hold_time) else {
+                continue;
+            };
+            match T::from_bytes(&data) {
+                Ok(message) => cache.cache_push_back(CacheMessage::new(message, received)),
+                Err(err) => warn!(path = %path.display(), %err, "ignoring invalid cached message"),
+            }
+        }
+        while cache.len() > max_messages as usize {
+            cache.cache_pop_front();
+        }
+        if !cache.is_empty() {
+            info!(path = %path.display(), count = cache.len(), "loaded cached messages");
+        }
+        cache.journal = Some(journal);
+        cache.compact();
+        Ok(cache)
+    }
+
+    fn cache_push_back(&mut self, message: CacheMessage<T>) {
+        self.bytes += message.message_size();
+        self.cache.push_back(message);
+    }
+
+    fn cache_push_front(&mut self, message: CacheMessage<T>) {
+        self.bytes += message.message_size();
+        self.cache.push_front(message);
+    }
+
+    fn cache_pop_front(&mut self) -> Option<CacheMessage<T>> {
+        let message = self.cache.pop_front()?;
+        self.bytes -= message.message_size();
+        Some(message)
+    }
+
+    fn cache_remove(&mut self, index: usize) -> Option<CacheMessage<T>> {
+        let message = self.cache.remove(index)?;
+        self.bytes -= message.message_size();
+        Some(message)
+    }
+
+    /// Whether the cache holds more messages, or more bytes, than allowed
+    fn is_over_limit(&self) -> bool {
+        self.len() > self.max_messages as usize
+            || self
+                .max_bytes
+                .is_some_and(|max_bytes| self.bytes > max_bytes)
+    }
+
+    /// Drops the oldest messages until the cache is within its limits.
+    /// Returns the number of dropped messages.
+    fn drop_over_limit(&mut self) -> usize {
+        let mut dropped = 0;
+        while self.is_over_limit() && self.cache_pop_front().is_some() {
+            self.record(Op::PopFront);
+            dropped += 1;
+        }
+        dropped
+    }
+
+    /// Records a change in the journal, if any. A journal that fails to write
+    /// is dropped, leaving an in-memory only cache.
+    fn record(&mut self, op: Op) {
+        let Some(journal) = self.journal.as_mut() else {
+            return;
+        };
+        match journal.write(op) {
+            Ok(false) => (),
+            Ok(true) => self.compact(),
+            Err(err) => {
+                warn!(%err, "failed to write cache journal, disabling persistence");
+                self.journal = None;
+            }
+        }
+    }
+
+    /// Rewrites the journal with the currently cached messages, dropping the
+    /// oldest messages if they do not fit the compacted journal size.
+    fn compact(&mut self) {
+        let Some(journal) = self.journal.as_ref() else {
+            return;
+        };
+        let mut entries: VecDeque<(u64, Vec<u8>)> = self
+            .cache
+            .iter()
+            .map(|msg| (received_millis(msg.received), msg.message.to_bytes()))
+            .collect();
+        let mut size: u64 = entries.iter().map(Journal::entry_size).sum();
+        let compacted_limit = journal.compacted_limit();
+        let mut dropped = 0;
+        while size > compacted_limit {
+            let Some(entry) = entries.pop_front() else {
+                break;
+            };
+            size -= Journal::entry_size(&entry);
+            self.cache_pop_front();
+            dropped += 1;
+        }
+        if dropped > 0 {
+            warn!(dropped, "cache journal full, dropped oldest messages");
+        }
+        let entries = entries
+            .iter()
+            .map(|(received, data)| (*received, data.as_slice()));
+        let Some(journal) = self.journal.as_mut() else {
+            return;
+        };
+        if let Err(err) = journal.compact(entries) {
+            warn!(%err, "failed to compact cache journal, disabling persistence");
+            self.journal = None;
         }
     }
 
+    fn record_push(&mut self, front: bool) {
+        if self.journal.is_none() {
+            return;
+        }
+        let msg = if front {
+            self.cache.front()
+        } else {
+            self.cache.back()
+        };
+        let Some(msg) = msg else {
+            return;
+        };
+        let (received, data) = (received_millis(msg.received), msg.message.to_bytes());
+        self.record(if front {
+            Op::PushFront(received, &data)
+        } else {
+            Op::PushBack(received, &data)
+        });
+    }
+
     /// Pushes a given at the end of the cache. The message is tagged with the
     /// given received time which can be used to calculate hold time of a
     /// packet.
     ///
     /// Pushing a packet onto the back of a full cache will cause the oldest
-    /// (first) message in the cache to be dropped.
-    pub fn push_back(&mut self, message: T, received: Instant) -> Option<CacheMessage<T>> {
-        self.cache.push_back(CacheMessage::new(message, received));
-        if self.len() > self.max_messages as usize {
-            self.cache.pop_front()
-        } else {
-            None
-        }
+    /// (first) messages in the cache to be dropped. Returns the number of
+    /// dropped messages.
+    pub fn push_back(&mut self, message: T, received: Instant) -> usize {
+        self.cache_push_back(CacheMessage::new(message, received));
+        self.record_push(false);
+        self.drop_over_limit()
     }
 
     /// Returns the index of the first matching message in the cache or None if
@@ -67,10 +263,11 @@ impl<T: PartialEq> MessageCache<T> {
     /// Promotes the given message to the back of the queue, effectively
     /// recreating an LRU cache. Returns true if a cache hit was found
     pub fn tag(&mut self, message: T, received: Instant) -> bool {
-        let result = self
-            .index_of(&message)
-            .and_then(|index| self.cache.remove(index))
-            .is_some();
+        let index = self.index_of(&message);
+        let result = index.and_then(|index| self.cache_remove(index)).is_some();
+        if let Some(index) = index {
+            self.record(Op::Remove(index as u32));
+        }
         self.push_back(message, received);
         result
     }
@@ -79,28 +276,51 @@ impl<T: PartialEq> MessageCache<T> {
         self.tag(message, Instant::now())
     }
 
+    /// Tags a message like [`Self::tag`], but only reports it as seen before
+    /// if it was last tagged at most the given duration before it was received
+    pub fn tag_within(&mut self, message: T, received: Instant, duration: Duration) -> bool {
+        let recent = self
+            .index_of(&message)
+            .and_then(|index| self.cache.get(index))
+            .is_some_and(|cached| received.saturating_duration_since(cached.received) <= duration);
+        self.tag(message, received) && recent
+    }
+
     /// Pushes a CacheMessage back on the front of the queue. This is useful to
     /// push a packet back at the front after a failed delivery attempt.
     ///
     /// Pushing to the front of a full cache will cause the given message to not
     /// be added.
     pub fn push_front(&mut self, cache_message: CacheMessage<T>) {
-        if self.len() > self.max_messages as usize {
+        if self.is_over_limit() {
             return;
         }
-        self.cache.push_front(cache_message);
+        self.cache_push_front(cache_message);
+        self.record_push(true);
     }
 
     pub fn pop_front(&mut self, duration: Duration) -> (usize, Option<CacheMessage<T>>) {
-        let mut dropped = 0;
+        let (dropped, front) = self.pop_front_with(|_| duration);
+        (dropped.len(), front)
+    }
+
+    /// Pops the first message held for at most the maximum hold time the
+    /// given function returns for it. Returns the messages dropped for being
+    /// held too long along with it.
+    pub fn pop_front_with(
+        &mut self,
+        max_hold_time: impl Fn(&T) -> Duration,
+    ) -> (Vec<CacheMessage<T>>, Option<CacheMessage<T>>) {
+        let mut dropped = vec![];
         let mut front = None;
-        while let Some(msg) = self.cache.pop_front() {
-            if msg.hold_time() <= duration {
+        while let Some(msg) = self.cache_pop_front() {
+            self.record(Op::PopFront);
+            if msg.hold_time() <= max_hold_time(&msg) {
                 front = Some(msg);
                 break;
             }
             // held for too long, count as dropped and move on
-            dropped += 1;
+            dropped.push(msg);
         }
         (dropped, front)
     }
@@ -111,18 +331,77 @@ impl<T: PartialEq> MessageCache<T> {
         self.cache.front()
     }
 
+    /// Returns an iterator over the messages in the cache, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = &CacheMessage<T>> {
+        self.cache.iter()
+    }
+
+    /// Removes all messages from the cache, returning the number of messages
+    /// removed
+    pub fn clear(&mut self) -> usize {
+        let removed = self.cache.len();
+        self.cache.clear();
+        self.bytes = 0;
+        self.record(Op::Clear);
+        removed
+    }
+
+    /// Changes the maximum number of cached messages. Returns the number of
+    /// oldest messages dropped to fit a smaller maximum.
+    pub fn set_max_messages(&mut self, max_messages: u16) -> usize {
+        self.max_messages = max_messages;
+        self.drop_over_limit()
+    }
+
+    /// Changes the maximum total size in bytes of the cached messages, or
+    /// removes the limit. Returns the number of oldest messages dropped to
+    /// fit a smaller maximum.
+    pub fn set_max_bytes(&mut self, max_bytes: Option<usize>) -> usize {
+        self.max_bytes = max_bytes;
+        self.drop_over_limit()
+    }
+
     pub fn len(&self) -> usize {
         self.cache.len()
     }
 
+    /// The total size in bytes of the cached messages
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
     pub fn is_empty(&self) -> bool {
         self.cache.is_empty()
     }
 }
 
+/// Returns the wall clock time of the given receive time in milliseconds since
+/// the unix epoch
+fn received_millis(received: Instant) -> u64 {
+    SystemTime::now()
+        .checked_sub(received.elapsed())
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Returns the receive time of the given wall clock time in milliseconds since
+/// the unix epoch, or None if the message was held longer than the given
+/// maximum hold time.
+fn received_instant(millis: u64, max_hold_time: Duration) -> Option<Instant> {
+    let age = SystemTime::now()
+        .duration_since(UNIX_EPOCH + Duration::from_millis(millis))
+        .unwrap_or_default();
+    if age > max_hold_time {
+        return None;
+    }
+    // The monotonic clock may not reach back before a reboot
+    Some(Instant::now().checked_sub(age).unwrap_or_else(Instant::now))
+}
+
 #[cfg(test)]
 mod test {
-    use super::MessageCache;
+    use super::*;
 
     #[test]
     fn test_cache_tagging() {
@@ -145,5 +424,82 @@ mod test {
         assert_eq!(Some(0), cache.index_of(&vec![1u8]));
         assert_eq!(Some(1), cache.index_of(&vec![3u8]));
         assert!(cache.index_of(&vec![2u8]).is_none());
+
+        // Entries tagged longer ago than the given duration are not seen
+        let now = Instant::now();
+        let ttl = Duration::from_secs(60);
+        assert!(cache.tag_within(vec![3], now, ttl));
+        assert!(!cache.tag_within(vec![3], now + Duration::from_secs(61), ttl));
+        assert!(cache.tag_within(vec![3], now + Duration::from_secs(62), ttl));
+    }
+
+    #[test]
+    fn test_cache_max_bytes() {
+        let mut cache = MessageCache::<Vec<u8>>::new(10);
+        cache.set_max_bytes(Some(8));
+        assert_eq!(0, cache.push_back(vec![1; 3], Instant::now()));
+        assert_eq!(0, cache.push_back(vec![2; 3], Instant::now()));
+        // A third message exceeds the byte budget, dropping the oldest
+        assert_eq!(1, cache.push_back(vec![3; 4], Instant::now()));
+        assert_eq!((2, 7), (cache.len(), cache.bytes()));
+        // Shrinking the budget drops messages until it fits
+        assert_eq!(1, cache.set_max_bytes(Some(4)));
+        assert_eq!(Some(0), cache.index_of(&vec![3; 4]));
+        cache.clear();
+        assert_eq!(0, cache.bytes());
+    }
+
+    #[test]
+    fn test_cache_pop_front_with() {
+        let mut cache = MessageCache::<Vec<u8>>::new(10);
+        let received = Instant::now() - Duration::from_secs(10);
+        cache.push_back(vec![1], received);
+        cache.push_back(vec![2, 2], received);
+        cache.push_back(vec![3], received);
+        // Messages of length 1 may be held for 5 seconds, others for a minute
+        let max_hold_time = |message: &Vec<u8>| match message.len() {
+            1 => Duration::from_secs(5),
+            _ => Duration::from_secs(60),
+        };
+        let (dropped, front) = cache.pop_front_with(max_hold_time);
+        assert_eq!(
+            vec![vec![1]],
+            dropped
+                .iter()
+                .map(|msg| msg.message.clone())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Some(vec![2, 2]), front.map(|msg| msg.message));
+        let (dropped, front) = cache.pop_front_with(max_hold_time);
+        assert_eq!(1, dropped.len());
+        assert!(front.is_none());
+    }
+
+    #[test]
+    fn test_cache_journal() {
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        let path = temp_dir.path().join("cache_journal");
+        let max_hold_time = Duration::from_secs(60);
+        let open = || MessageCache::<Vec<u8>>::persistent(3, &path, 96, max_hold_time);
+
+        let mut cache = open().expect("cache");
+        for i in 1..=6u8 {
+            cache.push_back(vec![i], Instant::now());
+        }
+        let (_, front) = cache.pop_front(max_hold_time);
+        cache.push_front(front.expect("front message"));
+        cache.pop_front(max_hold_time);
+        drop(cache);
+
+        // Replays the journal, including compactions, overflow and pops
+        let cache = open().expect("reopened cache");
+        let messages: Vec<Vec<u8>> = cache.iter().map(|msg| msg.message.clone()).collect();
+        assert_eq!(vec![vec![5u8], vec![6u8]], messages);
+        drop(cache);
+
+        let mut cache = open().expect("reopened cache");
+        cache.clear();
+        drop(cache);
+        assert!(open().expect("reopened cache").is_empty());
     }
 }
@@ -0,0 +1,184 @@
+//! Append-only journal of the changes to a message cache.
+//!
+//! The journal file starts with a magic header followed by length prefixed
+//! records, one per change to the cache. Replaying the records on startup
+//! recreates the cache as it was when the gateway stopped. A partially
+//! written last record, for example after a crash, is ignored.
+//!
+//! The journal grows with every change. Once it reaches its maximum size it
+//! is compacted by rewriting it with one record per cached message.
+
+use crate::{state_file, Result};
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use tracing::warn;
+
+const MAGIC: &[u8; 4] = b"GWQ1";
+
+const OP_PUSH_BACK: u8 = 1;
+const OP_PUSH_FRONT: u8 = 2;
+const OP_POP_FRONT: u8 = 3;
+const OP_REMOVE: u8 = 4;
+const OP_CLEAR: u8 = 5;
+
+/// A cached message as stored in the journal, the wall clock receive time in
+/// milliseconds since the unix epoch and the encoded message
+pub type Entry = (u64, Vec<u8>);
+
+#[derive(Debug)]
+pub enum Op<'a> {
+    PushBack(u64, &'a [u8]),
+    PushFront(u64, &'a [u8]),
+    PopFront,
+    Remove(u32),
+    Clear,
+}
+
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: fs::File,
+    size: u64,
+    max_size: u64,
+}
+
+impl Journal {
+    /// Opens the journal at the given path, creating it if it does not exist,
+    /// and returns it with the replayed entries, oldest first. The caller is
+    /// expected to compact the journal with the entries it keeps.
+    pub fn open(path: &Path, max_size: u64) -> Result<(Self, VecDeque<Entry>)> {
+        let entries = match fs::read(path) {
+            Ok(data) => replay(path, &data),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => VecDeque::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let file = append(path)?;
+        let size = file.metadata()?.len();
+        let journal = Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_size,
+        };
+        Ok((journal, entries))
+    }
+
+    /// The size in bytes a compacted journal of the given entries may have
+    /// while leaving room for further changes before the next compaction.
+    pub fn compacted_limit(&self) -> u64 {
+        self.max_size / 2
+    }
+
+    /// The size in bytes of the record of an entry
+    pub fn entry_size(entry: &Entry) -> u64 {
+        (4 + 1 + 8 + entry.1.len()) as u64
+    }
+
+    /// Appends a change to the journal. Returns true when the journal reached
+    /// its maximum size and should be compacted.
+    pub fn write(&mut self, op: Op) -> Result<bool> {
+        let record = encode(&op);
+        self.file.write_all(&record)?;
+        self.size += record.len() as u64;
+        Ok(self.size >= self.max_size)
+    }
+
+    /// Rewrites the journal with only the given entries. The new journal is
+    /// written to a temporary file first which then replaces the journal, so
+    /// a crash during compaction leaves the previous journal intact.
+    pub fn compact<'a>(&mut self, entries: impl Iterator<Item = (u64, &'a [u8])>) -> Result {
+        let mut data = MAGIC.to_vec();
+        for (received, message) in entries {
+            data.extend_from_slice(&encode(&Op::PushBack(received, message)));
+        }
+        state_file::write(&self.path, &data)?;
+        self.file = append(&self.path)?;
+        self.size = data.len() as u64;
+        Ok(())
+    }
+}
+
+fn append(path: &Path) -> Result<fs::File> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    if file.metadata()?.len() == 0 {
+        file.write_all(MAGIC)?;
+    }
+    Ok(file)
+}
+
+fn encode(op: &Op) -> Vec<u8> {
+    let mut body = vec![];
+    match op {
+        Op::PushBack(received, message) | Op::PushFront(received, message) => {
+            body.push(if matches!(op, Op::PushBack(..)) {
+                OP_PUSH_BACK
+            } else {
+                OP_PUSH_FRONT
+            });
+            body.extend_from_slice(&received.to_le_bytes());
+            body.extend_from_slice(message);
+        }
+        Op::PopFront => body.push(OP_POP_FRONT),
+        Op::Remove(index) => {
+            body.push(OP_REMOVE);
+            body.extend_from_slice(&index.to_le_bytes());
+        }
+        Op::Clear => body.push(OP_CLEAR),
+    }
+    let mut record = (body.len() as u32).to_le_bytes().to_vec();
+    record.extend_from_slice(&body);
+    record
+}
+
+fn replay(path: &Path, data: &[u8]) -> VecDeque<Entry> {
+    let mut entries = VecDeque::new();
+    let Some(mut data) = data.strip_prefix(MAGIC) else {
+        warn!(path = %path.display(), "ignoring invalid queue journal");
+        return entries;
+    };
+    while data.len() >= 4 {
+        let (len, rest) = data.split_at(4);
+        let len = u32::from_le_bytes(len.try_into().expect("record length")) as usize;
+        if len == 0 || rest.len() < len {
+            break;
+        }
+        let (body, rest) = rest.split_at(len);
+        data = rest;
+        let (op, body) = (body[0], &body[1..]);
+        match op {
+            OP_PUSH_BACK | OP_PUSH_FRONT if body.len() >= 8 => {
+                let (received, message) = body.split_at(8);
+                let received = u64::from_le_bytes(received.try_into().expect("received time"));
+                let entry = (received, message.to_vec());
+                if op == OP_PUSH_BACK {
+                    entries.push_back(entry);
+                } else {
+                    entries.push_front(entry);
+                }
+            }
+            OP_POP_FRONT => {
+                entries.pop_front();
+            }
+            OP_REMOVE if body.len() == 4 => {
+                let index = u32::from_le_bytes(body.try_into().expect("index"));
+                entries.remove(index as usize);
+            }
+            OP_CLEAR => entries.clear(),
+            _ => {
+                warn!(path = %path.display(), op, "ignoring invalid queue journal record");
+                break;
+            }
+        }
+    }
+    if !data.is_empty() {
+        warn!(path = %path.display(), "ignoring partial queue journal record");
+    }
+    entries
+}
@@ -0,0 +1,292 @@
+//! Prometheus metrics of the packet forwarders and the gateway.
+//!
+//! When a listen address is configured the latest stat message of each packet
+//! forwarder is served at `/metrics` in the Prometheus text format, so radio
+//! problems like a dropping CRC rate, an overheating concentrator or a
+//! forwarder that stopped transmitting can be alerted on remotely. Every
+//! forwarder metric is labeled with the MAC address of the forwarder.
+//!
+//! The number of uplinks dropped by the uplink filter and by each fport
+//! filter, labeled with the subnet of the filter, are served as counters. The
+//! beacon transmit health is served as gauges, so an antenna fault that
+//! suppresses beacons raises an alert instead of only a log line.
+
+use crate::{
+    beaconer::{self, BeaconerStatus},
+    forwarders::ForwarderStat,
+    gateway,
+    settings::MetricsSettings,
+    uplink_filter::FilterStatus,
+    Error, Result,
+};
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use std::{convert::Infallible, fmt::Write, net::SocketAddr};
+use tracing::{info, warn};
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Serves forwarder metrics over http
+pub struct Metrics {
+    listen: SocketAddr,
+    sources: Sources,
+}
+
+/// The services the metrics are collected from
+#[derive(Clone)]
+struct Sources {
+    gateway: gateway::MessageSender,
+    beacons: beaconer::MessageSender,
+}
+
+/// The values the metrics are rendered from
+struct Snapshot {
+    /// The latest stat message of each packet forwarder
+    stats: Vec<ForwarderStat>,
+    filters: FilterStatus,
+    poc: BeaconerStatus,
+}
+
+impl Metrics {
+    /// A metrics server if a listen address is configured
+    pub fn new(
+        settings: &MetricsSettings,
+        gateway: gateway::MessageSender,
+        beacons: beaconer::MessageSender,
+    ) -> Option<Self> {
+        Some(Self {
+            listen: settings.listen?,
+            sources: Sources { gateway, beacons },
+        })
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn run(&mut self, shutdown: &triggered::Listener) -> Result {
+        info!(listen = %self.listen, "starting");
+        let sources = self.sources.clone();
+        let make_service = make_service_fn(move |_| {
+            let sources = sources.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let sources = sources.clone();
+                    async move { Ok::<_, Infallible>(respond(request, &sources).await) }
+                }))
+            }
+        });
+        Server::try_bind(&self.listen)
+            .map_err(|err| Error::custom(format!("metrics listen: {err}")))?
+            .serve(make_service)
+            .with_graceful_shutdown(shutdown.clone())
+            .await
+            .map_err(|err| Error::custom(format!("metrics server: {err}")))?;
+        info!("shutting down");
+        Ok(())
+    }
+}
+
+async fn respond(request: Request<Body>, sources: &Sources) -> Response<Body> {
+    if request.method() != Method::GET || request.uri().path() != "/metrics" {
+        return status_response(StatusCode::NOT_FOUND);
+    }
+    match sources.snapshot().await {
+        Ok(snapshot) => Response::builder()
+            .header(hyper::header::CONTENT_TYPE, CONTENT_TYPE)
+            .body(Body::from(render(&snapshot)))
+            .unwrap_or_else(|_| status_response(StatusCode::INTERNAL_SERVER_ERROR)),
+        Err(err) => {
+            warn!(%err, "failed to get gateway stats");
+            status_response(StatusCode::SERVICE_UNAVAILABLE)
+        }
+    }
+}
+
+impl Sources {
+    async fn snapshot(&self) -> Result<Snapshot> {
+        Ok(Snapshot {
+            stats: self.gateway.forwarder_stats().await?,
+            filters: self.gateway.filter_status().await?,
+            poc: self.beacons.status().await?,
+        })
+    }
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+/// A gauge metric with its help text and the value of a stat, if reported
+type Gauge = (
+    &'static str,
+    &'static str,
+    fn(&ForwarderStat) -> Option<f64>,
+);
+
+const GAUGES: &[Gauge] = &[
+    (
+        "helium_gateway_forwarder_stat_timestamp_seconds",
+        "Unix time the latest stat of the forwarder was received",
+        |stat| Some(stat.timestamp as f64),
+    ),
+    (
+        "helium_gateway_forwarder_rx_packets",
+        "Radio packets received in the latest stat interval",
+        |stat| Some(stat.rxnb as f64),
+    ),
+    (
+        "helium_gateway_forwarder_rx_ok_packets",
+        "Radio packets received with a valid CRC in the latest stat interval",
+        |stat| Some(stat.rxok as f64),
+    ),
+    (
+        "helium_gateway_forwarder_rx_forwarded_packets",
+        "Radio packets forwarded in the latest stat interval",
+        |stat| Some(stat.rxfw as f64),
+    ),
+    (
+        "helium_gateway_forwarder_ack_ratio_percent",
+        "Percentage of upstream datagrams acknowledged in the latest stat interval",
+        |stat| stat.ackr,
+    ),
+    (
+        "helium_gateway_forwarder_downlink_datagrams",
+        "Downlink datagrams received in the latest stat interval",
+        |stat| Some(stat.dwnb as f64),
+    ),
+    (
+        "helium_gateway_forwarder_tx_packets",
+        "Packets transmitted in the latest stat interval",
+        |stat| Some(stat.txnb as f64),
+    ),
+    (
+        "helium_gateway_forwarder_temperature_celsius",
+        "Concentrator temperature",
+        |stat| stat.temp,
+    ),
+];
+
+/// Renders the given snapshot in the Prometheus text format. Metrics a
+/// forwarder does not report are left out for that forwarder.
+fn render(snapshot: &Snapshot) -> String {
+    let mut output = String::new();
+    for (name, help, value) in GAUGES {
+        header(&mut output, name, help, "gauge");
+        for stat in &snapshot.stats {
+            if let Some(value) = value(stat) {
+                let _ = writeln!(output, "{name}{{mac=\"{}\"}} {value}", stat.mac);
+            }
+        }
+    }
+    let filters = &snapshot.filters;
+    let name = "helium_gateway_uplink_filter_dropped_total";
+    header(
+        &mut output,
+        name,
+        "Uplinks dropped since their network is not in the uplink filter",
+        "counter",
+    );
+    let _ = writeln!(output, "{name} {}", filters.network_dropped);
+    let name = "helium_gateway_fport_filter_dropped_total";
+    header(
+        &mut output,
+        name,
+        "Uplinks dropped on a frame port not permitted for the subnet",
+        "counter",
+    );
+    for filter in &filters.fport_filters {
+        let _ = writeln!(
+            output,
+            "{name}{{subnet=\"{}\"}} {}",
+            filter.subnet, filter.dropped
+        );
+    }
+    let poc = &snapshot.poc;
+    for (name, help, value) in [
+        (
+            "helium_gateway_beacon_tx_failures",
+            "Consecutive failed beacon transmissions",
+            poc.tx_failures,
+        ),
+        (
+            "helium_gateway_beacon_tx_suppressed",
+            "Whether beacons are suppressed after repeated transmit failures",
+            poc.tx_suppressed.into(),
+        ),
+        (
+            "helium_gateway_beacon_tx_healthy",
+            "Whether beacon transmissions are healthy",
+            poc.tx_healthy.into(),
+        ),
+    ] {
+        header(&mut output, name, help, "gauge");
+        let _ = writeln!(output, "{name} {value}");
+    }
+    output
+}
+
+fn header(output: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(output, "# HELP {name} {help}");
+    let _ = writeln!(output, "# TYPE {name} {kind}");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::uplink_filter::FportFilterStatus;
+
+    #[test]
+    fn render_stats() {
+        let stat = ForwarderStat {
+            mac: "0102030405060708".to_string(),
+            timestamp: 1_700_000_000,
+            rxnb: 12,
+            rxok: 10,
+            rxfw: 10,
+            ackr: Some(100.0),
+            dwnb: 2,
+            txnb: 2,
+            temp: None,
+        };
+        let filters = FilterStatus {
+            network_dropped: 3,
+            fport_filters: vec![FportFilterStatus {
+                subnet: "48000800/25".to_string(),
+                dropped: 7,
+            }],
+        };
+        let poc = BeaconerStatus {
+            disabled: false,
+            paused: false,
+            tx_failures: 3,
+            tx_suppressed: true,
+            tx_healthy: false,
+            next_beacon_time: None,
+            last_beacon: None,
+            last_witness: None,
+            witness_alert: None,
+            witness_queue: Default::default(),
+        };
+        let output = render(&Snapshot {
+            stats: vec![stat],
+            filters,
+            poc,
+        });
+        assert!(output.contains("# TYPE helium_gateway_forwarder_rx_packets gauge\n"));
+        assert!(
+            output.contains("helium_gateway_forwarder_rx_packets{mac=\"0102030405060708\"} 12\n")
+        );
+        assert!(output.contains(
+            "helium_gateway_forwarder_ack_ratio_percent{mac=\"0102030405060708\"} 100\n"
+        ));
+        // The temperature is not reported by this forwarder
+        assert!(!output.contains("helium_gateway_forwarder_temperature_celsius{"));
+        assert!(output.contains("helium_gateway_uplink_filter_dropped_total 3\n"));
+        assert!(output
+            .contains("helium_gateway_fport_filter_dropped_total{subnet=\"48000800/25\"} 7\n"));
+        assert!(output.contains("helium_gateway_beacon_tx_suppressed 1\n"));
+        assert!(output.contains("helium_gateway_beacon_tx_healthy 0\n"));
+    }
+}
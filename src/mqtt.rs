@@ -0,0 +1,382 @@
+//! MQTT bridge for uplinks and downlinks.
+//!
+//! The bridge publishes every uplink delivered to the packet routers to an
+//! MQTT broker as a JSON object with the receive metadata of the packet
+//! forwarder, and transmits the downlinks published to its downlink topic.
+//! This runs in parallel with the packet routers, which lets private
+//! deployments with MQTT based network servers use the gateway without a
+//! second packet forwarder.
+//!
+//! A downlink is a JSON object with the base64 encoded payload and one or two
+//! receive windows, each with the concentrator timestamp in microseconds to
+//! transmit at, the frequency in Hz and the datarate, like "SF10BW500".
+//!
+//! Alternatively the bridge speaks the format of a ChirpStack gateway bridge,
+//! see [`chirpstack`].
+
+pub mod chirpstack;
+
+use crate::{
+    gateway,
+    settings::{MqttFormat, MqttSettings},
+    sync, Base64, Error, PacketDown, PacketUp, PublicKey, Result,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use helium_proto::{
+    services::router::{PacketRouterPacketDownV1, WindowV1},
+    DataRate, Message as ProtoMessage,
+};
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+/// Default port of an MQTT broker
+const DEFAULT_PORT: u16 = 1883;
+/// Number of requests buffered for the broker connection
+const CLIENT_CAPACITY: usize = 20;
+/// Delay before reconnecting after a broker connection error
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub enum Message {
+    /// An uplink and the likely vendor of the device of a join request
+    Uplink(PacketUp, Option<String>),
+    SetPaused(bool),
+}
+
+pub type MessageSender = sync::MessageSender<Message>;
+pub type MessageReceiver = sync::MessageReceiver<Message>;
+
+pub fn message_channel() -> (MessageSender, MessageReceiver) {
+    sync::message_channel(20)
+}
+
+impl MessageSender {
+    pub async fn uplink(&self, packet: PacketUp, vendor: Option<String>) {
+        self.send(Message::Uplink(packet, vendor)).await
+    }
+
+    /// Pauses or resumes uplink publishing and downlink commands
+    pub async fn set_paused(&self, paused: bool) {
+        self.send(Message::SetPaused(paused)).await
+    }
+}
+
+/// An uplink as published to the broker
+#[derive(Debug, Serialize)]
+pub struct UplinkEvent {
+    /// Public key of the gateway
+    pub gateway: String,
+    /// Unix time in milliseconds the uplink was published
+    pub timestamp: u64,
+    /// Concentrator timestamp in microseconds of the end of the uplink, which
+    /// downlink receive windows are relative to
+    pub tmst: u64,
+    /// Frequency in Hz
+    pub frequency: u32,
+    pub datarate: String,
+    /// Signal strength in dBm
+    pub rssi: i32,
+    /// Signal to noise ratio in dB
+    pub snr: f32,
+    pub region: String,
+    /// Device address of lorawan data frames, in hex
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dev_addr: Option<String>,
+    /// Join EUI of join requests, in hex
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub join_eui: Option<String>,
+    /// Likely device vendor of join requests, if the vendor lookup is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vendor: Option<String>,
+    /// Base64 encoded lorawan frame
+    pub payload: String,
+}
+
+impl UplinkEvent {
+    pub fn new(packet: &PacketUp, vendor: Option<String>, gateway: &PublicKey) -> Self {
+        Self {
+            gateway: gateway.to_string(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_millis() as u64)
+                .unwrap_or_default(),
+            tmst: packet.timestamp,
+            frequency: packet.frequency,
+            datarate: packet.datarate().as_str_name().to_string(),
+            rssi: packet.rssi,
+            snr: packet.snr,
+            region: packet.region().as_str_name().to_string(),
+            dev_addr: packet.dev_addr().map(|dev_addr| format!("{dev_addr:08X}")),
+            join_eui: packet.join_eui().map(|join_eui| format!("{join_eui:016X}")),
+            vendor,
+            payload: packet.payload().to_b64(),
+        }
+    }
+}
+
+/// A downlink as received from the broker
+#[derive(Debug, Deserialize)]
+pub struct DownlinkCommand {
+    /// Base64 encoded lorawan frame
+    pub payload: String,
+    pub rx1: DownlinkWindow,
+    #[serde(default)]
+    pub rx2: Option<DownlinkWindow>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DownlinkWindow {
+    /// Concentrator timestamp in microseconds to transmit at
+    pub timestamp: u64,
+    /// Frequency in Hz
+    pub frequency: u32,
+    pub datarate: String,
+    /// Whether to transmit immediately instead of at the timestamp
+    #[serde(default)]
+    pub immediate: bool,
+}
+
+impl TryFrom<DownlinkWindow> for WindowV1 {
+    type Error = Error;
+
+    fn try_from(value: DownlinkWindow) -> Result<Self> {
+        let datarate =
+            DataRate::from_str_name(&value.datarate.to_uppercase()).ok_or_else(|| {
+                Error::custom(format!("invalid downlink datarate: {}", value.datarate))
+            })?;
+        Ok(Self {
+            timestamp: value.timestamp,
+            frequency: value.frequency,
+            datarate: datarate as i32,
+            immediate: value.immediate,
+        })
+    }
+}
+
+impl TryFrom<DownlinkCommand> for PacketDown {
+    type Error = Error;
+
+    fn try_from(value: DownlinkCommand) -> Result<Self> {
+        let payload = STANDARD.decode(&value.payload)?;
+        Ok(PacketDown::from(PacketRouterPacketDownV1 {
+            payload,
+            rx1: Some(value.rx1.try_into()?),
+            rx2: value.rx2.map(WindowV1::try_from).transpose()?,
+        }))
+    }
+}
+
+pub struct MqttBridge {
+    messages: MessageReceiver,
+    client: AsyncClient,
+    eventloop: EventLoop,
+    qos: QoS,
+    gateway_key: PublicKey,
+    format: MqttFormat,
+    /// Gateway EUI in hex in the chirpstack format
+    gateway_id: String,
+    uplink_topic: String,
+    downlink_topic: String,
+    downlinks: gateway::MessageSender,
+    /// Uplinks are not published and downlink commands are ignored while
+    /// paused
+    paused: bool,
+}
+
+impl MqttBridge {
+    pub fn new(
+        settings: &MqttSettings,
+        gateway_key: PublicKey,
+        messages: MessageReceiver,
+        downlinks: gateway::MessageSender,
+    ) -> Result<Self> {
+        let host = settings
+            .uri
+            .host()
+            .ok_or_else(|| Error::custom(format!("missing mqtt broker host: {}", settings.uri)))?;
+        let port = settings.uri.port_u16().unwrap_or(DEFAULT_PORT);
+        let qos = rumqttc::qos(settings.qos)
+            .map_err(|_| Error::custom(format!("invalid mqtt qos: {}", settings.qos)))?;
+        let client_id = settings
+            .client_id
+            .clone()
+            .unwrap_or_else(|| gateway_key.to_string());
+        let mut options = MqttOptions::new(client_id, host, port);
+        options.set_keep_alive(Duration::from_secs(settings.keep_alive));
+        if let Some(username) = &settings.username {
+            let password = settings
+                .password
+                .as_ref()
+                .map(|password| password.expose())
+                .unwrap_or_default();
+            options.set_credentials(username, password);
+        }
+        let (client, eventloop) = AsyncClient::new(options, CLIENT_CAPACITY);
+        let (topic, gateway_id) = match settings.format {
+            MqttFormat::Json => (
+                format!("{}/{gateway_key}", settings.topic_prefix),
+                String::new(),
+            ),
+            MqttFormat::Chirpstack => {
+                let gateway_id = settings
+                    .gateway_id
+                    .as_deref()
+                    .and_then(|id| u64::from_str_radix(id, 16).ok())
+                    .map(|id| format!("{id:016x}"))
+                    .ok_or_else(|| {
+                        Error::custom("chirpstack mqtt format requires a hex mqtt gateway_id")
+                    })?;
+                (
+                    format!("{}/gateway/{gateway_id}", settings.topic_prefix),
+                    gateway_id,
+                )
+            }
+        };
+        Ok(Self {
+            messages,
+            client,
+            eventloop,
+            qos,
+            gateway_key,
+            format: settings.format,
+            gateway_id,
+            uplink_topic: format!("{topic}/event/up"),
+            downlink_topic: format!("{topic}/command/down"),
+            downlinks,
+            paused: false,
+        })
+    }
+
+    pub async fn run(&mut self, shutdown: &triggered::Listener) -> Result {
+        info!(uplinks = %self.uplink_topic, downlinks = %self.downlink_topic, "starting");
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => {
+                    info!("shutting down");
+                    _ = self.client.try_disconnect();
+                    return Ok(())
+                },
+                message = self.messages.recv() => match message {
+                    Some(Message::Uplink(packet, vendor)) => self.handle_uplink(&packet, vendor),
+                    Some(Message::SetPaused(paused)) => self.set_paused(paused),
+                    None => warn!("ignoring closed message channel"),
+                },
+                event = self.eventloop.poll() => match event {
+                    Ok(event) => self.handle_event(event).await,
+                    Err(err) => {
+                        warn!(%err, "broker connection error");
+                        tokio::time::sleep(RECONNECT_DELAY).await;
+                    }
+                },
+            }
+        }
+    }
+
+    /// Pauses or resumes uplink publishing and downlink commands
+    pub fn set_paused(&mut self, paused: bool) {
+        if paused != self.paused {
+            info!(paused, "mqtt pause changed");
+        }
+        self.paused = paused;
+    }
+
+    fn handle_uplink(&self, packet: &PacketUp, vendor: Option<String>) {
+        if self.paused {
+            return;
+        }
+        let payload = match self.format {
+            MqttFormat::Json => {
+                let event = UplinkEvent::new(packet, vendor, &self.gateway_key);
+                match serde_json::to_vec(&event) {
+                    Ok(payload) => payload,
+                    Err(err) => {
+                        warn!(%err, "failed to encode uplink");
+                        return;
+                    }
+                }
+            }
+            MqttFormat::Chirpstack => match chirpstack::uplink_frame(packet, &self.gateway_id) {
+                Some(frame) => frame.encode_to_vec(),
+                None => {
+                    debug!(datarate = ?packet.datarate(), "ignoring non lora uplink");
+                    return;
+                }
+            },
+        };
+        // The client only queues the publish, the event loop sends it
+        if let Err(err) = self
+            .client
+            .try_publish(&self.uplink_topic, self.qos, false, payload)
+        {
+            warn!(%err, "failed to publish uplink");
+        }
+    }
+
+    fn decode_downlink(&self, payload: &[u8]) -> Result<PacketDown> {
+        match self.format {
+            MqttFormat::Json => serde_json::from_slice::<DownlinkCommand>(payload)
+                .map_err(Error::from)
+                .and_then(PacketDown::try_from),
+            MqttFormat::Chirpstack => chirpstack::proto::DownlinkFrame::decode(payload)
+                .map_err(|err| Error::custom(format!("invalid chirpstack downlink: {err}")))
+                .and_then(chirpstack::packet_down),
+        }
+    }
+
+    async fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::Incoming(Packet::ConnAck(_)) => {
+                info!("connected to broker");
+                // Subscriptions do not survive a reconnect with a clean session
+                if let Err(err) = self.client.try_subscribe(&self.downlink_topic, self.qos) {
+                    warn!(%err, "failed to subscribe to downlinks");
+                }
+            }
+            Event::Incoming(Packet::Publish(publish)) if publish.topic == self.downlink_topic => {
+                match self.decode_downlink(&publish.payload) {
+                    Ok(_) if self.paused => debug!("ignoring downlink while paused"),
+                    Ok(downlink) => {
+                        debug!(downlink = ?downlink, "received downlink");
+                        self.downlinks.downlink(downlink).await
+                    }
+                    Err(err) => warn!(%err, "ignoring invalid downlink"),
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_downlink_command() {
+        let command: DownlinkCommand = serde_json::from_str(
+            r#"{
+                "payload": "IAECAwQ=",
+                "rx1": {"timestamp": 5000000, "frequency": 923300000, "datarate": "SF10BW500"},
+                "rx2": {"timestamp": 6000000, "frequency": 923300000, "datarate": "sf12bw500"}
+            }"#,
+        )
+        .unwrap();
+        let downlink = PacketDown::try_from(command).unwrap();
+        assert_eq!(vec![0x20, 1, 2, 3, 4], downlink.payload);
+        let rx1 = downlink.rx1.as_ref().unwrap();
+        assert_eq!((5_000_000, false), (rx1.timestamp, rx1.immediate));
+        assert_eq!(DataRate::Sf10bw500, rx1.datarate());
+        assert_eq!(
+            DataRate::Sf12bw500,
+            downlink.rx2.as_ref().unwrap().datarate()
+        );
+
+        let command: DownlinkCommand = serde_json::from_str(
+            r#"{"payload": "IAECAwQ=", "rx1": {"timestamp": 1, "frequency": 1, "datarate": "SF99"}}"#,
+        )
+        .unwrap();
+        assert!(PacketDown::try_from(command).is_err());
+    }
+}
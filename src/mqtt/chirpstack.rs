@@ -0,0 +1,164 @@
+//! ChirpStack gateway bridge format of the mqtt bridge.
+//!
+//! In this format uplinks are published as protobuf `UplinkFrame` messages
+//! and downlinks are received as `DownlinkFrame` messages, the same as a
+//! ChirpStack gateway bridge does. A ChirpStack network server connected to
+//! the same broker then serves the gateway like any other, in parallel with
+//! the Helium packet routers.
+//!
+//! The concentrator timestamp of an uplink is passed as its context and comes
+//! back in the downlinks in reply, whose delay is relative to it. The first
+//! two items of a downlink frame are used as the rx1 and rx2 windows. The
+//! transmit power of the items is not used, downlinks are sent with the power
+//! of the region parameters like all others. Transmit acknowledgements are
+//! not published.
+
+use crate::{Error, PacketDown, PacketUp, Result};
+use helium_proto::{
+    services::router::{PacketRouterPacketDownV1, WindowV1},
+    DataRate,
+};
+
+pub mod proto {
+    tonic::include_proto!("gw");
+}
+
+use proto::{modulation, timing, CodeRate, CrcStatus, LoraModulationInfo, Modulation};
+
+/// The uplink frame of a packet, or None if the packet was not received with
+/// a LoRa datarate
+pub fn uplink_frame(packet: &PacketUp, gateway_id: &str) -> Option<proto::UplinkFrame> {
+    Some(proto::UplinkFrame {
+        phy_payload: packet.payload().to_vec(),
+        tx_info: Some(proto::UplinkTxInfo {
+            frequency: packet.frequency,
+            modulation: Some(lora_modulation(packet.datarate(), false)?),
+        }),
+        rx_info: Some(proto::UplinkRxInfo {
+            gateway_id: gateway_id.to_string(),
+            uplink_id: rand::random(),
+            rssi: packet.rssi,
+            snr: packet.snr,
+            context: (packet.timestamp as u32).to_be_bytes().to_vec(),
+            crc_status: CrcStatus::CrcOk as i32,
+        }),
+    })
+}
+
+/// The downlink of a downlink frame
+pub fn packet_down(frame: proto::DownlinkFrame) -> Result<PacketDown> {
+    let mut items = frame.items.into_iter();
+    let rx1 = items
+        .next()
+        .ok_or_else(|| Error::custom("empty chirpstack downlink"))?;
+    let rx2 = items.next().map(|item| window(item.tx_info)).transpose()?;
+    Ok(PacketDown::from(PacketRouterPacketDownV1 {
+        rx1: Some(window(rx1.tx_info)?),
+        rx2,
+        payload: rx1.phy_payload,
+    }))
+}
+
+fn window(tx_info: Option<proto::DownlinkTxInfo>) -> Result<WindowV1> {
+    let tx_info = tx_info.ok_or_else(|| Error::custom("missing chirpstack tx info"))?;
+    let datarate = datarate(tx_info.modulation.as_ref())?;
+    let (timestamp, immediate) = match tx_info.timing.and_then(|timing| timing.parameters) {
+        Some(timing::Parameters::Immediately(_)) => (0, true),
+        Some(timing::Parameters::Delay(delay)) => {
+            let context: [u8; 4] = tx_info
+                .context
+                .as_slice()
+                .try_into()
+                .map_err(|_| Error::custom("invalid chirpstack downlink context"))?;
+            let delay = delay.delay.unwrap_or_default();
+            let delay_us = delay.seconds * 1_000_000 + i64::from(delay.nanos) / 1_000;
+            let timestamp = u32::from_be_bytes(context).wrapping_add(delay_us as u32);
+            (u64::from(timestamp), false)
+        }
+        Some(timing::Parameters::GpsEpoch(_)) => {
+            return Err(Error::custom("unsupported chirpstack gps epoch timing"))
+        }
+        None => return Err(Error::custom("missing chirpstack downlink timing")),
+    };
+    Ok(WindowV1 {
+        timestamp,
+        frequency: tx_info.frequency,
+        datarate: datarate as i32,
+        immediate,
+    })
+}
+
+/// The LoRa modulation of a datarate, or None for other modulations
+fn lora_modulation(datarate: DataRate, polarization_inversion: bool) -> Option<Modulation> {
+    let (spreading_factor, bandwidth) = datarate
+        .as_str_name()
+        .strip_prefix("SF")?
+        .split_once("BW")?;
+    Some(Modulation {
+        parameters: Some(modulation::Parameters::Lora(LoraModulationInfo {
+            bandwidth: bandwidth.parse::<u32>().ok()? * 1000,
+            spreading_factor: spreading_factor.parse().ok()?,
+            polarization_inversion,
+            code_rate: CodeRate::Cr45 as i32,
+        })),
+    })
+}
+
+fn datarate(modulation: Option<&Modulation>) -> Result<DataRate> {
+    let Some(modulation::Parameters::Lora(lora)) =
+        modulation.and_then(|modulation| modulation.parameters.as_ref())
+    else {
+        return Err(Error::custom("unsupported chirpstack downlink modulation"));
+    };
+    let name = format!("SF{}BW{}", lora.spreading_factor, lora.bandwidth / 1000);
+    DataRate::from_str_name(&name)
+        .ok_or_else(|| Error::custom(format!("invalid chirpstack downlink datarate: {name}")))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_chirpstack_downlink() {
+        let tx_info = |timing, datarate: DataRate| proto::DownlinkTxInfo {
+            frequency: 869_525_000,
+            power: 14,
+            modulation: lora_modulation(datarate, true),
+            timing: Some(proto::Timing {
+                parameters: Some(timing),
+            }),
+            context: 0xFFFF_FF00u32.to_be_bytes().to_vec(),
+        };
+        let delay = |seconds| {
+            timing::Parameters::Delay(proto::DelayTimingInfo {
+                delay: Some(proto::Duration { seconds, nanos: 0 }),
+            })
+        };
+        let frame = proto::DownlinkFrame {
+            downlink_id: 1,
+            items: vec![
+                proto::DownlinkFrameItem {
+                    phy_payload: vec![0x60, 1, 2, 3],
+                    tx_info: Some(tx_info(delay(1), DataRate::Sf7bw125)),
+                },
+                proto::DownlinkFrameItem {
+                    phy_payload: vec![0x60, 1, 2, 3],
+                    tx_info: Some(tx_info(delay(2), DataRate::Sf12bw125)),
+                },
+            ],
+            gateway_id: "0102030405060708".to_string(),
+        };
+        let downlink = packet_down(frame).unwrap();
+        assert_eq!(vec![0x60, 1, 2, 3], downlink.payload);
+        let rx1 = downlink.rx1.as_ref().unwrap();
+        // The delay wraps around the 32 bit concentrator counter
+        assert_eq!((999_744, false), (rx1.timestamp, rx1.immediate));
+        assert_eq!(DataRate::Sf7bw125, rx1.datarate());
+        let rx2 = downlink.rx2.as_ref().unwrap();
+        assert_eq!(1_999_744, rx2.timestamp);
+        assert_eq!(DataRate::Sf12bw125, rx2.datarate());
+
+        assert!(packet_down(proto::DownlinkFrame::default()).is_err());
+    }
+}
@@ -1,24 +1,68 @@
-use crate::{DecodeError, Error, PublicKey, Region, Result};
-use helium_proto::services::{
-    poc_lora,
-    router::{PacketRouterPacketDownV1, PacketRouterPacketUpV1},
+use crate::{
+    downlink_arbiter::TxWindow,
+    message_cache::{MessageSize, Persist},
+    DecodeError, Error, PublicKey, Region, Result,
 };
-use lorawan::{Direction, PHYPayloadFrame, MHDR};
+use helium_proto::{
+    services::{
+        poc_lora,
+        router::{PacketRouterPacketDownV1, PacketRouterPacketUpV1, WindowV1},
+    },
+    Message,
+};
+use lorawan::{Direction, MType, PHYPayloadFrame, MHDR};
 use semtech_udp::{
     pull_resp::{self, PhyData, Time},
     push_data::{self, CRC},
     CodingRate, DataRate, Modulation,
 };
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::{
     convert::TryFrom,
     fmt,
     ops::Deref,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct PacketUp(PacketRouterPacketUpV1);
+pub struct PacketUp {
+    packet: PacketRouterPacketUpV1,
+    /// Per antenna signal metadata, if reported by the packet forwarder
+    antenna_signals: Vec<AntennaSignal>,
+    /// GPS time the packet was received at, if the packet forwarder has a GPS
+    /// lock
+    gps_time: Option<SystemTime>,
+}
+
+/// Fine grained per antenna signal metadata as reported in the `rsig` field of
+/// v2 rxpk packets by newer packet forwarders. None of these are currently
+/// carried in the uplink or witness report protos.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AntennaSignal {
+    pub antenna: u32,
+    /// Channel rssi in dBm
+    pub channel_rssi: i32,
+    /// Signal rssi in dBm
+    pub signal_rssi: Option<i32>,
+    /// Signal to noise ratio in dB
+    pub snr: f32,
+    /// Frequency offset in Hz
+    pub freq_offset: Option<i32>,
+}
+
+impl From<&push_data::RSig> for AntennaSignal {
+    fn from(value: &push_data::RSig) -> Self {
+        Self {
+            antenna: value.ant as u32,
+            channel_rssi: value.rssic,
+            signal_rssi: value.rssis,
+            snr: value.lsnr,
+            freq_offset: value.foff.map(|foff| foff as i32),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct PacketDown(PacketRouterPacketDownV1);
@@ -26,6 +70,14 @@ pub struct PacketDown(PacketRouterPacketDownV1);
 impl Deref for PacketUp {
     type Target = PacketRouterPacketUpV1;
 
+    fn deref(&self) -> &Self::Target {
+        &self.packet
+    }
+}
+
+impl Deref for PacketDown {
+    type Target = PacketRouterPacketDownV1;
+
     fn deref(&self) -> &Self::Target {
         &self.0
     }
@@ -33,12 +85,38 @@ impl Deref for PacketUp {
 
 impl From<PacketUp> for PacketRouterPacketUpV1 {
     fn from(value: PacketUp) -> Self {
-        value.0
+        value.packet
     }
 }
 impl From<&PacketUp> for PacketRouterPacketUpV1 {
     fn from(value: &PacketUp) -> Self {
-        value.0.clone()
+        value.packet.clone()
+    }
+}
+
+/// Persisted packets carry the router uplink only, per antenna signal metadata
+/// and the GPS time are not persisted
+impl Persist for PacketUp {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.packet.encode_to_vec()
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Self> {
+        Ok(Self {
+            packet: PacketRouterPacketUpV1::decode(data)?,
+            antenna_signals: vec![],
+            gps_time: None,
+        })
+    }
+}
+
+impl MessageSize for PacketUp {
+    fn message_size(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.packet.payload.len()
+            + self.packet.gateway.len()
+            + self.packet.signature.len()
+            + self.antenna_signals.len() * std::mem::size_of::<AntennaSignal>()
     }
 }
 
@@ -52,12 +130,12 @@ impl fmt::Display for PacketUp {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_fmt(format_args!(
             "@{} us, {:.2} MHz, {:?}, snr: {}, rssi: {}, len: {}",
-            self.0.timestamp,
-            self.0.frequency,
-            self.0.datarate(),
-            self.0.snr,
-            self.0.rssi,
-            self.0.payload.len()
+            self.packet.timestamp,
+            self.packet.frequency,
+            self.packet.datarate(),
+            self.packet.snr,
+            self.packet.rssi,
+            self.packet.payload.len()
         ))
     }
 }
@@ -67,15 +145,17 @@ impl TryFrom<PacketUp> for poc_lora::LoraWitnessReportReqV1 {
     fn try_from(value: PacketUp) -> Result<Self> {
         let report = poc_lora::LoraWitnessReportReqV1 {
             data: vec![],
-            tmst: value.0.timestamp as u32,
-            timestamp: SystemTime::now()
+            tmst: value.packet.timestamp as u32,
+            timestamp: value
+                .gps_time
+                .unwrap_or_else(SystemTime::now)
                 .duration_since(UNIX_EPOCH)
                 .map_err(Error::from)?
                 .as_nanos() as u64,
-            signal: value.0.rssi * 10,
-            snr: (value.0.snr * 10.0) as i32,
-            frequency: value.0.frequency as u64,
-            datarate: value.0.datarate,
+            signal: value.packet.rssi * 10,
+            snr: (value.packet.snr * 10.0) as i32,
+            frequency: value.packet.frequency as u64,
+            datarate: value.packet.datarate,
             pub_key: vec![],
             signature: vec![],
         };
@@ -107,16 +187,40 @@ impl PacketUp {
             gateway: gateway.into(),
             signature: vec![],
         };
-        Ok(Self(packet))
+        let antenna_signals = match &rxpk {
+            push_data::RxPk::V1(_) => vec![],
+            push_data::RxPk::V2(rxpk) => rxpk.rsig.iter().map(AntennaSignal::from).collect(),
+        };
+        Ok(Self {
+            packet,
+            antenna_signals,
+            gps_time: gps_time(&rxpk),
+        })
     }
 
-    pub fn is_potential_beacon(&self) -> bool {
-        Self::parse_header(self.payload())
-            .map(|header| {
-                header.mtype() == lorawan::MType::Proprietary
-                    && self.payload().len() == beacon::BEACON_PAYLOAD_SIZE + Self::header_size()
-            })
-            .unwrap_or(false)
+    /// Returns the GPS time the packet was received at, if the packet
+    /// forwarder reported it. Witness reports are timestamped with it instead
+    /// of the local clock.
+    pub fn gps_time(&self) -> Option<SystemTime> {
+        self.gps_time
+    }
+
+    /// Returns the per antenna signal metadata for the packet. This is empty
+    /// for packet forwarders that do not report it.
+    pub fn antenna_signals(&self) -> &[AntennaSignal] {
+        &self.antenna_signals
+    }
+
+    /// Returns the beacon data of the packet if it is a potential beacon, a
+    /// proprietary frame of beacon size.
+    pub fn beacon_data(&self) -> Option<Vec<u8>> {
+        if self.payload().len() != beacon::BEACON_PAYLOAD_SIZE + Self::header_size() {
+            return None;
+        }
+        match Self::parse_frame(Direction::Uplink, self.payload()) {
+            Ok(PHYPayloadFrame::Proprietary(payload)) => Some(payload.into()),
+            _ => None,
+        }
     }
 
     pub fn is_uplink(&self) -> bool {
@@ -133,7 +237,33 @@ impl PacketUp {
     }
 
     pub fn payload(&self) -> &[u8] {
-        &self.0.payload
+        &self.packet.payload
+    }
+
+    /// Returns the device address of a lorawan data frame, or None for other
+    /// frame types
+    pub fn dev_addr(&self) -> Option<u32> {
+        match Self::parse_frame(Direction::Uplink, self.payload()) {
+            Ok(PHYPayloadFrame::MACPayload(payload)) => Some(payload.dev_addr()),
+            _ => None,
+        }
+    }
+
+    /// Returns the join EUI of a join request, or None for other frame types
+    pub fn join_eui(&self) -> Option<u64> {
+        match Self::parse_frame(Direction::Uplink, self.payload()) {
+            Ok(PHYPayloadFrame::JoinRequest(request)) => Some(request.app_eui),
+            _ => None,
+        }
+    }
+
+    /// Returns the frame port of a lorawan data frame, or None for other frame
+    /// types and data frames without a port
+    pub fn fport(&self) -> Option<u8> {
+        match Self::parse_frame(Direction::Uplink, self.payload()) {
+            Ok(PHYPayloadFrame::MACPayload(payload)) => payload.fport(),
+            _ => None,
+        }
     }
 
     pub fn parse_header(payload: &[u8]) -> Result<MHDR> {
@@ -152,60 +282,252 @@ impl PacketUp {
             .map_err(Error::from)
     }
 
-    pub fn hash(&self) -> Vec<u8> {
-        Sha256::digest(&self.0.payload).to_vec()
+    /// Hash of the payload, which identifies the packet to the packet router
+    pub fn hash(&self, algorithm: PayloadHash) -> Vec<u8> {
+        algorithm.digest(&self.packet.payload)
     }
 }
 
+/// Hash function for packet payload hashes. The packet router and the gateway
+/// have to agree on the hash function used in a session.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadHash {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl PayloadHash {
+    pub fn digest(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Sha256 => Sha256::digest(data).to_vec(),
+            Self::Blake3 => blake3::hash(data).as_bytes().to_vec(),
+        }
+    }
+}
+
+impl fmt::Display for PayloadHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sha256 => f.write_str("sha256"),
+            Self::Blake3 => f.write_str("blake3"),
+        }
+    }
+}
+
+/// Delay in microseconds of the rx2 window after the rx1 window
+const RX2_DELAY_US: u32 = 1_000_000;
+
 impl PacketDown {
-    pub fn to_rx1_pull_resp(&self, tx_power: u32) -> Result<pull_resp::TxPk> {
+    /// Adds an rx2 window with the given frequency in Hz and datarate one
+    /// second after the rx1 window, if the downlink has no rx2 window and is
+    /// not sent immediately
+    pub fn with_default_rx2(mut self, frequency: u32, datarate: helium_proto::DataRate) -> Self {
+        if self.0.rx2.is_none() {
+            if let Some(rx1_timestamp) = self.rx1_timestamp() {
+                self.0.rx2 = Some(WindowV1 {
+                    timestamp: u64::from(rx1_timestamp.wrapping_add(RX2_DELAY_US)),
+                    frequency,
+                    datarate: datarate as i32,
+                    immediate: false,
+                });
+            }
+        }
+        self
+    }
+
+    /// The lorawan message type of the downlink, or None if the payload has
+    /// no valid header
+    pub fn mtype(&self) -> Option<MType> {
+        PacketUp::parse_header(&self.0.payload)
+            .ok()
+            .map(|mhdr| mhdr.mtype())
+    }
+
+    /// The concentrator timestamp of the rx1 window, or None for immediate
+    /// downlinks
+    pub fn rx1_timestamp(&self) -> Option<u32> {
+        self.0
+            .rx1
+            .as_ref()
+            .filter(|rx1| !rx1.immediate)
+            .map(|rx1| rx1.timestamp as u32)
+    }
+
+    /// The transmit window of the rx1 window, or None for immediate downlinks
+    /// or an unsupported datarate
+    pub fn rx1_tx_window(&self) -> Option<TxWindow> {
+        self.rx1_timestamp()
+            .and_then(|tmst| self.tx_window(tmst, self.0.rx1.as_ref()?.datarate()))
+    }
+
+    /// The transmit window of the rx2 window, or None if there is no rx2
+    /// window or it has an unsupported datarate
+    pub fn rx2_tx_window(&self) -> Option<TxWindow> {
+        let rx2 = self.0.rx2.as_ref()?;
+        self.tx_window(rx2.timestamp as u32, rx2.datarate())
+    }
+
+    /// The airtime of the downlink in the given window, or None for an
+    /// unsupported datarate
+    pub fn airtime(&self, window: &WindowV1) -> Option<Duration> {
+        let datarate = datarate::from_proto(window.datarate()).ok()?;
+        Some(datarate::airtime(&datarate, self.0.payload.len()))
+    }
+
+    fn tx_window(&self, tmst: u32, datarate: helium_proto::DataRate) -> Option<TxWindow> {
+        let datarate = datarate::from_proto(datarate).ok()?;
+        Some(TxWindow {
+            tmst,
+            airtime: datarate::airtime(&datarate, self.0.payload.len()),
+        })
+    }
+
+    /// The transmit packet of the downlink in its rx1 window. The payload has
+    /// to fit the maximum payload size of the window datarate in the given
+    /// region.
+    pub fn to_rx1_pull_resp(&self, region: Region, tx_power: u32) -> Result<pull_resp::TxPk> {
         let rx1 = self.0.rx1.as_ref().ok_or_else(DecodeError::no_rx1_window)?;
         let time = if rx1.immediate {
             Time::immediate()
         } else {
             Time::by_tmst(rx1.timestamp as u32)
         };
-        self.inner_to_pull_resp(
-            time,
-            rx1.frequency,
-            datarate::from_proto(rx1.datarate())?,
-            tx_power,
-        )
+        self.window_tx_pk(rx1, time, region, tx_power)
     }
 
-    pub fn to_rx2_pull_resp(&self, tx_power: u32) -> Result<Option<pull_resp::TxPk>> {
+    /// The transmit packet of the downlink in its rx2 window, if it has one
+    pub fn to_rx2_pull_resp(
+        &self,
+        region: Region,
+        tx_power: u32,
+    ) -> Result<Option<pull_resp::TxPk>> {
         let rx2 = match self.0.rx2.as_ref() {
             Some(window) => window,
             None => return Ok(None),
         };
-
-        self.inner_to_pull_resp(
-            Time::by_tmst(rx2.timestamp as u32),
-            rx2.frequency,
-            datarate::from_proto(rx2.datarate())?,
-            tx_power,
-        )
-        .map(Some)
+        self.window_tx_pk(rx2, Time::by_tmst(rx2.timestamp as u32), region, tx_power)
+            .map(Some)
     }
 
-    fn inner_to_pull_resp(
+    fn window_tx_pk(
         &self,
+        window: &WindowV1,
         time: Time,
-        frequency_hz: u32,
-        datarate: DataRate,
+        region: Region,
         tx_power: u32,
     ) -> Result<pull_resp::TxPk> {
+        TxPkBuilder::new(
+            TxIntent::DeviceDownlink,
+            window.frequency,
+            datarate::from_proto(window.datarate())?,
+            self.0.payload.clone(),
+        )
+        .time(time)
+        .region(region)
+        .tx_power(tx_power)
+        .build()
+    }
+}
+
+/// What a transmission is for, which determines how its transmit packet is
+/// built and validated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxIntent {
+    /// A LoRaWAN downlink to a device. Sent with inverted polarization, and
+    /// the payload has to fit the maximum payload size of the datarate in the
+    /// region of the transmission.
+    DeviceDownlink,
+    /// A proof of coverage beacon. Sent with normal polarization so other
+    /// gateways receive it like an uplink.
+    Beacon,
+    /// A test transmission to other gateways, like a gateway to gateway ping.
+    /// Sent like a beacon.
+    Test,
+}
+
+impl TxIntent {
+    /// Whether the transmission uses inverted polarization
+    fn ipol(&self) -> bool {
+        matches!(self, Self::DeviceDownlink)
+    }
+}
+
+/// Builds the semtech udp transmit packet of a downlink, beacon or test
+/// transmission. All transmissions are LoRa at coding rate 4/5 on the first
+/// radio chain.
+#[derive(Debug, Clone)]
+pub struct TxPkBuilder {
+    intent: TxIntent,
+    /// Frequency in Hz
+    frequency: u32,
+    datarate: DataRate,
+    data: Vec<u8>,
+    time: Time,
+    /// Region of the transmission, which limits the payload size of device
+    /// downlinks
+    region: Region,
+    tx_power: u32,
+}
+
+impl TxPkBuilder {
+    /// A builder for an immediate transmission of the given PHY payload on
+    /// the given frequency in Hz
+    pub fn new(intent: TxIntent, frequency: u32, datarate: DataRate, data: Vec<u8>) -> Self {
+        Self {
+            intent,
+            frequency,
+            datarate,
+            data,
+            time: Time::immediate(),
+            region: Region::default(),
+            tx_power: 0,
+        }
+    }
+
+    pub fn time(self, time: Time) -> Self {
+        Self { time, ..self }
+    }
+
+    pub fn region(self, region: Region) -> Self {
+        Self { region, ..self }
+    }
+
+    /// The conducted transmit power in dBm
+    pub fn tx_power(self, tx_power: u32) -> Self {
+        Self { tx_power, ..self }
+    }
+
+    /// Builds the transmit packet. Fails for a device downlink whose payload
+    /// does not fit its datarate in its region.
+    pub fn build(self) -> Result<pull_resp::TxPk> {
+        let datarate = self.datarate;
+        if self.frequency == 0 {
+            return Err(Error::custom(format!(
+                "missing {:?} transmit frequency",
+                self.intent
+            )));
+        }
+        if self.intent == TxIntent::DeviceDownlink {
+            let max_size = datarate::max_payload_size(self.region, &datarate);
+            if self.data.len() > max_size {
+                return Err(DecodeError::payload_too_large(
+                    self.data.len(),
+                    max_size,
+                    datarate.to_string(),
+                ));
+            }
+        }
         Ok(pull_resp::TxPk {
-            time,
-            ipol: true,
+            time: self.time,
+            ipol: self.intent.ipol(),
             modu: Modulation::LORA,
             codr: CodingRate::_4_5,
             datr: datarate,
-            // for normal lorawan packets we're not selecting different frequencies
-            // like we are for PoC
-            freq: to_mhz(frequency_hz),
-            data: PhyData::new(self.0.payload.clone()),
-            powe: tx_power as u64,
+            freq: to_mhz(self.frequency),
+            data: PhyData::new(self.data),
+            powe: self.tx_power as u64,
             rfch: 0,
             fdev: None,
             prea: None,
@@ -214,6 +536,30 @@ impl PacketDown {
     }
 }
 
+/// Unix time in seconds of the GPS epoch, 1980-01-06
+const GPS_EPOCH: u64 = 315_964_800;
+/// Leap seconds GPS time is ahead of UTC, since 2017
+const GPS_LEAP_SECONDS: u64 = 18;
+
+/// The receive time of a packet stamped by a packet forwarder with a GPS lock.
+/// This is the UTC time with microsecond precision, or the GPS time in
+/// milliseconds for forwarders that only report that. Only v2 rxpk packets
+/// carry the GPS time.
+fn gps_time(rxpk: &push_data::RxPk) -> Option<SystemTime> {
+    let (time, tmms) = match rxpk {
+        push_data::RxPk::V1(rxpk) => (&rxpk.time, None),
+        push_data::RxPk::V2(rxpk) => (&rxpk.time, rxpk.tmms),
+    };
+    let utc = time
+        .as_deref()
+        .and_then(|time| OffsetDateTime::parse(time, &Rfc3339).ok())
+        .map(SystemTime::from);
+    utc.or_else(|| {
+        let millis = tmms?.checked_sub(GPS_LEAP_SECONDS * 1000)?;
+        UNIX_EPOCH.checked_add(Duration::from_secs(GPS_EPOCH) + Duration::from_millis(millis))
+    })
+}
+
 pub(crate) fn to_hz<M: Into<f64>>(mhz: M) -> u64 {
     (mhz.into() * 1_000_000f64).trunc() as u64
 }
@@ -223,9 +569,10 @@ pub(crate) fn to_mhz<H: Into<f64>>(hz: H) -> f64 {
 }
 
 pub(crate) mod datarate {
-    use super::{DecodeError, Result};
+    use super::{DecodeError, Region, Result};
     use helium_proto::DataRate as ProtoRate;
     use semtech_udp::{Bandwidth, DataRate, SpreadingFactor};
+    use std::time::Duration;
 
     pub fn from_proto(rate: ProtoRate) -> Result<DataRate> {
         let (spreading_factor, bandwidth) = match rate {
@@ -263,6 +610,72 @@ pub(crate) mod datarate {
         Ok(DataRate::new(spreading_factor, bandwidth))
     }
 
+    /// Size of the LoRaWAN MHDR and MIC around the MAC payload
+    const PHY_OVERHEAD: usize = 5;
+
+    /// Returns the maximum PHY payload size in bytes that can be transmitted
+    /// at the given datarate in the given region, 0 if the datarate can not
+    /// be used in the region. This uses the maximum MAC payload sizes from the
+    /// LoRaWAN regional parameters. US915, AU915 and AS923 use the sizes for a
+    /// 400 ms dwell time limit, which rules out SF11 and SF12 at 125 kHz. The
+    /// other regions share the sizes without dwell time limits.
+    pub fn max_payload_size(region: Region, rate: &DataRate) -> usize {
+        let dwell_limited = [
+            helium_proto::Region::Us915,
+            helium_proto::Region::Au915,
+            helium_proto::Region::As9231,
+            helium_proto::Region::As9231b,
+            helium_proto::Region::As9232,
+            helium_proto::Region::As9233,
+            helium_proto::Region::As9234,
+        ]
+        .into_iter()
+        .any(|dwell_region| region == Region::from(dwell_region));
+        let max_mac_payload = match (rate.spreading_factor(), rate.bandwidth()) {
+            (SpreadingFactor::SF12, Bandwidth::BW500) => Some(61),
+            (SpreadingFactor::SF11, Bandwidth::BW500) => Some(137),
+            (SpreadingFactor::SF12 | SpreadingFactor::SF11, _) if dwell_limited => None,
+            (SpreadingFactor::SF10, _) if dwell_limited => Some(19),
+            (SpreadingFactor::SF9, _) if dwell_limited => Some(61),
+            (SpreadingFactor::SF8, _) if dwell_limited => Some(133),
+            (SpreadingFactor::SF12 | SpreadingFactor::SF11 | SpreadingFactor::SF10, _) => Some(59),
+            (SpreadingFactor::SF9, _) => Some(123),
+            _ => Some(250),
+        };
+        max_mac_payload.map_or(0, |max_mac_payload| max_mac_payload + PHY_OVERHEAD)
+    }
+
+    /// Returns the time on air of a downlink with a PHY payload of the given
+    /// size at the given datarate. Downlinks are sent with an explicit header,
+    /// coding rate 4/5, no payload CRC and an 8 symbol preamble.
+    pub fn airtime(rate: &DataRate, payload_size: usize) -> Duration {
+        let sf = match rate.spreading_factor() {
+            SpreadingFactor::SF5 => 5,
+            SpreadingFactor::SF6 => 6,
+            SpreadingFactor::SF7 => 7,
+            SpreadingFactor::SF8 => 8,
+            SpreadingFactor::SF9 => 9,
+            SpreadingFactor::SF10 => 10,
+            SpreadingFactor::SF11 => 11,
+            SpreadingFactor::SF12 => 12,
+        };
+        // Microseconds per chip at the bandwidth
+        let chip_time = match rate.bandwidth() {
+            Bandwidth::BW125 => 8,
+            Bandwidth::BW250 => 4,
+            Bandwidth::BW500 => 2,
+        };
+        // Low data rate optimization is mandated for symbols longer than 16ms
+        let low_data_rate = sf >= 11 && matches!(rate.bandwidth(), Bandwidth::BW125);
+        let symbol_time = (1u64 << sf) * chip_time;
+        let payload_bits = 8 * payload_size as i64 - 4 * sf + 28;
+        let bits_per_symbol = 4 * (sf - 2 * i64::from(low_data_rate));
+        let payload_symbols =
+            8 + ((payload_bits + bits_per_symbol - 1) / bits_per_symbol).max(0) as u64 * 5;
+        // The preamble takes 12.25 symbols, so count in quarter symbols
+        Duration::from_micros((49 + 4 * payload_symbols) * symbol_time / 4)
+    }
+
     pub fn to_proto(rate: DataRate) -> Result<ProtoRate> {
         let rate = match (rate.spreading_factor(), rate.bandwidth()) {
             (SpreadingFactor::SF12, Bandwidth::BW125) => ProtoRate::Sf12bw125,
@@ -293,3 +706,173 @@ pub(crate) mod datarate {
         Ok(rate)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use helium_proto::services::router::WindowV1;
+
+    fn mk_downlink(payload_size: usize, datarate: helium_proto::DataRate) -> PacketDown {
+        PacketDown::from(PacketRouterPacketDownV1 {
+            payload: vec![0; payload_size],
+            rx1: Some(WindowV1 {
+                timestamp: 0,
+                frequency: 923_300_000,
+                datarate: datarate as i32,
+                immediate: false,
+            }),
+            rx2: None,
+        })
+    }
+
+    #[test]
+    fn test_tx_pk_builder() {
+        use semtech_udp::{Bandwidth, SpreadingFactor};
+
+        let datarate = DataRate::new(SpreadingFactor::SF12, Bandwidth::BW125);
+        let build = |intent, size| {
+            TxPkBuilder::new(intent, 869_525_000, datarate.clone(), vec![0; size]).build()
+        };
+        let downlink = build(TxIntent::DeviceDownlink, 13).expect("downlink");
+        assert!(downlink.ipol);
+        assert_eq!(869.525, downlink.freq);
+        let beacon = build(TxIntent::Beacon, 13).expect("beacon");
+        assert!(!beacon.ipol);
+
+        // Only device downlinks are limited to the payload size of the
+        // datarate
+        assert!(build(TxIntent::DeviceDownlink, 100).is_err());
+        assert!(build(TxIntent::Test, 100).is_ok());
+        assert!(TxPkBuilder::new(TxIntent::Beacon, 0, datarate, vec![0; 13])
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_default_rx2() {
+        let rx2 = |downlink: PacketDown| {
+            downlink
+                .with_default_rx2(869_525_000, helium_proto::DataRate::Sf9bw125)
+                .0
+                .rx2
+        };
+        let mut downlink = mk_downlink(13, helium_proto::DataRate::Sf7bw125);
+        downlink.0.rx1.as_mut().unwrap().timestamp = u64::from(u32::MAX);
+        let window = rx2(downlink.clone()).expect("default rx2 window");
+        assert_eq!(999_999, window.timestamp);
+        assert_eq!(869_525_000, window.frequency);
+        assert_eq!(helium_proto::DataRate::Sf9bw125, window.datarate());
+
+        // An rx2 window of the router is kept
+        let mut with_rx2 = downlink.clone();
+        with_rx2.0.rx2 = downlink.0.rx1.clone();
+        assert_eq!(with_rx2.0.rx2, rx2(with_rx2.clone()));
+
+        // Immediate downlinks have no rx2 window
+        downlink.0.rx1.as_mut().unwrap().immediate = true;
+        assert_eq!(None, rx2(downlink));
+    }
+
+    #[test]
+    fn test_downlink_airtime() {
+        use semtech_udp::{Bandwidth, DataRate, SpreadingFactor};
+
+        let airtime = |sf, bw, size| datarate::airtime(&DataRate::new(sf, bw), size).as_micros();
+        assert_eq!(41_216, airtime(SpreadingFactor::SF7, Bandwidth::BW125, 13));
+        assert_eq!(
+            1_155_072,
+            airtime(SpreadingFactor::SF12, Bandwidth::BW125, 13)
+        );
+        assert_eq!(
+            2_793_472,
+            airtime(SpreadingFactor::SF12, Bandwidth::BW125, 64)
+        );
+        assert_eq!(
+            113_152,
+            airtime(SpreadingFactor::SF10, Bandwidth::BW500, 33)
+        );
+    }
+
+    #[test]
+    fn test_downlink_max_payload() {
+        use helium_proto::DataRate as ProtoRate;
+
+        let eu868 = Region::from(helium_proto::Region::Eu868);
+        let downlink = mk_downlink(64, ProtoRate::Sf12bw125);
+        assert!(downlink.to_rx1_pull_resp(eu868, 27).is_ok());
+        let downlink = mk_downlink(65, ProtoRate::Sf12bw125);
+        assert!(matches!(
+            downlink.to_rx1_pull_resp(eu868, 27),
+            Err(Error::Decode(DecodeError::PayloadTooLarge {
+                size: 65,
+                max: 64,
+                ..
+            }))
+        ));
+        let downlink = mk_downlink(255, ProtoRate::Sf7bw500);
+        assert!(downlink.to_rx1_pull_resp(eu868, 27).is_ok());
+        let downlink = mk_downlink(70, ProtoRate::Sf12bw500);
+        assert!(downlink.to_rx1_pull_resp(eu868, 27).is_err());
+    }
+
+    #[test]
+    fn test_region_max_payload() {
+        use helium_proto::{DataRate as ProtoRate, Region as ProtoRegion};
+        use semtech_udp::{Bandwidth, DataRate, SpreadingFactor};
+
+        let max = |region, sf, bw| {
+            datarate::max_payload_size(Region::from(region), &DataRate::new(sf, bw))
+        };
+        // Without dwell time limits
+        for region in [ProtoRegion::Eu868, ProtoRegion::Kr920, ProtoRegion::In865] {
+            assert_eq!(64, max(region, SpreadingFactor::SF12, Bandwidth::BW125));
+            assert_eq!(64, max(region, SpreadingFactor::SF10, Bandwidth::BW125));
+            assert_eq!(128, max(region, SpreadingFactor::SF9, Bandwidth::BW125));
+            assert_eq!(255, max(region, SpreadingFactor::SF7, Bandwidth::BW250));
+        }
+        // With the 400 ms dwell time limit
+        for region in [
+            ProtoRegion::Us915,
+            ProtoRegion::Au915,
+            ProtoRegion::As9231,
+            ProtoRegion::As9232,
+        ] {
+            assert_eq!(0, max(region, SpreadingFactor::SF12, Bandwidth::BW125));
+            assert_eq!(0, max(region, SpreadingFactor::SF11, Bandwidth::BW125));
+            assert_eq!(24, max(region, SpreadingFactor::SF10, Bandwidth::BW125));
+            assert_eq!(66, max(region, SpreadingFactor::SF9, Bandwidth::BW125));
+            assert_eq!(138, max(region, SpreadingFactor::SF8, Bandwidth::BW125));
+            assert_eq!(255, max(region, SpreadingFactor::SF7, Bandwidth::BW125));
+        }
+        // The 500 kHz downlink datarates of US915 and AU915
+        for region in [ProtoRegion::Us915, ProtoRegion::Au915] {
+            assert_eq!(66, max(region, SpreadingFactor::SF12, Bandwidth::BW500));
+            assert_eq!(142, max(region, SpreadingFactor::SF11, Bandwidth::BW500));
+            assert_eq!(255, max(region, SpreadingFactor::SF10, Bandwidth::BW500));
+        }
+
+        // A US915 downlink that fits EU868 but not the dwell time limit
+        let downlink = mk_downlink(30, ProtoRate::Sf10bw125);
+        assert!(downlink
+            .to_rx1_pull_resp(Region::from(ProtoRegion::Eu868), 27)
+            .is_ok());
+        assert!(downlink
+            .to_rx1_pull_resp(Region::from(ProtoRegion::Us915), 27)
+            .is_err());
+    }
+
+    #[test]
+    fn test_payload_hash() {
+        let hex = |hash: Vec<u8>| -> String { hash.iter().map(|b| format!("{b:02x}")).collect() };
+        assert_eq!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            hex(PayloadHash::Sha256.digest(&[]))
+        );
+        assert_eq!(
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262",
+            hex(PayloadHash::Blake3.digest(&[]))
+        );
+        let setting: PayloadHash = serde_json::from_str("\"blake3\"").unwrap();
+        assert_eq!(PayloadHash::Blake3, setting);
+    }
+}
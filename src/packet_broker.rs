@@ -0,0 +1,119 @@
+//! Packet Broker peering.
+//!
+//! Community gateways can serve the devices of other LoRaWAN networks by
+//! peering through Packet Broker. Every uplink delivered to the packet routers
+//! is published to a Packet Broker routing endpoint as well, as a forwarder
+//! with the configured NetID, and Packet Broker delivers it to the home
+//! network of the device.
+//!
+//! Uplinks are queued while the endpoint can not be reached and retried with
+//! a backoff. Uplinks held longer than the maximum hold time are dropped since
+//! a home network can no longer answer them in time. Downlinks are not
+//! received from Packet Broker.
+
+use crate::{
+    message_cache::MessageCache,
+    service::{backhaul::Backhaul, packet_broker::PacketBrokerService, Reconnect},
+    settings::PacketBrokerSettings,
+    sync, PacketUp, PublicKey, Result,
+};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// Minimum and maximum wait between retries of queued uplinks
+const RETRY_MIN_WAIT: Duration = Duration::from_secs(1);
+const RETRY_MAX_WAIT: Duration = Duration::from_secs(60);
+const RETRY_BACKOFF_RETRIES: u32 = 10;
+
+#[derive(Debug)]
+pub enum Message {
+    Uplink { packet: PacketUp, received: Instant },
+}
+
+pub type MessageSender = sync::MessageSender<Message>;
+pub type MessageReceiver = sync::MessageReceiver<Message>;
+
+pub fn message_channel() -> (MessageSender, MessageReceiver) {
+    sync::message_channel(20)
+}
+
+impl MessageSender {
+    pub async fn uplink(&self, packet: PacketUp, received: Instant) {
+        self.send(Message::Uplink { packet, received }).await
+    }
+}
+
+pub struct PacketBroker {
+    messages: MessageReceiver,
+    service: PacketBrokerService,
+    store: MessageCache<PacketUp>,
+    max_hold_time: Duration,
+    reconnect: Reconnect,
+    /// Whether the last publish failed and queued uplinks wait for a retry
+    retrying: bool,
+}
+
+impl PacketBroker {
+    pub fn new(
+        settings: &PacketBrokerSettings,
+        gateway: &PublicKey,
+        backhaul: &Backhaul,
+        messages: MessageReceiver,
+    ) -> Result<Self> {
+        Ok(Self {
+            messages,
+            service: PacketBrokerService::new(settings, gateway, backhaul)?,
+            store: MessageCache::new(settings.queue),
+            max_hold_time: Duration::from_secs(settings.max_hold_time),
+            reconnect: Reconnect::new(RETRY_BACKOFF_RETRIES, RETRY_MIN_WAIT, RETRY_MAX_WAIT),
+            retrying: false,
+        })
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn run(&mut self, shutdown: &triggered::Listener) -> Result {
+        info!(uri = %self.service.uri, "starting");
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => {
+                    info!("shutting down");
+                    return Ok(())
+                },
+                message = self.messages.recv() => match message {
+                    Some(Message::Uplink { packet, received }) => {
+                        let dropped = self.store.push_back(packet, received);
+                        if dropped > 0 {
+                            warn!(dropped, "queue full, dropped oldest uplinks");
+                        }
+                        if !self.retrying {
+                            self.send_waiting_packets().await
+                        }
+                    }
+                    None => warn!("ignoring closed message channel"),
+                },
+                _ = self.reconnect.wait(), if self.retrying => self.send_waiting_packets().await,
+            }
+        }
+    }
+
+    async fn send_waiting_packets(&mut self) {
+        while let (removed, Some(packet)) = self.store.pop_front(self.max_hold_time) {
+            if removed > 0 {
+                info!(removed, "discarded queued uplinks");
+            }
+            match self.service.publish(&packet, packet.hold_time()).await {
+                Ok(Some(id)) => debug!(id, uplink = %*packet, "published uplink"),
+                Ok(None) => debug!(uplink = %*packet, "skipped unsupported uplink"),
+                Err(err) => {
+                    warn!(%err, "failed to publish uplink");
+                    self.store.push_front(packet);
+                    self.retrying = true;
+                    self.reconnect.update_next_time(true);
+                    return;
+                }
+            }
+        }
+        self.retrying = false;
+        self.reconnect.retry_count = 0;
+    }
+}
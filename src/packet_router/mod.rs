@@ -1,20 +1,31 @@
+mod route;
+
+pub use route::{select_routes, UplinkRoute};
+
 use crate::{
+    error::ServiceError,
     gateway,
+    hooks::StateHook,
     message_cache::{CacheMessage, MessageCache},
-    service::{packet_router::PacketRouterService, Reconnect},
-    sync, Base64, PacketUp, PublicKey, Result, Settings,
+    packet::PayloadHash,
+    service::{backhaul::Backhaul, packet_router::PacketRouterService, Reconnect},
+    settings::{HookState, RouterSettings},
+    sync, Base64, Error, PacketUp, PublicKey, Result, Settings,
 };
 use futures::TryFutureExt;
 use helium_proto::services::router::{
     envelope_down_v1, PacketRouterPacketDownV1, PacketRouterPacketUpV1, PacketRouterSessionOfferV1,
 };
 use serde::Serialize;
-use std::{ops::Deref, time::Instant as StdInstant};
+use std::{collections::HashMap, ops::Deref, time::Instant as StdInstant};
 use tokio::time::Duration;
 
 use tracing::{debug, info, warn};
 
-const STORE_GC_INTERVAL: Duration = Duration::from_secs(60);
+/// Lower bounds in seconds of the age buckets reported for queued packets
+const QUEUE_AGE_BUCKETS: [u64; 6] = [0, 1, 5, 15, 30, 60];
+/// Number of most frequent device addresses reported for queued packets
+const QUEUE_TOP_DEVADDRS: usize = 5;
 
 #[derive(Debug)]
 pub enum Message {
@@ -23,6 +34,58 @@ pub enum Message {
         received: StdInstant,
     },
     Status(sync::ResponseSender<RouterStatus>),
+    QueueStatus(sync::ResponseSender<QueueStatus>),
+    PurgeQueue(sync::ResponseSender<usize>),
+    SetQueue {
+        queue: u16,
+        max_bytes: Option<usize>,
+        max_hold_time: u64,
+        join_max_hold_time: u64,
+    },
+    DownlinkAck(DownlinkAck),
+}
+
+/// Outcome of the transmission of a downlink received from the router
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DownlinkAck {
+    /// Transmitted in the first receive window
+    Rx1,
+    /// Transmitted in the second receive window
+    Rx2,
+    /// Transmitted with a transmit power lowered by the packet forwarder
+    AdjustedPower,
+    /// Not transmitted since the forwarder received it before the window
+    TooEarly,
+    /// Not transmitted since the forwarder received it after the window
+    TooLate,
+    /// Not transmitted for another reason
+    Failed,
+}
+
+/// Counts of the downlink outcomes of a router
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DownlinkAcks {
+    pub rx1: u64,
+    pub rx2: u64,
+    pub adjusted_power: u64,
+    pub too_early: u64,
+    pub too_late: u64,
+    pub failed: u64,
+}
+
+impl DownlinkAcks {
+    fn record(&mut self, ack: DownlinkAck) {
+        let count = match ack {
+            DownlinkAck::Rx1 => &mut self.rx1,
+            DownlinkAck::Rx2 => &mut self.rx2,
+            DownlinkAck::AdjustedPower => &mut self.adjusted_power,
+            DownlinkAck::TooEarly => &mut self.too_early,
+            DownlinkAck::TooLate => &mut self.too_late,
+            DownlinkAck::Failed => &mut self.failed,
+        };
+        *count += 1;
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -31,6 +94,72 @@ pub struct RouterStatus {
     pub uri: http::Uri,
     pub connected: bool,
     pub session_key: Option<PublicKey>,
+    /// Seconds since the current session was established. Only reported by
+    /// the gateway status.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_age: Option<u64>,
+    /// Number of sessions replaced by a new router offer. Only reported by the
+    /// gateway status.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_renewals: Option<u64>,
+    /// Hash function for payload hashes in the current session. Only reported
+    /// by the gateway status.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_hash: Option<PayloadHash>,
+    /// Outcomes of the downlinks of this router. Only reported by the gateway
+    /// status.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub downlink_acks: Option<DownlinkAcks>,
+}
+
+/// Summary of the uplinks queued for delivery to the packet router
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueStatus {
+    pub count: usize,
+    /// Total size in bytes of the queued uplinks
+    pub bytes: usize,
+    pub ages: Vec<QueueAgeBucket>,
+    pub top_devaddrs: Vec<QueueDevAddrCount>,
+    pub delivery: DeliveryStats,
+}
+
+/// What happened to the uplinks queued for the packet router
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DeliveryStats {
+    /// Number of uplinks sent to the router. The router protocol does not
+    /// acknowledge uplinks, so these were sent but not necessarily received.
+    pub delivered: u64,
+    /// Number of uplinks dropped since they were held longer than the maximum
+    /// hold time
+    pub expired: u64,
+    /// Number of expired uplinks that were join requests
+    pub expired_joins: u64,
+    /// Number of uplinks dropped to make room in a full queue
+    pub evicted: u64,
+    /// Number of uplinks removed by purging the queue
+    pub purged: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueAgeBucket {
+    /// Minimum age in seconds of packets in this bucket. Packets up to the
+    /// minimum age of the next bucket are included.
+    pub min_age: u64,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueDevAddrCount {
+    #[serde(serialize_with = "serialize_devaddr")]
+    pub devaddr: u32,
+    pub count: usize,
+}
+
+fn serialize_devaddr<S: serde::Serializer>(
+    devaddr: &u32,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{devaddr:08X}"))
 }
 
 pub type MessageSender = sync::MessageSender<Message>;
@@ -48,39 +177,130 @@ impl MessageSender {
     pub async fn status(&self) -> Result<RouterStatus> {
         self.request(Message::Status).await
     }
+
+    pub async fn queue_status(&self) -> Result<QueueStatus> {
+        self.request(Message::QueueStatus).await
+    }
+
+    pub async fn purge_queue(&self) -> Result<usize> {
+        self.request(Message::PurgeQueue).await
+    }
+
+    /// Changes the maximum number and total size in bytes of queued uplinks
+    /// and the maximum time in seconds an uplink and a join request are held
+    /// in the queue
+    pub async fn set_queue(
+        &self,
+        queue: u16,
+        max_bytes: Option<usize>,
+        max_hold_time: u64,
+        join_max_hold_time: u64,
+    ) {
+        self.send(Message::SetQueue {
+            queue,
+            max_bytes,
+            max_hold_time,
+            join_max_hold_time,
+        })
+        .await
+    }
+
+    /// Reports the outcome of the transmission of a downlink of this router
+    pub async fn downlink_ack(&self, ack: DownlinkAck) {
+        self.send(Message::DownlinkAck(ack)).await
+    }
 }
 
 pub struct PacketRouter {
     messages: MessageReceiver,
+    /// Sender of this router's messages, passed along with its downlinks to
+    /// receive their outcome
+    acks: MessageSender,
     transmit: gateway::MessageSender,
     service: PacketRouterService,
     reconnect: Reconnect,
     store: MessageCache<PacketUp>,
+    /// Longest time a queued packet is held before it is discarded
+    max_hold_time: Duration,
+    /// Longest time a queued join request is held, if shorter than for
+    /// other packets
+    join_max_hold_time: Option<Duration>,
+    delivery: DeliveryStats,
+    /// Whether uplinks are delivered to this router
+    enabled: bool,
+    /// Whether downlinks from this router are transmitted
+    downlinks: bool,
+    /// Hooks run when the router session goes up or down. Only set for the
+    /// primary router
+    session_hook: Option<StateHook>,
+    downlink_acks: DownlinkAcks,
 }
 
 impl PacketRouter {
     pub fn new(
         settings: &Settings,
+        router_settings: &RouterSettings,
         messages: MessageReceiver,
+        acks: MessageSender,
         transmit: gateway::MessageSender,
     ) -> Self {
-        let router_settings = &settings.router;
-        let service =
-            PacketRouterService::new(router_settings.uri.clone(), settings.keypair.clone());
-        let store = MessageCache::new(router_settings.queue);
+        let mut service = PacketRouterService::new(
+            router_settings.uri.clone(),
+            settings.keypair.clone(),
+            router_settings.payload_hash,
+            Backhaul::from(&settings.network),
+        );
+        service.log_sessions(&settings.data_dir);
+        service.set_session_max_age(
+            (router_settings.session_max_age > 0)
+                .then(|| Duration::from_secs(router_settings.session_max_age)),
+        );
+        let max_hold_time = Duration::from_secs(router_settings.max_hold_time);
+        let (queue, max_bytes) = router_settings.queue_limits();
+        let mut store = if router_settings.persist {
+            let path = router_settings.queue_path(&settings.data_dir);
+            MessageCache::persistent(
+                queue,
+                &path,
+                router_settings.persist_max_size,
+                max_hold_time,
+            )
+            .unwrap_or_else(|err| {
+                warn!(path = %path.display(), %err, "failed to open queue journal, queue not persisted");
+                MessageCache::new(queue)
+            })
+        } else {
+            MessageCache::new(queue)
+        };
+        store.set_max_bytes(max_bytes);
         let reconnect = Reconnect::default();
+        let session_hook = std::ptr::eq(router_settings, &settings.router)
+            .then(|| StateHook::new(settings, HookState::Router));
         Self {
             service,
             transmit,
             messages,
+            acks,
             store,
+            max_hold_time,
+            join_max_hold_time: join_hold_time(router_settings.join_max_hold_time),
+            delivery: DeliveryStats::default(),
             reconnect,
+            enabled: router_settings.enabled,
+            downlinks: router_settings.downlinks,
+            session_hook,
+            downlink_acks: DownlinkAcks::default(),
         }
     }
 
     #[tracing::instrument(skip_all)]
     pub async fn run(&mut self, shutdown: &triggered::Listener) -> Result {
-        info!(uri = %self.service.uri, "starting");
+        info!(
+            uri = %self.service.uri,
+            enabled = self.enabled,
+            downlinks = self.downlinks,
+            "starting"
+        );
 
         loop {
             tokio::select! {
@@ -100,12 +320,37 @@ impl PacketRouter {
                             uri: self.service.uri.clone(),
                             connected: self.service.is_connected(),
                             session_key: self.service.session_key().cloned(),
+                            session_age: self.service.session_age().map(|age| age.as_secs()),
+                            session_renewals: Some(self.service.session_renewals()),
+                            payload_hash: self.service.session_payload_hash(),
+                            downlink_acks: Some(self.downlink_acks.clone()),
                         };
                         tx_resp.send(status)
                     }
+                    Some(Message::QueueStatus(tx_resp)) => tx_resp.send(self.queue_status()),
+                    Some(Message::PurgeQueue(tx_resp)) => {
+                        let purged = self.store.clear();
+                        self.delivery.purged += purged as u64;
+                        info!(purged, "purged queued packets");
+                        tx_resp.send(purged)
+                    }
+                    Some(Message::SetQueue { queue, max_bytes, max_hold_time, join_max_hold_time }) => {
+                        let dropped = self.store.set_max_messages(queue)
+                            + self.store.set_max_bytes(max_bytes);
+                        self.delivery.evicted += dropped as u64;
+                        self.max_hold_time = Duration::from_secs(max_hold_time);
+                        self.join_max_hold_time = join_hold_time(join_max_hold_time);
+                        info!(queue, ?max_bytes, max_hold_time, join_max_hold_time, dropped, "queue settings changed");
+                    }
+                    Some(Message::DownlinkAck(ack)) => self.handle_downlink_ack(ack),
                     None => warn!("ignoring closed message channel"),
                 },
-                _ = self.reconnect.wait() => {
+                _ = session_expiry(self.service.session_expires_in()) => {
+                    if self.renew_session().await.is_err() {
+                        self.reconnect.update_next_time(true);
+                    }
+                },
+                _ = self.reconnect.wait(), if self.enabled => {
                     let reconnect_result = self.handle_reconnect().await;
                     self.reconnect.update_next_time(reconnect_result.is_err());
                 },
@@ -129,6 +374,39 @@ impl PacketRouter {
                     },
                 }
             }
+            if let Some(hook) = self.session_hook.as_mut() {
+                hook.set(self.service.is_connected());
+            }
+        }
+    }
+
+    fn queue_status(&self) -> QueueStatus {
+        let mut ages: Vec<QueueAgeBucket> = QUEUE_AGE_BUCKETS
+            .iter()
+            .map(|&min_age| QueueAgeBucket { min_age, count: 0 })
+            .collect();
+        let mut devaddrs: HashMap<u32, usize> = HashMap::new();
+        for packet in self.store.iter() {
+            let age = packet.hold_time().as_secs();
+            if let Some(bucket) = ages.iter_mut().rev().find(|bucket| bucket.min_age <= age) {
+                bucket.count += 1;
+            }
+            if let Some(devaddr) = packet.dev_addr() {
+                *devaddrs.entry(devaddr).or_default() += 1;
+            }
+        }
+        let mut top_devaddrs: Vec<QueueDevAddrCount> = devaddrs
+            .into_iter()
+            .map(|(devaddr, count)| QueueDevAddrCount { devaddr, count })
+            .collect();
+        top_devaddrs.sort_unstable_by(|a, b| b.count.cmp(&a.count).then(a.devaddr.cmp(&b.devaddr)));
+        top_devaddrs.truncate(QUEUE_TOP_DEVADDRS);
+        QueueStatus {
+            count: self.store.len(),
+            bytes: self.store.bytes(),
+            ages,
+            top_devaddrs,
+            delivery: self.delivery.clone(),
         }
     }
 
@@ -143,7 +421,10 @@ impl PacketRouter {
     }
 
     async fn handle_uplink(&mut self, uplink: PacketUp, received: StdInstant) -> Result {
-        self.store.push_back(uplink, received);
+        if !self.enabled {
+            return Ok(());
+        }
+        self.delivery.evicted += self.store.push_back(uplink, received) as u64;
         if self.service.is_connected() {
             self.send_waiting_packets().await?;
         }
@@ -151,35 +432,105 @@ impl PacketRouter {
     }
 
     async fn handle_downlink(&mut self, message: PacketRouterPacketDownV1) {
-        self.transmit.downlink(message.into()).await;
+        if !self.downlinks {
+            debug!(uri = %self.service.uri, "ignoring downlink, downlinks disabled");
+            return;
+        }
+        self.transmit
+            .router_downlink(message.into(), self.acks.clone())
+            .await;
+    }
+
+    /// Records the outcome of a downlink. The router protocol has no message
+    /// to confirm downlinks yet, so outcomes are counted for the router status
+    /// until it does.
+    fn handle_downlink_ack(&mut self, ack: DownlinkAck) {
+        debug!(uri = %self.service.uri, ?ack, "downlink ack");
+        self.downlink_acks.record(ack);
     }
 
     async fn handle_session_offer(&mut self, message: PacketRouterSessionOfferV1) -> Result {
         self.service.session_init(&message.nonce).await?;
+        if let Some(payload_hash) = self.service.session_payload_hash() {
+            debug!(%payload_hash, "session payload hash");
+        }
         self.send_waiting_packets()
             .inspect_err(|err| warn!(%err, "failed to send queued packets"))
             .await
     }
 
     async fn send_waiting_packets(&mut self) -> Result {
-        while let (removed, Some(packet)) = self.store.pop_front(STORE_GC_INTERVAL) {
-            if removed > 0 {
-                info!(removed, "discarded queued packets");
+        loop {
+            let (max_hold_time, join_max_hold_time) = (self.max_hold_time, self.join_max_hold_time);
+            let (expired, packet) = self
+                .store
+                .pop_front_with(|packet| match join_max_hold_time {
+                    Some(join_max_hold_time) if packet.join_eui().is_some() => {
+                        join_max_hold_time.min(max_hold_time)
+                    }
+                    _ => max_hold_time,
+                });
+            if !expired.is_empty() {
+                let joins = expired
+                    .iter()
+                    .filter(|packet| packet.join_eui().is_some())
+                    .count();
+                self.delivery.expired += expired.len() as u64;
+                self.delivery.expired_joins += joins as u64;
+                info!(removed = expired.len(), joins, "discarded queued packets");
             }
+            let Some(packet) = packet else {
+                break;
+            };
             if let Err(err) = self.send_packet(&packet).await {
-                warn!(%err, "failed to send uplink");
                 self.store.push_front(packet);
+                if let Error::Service(ServiceError::SessionExpired { .. }) = err {
+                    return self.renew_session().await;
+                }
+                warn!(%err, "failed to send uplink");
                 return Err(err);
             }
+            self.delivery.delivered += 1;
         }
         Ok(())
     }
 
+    /// Renews an expired session by reconnecting for a new session offer.
+    /// Queued packets are signed when they are sent, so they are sent with the
+    /// new session key once the new session is established.
+    async fn renew_session(&mut self) -> Result {
+        let age = self.service.session_age().map(|age| age.as_secs());
+        info!(age, "session expired, renewing");
+        self.service
+            .reconnect()
+            .inspect_err(|err| warn!(%err, "failed to renew session"))
+            .await
+    }
+
     async fn send_packet(&mut self, packet: &CacheMessage<PacketUp>) -> Result {
-        debug!(packet_hash = packet.hash().to_b64(), "sending packet");
+        let payload_hash = self.service.session_payload_hash().unwrap_or_default();
+        debug!(
+            packet_hash = packet.hash(payload_hash).to_b64(),
+            "sending packet"
+        );
 
         let mut uplink: PacketRouterPacketUpV1 = packet.deref().into();
         uplink.hold_time = packet.hold_time().as_millis() as u64;
         self.service.send_uplink(uplink).await
     }
 }
+
+/// The maximum hold time of join requests for the given setting in seconds,
+/// None to hold them as long as other packets
+fn join_hold_time(join_max_hold_time: u64) -> Option<Duration> {
+    (join_max_hold_time > 0).then(|| Duration::from_secs(join_max_hold_time))
+}
+
+/// Completes when a session expiring in the given time expires. Never
+/// completes without an expiring session.
+async fn session_expiry(expires_in: Option<Duration>) {
+    match expires_in {
+        Some(expires_in) => tokio::time::sleep(expires_in).await,
+        None => futures::future::pending().await,
+    }
+}
@@ -0,0 +1,52 @@
+//! Selection of the packet routers an uplink is delivered to.
+//!
+//! Routers can be given rules that select the uplinks they receive by the
+//! NetID or subnet of the device address of data frames, or by the join EUI
+//! of join requests. An uplink goes to every router whose rules it matches,
+//! or to the routers without rules if it matches none.
+
+use super::MessageSender;
+use crate::{matcher::DevAddrJoinEuiMatcher, settings::RouterSettings, PacketUp};
+
+/// A packet router with the rules for the uplinks it receives
+#[derive(Debug, Clone)]
+pub struct UplinkRoute {
+    rules: DevAddrJoinEuiMatcher,
+    router: MessageSender,
+}
+
+impl UplinkRoute {
+    pub fn new(settings: &RouterSettings, router: MessageSender) -> Self {
+        Self {
+            rules: DevAddrJoinEuiMatcher::new(
+                &settings.net_ids,
+                &settings.devaddrs,
+                &settings.join_euis,
+            ),
+            router,
+        }
+    }
+
+    pub fn router(&self) -> &MessageSender {
+        &self.router
+    }
+
+    /// Whether the router has no rules and receives the uplinks that match
+    /// no other router
+    pub fn is_default(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+/// The routes an uplink is delivered to
+pub fn select_routes<'a>(routes: &'a [UplinkRoute], packet: &PacketUp) -> Vec<&'a UplinkRoute> {
+    let (devaddr, join_eui) = (packet.dev_addr(), packet.join_eui());
+    let matched: Vec<&UplinkRoute> = routes
+        .iter()
+        .filter(|route| !route.is_default() && route.rules.matches(devaddr, join_eui))
+        .collect();
+    if !matched.is_empty() {
+        return matched;
+    }
+    routes.iter().filter(|route| route.is_default()).collect()
+}
@@ -0,0 +1,161 @@
+//! Live trace of the uplinks and downlinks handled by the gateway.
+//!
+//! The gateway publishes an event for every uplink it receives from a packet
+//! forwarder and every downlink it transmits, with the routing decision it
+//! made for the packet. Local API clients can subscribe to these events to
+//! debug packet delivery without enabling debug logging. Events are dropped
+//! when nobody is subscribed, and slow subscribers miss events rather than
+//! slowing down the gateway.
+
+use crate::{PacketDown, PacketUp};
+use helium_proto::DataRate;
+use lorawan::{Direction, PHYPayloadFrame};
+use semtech_udp::MacAddress;
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+/// Number of events buffered per subscriber
+const TRACE_BUFFER: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PacketDirection {
+    Uplink,
+    Downlink,
+}
+
+/// What the gateway did with a traced packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PacketDecision {
+    /// The uplink was delivered to the packet routers
+    Routed,
+    /// The uplink was dropped since the region is not yet known
+    NoRegion,
+    /// The uplink was dropped by an fport filter
+    FportFiltered,
+    /// The downlink was transmitted in the first receive window
+    Rx1,
+    /// The downlink was transmitted in the second receive window
+    Rx2,
+    /// The downlink could not be transmitted
+    Failed,
+    /// The uplink was dropped as a copy of an uplink received with a better
+    /// signal by another packet forwarder
+    Duplicate,
+    /// The uplink was dropped since its network is not in the uplink filter
+    NetworkFiltered,
+}
+
+/// A traced uplink or downlink
+#[derive(Debug, Clone, Serialize)]
+pub struct PacketEvent {
+    /// Unix time in milliseconds of the event
+    pub timestamp: u64,
+    pub direction: PacketDirection,
+    /// The MAC address of the packet forwarder that received or transmitted
+    /// the packet
+    pub mac: String,
+    /// Device address of lorawan data frames
+    pub dev_addr: Option<u32>,
+    /// Frame counter of lorawan data frames
+    pub fcnt: Option<u16>,
+    /// Signal strength in dBm of uplinks
+    pub rssi: Option<i32>,
+    /// Signal to noise ratio in dB of uplinks
+    pub snr: Option<f32>,
+    /// Frequency in Hz
+    pub frequency: u32,
+    pub datarate: String,
+    pub decision: PacketDecision,
+}
+
+impl PacketEvent {
+    pub fn uplink(packet: &PacketUp, mac: MacAddress, decision: PacketDecision) -> Self {
+        let (dev_addr, fcnt) = frame_ids(Direction::Uplink, packet.payload());
+        Self {
+            timestamp: now_millis(),
+            direction: PacketDirection::Uplink,
+            mac: mac.to_string(),
+            dev_addr,
+            fcnt,
+            rssi: Some(packet.rssi),
+            snr: Some(packet.snr),
+            frequency: packet.frequency,
+            datarate: packet.datarate().as_str_name().to_string(),
+            decision,
+        }
+    }
+
+    /// An event for a downlink sent, or attempted to be sent, in the receive
+    /// window of the given decision. Failed downlinks are reported with the
+    /// frequency and datarate of the first receive window.
+    pub fn downlink(packet: &PacketDown, mac: MacAddress, decision: PacketDecision) -> Self {
+        let (dev_addr, fcnt) = frame_ids(Direction::Downlink, &packet.payload);
+        let window = match decision {
+            PacketDecision::Rx2 => packet.rx2.as_ref(),
+            _ => packet.rx1.as_ref(),
+        };
+        Self {
+            timestamp: now_millis(),
+            direction: PacketDirection::Downlink,
+            mac: mac.to_string(),
+            dev_addr,
+            fcnt,
+            rssi: None,
+            snr: None,
+            frequency: window.map(|window| window.frequency).unwrap_or_default(),
+            datarate: window
+                .map(|window| window.datarate())
+                .unwrap_or(DataRate::Sf12bw125)
+                .as_str_name()
+                .to_string(),
+            decision,
+        }
+    }
+}
+
+/// The device address and frame counter of a lorawan data frame
+fn frame_ids(direction: Direction, payload: &[u8]) -> (Option<u32>, Option<u16>) {
+    match PacketUp::parse_frame(direction, payload) {
+        Ok(PHYPayloadFrame::MACPayload(payload)) => {
+            (Some(payload.dev_addr()), Some(payload.fhdr.fcnt))
+        }
+        _ => (None, None),
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Publishes packet events to all current subscribers
+#[derive(Debug, Clone)]
+pub struct PacketTrace(broadcast::Sender<PacketEvent>);
+
+impl Default for PacketTrace {
+    fn default() -> Self {
+        Self(broadcast::channel(TRACE_BUFFER).0)
+    }
+}
+
+impl PacketTrace {
+    pub fn send(&self, event: PacketEvent) {
+        // Sending only fails when there are no subscribers
+        let _ = self.0.send(event);
+    }
+
+    /// Whether there are any subscribers, to avoid building events nobody
+    /// receives
+    pub fn is_traced(&self) -> bool {
+        self.0.receiver_count() > 0
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<PacketEvent> {
+        self.0.subscribe()
+    }
+}
@@ -0,0 +1,174 @@
+//! Gateway to gateway ping frames.
+//!
+//! A ping is a proprietary lorawan frame transmitted by one gateway and
+//! addressed by payload convention to another gateway running with pings
+//! enabled. The receiving gateway records the ping with its signal metadata
+//! so owners can test the RF link between their own sites.
+//!
+//! The ping payload is the ping magic followed by the ping id of the target
+//! gateway, the ping id of the sending gateway and a little endian sequence
+//! number. A ping id is the first bytes of the sha256 hash of the gateway
+//! public key, which keeps the frame short enough for every datarate.
+
+use crate::{PacketUp, PublicKey};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Magic bytes at the start of every ping payload
+const PING_MAGIC: [u8; 4] = *b"HGWP";
+/// Size in bytes of the ping id of a gateway
+const PING_ID_SIZE: usize = 8;
+/// Size in bytes of a ping payload
+pub const PING_PAYLOAD_SIZE: usize = PING_MAGIC.len() + 2 * PING_ID_SIZE + 4;
+
+/// Short identifier of a gateway used to address pings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PingId([u8; PING_ID_SIZE]);
+
+impl From<&PublicKey> for PingId {
+    fn from(value: &PublicKey) -> Self {
+        let digest = Sha256::digest(&value.to_vec());
+        let mut id = [0u8; PING_ID_SIZE];
+        id.copy_from_slice(&digest[..PING_ID_SIZE]);
+        Self(id)
+    }
+}
+
+impl PingId {
+    pub fn from_slice(value: &[u8]) -> Option<Self> {
+        value.try_into().ok().map(Self)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for PingId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+impl Serialize for PingId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PingFrame {
+    pub target: PingId,
+    pub sender: PingId,
+    pub seq: u32,
+}
+
+impl PingFrame {
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(PING_PAYLOAD_SIZE);
+        data.extend_from_slice(&PING_MAGIC);
+        data.extend_from_slice(self.target.as_bytes());
+        data.extend_from_slice(self.sender.as_bytes());
+        data.extend_from_slice(&self.seq.to_le_bytes());
+        data
+    }
+
+    /// Parses a ping frame from the payload of a received packet. Returns None
+    /// if the packet is not a proprietary frame carrying a ping.
+    pub fn from_packet(packet: &PacketUp) -> Option<Self> {
+        Self::from_payload(packet.payload())
+    }
+
+    fn from_payload(payload: &[u8]) -> Option<Self> {
+        let header = PacketUp::parse_header(payload).ok()?;
+        if header.mtype() != lorawan::MType::Proprietary {
+            return None;
+        }
+        let data = &payload[PacketUp::header_size()..];
+        if data.len() != PING_PAYLOAD_SIZE || !data.starts_with(&PING_MAGIC) {
+            return None;
+        }
+        let data = &data[PING_MAGIC.len()..];
+        let (target, data) = data.split_at(PING_ID_SIZE);
+        let (sender, seq) = data.split_at(PING_ID_SIZE);
+        Some(Self {
+            target: PingId::from_slice(target)?,
+            sender: PingId::from_slice(sender)?,
+            seq: u32::from_le_bytes(seq.try_into().ok()?),
+        })
+    }
+}
+
+/// A ping transmitted by this gateway
+#[derive(Debug, Clone, Serialize)]
+pub struct SentPing {
+    pub sender: PingId,
+    pub target: PingId,
+    pub seq: u32,
+    /// Frequency in Hz
+    pub frequency: u64,
+    pub datarate: String,
+    /// Conducted transmit power in dBm
+    pub tx_power: i32,
+}
+
+/// A ping addressed to and received by this gateway
+#[derive(Debug, Clone, Serialize)]
+pub struct ReceivedPing {
+    pub sender: PingId,
+    pub seq: u32,
+    /// Unix time in seconds the ping was received
+    pub timestamp: u64,
+    /// Frequency in Hz
+    pub frequency: u32,
+    pub datarate: String,
+    pub rssi: i32,
+    pub snr: f32,
+}
+
+impl ReceivedPing {
+    pub fn new(frame: &PingFrame, packet: &PacketUp) -> Self {
+        Self {
+            sender: frame.sender,
+            seq: frame.seq,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0),
+            frequency: packet.frequency,
+            datarate: packet.datarate().as_str_name().to_string(),
+            rssi: packet.rssi,
+            snr: packet.snr,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lorawan::PHYPayload;
+
+    #[test]
+    fn test_ping_frame_roundtrip() {
+        let frame = PingFrame {
+            target: PingId([1; PING_ID_SIZE]),
+            sender: PingId([2; PING_ID_SIZE]),
+            seq: 42,
+        };
+        let payload: Vec<u8> = PHYPayload::proprietary(&frame.to_vec())
+            .try_into()
+            .expect("ping payload");
+        assert_eq!(Some(frame), PingFrame::from_payload(&payload));
+        // Not a ping when truncated
+        assert_eq!(None, PingFrame::from_payload(&payload[..payload.len() - 1]));
+        // Not a ping without the magic
+        let payload: Vec<u8> = PHYPayload::proprietary(&[0; PING_PAYLOAD_SIZE])
+            .try_into()
+            .expect("proprietary payload");
+        assert_eq!(None, PingFrame::from_payload(&payload));
+    }
+}
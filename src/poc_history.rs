@@ -0,0 +1,390 @@
+use crate::{clock::SharedClock, state_file, Settings};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashSet, VecDeque},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use time::Date;
+use tokio::time::Instant;
+use tracing::warn;
+
+/// Name of the PoC history file in the data directory
+const HISTORY_FILE: &str = "poc_history.json";
+/// Time changes to the history are held before the history file is written,
+/// so a burst of witnesses results in a single write
+const SAVE_DELAY: Duration = Duration::from_secs(60);
+
+/// PoC activity of the gateway on a single (UTC) day
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PocDay {
+    /// The day in YYYY-MM-DD form
+    pub date: String,
+    /// Number of beacons transmitted
+    pub beacons: u32,
+    /// Number of witness reports submitted
+    pub witnesses: u32,
+    /// Number of distinct beacons witnessed
+    pub unique_beacons: u32,
+    /// Average rssi of witnessed beacons in dBm
+    pub avg_rssi: Option<f32>,
+    /// Average snr of witnessed beacons in dB
+    pub avg_snr: Option<f32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct DayRecord {
+    date: String,
+    beacons: u32,
+    witnesses: u32,
+    unique_beacons: u32,
+    rssi_sum: i64,
+    snr_sum: f64,
+}
+
+impl From<&DayRecord> for PocDay {
+    fn from(value: &DayRecord) -> Self {
+        let average =
+            |sum: f64| (value.witnesses > 0).then(|| (sum / value.witnesses as f64) as f32);
+        Self {
+            date: value.date.clone(),
+            beacons: value.beacons,
+            witnesses: value.witnesses,
+            unique_beacons: value.unique_beacons,
+            avg_rssi: average(value.rssi_sum as f64),
+            avg_snr: average(value.snr_sum),
+        }
+    }
+}
+
+/// A sharp drop of the daily witnesses below their rolling baseline, which
+/// usually indicates an antenna or radio failure
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WitnessAlert {
+    /// The day with the dropped witnesses in YYYY-MM-DD form
+    pub date: String,
+    /// Number of witness reports submitted on that day
+    pub witnesses: u32,
+    /// Average daily witness reports over the baseline days before it
+    pub baseline: f32,
+}
+
+/// What became of a beacon
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BeaconOutcome {
+    /// The beacon was transmitted and its report submitted
+    Submitted,
+    /// The beacon was transmitted but its report could not be submitted
+    SubmitFailed,
+    /// The beacon could not be transmitted
+    TxFailed,
+}
+
+/// A beacon transmitted, or attempted to be transmitted, by the gateway
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BeaconRecord {
+    pub beacon_id: String,
+    /// Unix time in seconds of the transmission
+    pub timestamp: i64,
+    /// Transmit power in dBm reported by the packet forwarder. Not known for
+    /// failed transmissions
+    pub tx_power: Option<i32>,
+    pub outcome: BeaconOutcome,
+    /// The error of a failed transmission or submission
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct HistoryFile {
+    days: VecDeque<DayRecord>,
+    /// Ids of the beacons witnessed today, used to count unique beacons
+    #[serde(default)]
+    beacon_ids: HashSet<String>,
+    /// The most recent beacons, oldest first
+    #[serde(default)]
+    beacons: VecDeque<BeaconRecord>,
+}
+
+/// Daily rollups of beacons and witnesses for the last configured number of
+/// days, and the last configured number of beacons, persisted in the data
+/// directory so they survive restarts. Changes are written in batches, see
+/// [`PocHistory::save_at`].
+#[derive(Debug)]
+pub struct PocHistory {
+    clock: SharedClock,
+    max_days: usize,
+    max_beacons: usize,
+    path: PathBuf,
+    history: HistoryFile,
+    /// Whether there are changes that are not written yet
+    dirty: bool,
+    /// Time at which unwritten changes are written
+    save_at: Instant,
+}
+
+impl PocHistory {
+    /// Loads the persisted history. A missing or invalid history file starts
+    /// an empty history.
+    pub fn new(settings: &Settings, clock: SharedClock) -> Self {
+        let path = settings.data_dir.join(HISTORY_FILE);
+        let history = read_history(&path).unwrap_or_default();
+        let save_at = clock.now();
+        Self {
+            clock,
+            max_days: settings.poc.history_days as usize,
+            max_beacons: settings.poc.beacon_history as usize,
+            path,
+            history,
+            dirty: false,
+            save_at,
+        }
+    }
+
+    /// Whether there are changes that are not written yet
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// The time at which the unwritten changes are written with
+    /// [`PocHistory::save`]
+    pub fn save_at(&self) -> Instant {
+        self.save_at
+    }
+
+    /// Returns the daily rollups, oldest first
+    pub fn days(&self) -> Vec<PocDay> {
+        self.history.days.iter().map(PocDay::from).collect()
+    }
+
+    /// Returns the most recent beacons, oldest first
+    pub fn beacons(&self) -> Vec<BeaconRecord> {
+        self.history.beacons.iter().cloned().collect()
+    }
+
+    /// Records a beacon attempt. Transmitted beacons are also counted in the
+    /// daily rollup.
+    pub fn record_beacon(
+        &mut self,
+        beacon_id: String,
+        tx_power: Option<i32>,
+        outcome: BeaconOutcome,
+        error: Option<String>,
+    ) {
+        if self.max_beacons > 0 {
+            let beacons = &mut self.history.beacons;
+            beacons.push_back(BeaconRecord {
+                beacon_id,
+                timestamp: self.clock.now_utc().unix_timestamp(),
+                tx_power,
+                outcome,
+                error,
+            });
+            while beacons.len() > self.max_beacons {
+                beacons.pop_front();
+            }
+        }
+        if outcome == BeaconOutcome::TxFailed || self.max_days == 0 {
+            self.changed();
+        } else {
+            self.update(|today, _| today.beacons += 1);
+        }
+    }
+
+    pub fn record_witness(&mut self, beacon_id: &str, rssi: i32, snr: f32) {
+        self.update(|today, beacon_ids| {
+            today.witnesses += 1;
+            today.rssi_sum += rssi as i64;
+            today.snr_sum += snr as f64;
+            if beacon_ids.insert(beacon_id.to_string()) {
+                today.unique_beacons += 1;
+            }
+        });
+    }
+
+    /// Returns a witness alert if the witnesses of the last full day dropped
+    /// by at least the given percentage below the average of the given number
+    /// of days before it. Days without a rollup had no witnesses. There is no
+    /// alert until the history covers all baseline days, or when the baseline
+    /// is below the given minimum.
+    pub fn witness_alert(
+        &self,
+        baseline_days: u16,
+        min_baseline: u32,
+        drop: u8,
+    ) -> Option<WitnessAlert> {
+        if drop == 0 || baseline_days == 0 {
+            return None;
+        }
+        let witnesses = |date: Date| {
+            let date = date.to_string();
+            self.history
+                .days
+                .iter()
+                .find(|day| day.date == date)
+                .map_or(0, |day| day.witnesses)
+        };
+        let last_day = self.clock.now_utc().date().previous_day()?;
+        let mut day = last_day;
+        let mut total = 0u64;
+        for _ in 0..baseline_days {
+            day = day.previous_day()?;
+            total += u64::from(witnesses(day));
+        }
+        // Dates in YYYY-MM-DD form order like the days
+        if day.to_string() < self.history.days.front()?.date {
+            return None;
+        }
+        let baseline = total as f32 / f32::from(baseline_days);
+        let count = witnesses(last_day);
+        let limit = baseline * f32::from(100 - drop.min(100)) / 100.0;
+        (baseline >= min_baseline as f32 && count as f32 <= limit).then(|| WitnessAlert {
+            date: last_day.to_string(),
+            witnesses: count,
+            baseline,
+        })
+    }
+
+    fn update<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut DayRecord, &mut HashSet<String>),
+    {
+        if self.max_days == 0 {
+            return;
+        }
+        let date = self.clock.now_utc().date().to_string();
+        let history = &mut self.history;
+        if history.days.back().map(|day| &day.date) != Some(&date) {
+            history.beacon_ids.clear();
+            history.days.push_back(DayRecord {
+                date,
+                ..Default::default()
+            });
+        }
+        while history.days.len() > self.max_days {
+            history.days.pop_front();
+        }
+        if let Some(today) = history.days.back_mut() {
+            f(today, &mut history.beacon_ids);
+        }
+        self.changed();
+    }
+
+    /// Schedules a write of the changed history, unless one is already
+    /// scheduled
+    fn changed(&mut self) {
+        if self.max_days == 0 && self.max_beacons == 0 {
+            return;
+        }
+        if !self.dirty {
+            self.dirty = true;
+            self.save_at = self.clock.now() + SAVE_DELAY;
+        }
+    }
+
+    /// Writes the history file if there are unwritten changes. Failed writes
+    /// are retried with the next change.
+    pub fn save(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+        if let Err(err) = state_file::write_json(&self.path, &self.history) {
+            warn!(path = %self.path.display(), %err, "failed to write poc history");
+        }
+    }
+}
+
+fn read_history(path: &Path) -> Option<HistoryFile> {
+    let data = std::fs::read(path).ok()?;
+    serde_json::from_slice(&data)
+        .map_err(|err| warn!(path = %path.display(), %err, "ignoring invalid poc history"))
+        .ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::TestClock;
+    use time::macros::datetime;
+
+    #[test]
+    fn witness_alert() {
+        let day = |date: &str, witnesses| DayRecord {
+            date: date.to_string(),
+            witnesses,
+            ..Default::default()
+        };
+        let history = |days: Vec<DayRecord>| PocHistory {
+            clock: TestClock::shared(datetime!(2023-09-10 09:20 UTC)),
+            max_days: 30,
+            max_beacons: 0,
+            path: PathBuf::new(),
+            history: HistoryFile {
+                days: days.into(),
+                ..Default::default()
+            },
+            dirty: false,
+            save_at: Instant::now(),
+        };
+        let baseline = || {
+            // 2023-09-05 has no rollup and counts as a day without witnesses
+            vec![
+                day("2023-09-04", 40),
+                day("2023-09-06", 40),
+                day("2023-09-07", 40),
+                day("2023-09-08", 40),
+            ]
+        };
+
+        let mut days = baseline();
+        days.push(day("2023-09-09", 2));
+        let alert = history(days)
+            .witness_alert(5, 10, 75)
+            .expect("witness alert");
+        assert_eq!(
+            ("2023-09-09", 2, 32.0),
+            (alert.date.as_str(), alert.witnesses, alert.baseline)
+        );
+
+        // A missing day is a day without witnesses
+        assert!(history(baseline()).witness_alert(5, 10, 75).is_some());
+
+        let mut days = baseline();
+        days.push(day("2023-09-09", 20));
+        assert!(history(days).witness_alert(5, 10, 75).is_none());
+        // Baselines below the minimum do not alert
+        assert!(history(baseline()).witness_alert(5, 40, 75).is_none());
+        // The history has to cover the baseline days
+        assert!(history(baseline()).witness_alert(6, 10, 75).is_none());
+        assert!(history(baseline()).witness_alert(5, 10, 0).is_none());
+    }
+
+    #[test]
+    fn batched_save() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let path = dir.path().join(HISTORY_FILE);
+        let mut history = PocHistory {
+            clock: TestClock::shared(datetime!(2023-09-10 09:20 UTC)),
+            max_days: 30,
+            max_beacons: 0,
+            path: path.clone(),
+            history: HistoryFile::default(),
+            dirty: false,
+            save_at: Instant::now(),
+        };
+        history.record_witness("beacon", -100, 5.0);
+        let save_at = history.save_at();
+        history.record_witness("beacon", -90, 7.0);
+        // Further changes are written with the first one
+        assert!(history.is_dirty());
+        assert_eq!(save_at, history.save_at());
+        assert!(!path.exists());
+
+        history.save();
+        assert!(!history.is_dirty());
+        let saved = read_history(&path).expect("saved history");
+        assert_eq!(2, saved.days[0].witnesses);
+        assert_eq!(1, saved.days[0].unique_beacons);
+    }
+}
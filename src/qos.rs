@@ -0,0 +1,41 @@
+//! DSCP marking of gateway traffic.
+//!
+//! Radio traffic (the semtech udp replies to the packet forwarder, including
+//! downlink PULL_RESP packets) and backhaul traffic (the gRPC connections to
+//! the packet router and other services) can be marked with separate DSCP
+//! values so QoS policies on constrained uplinks can prioritize downlink timing
+//! over bulk traffic.
+
+use crate::{Error, Result};
+use socket2::SockRef;
+use std::{io, net::SocketAddr};
+
+/// Largest valid DSCP value
+const DSCP_MAX: u8 = 63;
+
+pub fn check_dscp(dscp: u8) -> Result {
+    if dscp > DSCP_MAX {
+        return Err(Error::custom(format!(
+            "invalid dscp {dscp}, must be at most {DSCP_MAX}"
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the type of service byte for the given DSCP value. The DSCP value
+/// is the upper six bits of the type of service byte.
+fn tos(dscp: u8) -> u32 {
+    (dscp as u32) << 2
+}
+
+/// Marks the traffic of the given socket for the given address family with
+/// the given DSCP value. A value of 0 leaves the socket unchanged.
+pub fn set_dscp(socket: SockRef, addr: &SocketAddr, dscp: u8) -> io::Result<()> {
+    if dscp == 0 {
+        return Ok(());
+    }
+    match addr {
+        SocketAddr::V4(_) => socket.set_tos(tos(dscp)),
+        SocketAddr::V6(_) => socket.set_tclass_v6(tos(dscp)),
+    }
+}
@@ -1,12 +1,30 @@
-use crate::{settings::Settings, KeyedUri, Keypair, Region, RegionParams, Result};
+use crate::{
+    gps_region::{self, RegionDetector},
+    keyed_uri::KeyedUris,
+    service::{backhaul::Backhaul, config::ConfigService},
+    settings::Settings,
+    state_file, Base64, Error, Keypair, Region, RegionParams, Result,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
 use exponential_backoff::Backoff;
-use std::{sync::Arc, time::Duration};
+use helium_proto::{BlockchainRegionParamsV1, Message};
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use tokio::{sync::watch, time};
 use tracing::{info, warn};
 
 const REGION_BACKOFF_RETRIES: u32 = 10;
 const REGION_BACKOFF_MIN_WAIT: Duration = Duration::from_secs(5);
 const REGION_BACKOFF_MAX_WAIT: Duration = Duration::from_secs(3600); // 60 minutes
+/// Name of the file with the last fetched region parameters in the data
+/// directory
+const REGION_PARAMS_CACHE_FILE: &str = "region_params.json";
 
 pub type MessageSender = watch::Sender<RegionParams>;
 pub type MessageReceiver = watch::Receiver<RegionParams>;
@@ -18,38 +36,212 @@ where
     receiver.borrow().clone()
 }
 
+/// The transmit power limits that follow from the current region parameters
+#[derive(Debug, Clone, Serialize)]
+pub struct RegionPowerStatus {
+    /// The region, or None if not yet known
+    pub region: Option<String>,
+    /// Maximum EIRP in dBm of the region parameters, or None without region
+    /// parameters
+    pub max_eirp: Option<Decimal>,
+    /// Antenna gain in dBi that is subtracted from the maximum EIRP
+    pub gain: Decimal,
+    /// Whether the gain is the configured antenna gain instead of the gain of
+    /// the asserted antenna in the region parameters
+    pub gain_override: bool,
+    /// Maximum conducted transmit power in dBm, the maximum EIRP minus the
+    /// gain, or None without region parameters
+    pub max_conducted_power: Option<u32>,
+    /// Conducted beacon transmit power in dBm, the poc transmit power override
+    /// capped at the maximum conducted power
+    pub beacon_tx_power: Option<u32>,
+}
+
+impl RegionPowerStatus {
+    pub fn new(
+        region_params: &RegionParams,
+        antenna_gain: Option<u64>,
+        beacon_tx_power_override: Option<u32>,
+    ) -> Self {
+        let max_conducted_power = region_params.max_conducted_power().ok();
+        Self {
+            region: (!region_params.region.is_unknown()).then(|| region_params.region.to_string()),
+            max_eirp: region_params.max_eirp(),
+            gain: region_params.gain,
+            gain_override: antenna_gain.is_some(),
+            max_conducted_power,
+            beacon_tx_power: max_conducted_power
+                .map(|max| beacon_tx_power_override.map_or(max, |tx_power| tx_power.min(max))),
+        }
+    }
+}
+
+/// A region parameters update as streamed over the local API
+#[derive(Debug, Clone, Serialize)]
+pub struct RegionEvent {
+    /// Unix time in seconds of the region parameters, or None for the default
+    /// parameters of a region
+    pub timestamp: Option<u64>,
+    /// The transmit power limits of the region parameters
+    pub power: RegionPowerStatus,
+    /// The channel plan of the region parameters
+    pub channels: Vec<RegionChannel>,
+}
+
+/// A channel of the region parameters
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RegionChannel {
+    /// Frequency in Hz
+    pub frequency: u64,
+    /// Bandwidth in Hz
+    pub bandwidth: u64,
+    /// Maximum EIRP in tenths of dBm
+    pub max_eirp: u64,
+    /// The spreading factors allowed on the channel, like SF7
+    pub spreading: Vec<String>,
+}
+
+impl RegionEvent {
+    pub fn new(
+        region_params: &RegionParams,
+        antenna_gain: Option<u64>,
+        beacon_tx_power_override: Option<u32>,
+    ) -> Self {
+        let channels = region_params
+            .params
+            .iter()
+            .map(|params| RegionChannel {
+                frequency: params.channel_frequency,
+                bandwidth: params.bandwidth,
+                max_eirp: params.max_eirp,
+                spreading: params
+                    .spreading
+                    .iter()
+                    .flat_map(|spreading| &spreading.tagged_spreading)
+                    .map(|tagged| tagged.region_spreading().as_str_name().to_string())
+                    .collect(),
+            })
+            .collect();
+        Self {
+            timestamp: (region_params.timestamp != 0).then_some(region_params.timestamp),
+            power: RegionPowerStatus::new(region_params, antenna_gain, beacon_tx_power_override),
+            channels,
+        }
+    }
+}
+
 pub struct RegionWatcher {
     keypair: Arc<Keypair>,
-    config_uri: KeyedUri,
+    config_uris: KeyedUris,
+    /// Index of the config uri in use
+    config_index: usize,
+    backhaul: Backhaul,
     default_region: Region,
     request_retry: u32,
+    /// Whether the region parameters were loaded from a file, in which case
+    /// the config service is not used
+    from_file: bool,
+    /// Configured antenna gain in tenths of dBi, which replaces the gain of
+    /// fetched region parameters
+    antenna_gain: Option<u64>,
+    /// Region detection from GPS positions, if enabled
+    gps_region: Option<GpsRegion>,
+    /// Data directory the last fetched region parameters are cached in
+    data_dir: PathBuf,
+    /// Whether the current region parameters were loaded from the cache and
+    /// not yet replaced by fetched ones
+    stale: bool,
     watch: MessageSender,
 }
 
+struct GpsRegion {
+    detector: RegionDetector,
+    positions: gps_region::MessageSender,
+    receiver: gps_region::MessageReceiver,
+    /// The last detected region, which region parameters are requested for
+    region: Option<Region>,
+}
+
 impl RegionWatcher {
-    pub fn new(settings: &Settings) -> Self {
-        let default_params = RegionParams::from(settings.region);
+    pub fn new(settings: &Settings) -> Result<Self> {
+        let (default_params, from_file, stale) = match &settings.region_params_file {
+            Some(path) => (
+                load_region_params(
+                    settings.region,
+                    settings.antenna_gain.unwrap_or(settings.region_params_gain),
+                    path,
+                )?,
+                true,
+                false,
+            ),
+            // Use the last fetched region parameters until they are fetched
+            // again, so traffic is handled right away when the config service
+            // is unreachable at startup
+            None => match load_cached_params(&settings.data_dir, settings.region) {
+                Some(mut params) => {
+                    if let Some(gain) = settings.antenna_gain {
+                        params.gain = Decimal::new(gain as i64, 1);
+                    }
+                    (params, false, true)
+                }
+                None => (RegionParams::from(settings.region), false, false),
+            },
+        };
         let (watch, _) = watch::channel(default_params);
-        Self {
+        let gps_region = if settings.gps_region.enabled && !from_file {
+            let (positions, receiver) = gps_region::message_channel();
+            Some(GpsRegion {
+                detector: RegionDetector::new(&settings.gps_region)?,
+                positions,
+                receiver,
+                region: None,
+            })
+        } else {
+            None
+        };
+        Ok(Self {
             keypair: settings.keypair.clone(),
-            config_uri: settings.config.clone(),
+            config_uris: settings.config.clone(),
+            config_index: 0,
+            backhaul: Backhaul::from(&settings.network),
             // Start retry at 1 to get some jitter in the first request time
             request_retry: 1,
             default_region: settings.region,
+            from_file,
+            antenna_gain: settings.antenna_gain,
+            gps_region,
+            data_dir: settings.data_dir.clone(),
+            stale,
             watch,
-        }
+        })
     }
 
     pub fn watcher(&mut self) -> watch::Receiver<RegionParams> {
         self.watch.subscribe()
     }
 
+    /// The sender for GPS positions to detect the region from, if enabled
+    pub fn gps_positions(&self) -> Option<gps_region::MessageSender> {
+        self.gps_region
+            .as_ref()
+            .map(|gps_region| gps_region.positions.clone())
+    }
+
     pub async fn run(&mut self, shutdown: &triggered::Listener) -> Result {
         info!(
             default_region = %self.default_region,
+            from_file = self.from_file,
+            stale = self.stale,
+            gps_region = self.gps_region.is_some(),
             "starting",
         );
 
+        if self.from_file {
+            shutdown.clone().await;
+            info!("shutting down");
+            return Ok(());
+        }
+
         let backoff = Backoff::new(
             REGION_BACKOFF_RETRIES,
             REGION_BACKOFF_MIN_WAIT,
@@ -76,23 +268,56 @@ impl RegionWatcher {
                         (self.request_retry + 1).min(REGION_BACKOFF_RETRIES)
                     },
                     Ok(None) => (),
-                    Ok(Some(remote_params)) => {
+                    Ok(Some(mut remote_params)) => {
+                        if remote_params.check_valid().is_ok() {
+                            if let Err(err) = store_cached_params(&self.data_dir, &remote_params) {
+                                warn!(%err, "failed to cache region params");
+                            }
+                        }
+                        if self.stale {
+                            info!("replacing cached region params");
+                            self.stale = false;
+                        }
+                        if let Some(gain) = self.antenna_gain {
+                            remote_params.gain = Decimal::new(gain as i64, 1);
+                        }
                         self.request_retry = REGION_BACKOFF_RETRIES + 1;
                         // We do not check for a change in params here since we
                         // want to propagate the timestamp in the remote params
                         _ = self.watch.send_replace(remote_params);
                     },
-                }
+                },
+                Some(message) = recv_position(&mut self.gps_region) => self.handle_position(message),
             }
         }
     }
 
+    fn handle_position(&mut self, message: gps_region::Message) {
+        let Some(gps_region) = &mut self.gps_region else {
+            return;
+        };
+        let gps_region::Message::Position { lat, lon } = message;
+        let Some(region) = gps_region.detector.update(lat, lon) else {
+            return;
+        };
+        info!(%region, lat, lon, "detected region from gps position");
+        gps_region.region = Some(region);
+        // Use the default parameters of the new region until the parameters
+        // for it are fetched, which is retried soon
+        _ = self.watch.send_replace(RegionParams::from(region));
+        self.request_retry = 1;
+    }
+
     pub async fn check_region(
         &mut self,
         shutdown: &triggered::Listener,
     ) -> Result<Option<RegionParams>> {
-        let mut service = crate::service::config::ConfigService::new(&self.config_uri);
-        let current_region = self.watch.borrow().region;
+        let mut service = ConfigService::new(&self.config_uris[self.config_index], &self.backhaul);
+        let current_region = self
+            .gps_region
+            .as_ref()
+            .and_then(|gps_region| gps_region.region)
+            .unwrap_or_else(|| self.watch.borrow().region);
         let service_uri = service.uri.clone();
 
         tokio::select! {
@@ -106,10 +331,18 @@ impl RegionWatcher {
                         %err,
                         "failed to get region_params"
                     );
+                    self.next_config_uri();
                     Err(err)
                 }
                 Ok(other) => {
                     let region = other.as_ref().map(|params| params.region).unwrap_or_default();
+                    if self.gps_region.is_some() && region != current_region {
+                        warn!(
+                            detected_region = %current_region,
+                            %region,
+                            "config service returned parameters of another region"
+                        );
+                    }
                     info!(
                         pubkey = %service_uri.pubkey,
                         uri = %service_uri.uri,
@@ -122,4 +355,143 @@ impl RegionWatcher {
         }
         }
     }
+
+    /// Moves on to the next config uri, if more than one is configured
+    fn next_config_uri(&mut self) {
+        if self.config_uris.len() < 2 {
+            return;
+        }
+        self.config_index = (self.config_index + 1) % self.config_uris.len();
+        let next = &self.config_uris[self.config_index];
+        info!(pubkey = %next.pubkey, uri = %next.uri, "switching config service");
+    }
+}
+
+/// Receives the next GPS position, if region detection is enabled
+async fn recv_position(gps_region: &mut Option<GpsRegion>) -> Option<gps_region::Message> {
+    match gps_region {
+        Some(gps_region) => gps_region.receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// The last fetched region parameters as stored in the data directory
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedParams {
+    region: i32,
+    /// Antenna gain in tenths of dBi
+    gain: u64,
+    timestamp: u64,
+    /// Base64 encoded region parameters
+    params: String,
+}
+
+fn cache_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(REGION_PARAMS_CACHE_FILE)
+}
+
+/// Stores the given fetched region parameters in the data directory
+fn store_cached_params(data_dir: &Path, params: &RegionParams) -> Result {
+    let encoded = BlockchainRegionParamsV1 {
+        region_params: params.params.clone(),
+    }
+    .encode_to_vec();
+    let cached = CachedParams {
+        region: params.region.into(),
+        gain: (params.gain * Decimal::TEN).to_u64().unwrap_or_default(),
+        timestamp: params.timestamp,
+        params: encoded.to_b64(),
+    };
+    state_file::write_json(&cache_path(data_dir), &cached)
+}
+
+/// Loads the cached region parameters from the data directory if they are
+/// valid and for the configured region, or any region if the configured one
+/// is unknown
+fn load_cached_params(data_dir: &Path, region: Region) -> Option<RegionParams> {
+    let path = cache_path(data_dir);
+    let data = fs::read(&path).ok()?;
+    let load = || -> Result<RegionParams> {
+        let cached: CachedParams = serde_json::from_slice(&data)?;
+        let encoded = STANDARD
+            .decode(&cached.params)
+            .map_err(|_| Error::custom("invalid region params encoding"))?;
+        let params = RegionParams::from_bytes(
+            Region::from_i32(cached.region)?,
+            cached.gain,
+            &encoded,
+            cached.timestamp,
+        )?;
+        params.check_valid()?;
+        Ok(params)
+    };
+    let params = load()
+        .map_err(|err| warn!(path = %path.display(), %err, "ignoring invalid cached region params"))
+        .ok()?;
+    if !region.is_unknown() && params.region != region {
+        info!(%region, cached_region = %params.region, "ignoring cached region params of another region");
+        return None;
+    }
+    warn!(region = %params.region, timestamp = params.timestamp, "using stale cached region params");
+    Some(params)
+}
+
+/// Loads the encoded region parameters of the given region from a file
+fn load_region_params(region: Region, gain: u64, path: &Path) -> Result<RegionParams> {
+    if region.is_unknown() {
+        return Err(Error::custom(
+            "region must be set to use a region params file",
+        ));
+    }
+    let data = fs::read(path).map_err(|err| {
+        Error::custom(format!(
+            "failed to read region params file {}: {err}",
+            path.display()
+        ))
+    })?;
+    let timestamp = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .unwrap_or_else(|_| SystemTime::now())
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let params = RegionParams::from_bytes(region, gain, &data, timestamp)?;
+    params.check_valid()?;
+    info!(%region, path = %path.display(), "loaded region params from file");
+    Ok(params)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use helium_proto::BlockchainRegionParamV1;
+
+    #[test]
+    fn cached_params() {
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        let data_dir = temp_dir.path();
+        let eu868 = Region::from(helium_proto::Region::Eu868);
+        let us915 = Region::from(helium_proto::Region::Us915);
+        assert_eq!(None, load_cached_params(data_dir, eu868));
+
+        let mut params = RegionParams::from(eu868);
+        params.timestamp = 1_700_000_000;
+        params.params = vec![BlockchainRegionParamV1 {
+            channel_frequency: 868_100_000,
+            bandwidth: 125_000,
+            max_eirp: 160,
+            spreading: None,
+        }];
+        store_cached_params(data_dir, &params).expect("store");
+
+        let cached = load_cached_params(data_dir, eu868).expect("cached params");
+        assert_eq!((eu868, 1_700_000_000), (cached.region, cached.timestamp));
+        // An unknown configured region uses the cached region
+        let unknown = Region::from(helium_proto::Region::Unknown);
+        assert!(load_cached_params(data_dir, unknown).is_some());
+        assert_eq!(None, load_cached_params(data_dir, us915));
+
+        fs::write(cache_path(data_dir), b"invalid").expect("write");
+        assert_eq!(None, load_cached_params(data_dir, eu868));
+    }
 }
@@ -0,0 +1,223 @@
+//! Reloading of settings in a running gateway.
+//!
+//! On SIGHUP, or a reload request through the local API, the settings are
+//! loaded again from the settings file and environment the gateway was
+//! started with. Changes to the log level, the beacon interval, the packet
+//! forwarder listen address and the queue sizes and hold times of the packet
+//! routers are applied to the running gateway. All other settings still
+//! require a restart to take effect.
+
+use crate::{
+    beaconer, gateway, packet_router,
+    settings::{log_level, ReloadableSettings, Settings, SettingsSource},
+    sync, Error, Result,
+};
+use std::sync::OnceLock;
+use tracing::{info, warn};
+
+#[derive(Debug)]
+pub enum Message {
+    Reload(sync::ResponseSender<Result<Vec<String>>>),
+}
+
+pub type MessageSender = sync::MessageSender<Message>;
+pub type MessageReceiver = sync::MessageReceiver<Message>;
+
+pub fn message_channel() -> (MessageSender, MessageReceiver) {
+    sync::message_channel(2)
+}
+
+impl MessageSender {
+    /// Reloads the settings and returns the names of the settings that
+    /// changed and were applied
+    pub async fn reload(&self) -> Result<Vec<String>> {
+        self.request(Message::Reload).await?
+    }
+}
+
+type LogLevelHandler = Box<dyn Fn(log_level::Level) -> Result + Send + Sync>;
+
+static LOG_LEVEL_HANDLER: OnceLock<LogLevelHandler> = OnceLock::new();
+
+/// Sets the function that changes the log level of the running process. The
+/// log subscriber is set up by the binary, so without a handler log level
+/// changes can not be applied.
+pub fn set_log_level_handler<F>(handler: F)
+where
+    F: Fn(log_level::Level) -> Result + Send + Sync + 'static,
+{
+    if LOG_LEVEL_HANDLER.set(Box::new(handler)).is_err() {
+        warn!("log level handler already set");
+    }
+}
+
+fn set_log_level(level: log_level::Level) -> Result {
+    match LOG_LEVEL_HANDLER.get() {
+        Some(handler) => handler(level),
+        None => Err(Error::custom("log level can not be changed")),
+    }
+}
+
+pub struct Reloader {
+    messages: MessageReceiver,
+    source: SettingsSource,
+    /// The currently applied settings
+    current: ReloadableSettings,
+    gateway: gateway::MessageSender,
+    beaconer: beaconer::MessageSender,
+    /// The primary packet router followed by the secondary routers
+    routers: Vec<packet_router::MessageSender>,
+}
+
+impl Reloader {
+    pub fn new(
+        settings: &Settings,
+        messages: MessageReceiver,
+        gateway: gateway::MessageSender,
+        beaconer: beaconer::MessageSender,
+        routers: Vec<packet_router::MessageSender>,
+    ) -> Self {
+        Self {
+            messages,
+            source: settings.source.clone(),
+            current: settings.into(),
+            gateway,
+            beaconer,
+            routers,
+        }
+    }
+
+    pub async fn run(&mut self, shutdown: &triggered::Listener) -> Result {
+        let mut hangup = Hangup::new()?;
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => {
+                    info!("shutting down");
+                    return Ok(())
+                },
+                _ = hangup.recv() => match self.reload().await {
+                    Ok(applied) => info!(?applied, "settings reloaded"),
+                    Err(err) => warn!(%err, "failed to reload settings"),
+                },
+                message = self.messages.recv() => match message {
+                    Some(Message::Reload(tx_resp)) => tx_resp.send(self.reload().await),
+                    None => warn!("ignoring closed message channel"),
+                },
+            }
+        }
+    }
+
+    /// Loads the settings again and applies the changed settings. Settings
+    /// that fail to apply are kept at their current value and tried again on
+    /// the next reload.
+    async fn reload(&mut self) -> Result<Vec<String>> {
+        let settings = self.source.reload()?;
+        let mut applied = vec![];
+
+        if settings.log.level != self.current.log.level {
+            match set_log_level(settings.log.level) {
+                Ok(()) => {
+                    self.current.log.level = settings.log.level;
+                    applied.push("log.level".to_string());
+                }
+                Err(err) => warn!(%err, "failed to change log level"),
+            }
+        }
+
+        if settings.poc.interval != self.current.poc.interval {
+            self.beaconer.set_interval(settings.poc.interval).await;
+            self.current.poc.interval = settings.poc.interval;
+            applied.push("poc.interval".to_string());
+        }
+
+        if settings.listen != self.current.listen
+            || settings.listen_interface != self.current.listen_interface
+        {
+            self.gateway
+                .set_listen(settings.listen.clone(), settings.listen_interface.clone())
+                .await;
+            self.current.listen = settings.listen;
+            self.current.listen_interface = settings.listen_interface;
+            applied.push("listen".to_string());
+        }
+
+        if settings.secondary_routers.len() != self.current.secondary_routers.len() {
+            warn!("number of secondary routers changed, a restart is required");
+        }
+        let routers = std::iter::once(settings.router).chain(settings.secondary_routers);
+        let current = std::iter::once(&mut self.current.router)
+            .chain(self.current.secondary_routers.iter_mut());
+        for (index, ((router, current), sender)) in
+            routers.zip(current).zip(&self.routers).enumerate()
+        {
+            if router.uri != current.uri {
+                warn!(uri = %router.uri, "router uri changed, a restart is required");
+                continue;
+            }
+            if router.queue == current.queue
+                && router.queue_auto == current.queue_auto
+                && router.queue_max_bytes == current.queue_max_bytes
+                && router.max_hold_time == current.max_hold_time
+                && router.join_max_hold_time == current.join_max_hold_time
+            {
+                continue;
+            }
+            let (queue, max_bytes) = router.queue_limits();
+            sender
+                .set_queue(
+                    queue,
+                    max_bytes,
+                    router.max_hold_time,
+                    router.join_max_hold_time,
+                )
+                .await;
+            current.queue = router.queue;
+            current.queue_auto = router.queue_auto;
+            current.queue_max_bytes = router.queue_max_bytes;
+            current.max_hold_time = router.max_hold_time;
+            current.join_max_hold_time = router.join_max_hold_time;
+            applied.push(router_key(index));
+        }
+        Ok(applied)
+    }
+}
+
+/// The settings key of the router at the given index in the list of the
+/// primary router followed by the secondary routers
+fn router_key(index: usize) -> String {
+    match index {
+        0 => "router".to_string(),
+        index => format!("secondary_routers[{}]", index - 1),
+    }
+}
+
+/// Receives SIGHUP signals. There is no SIGHUP on non unix platforms, where
+/// settings can only be reloaded through the local API.
+#[cfg(unix)]
+struct Hangup(tokio::signal::unix::Signal);
+
+#[cfg(unix)]
+impl Hangup {
+    fn new() -> Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+        Ok(Self(signal(SignalKind::hangup())?))
+    }
+
+    async fn recv(&mut self) {
+        self.0.recv().await;
+    }
+}
+
+#[cfg(not(unix))]
+struct Hangup;
+
+#[cfg(not(unix))]
+impl Hangup {
+    fn new() -> Result<Self> {
+        Ok(Self)
+    }
+
+    async fn recv(&mut self) {
+        futures::future::pending::<()>().await
+    }
+}
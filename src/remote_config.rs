@@ -0,0 +1,270 @@
+//! Remote configuration of gateways, similar to the configuration and update
+//! server (CUPS) of a LoRa Basics Station.
+//!
+//! Fleet operators set the `remote_config` section to have gateways
+//! periodically fetch a signed configuration from a remote endpoint instead of
+//! pushing settings files to every device. The endpoint returns a JSON
+//! document with the base64 encoded configuration and its signature by the
+//! configured key:
+//!
+//! ```json
+//! {"config": "<base64>", "signature": "<base64>"}
+//! ```
+//!
+//! The configuration is a JSON object with a version and any of the router
+//! uri, PoC settings and region:
+//!
+//! ```json
+//! {"version": 2, "router": {"uri": "http://..."}, "poc": {"interval": 21600}, "region": "EU868"}
+//! ```
+//!
+//! A configuration with a valid signature and a higher version than the
+//! stored one is stored in the data directory, where it is layered on top of
+//! the settings file and below environment overrides, and applied with a
+//! settings reload. A changed PoC interval applies right away, changes to the
+//! router uri, disabling PoC and the region apply on the next restart.
+
+use crate::{
+    http_client, reload,
+    settings::{RemoteConfigSettings, Settings},
+    state_file, Error, Keypair, PublicKey, Region, Result,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use helium_crypto::Verify;
+use http::Uri;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tracing::{info, warn};
+
+/// Name of the file with the stored configuration in the data directory
+const REMOTE_CONFIG_FILE: &str = "remote_config.json";
+/// Time to wait for the configuration endpoint
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The path of the stored remote configuration in the given data directory
+pub fn stored_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(REMOTE_CONFIG_FILE)
+}
+
+/// A signed configuration as returned by the endpoint
+#[derive(Debug, Deserialize)]
+struct SignedConfig {
+    config: String,
+    signature: String,
+}
+
+/// The settings a remote configuration can change
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteConfig {
+    /// Increasing version of the configuration. Configurations with a
+    /// version at or below the stored one are ignored.
+    pub version: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub router: Option<RemoteRouter>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poc: Option<RemotePoc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteRouter {
+    pub uri: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemotePoc {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disable: Option<bool>,
+}
+
+impl RemoteConfig {
+    /// Verifies the signature of a signed configuration document with the
+    /// given key and returns the configuration it contains
+    pub fn from_signed(data: &[u8], key: &PublicKey) -> Result<Self> {
+        let signed: SignedConfig = serde_json::from_slice(data)?;
+        let config = STANDARD
+            .decode(&signed.config)
+            .map_err(|_| Error::custom("invalid remote config encoding"))?;
+        let signature = STANDARD
+            .decode(&signed.signature)
+            .map_err(|_| Error::custom("invalid remote config signature encoding"))?;
+        key.verify(&config, &signature)
+            .map_err(|_| Error::custom("invalid remote config signature"))?;
+        let config: Self = serde_json::from_slice(&config)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the values of the configuration, so a stored configuration can
+    /// not keep the gateway from starting
+    fn validate(&self) -> Result {
+        if let Some(router) = &self.router {
+            router
+                .uri
+                .parse::<Uri>()
+                .map_err(|_| Error::custom(format!("invalid remote router uri {}", router.uri)))?;
+        }
+        if let Some(region) = &self.region {
+            serde_json::from_value::<Region>(serde_json::Value::String(region.clone()))
+                .map_err(|_| Error::custom(format!("invalid remote region {region}")))?;
+        }
+        if self.poc.as_ref().and_then(|poc| poc.interval) == Some(0) {
+            return Err(Error::custom("invalid remote poc interval 0"));
+        }
+        Ok(())
+    }
+
+    /// Reads the stored configuration from the given data directory. A
+    /// missing or invalid file is an empty configuration.
+    pub fn load(data_dir: &Path) -> Self {
+        let path = stored_path(data_dir);
+        let Ok(data) = std::fs::read(&path) else {
+            return Self::default();
+        };
+        serde_json::from_slice(&data)
+            .map_err(|err| warn!(path = %path.display(), %err, "ignoring invalid remote config"))
+            .unwrap_or_default()
+    }
+
+    fn store(&self, data_dir: &Path) -> Result {
+        state_file::write_json(&stored_path(data_dir), self)
+    }
+
+    /// The keys of the settings that changed from the given configuration and
+    /// only apply on a restart
+    fn restart_keys(&self, previous: &Self) -> Vec<&'static str> {
+        let mut keys = vec![];
+        if self.router != previous.router {
+            keys.push("router.uri");
+        }
+        let disable = |config: &Self| config.poc.as_ref().and_then(|poc| poc.disable);
+        if disable(self) != disable(previous) {
+            keys.push("poc.disable");
+        }
+        if self.region != previous.region {
+            keys.push("region");
+        }
+        keys
+    }
+}
+
+pub struct RemoteConfigFetcher {
+    uri: Uri,
+    key: PublicKey,
+    interval: Duration,
+    data_dir: PathBuf,
+    keypair: Arc<Keypair>,
+    reload: reload::MessageSender,
+}
+
+impl RemoteConfigFetcher {
+    pub fn new(
+        settings: &Settings,
+        remote: &RemoteConfigSettings,
+        reload: reload::MessageSender,
+    ) -> Self {
+        Self {
+            uri: remote.uri.clone(),
+            key: remote.key.clone(),
+            interval: Duration::from_secs(remote.interval),
+            data_dir: settings.data_dir.clone(),
+            keypair: settings.keypair.clone(),
+            reload,
+        }
+    }
+
+    pub async fn run(&mut self, shutdown: &triggered::Listener) -> Result {
+        info!(uri = %self.uri, "starting");
+        let mut timer = tokio::time::interval(self.interval);
+        timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => {
+                    info!("shutting down");
+                    return Ok(())
+                },
+                _ = timer.tick() => if let Err(err) = self.update().await {
+                    warn!(%err, "failed to update remote config");
+                },
+            }
+        }
+    }
+
+    /// Fetches the configuration and stores and applies it if it is newer
+    /// than the stored one
+    async fn update(&self) -> Result {
+        let config = RemoteConfig::from_signed(&self.fetch().await?, &self.key)?;
+        let previous = RemoteConfig::load(&self.data_dir);
+        if config.version <= previous.version {
+            return Ok(());
+        }
+        config.store(&self.data_dir)?;
+        let applied = self.reload.reload().await?;
+        info!(version = config.version, ?applied, "remote config updated");
+        let restart = config.restart_keys(&previous);
+        if !restart.is_empty() {
+            warn!(?restart, "remote config changes require a restart");
+        }
+        Ok(())
+    }
+
+    async fn fetch(&self) -> Result<Vec<u8>> {
+        let gateway = self.keypair.public_key().to_string();
+        let query = serde_urlencoded::to_string([("gateway", gateway)])
+            .map_err(|err| Error::custom(format!("remote config query: {err}")))?;
+        let uri: Uri = match self.uri.query() {
+            Some(_) => format!("{}&{query}", self.uri),
+            None => format!("{}?{query}", self.uri),
+        }
+        .parse()?;
+        let response = tokio::time::timeout(FETCH_TIMEOUT, http_client::new().get(uri))
+            .await
+            .map_err(|_| Error::custom("remote config request timed out"))?
+            .map_err(|err| Error::custom(format!("remote config request: {err}")))?;
+        if !response.status().is_success() {
+            return Err(Error::custom(format!(
+                "remote config request: {}",
+                response.status()
+            )));
+        }
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|err| Error::custom(format!("remote config response: {err}")))?;
+        Ok(body.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn restart_keys() {
+        let config = |json: &str| serde_json::from_str::<RemoteConfig>(json).expect("config");
+        let previous = config(r#"{"version": 1, "poc": {"interval": 3600}}"#);
+        let next = config(r#"{"version": 2, "poc": {"interval": 7200}}"#);
+        assert!(next.validate().is_ok());
+        assert!(next.restart_keys(&previous).is_empty());
+
+        let next = config(
+            r#"{"version": 3, "router": {"uri": "http://router.example.com:8080"},
+                "poc": {"interval": 7200, "disable": true}}"#,
+        );
+        assert!(next.validate().is_ok());
+        assert_eq!(
+            vec!["router.uri", "poc.disable"],
+            next.restart_keys(&previous)
+        );
+
+        assert!(config(r#"{"version": 4, "poc": {"interval": 0}}"#)
+            .validate()
+            .is_err());
+    }
+}
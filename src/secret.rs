@@ -0,0 +1,142 @@
+//! Secret settings values.
+//!
+//! Settings that carry secrets, like credentials for external services, can
+//! be given either in plain text or sealed to the public key of the gateway so
+//! that fleet managed settings files do not contain plain text secrets at rest.
+//! Sealed values are unsealed with the gateway keypair when the settings are
+//! loaded.
+//!
+//! A sealed value has the form `sealed:<ephemeral key>:<ciphertext>`. The
+//! ephemeral key is a fresh ecc_compact public key, and the ciphertext is the
+//! base64 encoded AES-256-GCM nonce and encrypted value, with the key derived
+//! from the ECDH shared secret of the ephemeral key and the gateway key. Only
+//! ecc_compact gateway keys, like those of ECC608 based gateways, support
+//! sealed values.
+//!
+//! Sealed values need the `sealed-secrets` feature. Builds without it accept
+//! plain text secrets only.
+
+use crate::{Error, Keypair, Result};
+use serde::Deserialize;
+use std::fmt;
+#[cfg(feature = "sealed-secrets")]
+use {
+    crate::{Base64, PublicKey},
+    aes_gcm::{
+        aead::{Aead, KeyInit},
+        Aes256Gcm, Key, Nonce,
+    },
+    base64::{engine::general_purpose::STANDARD, Engine},
+    helium_crypto::{KeyTag, KeyType, Network},
+    rand::{rngs::OsRng, RngCore},
+    sha2::{Digest, Sha256},
+    std::str::FromStr,
+};
+
+const SEALED_PREFIX: &str = "sealed:";
+#[cfg(feature = "sealed-secrets")]
+const NONCE_SIZE: usize = 12;
+
+/// A secret settings value. The value is never included in debug output.
+#[derive(Clone, Deserialize, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.is_sealed() { "sealed" } else { "***" };
+        f.debug_tuple("Secret").field(&state).finish()
+    }
+}
+
+impl Secret {
+    /// Returns the secret value. This is the sealed form if the secret was not
+    /// unsealed.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_sealed(&self) -> bool {
+        self.0.starts_with(SEALED_PREFIX)
+    }
+
+    /// Unseals a sealed secret in place with the given gateway keypair. Plain
+    /// text secrets are left unchanged.
+    #[cfg(not(feature = "sealed-secrets"))]
+    pub fn unseal(&mut self, _keypair: &Keypair) -> Result {
+        if self.is_sealed() {
+            return Err(Error::custom(
+                "sealed secrets are not supported by this build",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Unseals a sealed secret in place with the given gateway keypair. Plain
+    /// text secrets are left unchanged.
+    #[cfg(feature = "sealed-secrets")]
+    pub fn unseal(&mut self, keypair: &Keypair) -> Result {
+        let Some(sealed) = self.0.strip_prefix(SEALED_PREFIX) else {
+            return Ok(());
+        };
+        let (ephemeral, ciphertext) = sealed
+            .split_once(':')
+            .ok_or_else(|| Error::custom("invalid sealed secret"))?;
+        let ephemeral = PublicKey::from_str(ephemeral)
+            .map_err(|err| Error::custom(format!("invalid sealed secret key: {err}")))?;
+        let data = STANDARD.decode(ciphertext)?;
+        if data.len() < NONCE_SIZE {
+            return Err(Error::custom("invalid sealed secret ciphertext"));
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+        let cipher = cipher(keypair, &ephemeral, keypair.public_key())?;
+        let value = cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| Error::custom("unable to unseal secret, sealed to another key?"))?;
+        self.0 = String::from_utf8(value)
+            .map_err(|_| Error::custom("unsealed secret is not valid utf8"))?;
+        Ok(())
+    }
+}
+
+/// Seals the given value to the given gateway public key. The returned string
+/// can be used as the value of any secret setting of that gateway.
+#[cfg(feature = "sealed-secrets")]
+pub fn seal(value: &str, public_key: &PublicKey) -> Result<String> {
+    let ephemeral: Keypair = helium_crypto::Keypair::generate(
+        KeyTag {
+            network: Network::MainNet,
+            key_type: KeyType::EccCompact,
+        },
+        &mut OsRng,
+    )
+    .into();
+    let cipher = cipher(&ephemeral, public_key, public_key)?;
+    let mut nonce = [0u8; NONCE_SIZE];
+    OsRng.fill_bytes(&mut nonce);
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), value.as_bytes())
+        .map_err(|_| Error::custom("unable to seal secret"))?;
+    let mut data = nonce.to_vec();
+    data.extend_from_slice(&ciphertext);
+    Ok(format!(
+        "{SEALED_PREFIX}{}:{}",
+        ephemeral.public_key(),
+        data.to_b64()
+    ))
+}
+
+/// Derives the cipher for a sealed secret from the ECDH shared secret of the
+/// given keypair and public key. The gateway public key is bound into the key
+/// derivation.
+#[cfg(feature = "sealed-secrets")]
+fn cipher(keypair: &Keypair, public_key: &PublicKey, gateway: &PublicKey) -> Result<Aes256Gcm> {
+    let shared_secret = keypair
+        .ecdh(public_key)
+        .map_err(|err| Error::custom(format!("sealed secrets need an ecc_compact key: {err}")))?;
+    let mut hasher = Sha256::new();
+    hasher.update(shared_secret.raw_secret_bytes());
+    hasher.update(gateway.to_vec());
+    let key = hasher.finalize();
+    Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+}
@@ -1,45 +1,335 @@
+#[cfg(feature = "metrics")]
+use crate::metrics;
+#[cfg(feature = "mqtt")]
+use crate::mqtt;
+#[cfg(feature = "remote-config")]
+use crate::remote_config;
 use crate::{
     api::LocalServer,
-    beaconer, gateway, packet_router, region_watcher,
-    settings::{self, Settings},
-    Result,
+    beaconer, gateway, gps,
+    keypair::SelfTest,
+    packet_broker, packet_router, region_watcher, reload,
+    service::backhaul::Backhaul,
+    settings::{self, KeypairSelfTest, Settings},
+    sim::{SimOptions, Simulator},
+    subsystems::PausedSubsystems,
+    uptime::Uptime,
+    Error, Result,
 };
-use tracing::info;
+use futures::future::try_join_all;
+use std::{future::Future, pin::Pin};
+use tracing::{info, warn};
 
 #[tracing::instrument(skip_all)]
-pub async fn run(shutdown: &triggered::Listener, settings: &Settings) -> Result {
+pub async fn run(
+    shutdown: &triggered::Listener,
+    settings: &Settings,
+    sim: Option<SimOptions>,
+) -> Result {
+    let keypair_test = keypair_self_test(settings).await?;
+    settings.network.validate()?;
     let (gateway_tx, gateway_rx) = gateway::message_channel();
     let (router_tx, router_rx) = packet_router::message_channel();
     let (beacon_tx, beacon_rx) = beaconer::message_channel();
+    let (reload_tx, reload_rx) = reload::message_channel();
 
-    let mut region_watcher = region_watcher::RegionWatcher::new(settings);
+    let mut region_watcher = region_watcher::RegionWatcher::new(settings)?;
     let region_rx = region_watcher.watcher();
 
+    let paused = PausedSubsystems::load(&settings.data_dir);
     let mut beaconer =
         beaconer::Beaconer::new(settings, beacon_rx, region_rx.clone(), gateway_tx.clone());
+    beaconer.set_paused(paused.poc);
 
-    let mut router = packet_router::PacketRouter::new(settings, router_rx, gateway_tx.clone());
+    let mut router = packet_router::PacketRouter::new(
+        settings,
+        &settings.router,
+        router_rx,
+        router_tx.clone(),
+        gateway_tx.clone(),
+    );
+
+    let mut uplinks = vec![router_tx.clone()];
+    let mut secondary_routers = vec![];
+    for router_settings in &settings.secondary_routers {
+        let (tx, rx) = packet_router::message_channel();
+        secondary_routers.push(packet_router::PacketRouter::new(
+            settings,
+            router_settings,
+            rx,
+            tx.clone(),
+            gateway_tx.clone(),
+        ));
+        uplinks.push(tx);
+    }
+    let router_settings = || std::iter::once(&settings.router).chain(&settings.secondary_routers);
+    // Routers with routing rules receive disjoint traffic, so only routers
+    // without rules can duplicate downlinks
+    let downlink_routers = router_settings()
+        .filter(|router| router.enabled && router.downlinks && !router.has_routing_rules())
+        .count();
+    if downlink_routers > 1 {
+        warn!(
+            downlink_routers,
+            "multiple routers with downlinks enabled, downlinks may be duplicated"
+        );
+    }
+
+    #[cfg(feature = "mqtt")]
+    let (mqtt_tx, mqtt_bridge) = match &settings.mqtt {
+        Some(mqtt_settings) => {
+            let (tx, rx) = mqtt::message_channel();
+            let mut bridge = mqtt::MqttBridge::new(
+                mqtt_settings,
+                settings.keypair.public_key().clone(),
+                rx,
+                gateway_tx.clone(),
+            )?;
+            bridge.set_paused(paused.mqtt);
+            (Some(tx), Some(bridge))
+        }
+        None => (None, None),
+    };
+    #[cfg(not(feature = "mqtt"))]
+    if settings.mqtt.is_some() {
+        warn!("mqtt bridge is not supported by this build");
+    }
+
+    let (packet_broker_tx, mut packet_broker) = match &settings.packet_broker {
+        Some(packet_broker_settings) => {
+            let (tx, rx) = packet_broker::message_channel();
+            let packet_broker = packet_broker::PacketBroker::new(
+                packet_broker_settings,
+                settings.keypair.public_key(),
+                &Backhaul::from(&settings.network),
+                rx,
+            )?;
+            (Some(tx), Some(packet_broker))
+        }
+        None => (None, None),
+    };
+
+    let mut reloader = reload::Reloader::new(
+        settings,
+        reload_rx,
+        gateway_tx.clone(),
+        beacon_tx.clone(),
+        uplinks.clone(),
+    );
+
+    #[cfg(feature = "remote-config")]
+    let remote_config = settings
+        .remote_config
+        .as_ref()
+        .map(|remote| remote_config::RemoteConfigFetcher::new(settings, remote, reload_tx.clone()));
+    #[cfg(not(feature = "remote-config"))]
+    if settings.remote_config.is_some() {
+        warn!("remote configuration is not supported by this build");
+    }
 
     let mut gateway = gateway::Gateway::new(
         settings,
         gateway_rx,
+        region_rx.clone(),
+        router_settings()
+            .zip(uplinks)
+            .map(|(router, sender)| packet_router::UplinkRoute::new(router, sender))
+            .collect(),
+        region_watcher.gps_positions(),
+        beacon_tx.clone(),
+    )
+    .await?
+    .with_packet_broker(packet_broker_tx);
+    #[cfg(feature = "mqtt")]
+    {
+        gateway = gateway.with_mqtt(mqtt_tx.clone());
+    }
+    let mut gpsd = gps::Gpsd::new(&settings.gps, gateway_tx.clone());
+    #[cfg(feature = "metrics")]
+    let metrics = metrics::Metrics::new(&settings.metrics, gateway_tx.clone(), beacon_tx.clone());
+    #[cfg(not(feature = "metrics"))]
+    if settings.metrics.listen.is_some() {
+        warn!("metrics are not supported by this build");
+    }
+    let mut simulator =
+        sim.map(|options| Simulator::new(options, gateway_tx.clone(), region_rx.clone()));
+    let uptime = Uptime::start(settings);
+    let api = LocalServer::new(
         region_rx.clone(),
         router_tx.clone(),
+        gateway_tx.clone(),
         beacon_tx,
-    )
-    .await?;
-    let api = LocalServer::new(region_rx.clone(), router_tx.clone(), settings)?;
+        reload_tx,
+        uptime.clone(),
+        settings,
+    )?
+    .with_keypair_test(keypair_test);
+    #[cfg(feature = "mqtt")]
+    let api = api.with_mqtt(mqtt_tx);
     info!(
         version = %settings::version().to_string(),
         key = %settings.keypair.public_key().to_string(),
         "starting server",
     );
-    tokio::try_join!(
-        region_watcher.run(shutdown),
-        beaconer.run(shutdown),
-        gateway.run(shutdown),
-        router.run(shutdown),
-        api.run(shutdown),
-    )
-    .map(|_| ())
+    if !settings.env_overrides.is_empty() {
+        let overrides: Vec<String> = settings
+            .env_overrides
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        info!(
+            overrides = overrides.join(", "),
+            "applied environment overrides"
+        );
+    }
+    for deprecated in &settings.deprecated {
+        warn!(
+            key = deprecated.key,
+            "{}, run \"helium_gateway settings check\" for all settings to migrate",
+            deprecated.message
+        );
+    }
+    let listener = || shutdown.clone();
+    // Subsystems run as local tasks since not all of them can be moved
+    // between threads
+    let subsystems = async move {
+        // Subsystems that can be left out of a build, if configured. Stays
+        // empty in builds without any of them.
+        #[allow(unused_mut)]
+        let mut optional: Vec<Pin<Box<dyn Future<Output = Result>>>> = vec![];
+        #[cfg(feature = "mqtt")]
+        if let Some(mut bridge) = mqtt_bridge {
+            let shutdown = listener();
+            optional.push(Box::pin(spawn("mqtt", async move {
+                bridge.run(&shutdown).await
+            })));
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(mut metrics) = metrics {
+            let shutdown = listener();
+            optional.push(Box::pin(spawn("metrics", async move {
+                metrics.run(&shutdown).await
+            })));
+        }
+        #[cfg(feature = "remote-config")]
+        if let Some(mut fetcher) = remote_config {
+            let shutdown = listener();
+            optional.push(Box::pin(spawn("remote_config", async move {
+                fetcher.run(&shutdown).await
+            })));
+        }
+        tokio::try_join!(
+            spawn("region_watcher", {
+                let shutdown = listener();
+                async move { region_watcher.run(&shutdown).await }
+            }),
+            spawn("beaconer", {
+                let shutdown = listener();
+                async move { beaconer.run(&shutdown).await }
+            }),
+            spawn("gateway", {
+                let shutdown = listener();
+                async move { gateway.run(&shutdown).await }
+            }),
+            spawn("packet_router", {
+                let shutdown = listener();
+                async move { router.run(&shutdown).await }
+            }),
+            try_join_all(secondary_routers.into_iter().map(|mut router| {
+                let shutdown = listener();
+                spawn(
+                    "secondary_router",
+                    async move { router.run(&shutdown).await },
+                )
+            })),
+            spawn("api", {
+                let shutdown = listener();
+                async move { api.run(&shutdown).await }
+            }),
+            spawn("reloader", {
+                let shutdown = listener();
+                async move { reloader.run(&shutdown).await }
+            }),
+            spawn("packet_broker", {
+                let shutdown = listener();
+                async move {
+                    match &mut packet_broker {
+                        Some(packet_broker) => packet_broker.run(&shutdown).await,
+                        None => Ok(()),
+                    }
+                }
+            }),
+            spawn("gpsd", {
+                let shutdown = listener();
+                async move {
+                    match &mut gpsd {
+                        Some(gpsd) => gpsd.run(&shutdown).await,
+                        None => Ok(()),
+                    }
+                }
+            }),
+            spawn("sim", {
+                let shutdown = listener();
+                async move {
+                    match &mut simulator {
+                        Some(simulator) => simulator.run(&shutdown).await,
+                        None => Ok(()),
+                    }
+                }
+            }),
+            try_join_all(optional),
+        )
+    };
+    let result = tokio::task::LocalSet::new().run_until(subsystems).await;
+    // A failed subsystem shuts the service down in order too, only a crash
+    // leaves it marked as running
+    uptime.stopped();
+    result.map(|_| ())
+}
+
+/// Runs a subsystem in its own local task. With the `console` feature the task is
+/// named after the subsystem, so a stalled subsystem can be told apart from
+/// the others in tokio-console.
+fn spawn<F>(name: &'static str, future: F) -> impl Future<Output = Result>
+where
+    F: Future<Output = Result> + 'static,
+{
+    #[cfg(feature = "console")]
+    let task = tokio::task::Builder::new().name(name).spawn_local(future);
+    #[cfg(not(feature = "console"))]
+    let task = Ok::<_, std::io::Error>(tokio::task::spawn_local(future));
+    async move {
+        task?
+            .await
+            .map_err(|err| Error::custom(format!("{name} task failed: {err}")))?
+    }
+}
+
+/// Runs the keypair self test unless it is turned off. A failed self test
+/// stops the service unless it may start degraded.
+async fn keypair_self_test(settings: &Settings) -> Result<Option<SelfTest>> {
+    if settings.keypair_self_test == KeypairSelfTest::Off {
+        return Ok(None);
+    }
+    let test = SelfTest::run(settings.keypair.clone()).await;
+    match &test.error {
+        None => info!(
+            key_type = test.key_type,
+            sign_ms = test.sign_ms,
+            "keypair self test passed"
+        ),
+        Some(error) if settings.keypair_self_test == KeypairSelfTest::Required => {
+            return Err(Error::custom(format!(
+                "keypair self test failed: {error}. Check the keypair setting and that a \
+                 secure element is reachable, or set keypair_self_test = \"degraded\" to \
+                 start anyway"
+            )))
+        }
+        Some(error) => warn!(
+            key_type = test.key_type,
+            %error,
+            "keypair self test failed, starting degraded"
+        ),
+    }
+    Ok(Some(test))
 }
@@ -0,0 +1,113 @@
+//! Connection options of the gRPC connections to the backhaul services.
+//!
+//! Connections to the packet router, config, entropy, poc and Packet Broker
+//! services can be marked with a DSCP value, see [`crate::qos`], and bound
+//! to a network interface to keep them off the radio network on multi-homed
+//! hosts.
+//!
+//! Long lived conduit connections can send HTTP/2 keepalive pings. Some
+//! middleboxes drop idle HTTP/2 streams while keeping the tcp connection alive,
+//! which without pings only shows up as eventual ack timeouts.
+
+use crate::{interface, qos, settings::NetworkSettings};
+use helium_proto::services::{Channel, Endpoint};
+use http::Uri;
+use socket2::{SockRef, TcpKeepalive};
+use std::{io, net::SocketAddr, time::Duration};
+use tokio::net::{TcpSocket, TcpStream};
+
+#[derive(Debug, Clone, Default)]
+pub struct Backhaul {
+    /// DSCP value to mark connections with, 0 to leave them unmarked
+    dscp: u8,
+    /// Network interface to bind connections to, if any
+    interface: Option<String>,
+    /// HTTP/2 keepalive interval and timeout of conduit connections, if
+    /// enabled
+    http2_keepalive: Option<(Duration, Duration)>,
+}
+
+impl From<&NetworkSettings> for Backhaul {
+    fn from(settings: &NetworkSettings) -> Self {
+        Self {
+            dscp: settings.backhaul_dscp,
+            interface: settings.backhaul_interface.clone(),
+            http2_keepalive: (settings.http2_keepalive_interval > 0).then(|| {
+                (
+                    Duration::from_secs(settings.http2_keepalive_interval),
+                    Duration::from_secs(settings.http2_keepalive_timeout),
+                )
+            }),
+        }
+    }
+}
+
+impl Backhaul {
+    /// Applies the HTTP/2 keepalive, if enabled, to the given endpoint. Pings
+    /// are sent even while no stream is active so a dead connection is
+    /// detected before the next stream is opened on it.
+    pub fn http2_keepalive(&self, endpoint: Endpoint) -> Endpoint {
+        match self.http2_keepalive {
+            Some((interval, timeout)) => endpoint
+                .http2_keep_alive_interval(interval)
+                .keep_alive_timeout(timeout)
+                .keep_alive_while_idle(true),
+            None => endpoint,
+        }
+    }
+
+    /// Creates a lazily connecting channel for the given endpoint. Connections
+    /// are marked with the DSCP value and bound to the interface, if any.
+    ///
+    /// A marked or bound connection uses its own connector, which does not see
+    /// the tcp options of the endpoint, so the tcp keepalive is passed in
+    /// separately.
+    pub fn connect_lazy(&self, endpoint: Endpoint, keepalive: Option<Duration>) -> Channel {
+        if self.dscp == 0 && self.interface.is_none() {
+            return endpoint.connect_lazy();
+        }
+        let backhaul = self.clone();
+        let connector = tower::service_fn(move |uri: Uri| {
+            let backhaul = backhaul.clone();
+            async move { backhaul.connect(uri, keepalive).await }
+        });
+        endpoint.connect_with_connector_lazy(connector)
+    }
+
+    async fn connect(&self, uri: Uri, keepalive: Option<Duration>) -> io::Result<TcpStream> {
+        let host = uri
+            .host()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "missing host in uri"))?
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+            Some("https") => 443,
+            _ => 80,
+        });
+        let mut last_err = None;
+        for addr in tokio::net::lookup_host((host, port)).await? {
+            let socket = match addr {
+                SocketAddr::V4(_) => TcpSocket::new_v4()?,
+                SocketAddr::V6(_) => TcpSocket::new_v6()?,
+            };
+            qos::set_dscp(SockRef::from(&socket), &addr, self.dscp)?;
+            if let Some(interface) = &self.interface {
+                interface::bind_device(SockRef::from(&socket), interface)?;
+            }
+            if let Some(keepalive) = keepalive {
+                SockRef::from(&socket)
+                    .set_tcp_keepalive(&TcpKeepalive::new().with_time(keepalive))?;
+            }
+            match socket.connect(addr).await {
+                Ok(stream) => {
+                    stream.set_nodelay(true)?;
+                    return Ok(stream);
+                }
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "no addresses found for host")
+        }))
+    }
+}
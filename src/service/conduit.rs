@@ -1,11 +1,15 @@
 use crate::{
-    service::{CONNECT_TIMEOUT, RPC_TIMEOUT},
+    service::{backhaul::Backhaul, session_log::SessionLog, CONNECT_TIMEOUT, RPC_TIMEOUT},
     Error, Keypair, PublicKey, Result, Sign,
 };
-use futures::TryFutureExt;
 use helium_proto::services::{Channel, Endpoint};
 use http::Uri;
-use std::sync::Arc;
+use std::{
+    future::Future,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use tracing::{info, warn};
@@ -17,15 +21,59 @@ use tracing::{info, warn};
 pub const TCP_KEEP_ALIVE_DURATION: std::time::Duration = std::time::Duration::from_secs(300);
 pub const CONDUIT_CAPACITY: usize = 50;
 
+/// Session lifecycle events. These are logged as single line events with a
+/// stable `session_event` key so connectivity churn can be tracked from logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// A session offer was received from the remote service
+    Offered,
+    /// The session init in response to an offer was sent
+    Established,
+    /// An established session ended through a disconnect
+    Expired,
+    /// An established session was replaced by a new offer from the remote
+    /// service
+    Renewed,
+    /// The session could not be established
+    Rejected,
+}
+
+impl SessionEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Offered => "offered",
+            Self::Established => "established",
+            Self::Expired => "expired",
+            Self::Renewed => "renewed",
+            Self::Rejected => "rejected",
+        }
+    }
+}
+
+impl std::fmt::Display for SessionEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 /// A conduit service maintains a re-connectable connection to a remote service.
 #[derive(Debug)]
 pub struct ConduitService<U, D, C: ConduitClient<U, D>> {
     pub uri: Uri,
     module: &'static str,
     session_keypair: Option<Arc<Keypair>>,
+    /// When the current session was established
+    session_started: Option<Instant>,
+    /// Maximum age of a session before it is considered expired
+    session_max_age: Option<Duration>,
+    /// Number of sessions replaced by a new offer from the remote service
+    session_renewals: u64,
+    /// Record of the last session in the data directory, if kept
+    session_log: Option<SessionLog>,
     conduit: Option<Conduit<U, D>>,
     keypair: Arc<Keypair>,
     client: C,
+    backhaul: Backhaul,
 }
 
 #[derive(Debug)]
@@ -34,22 +82,23 @@ struct Conduit<U, D> {
     rx: tonic::Streaming<D>,
 }
 
-#[tonic::async_trait]
+/// The service specific part of a conduit. Implementations are used through
+/// generics, so their futures are not boxed.
 pub trait ConduitClient<U, D> {
-    async fn init(
+    fn init(
         &mut self,
         endpoint: Channel,
         tx: mpsc::Sender<U>,
         client_rx: ReceiverStream<U>,
         keypair: Arc<Keypair>,
-    ) -> Result<tonic::Streaming<D>>;
+    ) -> impl Future<Output = Result<tonic::Streaming<D>>> + Send;
 
-    async fn mk_session_init(
+    fn mk_session_init(
         &self,
         nonce: &[u8],
         session_key: &PublicKey,
         keypair: Arc<Keypair>,
-    ) -> Result<U>;
+    ) -> impl Future<Output = Result<U>> + Send;
 }
 
 impl<U, D> Conduit<U, D> {
@@ -57,12 +106,17 @@ impl<U, D> Conduit<U, D> {
         uri: Uri,
         client: &mut C,
         keypair: Arc<Keypair>,
+        backhaul: &Backhaul,
     ) -> Result<Self> {
-        let endpoint = Endpoint::from(uri)
-            .timeout(RPC_TIMEOUT)
-            .connect_timeout(CONNECT_TIMEOUT)
-            .tcp_keepalive(Some(TCP_KEEP_ALIVE_DURATION))
-            .connect_lazy();
+        let endpoint = backhaul.connect_lazy(
+            backhaul.http2_keepalive(
+                Endpoint::from(uri)
+                    .timeout(RPC_TIMEOUT)
+                    .connect_timeout(CONNECT_TIMEOUT)
+                    .tcp_keepalive(Some(TCP_KEEP_ALIVE_DURATION)),
+            ),
+            Some(TCP_KEEP_ALIVE_DURATION),
+        );
         let (tx, client_rx) = mpsc::channel(CONDUIT_CAPACITY);
         let rx = client
             .init(
@@ -85,17 +139,61 @@ impl<U, D> Conduit<U, D> {
 }
 
 impl<U, D, C: ConduitClient<U, D>> ConduitService<U, D, C> {
-    pub fn new(module: &'static str, uri: Uri, client: C, keypair: Arc<Keypair>) -> Self {
+    pub fn new(
+        module: &'static str,
+        uri: Uri,
+        client: C,
+        keypair: Arc<Keypair>,
+        backhaul: Backhaul,
+    ) -> Self {
         Self {
             uri,
             module,
             keypair,
             client,
+            backhaul,
             conduit: None,
             session_keypair: None,
+            session_started: None,
+            session_max_age: None,
+            session_renewals: 0,
+            session_log: None,
+        }
+    }
+
+    /// Keeps a record of the last session with the service in the given data
+    /// directory
+    pub fn log_sessions(&mut self, data_dir: &Path) {
+        self.session_log = Some(SessionLog::new(data_dir, self.module, self.uri.to_string()));
+    }
+
+    /// Sets the maximum age of a session. Signing with an older session fails
+    /// with a session expired error, after which the owner is expected to
+    /// reconnect for a new session offer.
+    pub fn set_session_max_age(&mut self, max_age: Option<Duration>) {
+        self.session_max_age = max_age;
+    }
+
+    /// Whether the current session is older than the maximum session age
+    pub fn session_expired(&self) -> bool {
+        match (self.session_age(), self.session_max_age) {
+            (Some(age), Some(max_age)) => age > max_age,
+            _ => false,
         }
     }
 
+    /// Time until the current session expires, None if there is no session or
+    /// sessions do not expire
+    pub fn session_expires_in(&self) -> Option<Duration> {
+        let (age, max_age) = (self.session_age()?, self.session_max_age?);
+        Some(max_age.saturating_sub(age))
+    }
+
+    /// Number of sessions replaced by a new offer from the remote service
+    pub fn session_renewals(&self) -> u64 {
+        self.session_renewals
+    }
+
     pub async fn send(&mut self, msg: U) -> Result {
         if self.conduit.is_none() {
             self.connect().await?;
@@ -103,9 +201,9 @@ impl<U, D, C: ConduitClient<U, D>> ConduitService<U, D, C> {
         // Unwrap since the above connect early exits if no conduit is created
         match self.conduit.as_mut().unwrap().send(msg).await {
             Ok(()) => Ok(()),
-            other => {
-                self.disconnect();
-                other
+            Err(err) => {
+                self.close(&format!("send failed: {err}"));
+                Err(err)
             }
         }
     }
@@ -123,30 +221,58 @@ impl<U, D, C: ConduitClient<U, D>> ConduitService<U, D, C> {
         match self.conduit.as_mut().unwrap().recv().await {
             Ok(Some(msg)) => Ok(msg),
             Ok(None) => {
-                self.disconnect();
+                self.close("stream closed");
                 Err(Error::no_stream())
             }
             Err(err) => {
-                self.disconnect();
+                self.close(&format!("receive failed: {err}"));
                 Err(err)
             }
         }
     }
 
     pub fn disconnect(&mut self) {
+        self.close("disconnect");
+    }
+
+    /// Disconnects, recording the given reason as the close reason of an
+    /// established session
+    fn close(&mut self, reason: &str) {
         self.conduit = None;
-        self.session_keypair = None;
+        self.session_started = None;
+        if let Some(session_keypair) = self.session_keypair.take() {
+            self.session_event(SessionEvent::Expired, Some(session_keypair.public_key()));
+            if let Some(session_log) = &self.session_log {
+                session_log.closed(reason);
+            }
+        }
+    }
+
+    fn session_event(&self, event: SessionEvent, session_key: Option<&PublicKey>) {
+        let module = self.module;
+        let uri = &self.uri;
+        match session_key {
+            Some(session_key) => {
+                info!(module, %uri, session_event = %event, %session_key, "session event")
+            }
+            None => info!(module, %uri, session_event = %event, "session event"),
+        }
     }
 
     pub async fn connect(&mut self) -> Result {
-        let conduit =
-            Conduit::new(self.uri.clone(), &mut self.client, self.keypair.clone()).await?;
+        let conduit = Conduit::new(
+            self.uri.clone(),
+            &mut self.client,
+            self.keypair.clone(),
+            &self.backhaul,
+        )
+        .await?;
         self.conduit = Some(conduit);
         Ok(())
     }
 
     pub async fn reconnect(&mut self) -> Result {
-        self.disconnect();
+        self.close("reconnect");
         self.connect().await
     }
 
@@ -166,7 +292,19 @@ impl<U, D, C: ConduitClient<U, D>> ConduitService<U, D, C> {
         self.session_keypair.clone()
     }
 
+    /// Time since the current session was established
+    pub fn session_age(&self) -> Option<Duration> {
+        self.session_keypair
+            .as_ref()
+            .and(self.session_started)
+            .map(|started| started.elapsed())
+    }
+
     pub async fn session_sign<M: Sign>(&self, msg: &mut M) -> Result {
+        if self.session_expired() {
+            let age = self.session_age().unwrap_or_default().as_secs();
+            return Err(Error::session_expired(age));
+        }
         if let Some(keypair) = self.session_keypair.as_ref() {
             msg.sign(keypair.clone()).await?;
             Ok(())
@@ -176,18 +314,38 @@ impl<U, D, C: ConduitClient<U, D>> ConduitService<U, D, C> {
     }
 
     pub async fn session_init(&mut self, nonce: &[u8]) -> Result {
+        self.session_event(SessionEvent::Offered, None);
+        // A new offer in an established session replaces the current session
+        if let Some(session_keypair) = self.session_keypair.take() {
+            self.session_started = None;
+            self.session_renewals += 1;
+            self.session_event(SessionEvent::Renewed, Some(session_keypair.public_key()));
+            if let Some(session_log) = &self.session_log {
+                session_log.closed("renewed");
+            }
+        }
         let session_keypair = Arc::new(Keypair::new());
         let session_key = session_keypair.public_key();
         let module: &'static str = self.module;
-        let msg = self
+        let result = match self
             .client
             .mk_session_init(nonce, session_key, self.keypair.clone())
-            .await?;
-        self.send(msg)
-            .inspect_err(|err| warn!(module, %err, "failed to initialize session"))
-            .await?;
+            .await
+        {
+            Ok(msg) => self.send(msg).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            warn!(module, %err, "failed to initialize session");
+            self.session_event(SessionEvent::Rejected, Some(session_key));
+            return Err(err);
+        }
         self.session_keypair = Some(session_keypair.clone());
-        info!(module, %session_key, "initialized session");
+        self.session_started = Some(Instant::now());
+        self.session_event(SessionEvent::Established, Some(session_key));
+        if let Some(session_log) = &self.session_log {
+            session_log.established(session_key);
+        }
         Ok(())
     }
 }
@@ -1,12 +1,15 @@
 use crate::{
     impl_sign, impl_verify,
-    service::{CONNECT_TIMEOUT, RPC_TIMEOUT},
+    service::{backhaul::Backhaul, CONNECT_TIMEOUT, RPC_TIMEOUT},
     KeyedUri, Keypair, Region, RegionParams, Result, Sign, Verify,
 };
 use helium_proto::{
     services::{
         self,
-        iot_config::{GatewayRegionParamsReqV1, GatewayRegionParamsResV1},
+        iot_config::{
+            GatewayInfo, GatewayInfoReqV1, GatewayInfoResV1, GatewayRegionParamsReqV1,
+            GatewayRegionParamsResV1,
+        },
         Channel, Endpoint,
     },
     Message,
@@ -22,11 +25,11 @@ pub struct ConfigService {
 }
 
 impl ConfigService {
-    pub fn new(keyed_uri: &KeyedUri) -> Self {
-        let channel = Endpoint::from(keyed_uri.uri.clone())
+    pub fn new(keyed_uri: &KeyedUri, backhaul: &Backhaul) -> Self {
+        let endpoint = Endpoint::from(keyed_uri.uri.clone())
             .connect_timeout(CONNECT_TIMEOUT)
-            .timeout(RPC_TIMEOUT)
-            .connect_lazy();
+            .timeout(RPC_TIMEOUT);
+        let channel = backhaul.connect_lazy(endpoint, None);
         Self {
             uri: keyed_uri.clone(),
             client: ConfigClient::new(channel),
@@ -49,7 +52,31 @@ impl ConfigService {
         resp.verify(&self.uri.pubkey)?;
         Ok(RegionParams::try_from(resp)?)
     }
+
+    /// Fetches what the network knows about the gateway, like its staking
+    /// mode and asserted location. Returns None if the gateway is not known to
+    /// the network.
+    pub async fn gateway_info(&mut self, keypair: Arc<Keypair>) -> Result<Option<GatewayInfo>> {
+        let mut req = GatewayInfoReqV1 {
+            address: keypair.public_key().to_vec(),
+            signer: keypair.public_key().to_vec(),
+            signature: vec![],
+        };
+        req.sign(keypair).await?;
+
+        match self.client.info(req).await {
+            Ok(resp) => {
+                let resp = resp.into_inner();
+                resp.verify(&self.uri.pubkey)?;
+                Ok(resp.info)
+            }
+            Err(status) if status.code() == tonic::Code::NotFound => Ok(None),
+            Err(status) => Err(status.into()),
+        }
+    }
 }
 
 impl_sign!(GatewayRegionParamsReqV1);
+impl_sign!(GatewayInfoReqV1);
 impl_verify!(GatewayRegionParamsResV1);
+impl_verify!(GatewayInfoResV1);
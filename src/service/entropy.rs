@@ -1,10 +1,13 @@
 use crate::{
-    service::{CONNECT_TIMEOUT, RPC_TIMEOUT},
-    Result,
+    clock::SharedClock,
+    service::{backhaul::Backhaul, CONNECT_TIMEOUT, RPC_TIMEOUT},
+    Error, Result,
 };
 use beacon::Entropy;
 use helium_proto::services::{self, poc_entropy::EntropyReqV1, Channel, Endpoint};
 use http::Uri;
+use std::time::Duration;
+use tracing::{info, warn};
 
 type EntropyClient = helium_proto::services::poc_entropy::Client<Channel>;
 
@@ -12,11 +15,11 @@ type EntropyClient = helium_proto::services::poc_entropy::Client<Channel>;
 pub struct EntropyService(EntropyClient);
 
 impl EntropyService {
-    pub fn new(uri: Uri) -> Self {
-        let channel = Endpoint::from(uri)
+    pub fn new(uri: Uri, backhaul: &Backhaul) -> Self {
+        let endpoint = Endpoint::from(uri)
             .connect_timeout(CONNECT_TIMEOUT)
-            .timeout(RPC_TIMEOUT)
-            .connect_lazy();
+            .timeout(RPC_TIMEOUT);
+        let channel = backhaul.connect_lazy(endpoint, None);
         let client = services::poc_entropy::Client::new(channel);
         Self(client)
     }
@@ -27,3 +30,63 @@ impl EntropyService {
         Ok(resp.into_inner().into())
     }
 }
+
+/// Remote entropy for beacons from a list of entropy services, tried in
+/// order. The last fetched entropy is cached and used while all services fail
+/// for as long as it is younger than the maximum age, so a short entropy
+/// service outage does not skip a beacon.
+#[derive(Debug)]
+pub struct EntropySources {
+    services: Vec<(Uri, EntropyService)>,
+    max_age: Duration,
+    cached: Option<Entropy>,
+    clock: SharedClock,
+}
+
+impl EntropySources {
+    pub fn new(uris: Vec<Uri>, max_age: Duration, clock: SharedClock, backhaul: &Backhaul) -> Self {
+        let services = uris
+            .into_iter()
+            .map(|uri| (uri.clone(), EntropyService::new(uri, backhaul)))
+            .collect();
+        Self {
+            services,
+            max_age,
+            cached: None,
+            clock,
+        }
+    }
+
+    /// Whether fetched entropy is cached for outages
+    pub fn is_cached(&self) -> bool {
+        !self.max_age.is_zero()
+    }
+
+    /// Fetches entropy from the first service that responds, falling back to
+    /// the cached entropy if it is recent enough
+    pub async fn get_entropy(&mut self) -> Result<Entropy> {
+        let mut last_err = None;
+        for (uri, service) in &mut self.services {
+            match service.get_entropy().await {
+                Ok(entropy) => {
+                    self.cached = Some(entropy.clone());
+                    return Ok(entropy);
+                }
+                Err(err) => {
+                    warn!(%uri, %err, "failed to fetch entropy");
+                    last_err = Some(err);
+                }
+            }
+        }
+        let last_err = last_err.unwrap_or_else(|| Error::custom("no entropy service"));
+        let Some(cached) = self.cached.as_ref().filter(|_| self.is_cached()) else {
+            return Err(last_err);
+        };
+        let age = self.clock.now_utc().unix_timestamp() - cached.timestamp;
+        if age > self.max_age.as_secs() as i64 {
+            return Err(last_err);
+        }
+        info!(age, "using cached entropy");
+        Ok(cached.clone())
+    }
+}
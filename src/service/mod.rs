@@ -1,3 +1,4 @@
+use crate::clock::{self, SharedClock};
 use tokio::time::{self, Duration, Instant};
 
 pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
@@ -7,15 +8,19 @@ pub const RECONNECT_BACKOFF_RETRIES: u32 = 40;
 pub const RECONNECT_BACKOFF_MIN_WAIT: Duration = Duration::from_secs(5);
 pub const RECONNECT_BACKOFF_MAX_WAIT: Duration = Duration::from_secs(1800); // 30 minutes
 
+pub mod backhaul;
 pub mod conduit;
 pub mod config;
 pub mod entropy;
+pub mod packet_broker;
 pub mod packet_router;
 pub mod poc;
+pub mod session_log;
 
 #[derive(Debug)]
 pub struct Reconnect {
     backoff: exponential_backoff::Backoff,
+    clock: SharedClock,
     next_time: Instant,
     pub max_wait: Duration,
     pub max_retries: u32,
@@ -34,9 +39,14 @@ impl Default for Reconnect {
 
 impl Reconnect {
     pub fn new(retries: u32, min: Duration, max: Duration) -> Self {
+        Self::with_clock(retries, min, max, clock::system())
+    }
+
+    pub fn with_clock(retries: u32, min: Duration, max: Duration, clock: SharedClock) -> Self {
         Self {
             backoff: exponential_backoff::Backoff::new(retries, min, max),
-            next_time: Instant::now() + min,
+            next_time: clock.now() + min,
+            clock,
             max_retries: retries,
             max_wait: max,
             retry_count: 0,
@@ -56,6 +66,38 @@ impl Reconnect {
             }
         }
         let backoff = self.backoff.next(self.retry_count).unwrap_or(self.max_wait);
-        self.next_time = Instant::now() + backoff;
+        self.next_time = self.clock.now() + backoff;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::TestClock;
+    use ::time::macros::datetime;
+
+    #[tokio::test(start_paused = true)]
+    async fn reconnect_backoff() {
+        let clock = TestClock::shared(datetime!(2023-09-01 09:20 UTC));
+        let min = Duration::from_secs(5);
+        let max = Duration::from_secs(60);
+        let mut reconnect = Reconnect::with_clock(3, min, max, clock.clone());
+
+        let start = clock.now();
+        reconnect.wait().await;
+        assert_eq!(min, clock.now() - start);
+
+        // Retries back off between the minimum and maximum wait
+        for _ in 0..reconnect.max_retries {
+            let start = clock.now();
+            reconnect.update_next_time(true);
+            reconnect.wait().await;
+            let waited = clock.now() - start;
+            assert!(waited >= min && waited <= max, "waited {waited:?}");
+        }
+
+        // Past the maximum retries the count wraps back to the start
+        reconnect.update_next_time(true);
+        assert_eq!(0, reconnect.retry_count);
     }
 }
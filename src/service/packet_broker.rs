@@ -0,0 +1,236 @@
+use crate::{
+    service::{backhaul::Backhaul, CONNECT_TIMEOUT, RPC_TIMEOUT},
+    settings::PacketBrokerSettings,
+    Error, PacketUp, PublicKey, Result,
+};
+use helium_proto::services::{Channel, Endpoint};
+use http::{uri::Scheme, Uri};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tonic::{
+    metadata::{Ascii, MetadataValue},
+    transport::ClientTlsConfig,
+};
+
+pub mod proto {
+    pub mod v3 {
+        tonic::include_proto!("org.packetbroker.v3");
+    }
+    pub mod routing {
+        pub mod v2 {
+            tonic::include_proto!("org.packetbroker.routing.v2");
+        }
+    }
+}
+
+use proto::{
+    routing::v2::{
+        router_forwarder_data_client::RouterForwarderDataClient, PublishUplinkMessageRequest,
+    },
+    v3::{self, data_rate, gateway_identifier, gateway_metadata, uplink_message},
+};
+
+/// Publishes uplinks to a Packet Broker routing endpoint as a forwarder
+#[derive(Debug)]
+pub struct PacketBrokerService {
+    pub uri: Uri,
+    client: RouterForwarderDataClient<Channel>,
+    token: Option<MetadataValue<Ascii>>,
+    net_id: u32,
+    tenant_id: String,
+    cluster_id: String,
+    gateway_id: String,
+}
+
+impl PacketBrokerService {
+    pub fn new(
+        settings: &PacketBrokerSettings,
+        gateway: &PublicKey,
+        backhaul: &Backhaul,
+    ) -> Result<Self> {
+        let https = settings.uri.scheme() == Some(&Scheme::HTTPS);
+        if settings.token.is_some() && !https {
+            return Err(Error::custom(
+                "refusing to send the packet broker token without https",
+            ));
+        }
+        let token = settings
+            .token
+            .as_ref()
+            .map(|token| {
+                format!("Bearer {}", token.expose())
+                    .parse()
+                    .map_err(|_| Error::custom("invalid packet broker token"))
+            })
+            .transpose()?;
+        let mut endpoint = Endpoint::from(settings.uri.clone())
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(RPC_TIMEOUT);
+        if https {
+            endpoint = endpoint
+                .tls_config(ClientTlsConfig::new())
+                .map_err(|err| Error::custom(format!("packet broker tls: {err}")))?;
+        }
+        let client = RouterForwarderDataClient::new(backhaul.connect_lazy(endpoint, None));
+        Ok(Self {
+            uri: settings.uri.clone(),
+            client,
+            token,
+            net_id: settings.net_id.into(),
+            tenant_id: settings.tenant_id.clone().unwrap_or_default(),
+            cluster_id: settings.cluster_id.clone().unwrap_or_default(),
+            gateway_id: gateway.to_string(),
+        })
+    }
+
+    /// Publishes an uplink held for the given time and returns the id Packet
+    /// Broker assigned to it, or None if the uplink is not supported by
+    /// Packet Broker
+    pub async fn publish(
+        &mut self,
+        packet: &PacketUp,
+        hold_time: Duration,
+    ) -> Result<Option<String>> {
+        let now = SystemTime::now();
+        let Some(message) = uplink_message(packet, &self.gateway_id, now - hold_time, now) else {
+            return Ok(None);
+        };
+        let mut request = tonic::Request::new(PublishUplinkMessageRequest {
+            forwarder_net_id: self.net_id,
+            forwarder_tenant_id: self.tenant_id.clone(),
+            forwarder_cluster_id: self.cluster_id.clone(),
+            message: Some(message),
+        });
+        if let Some(token) = &self.token {
+            request
+                .metadata_mut()
+                .insert("authorization", token.clone());
+        }
+        Ok(Some(self.client.publish(request).await?.into_inner().id))
+    }
+}
+
+/// The Packet Broker uplink message of a packet, or None if the packet was
+/// not received with a LoRa datarate or in a region Packet Broker supports
+pub fn uplink_message(
+    packet: &PacketUp,
+    gateway_id: &str,
+    received: SystemTime,
+    forwarded: SystemTime,
+) -> Option<v3::UplinkMessage> {
+    let (spreading_factor, bandwidth) = packet
+        .datarate()
+        .as_str_name()
+        .strip_prefix("SF")?
+        .split_once("BW")?;
+    let antenna = v3::GatewayAntennaSignalQuality {
+        index: 0,
+        value: Some(v3::TerrestrialGatewayAntennaSignalQuality {
+            channel_rssi: packet.rssi as f32,
+            signal_rssi: packet.rssi as f32,
+            snr: packet.snr,
+        }),
+    };
+    Some(v3::UplinkMessage {
+        gateway_id: Some(v3::GatewayIdentifier {
+            id: Some(gateway_identifier::Id::Plain(gateway_id.to_string())),
+        }),
+        gateway_region: region(packet.region())? as i32,
+        phy_payload: Some(uplink_message::PhyPayload {
+            value: Some(uplink_message::phy_payload::Value::Plain(
+                packet.payload().to_vec(),
+            )),
+        }),
+        data_rate: Some(v3::DataRate {
+            modulation: Some(data_rate::Modulation::Lora(v3::LoRaDataRate {
+                spreading_factor: spreading_factor.parse().ok()?,
+                bandwidth: bandwidth.parse::<u32>().ok()? * 1000,
+                coding_rate: "4/5".to_string(),
+            })),
+        }),
+        frequency: packet.frequency as u64,
+        gateway_metadata: Some(v3::GatewayMetadata {
+            signal_quality: Some(gateway_metadata::SignalQuality::PlainSignalQuality(
+                v3::GatewaySignalQuality {
+                    terrestrial: vec![antenna],
+                },
+            )),
+        }),
+        forwarder_receive_time: Some(timestamp(forwarded)),
+        gateway_receive_time: Some(timestamp(received)),
+        gateway_uplink_token: (packet.timestamp as u32).to_be_bytes().to_vec(),
+    })
+}
+
+fn region(region: helium_proto::Region) -> Option<v3::Region> {
+    use helium_proto::Region;
+    let region = match region {
+        Region::Us915 => v3::Region::Us902928,
+        Region::Eu868 | Region::Eu868A => v3::Region::Eu863870,
+        Region::Eu433 => v3::Region::Eu433,
+        Region::Cn470 => v3::Region::Cn470510,
+        Region::Cn779 => v3::Region::Cn779787,
+        Region::Au915 => v3::Region::Au915928,
+        Region::As9231 | Region::As9231b => v3::Region::As923,
+        Region::As9232 => v3::Region::As9232,
+        Region::As9233 => v3::Region::As9233,
+        Region::As9234 => v3::Region::As9234,
+        Region::Kr920 => v3::Region::Kr920923,
+        Region::In865 => v3::Region::In865867,
+        Region::Ru864 => v3::Region::Ru864870,
+        _ => return None,
+    };
+    Some(region)
+}
+
+fn timestamp(time: SystemTime) -> v3::Timestamp {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    v3::Timestamp {
+        seconds: since_epoch.as_secs() as i64,
+        nanos: since_epoch.subsec_nanos() as i32,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message_cache::Persist;
+    use helium_proto::{services::router::PacketRouterPacketUpV1, DataRate, Message, Region};
+
+    fn uplink(datarate: DataRate, region: Region) -> PacketUp {
+        let packet = PacketRouterPacketUpV1 {
+            payload: vec![0x40, 0x01, 0x02, 0x03, 0x04],
+            timestamp: 0x1_0000_0010,
+            rssi: -110,
+            snr: 5.5,
+            frequency: 868_100_000,
+            datarate: datarate as i32,
+            region: region as i32,
+            ..Default::default()
+        };
+        PacketUp::from_bytes(&packet.encode_to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_uplink_message() {
+        let now = SystemTime::now();
+        let packet = uplink(DataRate::Sf9bw125, Region::Eu868);
+        let message = uplink_message(&packet, "gateway", now, now).expect("uplink message");
+        assert_eq!(v3::Region::Eu863870 as i32, message.gateway_region);
+        assert_eq!(
+            Some(data_rate::Modulation::Lora(v3::LoRaDataRate {
+                spreading_factor: 9,
+                bandwidth: 125_000,
+                coding_rate: "4/5".to_string(),
+            })),
+            message.data_rate.and_then(|data_rate| data_rate.modulation)
+        );
+        assert_eq!(868_100_000, message.frequency);
+        assert_eq!(vec![0, 0, 0, 0x10], message.gateway_uplink_token);
+
+        // LR-FHSS and unknown regions are not supported
+        let packet = uplink(DataRate::Lrfhss1bw137, Region::Eu868);
+        assert!(uplink_message(&packet, "gateway", now, now).is_none());
+        let packet = uplink(DataRate::Sf9bw125, Region::Unknown);
+        assert!(uplink_message(&packet, "gateway", now, now).is_none());
+    }
+}
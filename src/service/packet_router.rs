@@ -1,6 +1,10 @@
 use crate::{
     impl_sign,
-    service::conduit::{ConduitClient, ConduitService},
+    packet::PayloadHash,
+    service::{
+        backhaul::Backhaul,
+        conduit::{ConduitClient, ConduitService},
+    },
     DecodeError, Error, Keypair, PublicKey, Result, Sign,
 };
 use helium_proto::{
@@ -20,19 +24,19 @@ use std::{
 };
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
-use tonic::async_trait;
 
 // The router service maintains a re-connectable connection to a remote packet
 // router. The service will connect when (re)connect or a packet send is
 // attempted. It will ensure that the register rpc is called on the constructed
 // connection before a packet is sent.
-pub struct PacketRouterService(
-    ConduitService<EnvelopeUpV1, EnvelopeDownV1, PacketRouterConduitClient>,
-);
+pub struct PacketRouterService {
+    conduit: ConduitService<EnvelopeUpV1, EnvelopeDownV1, PacketRouterConduitClient>,
+    // Hash function for payload hashes in an established session
+    payload_hash: PayloadHash,
+}
 
 pub struct PacketRouterConduitClient {}
 
-#[async_trait]
 impl ConduitClient<EnvelopeUpV1, EnvelopeDownV1> for PacketRouterConduitClient {
     async fn init(
         &mut self,
@@ -87,20 +91,36 @@ impl_sign!(PacketRouterSessionInitV1);
 impl std::ops::Deref for PacketRouterService {
     type Target = ConduitService<EnvelopeUpV1, EnvelopeDownV1, PacketRouterConduitClient>;
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.conduit
     }
 }
 
 impl std::ops::DerefMut for PacketRouterService {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.conduit
     }
 }
 
 impl PacketRouterService {
-    pub fn new(uri: Uri, keypair: Arc<Keypair>) -> Self {
+    pub fn new(
+        uri: Uri,
+        keypair: Arc<Keypair>,
+        payload_hash: PayloadHash,
+        backhaul: Backhaul,
+    ) -> Self {
         let client = PacketRouterConduitClient {};
-        Self(ConduitService::new("packet_router", uri, client, keypair))
+        Self {
+            conduit: ConduitService::new("packet_router", uri, client, keypair, backhaul),
+            payload_hash,
+        }
+    }
+
+    /// The payload hash function of the established session, or None without
+    /// a session. Session offers do not name a hash function, so a session
+    /// uses the one configured for the router, which has to match the one the
+    /// router expects.
+    pub fn session_payload_hash(&self) -> Option<PayloadHash> {
+        self.session_key().map(|_| self.payload_hash)
     }
 
     pub async fn send_uplink(&mut self, mut msg: PacketRouterPacketUpV1) -> Result {
@@ -108,11 +128,11 @@ impl PacketRouterService {
         let msg = EnvelopeUpV1 {
             data: Some(envelope_up_v1::Data::Packet(msg)),
         };
-        self.0.send(msg).await
+        self.conduit.send(msg).await
     }
 
     pub async fn recv(&mut self) -> Result<envelope_down_v1::Data> {
-        self.0.recv().await.and_then(|msg| match msg.data {
+        self.conduit.recv().await.and_then(|msg| match msg.data {
             Some(data) => Ok(data),
             None => Err(DecodeError::invalid_envelope()),
         })
@@ -1,6 +1,9 @@
 use crate::{
     impl_sign,
-    service::conduit::{ConduitClient, ConduitService},
+    service::{
+        backhaul::Backhaul,
+        conduit::{ConduitClient, ConduitService},
+    },
     DecodeError, Keypair, PublicKey, Result, Sign,
 };
 use helium_proto::{
@@ -17,7 +20,6 @@ use http::Uri;
 use std::sync::Arc;
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
-use tonic::async_trait;
 
 // The poc service maintains a re-connectable connection to a remote poc
 // ingester. The service will (re)connect when a poc report send is attempted.
@@ -29,7 +31,6 @@ pub struct PocIotService(
 
 pub struct PocIotConduitClient {}
 
-#[async_trait]
 impl ConduitClient<LoraStreamRequestV1, LoraStreamResponseV1> for PocIotConduitClient {
     async fn init(
         &mut self,
@@ -81,9 +82,9 @@ impl std::ops::DerefMut for PocIotService {
 }
 
 impl PocIotService {
-    pub fn new(module: &'static str, uri: Uri, keypair: Arc<Keypair>) -> Self {
+    pub fn new(module: &'static str, uri: Uri, keypair: Arc<Keypair>, backhaul: Backhaul) -> Self {
         let client = PocIotConduitClient {};
-        Self(ConduitService::new(module, uri, client, keypair))
+        Self(ConduitService::new(module, uri, client, keypair, backhaul))
     }
 
     pub async fn send(&mut self, msg: lora_stream_request_v1::Request) -> Result {
@@ -0,0 +1,155 @@
+//! Metadata of the last sessions with the packet router and the PoC ingest.
+//!
+//! Disconnect patterns are often reported over days, long after the logs of
+//! the disconnects are gone. The last established session of every service is
+//! kept in a small file in the data directory with the time it was
+//! established and when and why it was closed, and can be fetched through the
+//! local API for a postmortem.
+
+use crate::{state_file, Base64, PublicKey, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
+use tracing::warn;
+
+/// Name of the file with the session records in the data directory
+const SESSIONS_FILE: &str = "sessions.json";
+
+/// The last session with a service
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionRecord {
+    /// The module the session belongs to, like packet_router or beaconer
+    pub module: String,
+    /// The uri of the service
+    pub uri: String,
+    /// Base64 encoded SHA256 hash of the session public key
+    pub session_key_hash: String,
+    /// Unix time in seconds the session was established
+    pub established: i64,
+    /// Unix time in seconds the session was closed, if closed
+    #[serde(default)]
+    pub closed: Option<i64>,
+    /// Why the session was closed, if closed
+    #[serde(default)]
+    pub close_reason: Option<String>,
+}
+
+/// Reads the session records from the given data directory. A missing or
+/// invalid file has no records.
+pub fn load(data_dir: &Path) -> Vec<SessionRecord> {
+    let path = sessions_path(data_dir);
+    let Ok(data) = std::fs::read(&path) else {
+        return vec![];
+    };
+    serde_json::from_slice(&data)
+        .map_err(|err| warn!(path = %path.display(), %err, "ignoring invalid session records"))
+        .unwrap_or_default()
+}
+
+fn sessions_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(SESSIONS_FILE)
+}
+
+/// Records the sessions of one service in the data directory
+#[derive(Debug, Clone)]
+pub struct SessionLog {
+    data_dir: PathBuf,
+    module: &'static str,
+    uri: String,
+}
+
+impl SessionLog {
+    pub fn new(data_dir: &Path, module: &'static str, uri: String) -> Self {
+        Self {
+            data_dir: data_dir.to_path_buf(),
+            module,
+            uri,
+        }
+    }
+
+    /// Records a newly established session, replacing the last session of
+    /// the service
+    pub fn established(&self, session_key: &PublicKey) {
+        let record = SessionRecord {
+            module: self.module.to_string(),
+            uri: self.uri.clone(),
+            session_key_hash: Sha256::digest(&session_key.to_vec()).to_vec().to_b64(),
+            established: OffsetDateTime::now_utc().unix_timestamp(),
+            closed: None,
+            close_reason: None,
+        };
+        self.update(|records| {
+            records.retain(|existing| !self.is_service(existing));
+            records.push(record);
+        });
+    }
+
+    /// Records the close of the last session of the service, if still open
+    pub fn closed(&self, reason: &str) {
+        self.update(|records| {
+            let open = records
+                .iter_mut()
+                .find(|record| self.is_service(record) && record.closed.is_none());
+            if let Some(record) = open {
+                record.closed = Some(OffsetDateTime::now_utc().unix_timestamp());
+                record.close_reason = Some(reason.to_string());
+            }
+        });
+    }
+
+    fn is_service(&self, record: &SessionRecord) -> bool {
+        record.module == self.module && record.uri == self.uri
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Vec<SessionRecord>)) {
+        let mut records = load(&self.data_dir);
+        f(&mut records);
+        if let Err(err) = self.store(&records) {
+            warn!(module = self.module, %err, "failed to store session records");
+        }
+    }
+
+    fn store(&self, records: &[SessionRecord]) -> Result {
+        state_file::write_json(&sessions_path(&self.data_dir), records)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn session_records() {
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        let data_dir = temp_dir.path();
+        let session_key =
+            PublicKey::from_str("137oJzq1qZpSbzHawaysTGGsRCYTXG1MiTMQNxYSsQJp4YMDdN8").unwrap();
+        let router = SessionLog::new(data_dir, "packet_router", "http://router".to_string());
+        let ingest = SessionLog::new(data_dir, "beaconer", "http://ingest".to_string());
+
+        router.established(&session_key);
+        ingest.established(&session_key);
+        router.closed("stream closed");
+        // Only an open session is closed
+        router.closed("disconnect");
+        let records = load(data_dir);
+        assert_eq!(2, records.len());
+        assert_eq!(Some("stream closed"), records[0].close_reason.as_deref());
+        assert!(records[0].closed.is_some());
+        assert_eq!(None, records[1].closed);
+
+        // A new session replaces the last one of the service
+        router.established(&session_key);
+        let records = load(data_dir);
+        assert_eq!(
+            vec!["beaconer", "packet_router"],
+            records
+                .iter()
+                .map(|record| record.module.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(None, records[1].close_reason);
+    }
+}
@@ -1,8 +1,41 @@
-use crate::{api::GatewayStakingMode, KeyedUri, Keypair, PublicKey, Region, Result};
-use config::{Config, Environment, File};
+#[cfg(feature = "remote-config")]
+use crate::remote_config;
+use crate::{
+    api::GatewayStakingMode,
+    keyed_uri::KeyedUris,
+    matcher::{DevAddrSubnet, JoinEuiPrefix},
+    packet::PayloadHash,
+    qos,
+    secret::Secret,
+    Keypair, PublicKey, Region, Result,
+};
+use config::{Config, File, FileFormat};
 use http::uri::Uri;
+use lorawan::subnet::NetId;
+use rust_decimal::{prelude::ToPrimitive, Decimal};
 use serde::Deserialize;
-use std::{fmt, path::Path, str::FromStr, sync::Arc};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+use tracing::warn;
+
+mod env;
+mod legacy;
+pub use env::EnvOverride;
+pub use legacy::DeprecatedSetting;
+
+/// The default settings file shipped with the gateway
+const DEFAULT_SETTINGS: &str = include_str!("../config/settings.toml");
+
+/// Overrides of the default settings for local development
+const DEV_SETTINGS: &str = r#"
+keypair = "gateway_key.bin"
+data_dir = "."
+region = "US915"
+"#;
 
 pub fn version() -> semver::Version {
     semver::Version::parse(env!("CARGO_PKG_VERSION")).expect("unable to parse version")
@@ -12,16 +45,64 @@ pub fn version() -> semver::Version {
 #[derive(Debug, Deserialize)]
 pub struct Settings {
     /// The listen address to use for listening for the semtech UDP packet forwarder.
-    /// Default "127.0.0.1:1680"
-    #[serde(default = "default_listen")]
-    pub listen: String,
+    /// A list of addresses serves packet forwarders on several ports or
+    /// interfaces at once. Default "127.0.0.1:1680"
+    #[serde(default = "default_listen", deserialize_with = "deserialize_listen")]
+    pub listen: Vec<String>,
+    /// The name of a network interface to bind the semtech UDP listener to.
+    /// When set, a wildcard listen address is bound to the current address of
+    /// this interface and bound again when that address changes, see
+    /// `listen_idle_timeout`.
+    #[serde(default)]
+    pub listen_interface: Option<String>,
+    /// Seconds without any traffic from the packet forwarder after which the
+    /// UDP listener checks its socket and binds it again if it is dead, to
+    /// recover without a restart of the service. A value of 0 disables this.
+    /// Default 0.
+    #[serde(default)]
+    pub listen_idle_timeout: u64,
+    /// How downlinks are routed when several packet forwarders are connected.
+    /// Defaults to "uplink".
+    #[serde(default)]
+    pub downlink_routing: DownlinkRouting,
+    /// The rx2 window to also offer downlinks in that the packet router sent
+    /// without one, for networks with a non default rx2 frequency or
+    /// datarate. Not set by default.
+    #[serde(default)]
+    pub rx2: Option<Rx2Settings>,
+    /// Handling of packets when the gateway can not keep up with the packet
+    /// forwarders.
+    #[serde(default)]
+    pub backpressure: BackpressureSettings,
+    /// Detection of packet forwarders that went silent.
+    #[serde(default)]
+    pub forwarder_watchdog: ForwarderWatchdogSettings,
+    /// Deduplication of uplinks heard by several packet forwarders.
+    #[serde(default)]
+    pub uplink_dedup: UplinkDedupSettings,
     /// The listening network port for the grpc / jsonrpc API.
     /// Default 4467
     #[serde(default = "default_api")]
     pub api: ListenAddress,
+    /// Credentials required for local API requests that change the gateway.
+    /// Not required by default.
+    #[serde(default)]
+    pub api_auth: ApiAuthSettings,
+    /// Whether local API requests that change the gateway are recorded in a
+    /// signed audit log in the data directory. Defaults to false.
+    #[serde(default)]
+    pub audit_log: bool,
+    /// The directory to keep gateway state in that needs to survive restarts.
+    /// Default "/etc/helium_gateway"
+    #[serde(default = "default_data_dir")]
+    pub data_dir: PathBuf,
     /// The location of the keypair binary file for the gateway. If the keyfile
     /// is not found there a new one is generated and saved in that location.
     pub keypair: Arc<Keypair>,
+    /// What the service does when the startup sign and verify self test of
+    /// the keypair fails. Defaults to required.
+    #[serde(default)]
+    pub keypair_self_test: KeypairSelfTest,
     /// The location of the onboarding keypair binary file for the gateway. If
     /// the keyfile is not found there a new one is generated and saved in that
     /// location.
@@ -32,24 +113,233 @@ pub struct Settings {
     /// asserted location/region is fetched.
     #[serde(default)]
     pub region: Region,
+    /// File with the protobuf encoded `BlockchainRegionParamsV1` parameters of
+    /// the configured region. When set the region parameters are loaded from
+    /// this file instead of the config service, for example to operate on a
+    /// private network. Requires the region to be set.
+    #[serde(default)]
+    pub region_params_file: Option<PathBuf>,
+    /// Antenna gain in tenths of dBi to use with the region parameters file.
+    /// Default 0
+    #[serde(default)]
+    pub region_params_gain: u64,
+    /// Antenna gain in tenths of dBi of the installed antenna. When set this
+    /// replaces the gain of the region parameters from the config service or
+    /// the region params file in transmit power calculations.
+    #[serde(default)]
+    pub antenna_gain: Option<u64>,
+    /// Maximum EIRP per frequency range, for channels that need a lower
+    /// transmit power than the region allows with the installed antenna.
+    /// Limits are capped at the maximum EIRP of the region. None by default.
+    #[serde(default, deserialize_with = "deserialize_tx_power_limits")]
+    pub tx_power_limits: Vec<TxPowerLimit>,
+    /// Elevation in meters of the antenna above ground level, for reference
+    /// in status output. Not set by default.
+    #[serde(default)]
+    pub elevation: Option<i32>,
+    /// Region detection from the GPS position of mobile gateways. Disabled by
+    /// default.
+    #[serde(default)]
+    pub gps_region: GpsRegionSettings,
+    /// GPS position sources besides the packet forwarders. None by default.
+    #[serde(default)]
+    pub gps: GpsSettings,
+    /// Prometheus metrics of the packet forwarders and the gateway. Disabled
+    /// by default.
+    #[serde(default)]
+    pub metrics: MetricsSettings,
     /// Log settings
     pub log: LogSettings,
-    /// The config service to use for region and other config settings
-    pub config: KeyedUri,
+    /// The config service to use for region and other config settings. This is
+    /// either a single keyed uri or a list of keyed uris, in which case the
+    /// next entry is tried when a request to the current one fails.
+    pub config: KeyedUris,
     /// The packet router to deliver all packets when packet router is active.
     pub router: RouterSettings,
+    /// Additional packet routers to deliver all uplinks to. Each router has
+    /// its own session and queue, which allows for example validating a new
+    /// router before cutting over to it.
+    #[serde(default)]
+    pub secondary_routers: Vec<RouterSettings>,
+    /// MQTT broker to publish uplinks to and receive downlinks from, in
+    /// addition to the packet routers. Disabled when not set.
+    #[serde(default)]
+    pub mqtt: Option<MqttSettings>,
+    /// Packet Broker endpoint to publish uplinks to for delivery to peered
+    /// LoRaWAN networks, in addition to the packet routers. Disabled when not
+    /// set.
+    #[serde(default)]
+    pub packet_broker: Option<PacketBrokerSettings>,
+    /// Signed configuration to periodically fetch from a remote endpoint and
+    /// apply on top of the settings file. Disabled when not set.
+    #[serde(default)]
+    pub remote_config: Option<RemoteConfigSettings>,
+    /// Onboarding server `helium_gateway add --submit` posts add gateway
+    /// transactions to. Not set by default.
+    #[serde(default)]
+    pub onboarding_server: Option<OnboardingServerSettings>,
     /// Proof-of-coverage (PoC) settings.
     pub poc: PocSettings,
+    /// Transmit duty cycle accounting settings.
+    #[serde(default)]
+    pub duty_cycle: DutyCycleSettings,
+    /// Device vendor lookup for the join EUI of join requests.
+    #[serde(default)]
+    pub join_vendors: JoinVendorSettings,
+    /// Frame ports permitted for uplinks per device address subnet. Uplinks
+    /// from a device address in a filtered subnet on any other port are
+    /// dropped. The first filter whose subnet contains the device address
+    /// applies.
+    #[serde(default)]
+    pub fport_filters: Vec<FportFilterSettings>,
+    /// NetIDs, device address subnets and join EUI prefixes of the networks
+    /// whose uplinks are forwarded. Not filtered by default.
+    #[serde(default)]
+    pub uplink_filter: UplinkFilterSettings,
+    /// Gateway to gateway ping settings.
+    #[serde(default)]
+    pub ping: PingSettings,
+    /// Network quality of service settings.
+    #[serde(default)]
+    pub network: NetworkSettings,
+    /// Hooks to run on service state changes, for example to drive status
+    /// LEDs.
+    #[serde(default)]
+    pub hooks: Vec<HookSettings>,
+    /// Legacy settings keys found in the loaded settings. These are mapped to
+    /// their current equivalent where possible and reported at startup.
+    #[serde(skip)]
+    pub deprecated: Vec<DeprecatedSetting>,
+    /// Settings keys set by `GW_` environment variables, reported at startup
+    #[serde(skip)]
+    pub env_overrides: Vec<EnvOverride>,
+    /// Where the settings were loaded from, to load them again on a reload
+    #[serde(skip)]
+    pub source: SettingsSource,
+}
+
+/// The settings that can be changed in a running gateway without a restart.
+/// These are loaded again from the [`SettingsSource`] of the running gateway
+/// on a reload.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReloadableSettings {
+    #[serde(default = "default_listen", deserialize_with = "deserialize_listen")]
+    pub listen: Vec<String>,
+    #[serde(default)]
+    pub listen_interface: Option<String>,
+    pub log: LogSettings,
+    pub poc: PocSettings,
+    pub router: RouterSettings,
+    #[serde(default)]
+    pub secondary_routers: Vec<RouterSettings>,
+}
+
+impl From<&Settings> for ReloadableSettings {
+    fn from(settings: &Settings) -> Self {
+        Self {
+            listen: settings.listen.clone(),
+            listen_interface: settings.listen_interface.clone(),
+            log: settings.log.clone(),
+            poc: settings.poc.clone(),
+            router: settings.router.clone(),
+            secondary_routers: settings.secondary_routers.clone(),
+        }
+    }
+}
+
+/// The settings file and defaults settings were loaded from
+#[derive(Debug, Clone, Default)]
+pub struct SettingsSource {
+    /// The settings file
+    pub path: PathBuf,
+    /// Whether the local development defaults were applied
+    pub dev: bool,
+}
+
+impl SettingsSource {
+    /// The keypair setting as configured, before the keypair is loaded
+    pub fn keypair_uri(&self) -> Result<String> {
+        let (config, _, _) = self.config()?;
+        Ok(config.get_string("keypair")?)
+    }
+
+    /// Loads the settings that can be changed without a restart again
+    pub fn reload(&self) -> Result<ReloadableSettings> {
+        let (config, _, env_overrides) = self.config()?;
+        config
+            .try_deserialize()
+            .map_err(|err| env::annotate(err, &env_overrides))
+    }
+
+    /// Builds the configuration from the default settings, if any, the
+    /// settings file and environment overrides. Returns the configuration
+    /// with the legacy keys it contained and the keys set from the
+    /// environment.
+    fn config(&self) -> Result<(Config, Vec<DeprecatedSetting>, Vec<EnvOverride>)> {
+        let mut builder = Config::builder();
+        if self.dev {
+            builder = builder
+                .add_source(File::from_str(DEFAULT_SETTINGS, FileFormat::Toml))
+                .add_source(File::from_str(DEV_SETTINGS, FileFormat::Toml));
+        }
+        // Source settings file
+        builder = builder
+            .add_source(File::with_name(self.path.to_str().expect("file name")).required(false));
+        // Add in settings from the environment, for example `GW_LOG_LEVEL=debug`
+        // sets the `level` key in `[log]`
+        let vars = std::env::vars_os().filter_map(|(var, value)| {
+            let var = var.into_string().ok()?;
+            match value.into_string() {
+                Ok(value) => Some((var, value)),
+                Err(_) => {
+                    warn!(var, "ignoring environment override with non unicode value");
+                    None
+                }
+            }
+        });
+        let mut env_overrides = vec![];
+        for (env_override, value) in env::overrides(vars) {
+            builder = builder.set_override(&env_override.key, value)?;
+            env_overrides.push(env_override);
+        }
+        // Add the stored remote configuration on top of the settings file.
+        // Environment overrides still take precedence.
+        #[cfg(feature = "remote-config")]
+        let config = builder.build_cloned()?;
+        #[cfg(feature = "remote-config")]
+        if config.get_string("remote_config.uri").is_ok() {
+            let data_dir = config
+                .get_string("data_dir")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| default_data_dir());
+            builder = builder.add_source(
+                File::from(remote_config::stored_path(&data_dir))
+                    .format(FileFormat::Json)
+                    .required(false),
+            );
+        }
+        // Map legacy keys of older settings files before deserializing
+        let (deprecated, overrides) = legacy::check(&builder.build_cloned()?);
+        for (key, value) in overrides {
+            builder = builder.set_override(key, value)?;
+        }
+        Ok((builder.build()?, deprecated, env_overrides))
+    }
 }
 
 /// Settings for log method and level to be used by the running service.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct LogSettings {
     /// Log level to show (default info)
     pub level: log_level::Level,
 
     /// Whehter to show timestamps in the stdio output stream (default false)
     pub timestamp: bool,
+
+    /// Number of most recent log events kept in memory for the local API. A
+    /// value of 0 disables the buffer. Defaults to 500.
+    #[serde(default = "default_log_buffer")]
+    pub buffer: u16,
 }
 
 impl LogSettings {
@@ -85,6 +375,16 @@ pub struct PocSettings {
     /// Entropy URL.
     #[serde(with = "http_serde::uri")]
     pub entropy_uri: Uri,
+    /// Entropy URLs tried in order when the entropy URL fails. Defaults to
+    /// none.
+    #[serde(default, deserialize_with = "deserialize_uris")]
+    pub entropy_fallback_uris: Vec<Uri>,
+    /// Maximum age in seconds of the last fetched entropy to use for a beacon
+    /// when all entropy services fail. The entropy is also fetched shortly
+    /// before every beacon to keep it recent. A value of 0 disables the
+    /// cached entropy. Defaults to 300.
+    #[serde(default = "default_poc_entropy_max_age")]
+    pub entropy_max_age: u64,
     /// Remote ingestor URL.
     #[serde(with = "http_serde::uri")]
     pub ingest_uri: Uri,
@@ -93,6 +393,540 @@ pub struct PocSettings {
     /// increase rewards
     #[serde(default = "default_poc_interval")]
     pub interval: u64,
+    /// Number of consecutive failed beacon transmissions after which beaconing
+    /// is suppressed for `beacon_tx_cooldown` seconds. A value of 0 disables
+    /// suppression. Defaults to 3.
+    #[serde(default = "default_beacon_tx_failures")]
+    pub beacon_tx_failures: u32,
+    /// Time in seconds to suppress beaconing for after repeated transmit
+    /// failures. Defaults to 24 hours.
+    #[serde(default = "default_beacon_tx_cooldown")]
+    pub beacon_tx_cooldown: u64,
+    /// Conducted beacon transmit power in dBm to use instead of the regional
+    /// maximum, for installations that must beacon at reduced power. The
+    /// override is capped at the regional maximum.
+    #[serde(default)]
+    pub tx_power_override: Option<u32>,
+    /// Number of days of daily beacon and witness rollups to keep in the data
+    /// directory. A value of 0 disables the history. Defaults to 30.
+    #[serde(default = "default_poc_history_days")]
+    pub history_days: u16,
+    /// Number of most recent beacons to keep in the data directory. A value
+    /// of 0 disables the beacon history. Defaults to 100.
+    #[serde(default = "default_poc_beacon_history")]
+    pub beacon_history: u16,
+    /// Maximum number of witness reports submitted per hour. Witnesses beyond
+    /// this are dropped. A value of 0 disables the limit. Defaults to 0.
+    #[serde(default)]
+    pub witness_max_per_hour: u32,
+    /// Number of recently seen beacons kept to drop duplicate witnesses of
+    /// the same beacon. Defaults to 15.
+    #[serde(default = "default_poc_witness_dedup_size")]
+    pub witness_dedup_size: u16,
+    /// Time in seconds a seen beacon suppresses duplicate witnesses. A value
+    /// of 0 keeps seen beacons until they are evicted by newer ones. Defaults
+    /// to 0.
+    #[serde(default)]
+    pub witness_dedup_ttl: u64,
+    /// The datarate policy for beacons. Defaults to the datarate selected by
+    /// the beacon rules of the region.
+    #[serde(default)]
+    pub beacon_datarate: BeaconDatarate,
+    /// Hardware random number generator devices, like /dev/hwrng, to mix into
+    /// the local entropy of beacons in addition to OS randomness. Sources that
+    /// fail their health checks are skipped. Defaults to none.
+    #[serde(default)]
+    pub entropy_devices: Vec<PathBuf>,
+    /// Drop in percent of the witnesses of the last day relative to the
+    /// average of the baseline days before it that raises a witness alert. A
+    /// value of 0 disables witness alerts. Defaults to 75.
+    #[serde(default = "default_poc_witness_alert_drop")]
+    pub witness_alert_drop: u8,
+    /// Number of days the witness baseline is averaged over. Defaults to 7.
+    #[serde(default = "default_poc_witness_alert_days")]
+    pub witness_alert_days: u16,
+    /// Lowest average of daily witnesses over the baseline days for which
+    /// alerts are raised, to avoid alerts for gateways that rarely witness
+    /// anyway. Defaults to 10.
+    #[serde(default = "default_poc_witness_alert_min")]
+    pub witness_alert_min: u32,
+    /// Url a witness alert is posted to when it is raised or cleared.
+    /// Defaults to none.
+    #[serde(default, deserialize_with = "deserialize_optional_uri")]
+    pub witness_alert_webhook: Option<Uri>,
+    /// Maximum number of failed witness reports kept in the data directory
+    /// to be retried. A value of 0 drops failed witness reports. Defaults to
+    /// 100.
+    #[serde(default = "default_poc_witness_queue")]
+    pub witness_queue: u16,
+    /// Maximum number of queued witness reports submitted per retry.
+    /// Defaults to 10.
+    #[serde(default = "default_poc_witness_queue_batch")]
+    pub witness_queue_batch: u16,
+    /// Time in seconds a failed witness report is retried for before it is
+    /// dropped. Defaults to 1 hour.
+    #[serde(default = "default_poc_witness_queue_max_age")]
+    pub witness_queue_max_age: u64,
+}
+
+/// Policy for the datarate beacons are transmitted at.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BeaconDatarate {
+    /// The slowest datarate of the region spreading table that fits the beacon
+    /// payload, as expected by beacon verifiers
+    #[default]
+    Default,
+    /// The fastest datarate of the region spreading table that fits the beacon
+    /// payload, which reduces airtime and duty-cycle use
+    Fastest,
+}
+
+/// Policy for the packet forwarder downlinks are sent to.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DownlinkRouting {
+    /// The forwarder that received the uplink the downlink responds to,
+    /// falling back to the most recently connected forwarder
+    #[default]
+    Uplink,
+    /// The most recently connected forwarder
+    Latest,
+}
+
+/// The rx2 window of downlinks the packet router sent without one
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct Rx2Settings {
+    /// Frequency in Hz of the rx2 window
+    pub frequency: u32,
+    /// Datarate of the rx2 window, for example "SF12BW125"
+    #[serde(deserialize_with = "deserialize_datarate")]
+    pub datarate: helium_proto::DataRate,
+}
+
+/// Backpressure settings for packets received from packet forwarders.
+#[derive(Debug, Deserialize, Clone)]
+pub struct BackpressureSettings {
+    /// Number of packets queued across all packet forwarders at which the
+    /// gateway reports a saturated state. A value of 0 disables saturation
+    /// tracking. Defaults to 128.
+    #[serde(default = "default_backpressure_threshold")]
+    pub threshold: usize,
+    /// Whether uplinks other than join requests are dropped while the gateway
+    /// is saturated, to preserve join latency during overload. Defaults to
+    /// false.
+    #[serde(default)]
+    pub drop_non_join: bool,
+}
+
+impl Default for BackpressureSettings {
+    fn default() -> Self {
+        Self {
+            threshold: default_backpressure_threshold(),
+            drop_non_join: false,
+        }
+    }
+}
+
+/// Handling of the startup self test of the gateway keypair
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum KeypairSelfTest {
+    /// The service does not start when the self test fails
+    #[default]
+    Required,
+    /// The service starts and reports the failed self test in its status
+    Degraded,
+    /// No self test is run
+    Off,
+}
+
+/// Settings of the packet forwarder watchdog
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ForwarderWatchdogSettings {
+    /// Seconds without a poll, packet or stat from a packet forwarder after
+    /// which it is reported as silent. 0 disables the watchdog. Defaults to 0.
+    #[serde(default)]
+    pub silence: u64,
+}
+
+/// Settings of the deduplication of uplinks heard by several packet
+/// forwarders
+#[derive(Debug, Deserialize, Clone)]
+pub struct UplinkDedupSettings {
+    /// Milliseconds an uplink is held for copies from other packet forwarders
+    /// while more than one is connected. 0 disables deduplication. Defaults
+    /// to 200.
+    #[serde(default = "default_uplink_dedup_window")]
+    pub window: u64,
+}
+
+impl Default for UplinkDedupSettings {
+    fn default() -> Self {
+        Self {
+            window: default_uplink_dedup_window(),
+        }
+    }
+}
+
+/// Settings for gateway to gateway pings, used by owners to test the RF link
+/// between their own gateways.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct PingSettings {
+    /// Whether pings can be sent and received pings addressed to this gateway
+    /// are recorded. Defaults to false.
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+/// DSCP marking of gateway traffic, so QoS policies on constrained uplinks can
+/// prioritize radio traffic over backhaul traffic. A DSCP value of 0 leaves
+/// the traffic unmarked.
+///
+/// HTTP/2 keepalive pings on the long lived packet router and poc
+/// connections, which detect streams dropped by middleboxes that keep the tcp
+/// connection alive.
+#[derive(Debug, Deserialize, Clone)]
+pub struct NetworkSettings {
+    /// DSCP value (0-63) for semtech udp traffic to the packet forwarder,
+    /// which includes downlink transmissions. Linux only. Defaults to 0.
+    #[serde(default)]
+    pub radio_dscp: u8,
+    /// DSCP value (0-63) for gRPC connections to the packet router, config,
+    /// entropy and poc services. Defaults to 0.
+    #[serde(default)]
+    pub backhaul_dscp: u8,
+    /// Seconds between HTTP/2 keepalive pings on packet router and poc
+    /// connections. A value of 0 disables pings. Defaults to 0.
+    #[serde(default)]
+    pub http2_keepalive_interval: u64,
+    /// Seconds to wait for the acknowledgement of an HTTP/2 keepalive ping
+    /// before the connection is considered dead. Defaults to 20.
+    #[serde(default = "default_http2_keepalive_timeout")]
+    pub http2_keepalive_timeout: u64,
+    /// The name of a network interface to bind gRPC connections to the
+    /// packet router, config, entropy and poc services to. Linux only. Not
+    /// set by default.
+    #[serde(default)]
+    pub backhaul_interface: Option<String>,
+}
+
+impl NetworkSettings {
+    /// Checks the DSCP values and the HTTP/2 keepalive timeout
+    pub fn validate(&self) -> Result {
+        qos::check_dscp(self.radio_dscp)?;
+        qos::check_dscp(self.backhaul_dscp)?;
+        if self.http2_keepalive_interval > 0 && self.http2_keepalive_timeout == 0 {
+            return Err(crate::Error::custom(
+                "http2 keepalive timeout must be greater than 0",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        Self {
+            radio_dscp: 0,
+            backhaul_dscp: 0,
+            http2_keepalive_interval: 0,
+            http2_keepalive_timeout: default_http2_keepalive_timeout(),
+            backhaul_interface: None,
+        }
+    }
+}
+
+/// Settings for the authentication of mutating local API requests
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ApiAuthSettings {
+    /// Token to accept as bearer token
+    #[serde(default)]
+    pub token: Option<Secret>,
+    /// Public keys to accept request signatures of, in addition to the
+    /// gateway key
+    #[serde(default)]
+    pub keys: Vec<PublicKey>,
+}
+
+/// Settings for the device vendor lookup of join requests
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct JoinVendorSettings {
+    /// Whether join requests are annotated with the likely device vendor.
+    /// Defaults to false.
+    #[serde(default)]
+    pub enabled: bool,
+    /// File with join EUI prefixes and vendors in addition to the built in
+    /// ones
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+}
+
+/// Settings for the transmit duty cycle limits of the region
+#[derive(Debug, Deserialize, Clone)]
+pub struct DutyCycleSettings {
+    /// Whether downlinks, beacons and pings that would exceed the duty cycle
+    /// limit of their sub-band are rejected. Utilization is tracked either
+    /// way. Defaults to true.
+    #[serde(default = "default_duty_cycle_enabled")]
+    pub enabled: bool,
+    /// Length in seconds of the sliding window the duty cycle is accounted
+    /// over. Defaults to 3600.
+    #[serde(default = "default_duty_cycle_window")]
+    pub window: u64,
+}
+
+impl Default for DutyCycleSettings {
+    fn default() -> Self {
+        Self {
+            enabled: default_duty_cycle_enabled(),
+            window: default_duty_cycle_window(),
+        }
+    }
+}
+
+/// A service state that hooks can be run for
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HookState {
+    /// A packet forwarder is connected
+    Forwarder,
+    /// A session with the packet router is established
+    Router,
+    /// A session with the poc ingester is established and the last beacon
+    /// was transmitted
+    Poc,
+    /// No packet forwarder is silent for longer than the watchdog silence
+    /// period
+    #[serde(rename = "forwarder_activity")]
+    ForwarderActivity,
+}
+
+impl fmt::Display for HookState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Forwarder => "forwarder",
+            Self::Router => "router",
+            Self::Poc => "poc",
+            Self::ForwarderActivity => "forwarder_activity",
+        };
+        f.write_str(s)
+    }
+}
+
+/// A hook run on changes of a service state. The command is executed with the
+/// state name and "up" or "down" as arguments. The up or down value is written
+/// to the path.
+#[derive(Debug, Deserialize, Clone)]
+pub struct HookSettings {
+    pub state: HookState,
+    #[serde(default)]
+    pub command: Option<PathBuf>,
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    /// Value written to the path when the state is up. Defaults to "1".
+    #[serde(default = "default_hook_up_value")]
+    pub up_value: String,
+    /// Value written to the path when the state is down. Defaults to "0".
+    #[serde(default = "default_hook_down_value")]
+    pub down_value: String,
+}
+
+/// Frame ports permitted for uplinks from a device address subnet. Frames
+/// without a port or on port 0 only carry MAC commands and are always
+/// permitted.
+#[derive(Debug, Deserialize, Clone)]
+pub struct FportFilterSettings {
+    pub subnet: DevAddrSubnet,
+    pub fports: Vec<u8>,
+}
+
+/// Allowlist of the networks whose uplinks are forwarded. Data frames are
+/// only forwarded if their device address matches one of the NetIDs or
+/// subnets, and join requests only if their join EUI matches one of the
+/// prefixes. Without NetIDs and subnets data frames are not filtered, and
+/// without prefixes join requests are not filtered.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct UplinkFilterSettings {
+    /// NetIDs, in hex, whose data uplinks are forwarded
+    #[serde(default, deserialize_with = "deserialize_net_ids")]
+    pub net_ids: Vec<NetId>,
+    /// Device address subnets whose data uplinks are forwarded
+    #[serde(default)]
+    pub devaddrs: Vec<DevAddrSubnet>,
+    /// Join EUI prefixes whose join requests are forwarded
+    #[serde(default)]
+    pub join_euis: Vec<JoinEuiPrefix>,
+}
+
+/// Settings for reading the GPS position of the gateway
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct GpsSettings {
+    /// Address of a local gpsd to read the GPS fix from, for example
+    /// "127.0.0.1:2947". Not set by default.
+    #[serde(default)]
+    pub gpsd: Option<String>,
+}
+
+/// Settings for serving packet forwarder metrics to Prometheus
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct MetricsSettings {
+    /// Address to serve metrics on at `/metrics`, for example
+    /// "127.0.0.1:9100". Not set by default.
+    #[serde(default)]
+    pub listen: Option<std::net::SocketAddr>,
+}
+
+/// Settings for selecting the region from the GPS position reported by the
+/// packet forwarder, for gateways on ships or vehicles
+#[derive(Debug, Deserialize, Clone)]
+pub struct GpsRegionSettings {
+    /// Whether to select the region from the GPS position instead of the
+    /// asserted location. Ignored with a region params file. Defaults to false.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Number of consecutive positions in a new region before it is selected.
+    /// Defaults to 5.
+    #[serde(default = "default_gps_region_confirm")]
+    pub confirm: u32,
+    /// Distance in km a position has to be outside the current region and
+    /// inside a new region before the region changes. Defaults to 10.
+    #[serde(default = "default_gps_region_margin_km")]
+    pub margin_km: f64,
+    /// Areas checked before the built in region areas, for example to cover
+    /// a coastline more precisely.
+    #[serde(default)]
+    pub areas: Vec<RegionArea>,
+}
+
+impl Default for GpsRegionSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            confirm: default_gps_region_confirm(),
+            margin_km: default_gps_region_margin_km(),
+            areas: vec![],
+        }
+    }
+}
+
+/// A latitude/longitude box in decimal degrees and the region used in it
+#[derive(Debug, Deserialize, Clone)]
+pub struct RegionArea {
+    pub region: Region,
+    pub min_lat: f64,
+    pub max_lat: f64,
+    pub min_lon: f64,
+    pub max_lon: f64,
+}
+
+/// Settings for the MQTT bridge
+#[derive(Debug, Deserialize, Clone)]
+pub struct MqttSettings {
+    /// The broker uri, for example "mqtt://localhost:1883". The port defaults
+    /// to 1883.
+    #[serde(with = "http_serde::uri")]
+    pub uri: Uri,
+    /// The MQTT client id. Defaults to the gateway public key.
+    #[serde(default)]
+    pub client_id: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<Secret>,
+    /// Prefix of the topics of the gateway. Uplinks are published to
+    /// "<prefix>/<gateway>/event/up" and downlinks are received on
+    /// "<prefix>/<gateway>/command/down". Defaults to "gateway".
+    #[serde(default = "default_mqtt_topic_prefix")]
+    pub topic_prefix: String,
+    /// MQTT quality of service level (0-2) of published uplinks and the
+    /// downlink subscription. Defaults to 0.
+    #[serde(default)]
+    pub qos: u8,
+    /// Seconds between keep alive pings to the broker. Defaults to 30.
+    #[serde(default = "default_mqtt_keep_alive")]
+    pub keep_alive: u64,
+    /// Format of the published uplinks and received downlinks. Defaults to
+    /// json.
+    #[serde(default)]
+    pub format: MqttFormat,
+    /// Gateway EUI in hex as known to a ChirpStack network server. Required
+    /// for the chirpstack format.
+    #[serde(default)]
+    pub gateway_id: Option<String>,
+}
+
+/// Settings for publishing uplinks to Packet Broker
+#[derive(Debug, Deserialize, Clone)]
+pub struct PacketBrokerSettings {
+    /// The uri of the Packet Broker routing endpoint. Connections to https
+    /// uris use TLS.
+    #[serde(with = "http_serde::uri")]
+    pub uri: Uri,
+    /// NetID, in hex, the gateway forwards uplinks as
+    #[serde(deserialize_with = "deserialize_net_id")]
+    pub net_id: NetId,
+    /// Tenant of the forwarder within its NetID. Not set by default.
+    #[serde(default)]
+    pub tenant_id: Option<String>,
+    /// Cluster of the forwarder. Not set by default.
+    #[serde(default)]
+    pub cluster_id: Option<String>,
+    /// Access token sent as a bearer token with every request, only over
+    /// https. Not set by default.
+    #[serde(default)]
+    pub token: Option<Secret>,
+    /// Maximum number of uplinks queued while the endpoint can not be
+    /// reached. Defaults to 20.
+    #[serde(default = "default_packet_broker_queue")]
+    pub queue: u16,
+    /// Maximum time in seconds an uplink is held in the queue. Defaults to 5.
+    #[serde(default = "default_packet_broker_max_hold_time")]
+    pub max_hold_time: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MqttFormat {
+    /// JSON objects on "<prefix>/<gateway key>/event/up" and
+    /// "<prefix>/<gateway key>/command/down"
+    #[default]
+    Json,
+    /// ChirpStack gateway bridge protobuf messages on
+    /// "<prefix>/gateway/<gateway id>/event/up" and
+    /// "<prefix>/gateway/<gateway id>/command/down"
+    Chirpstack,
+}
+
+/// Settings for the remote configuration fetch
+#[derive(Debug, Deserialize, Clone)]
+pub struct RemoteConfigSettings {
+    /// The endpoint to fetch the signed configuration from, for example
+    /// "https://config.example.com/gateway". The gateway public key is passed
+    /// in the "gateway" query parameter.
+    #[serde(with = "http_serde::uri")]
+    pub uri: Uri,
+    /// The public key configurations must be signed with
+    pub key: PublicKey,
+    /// Seconds between fetches of the configuration. Defaults to 1 hour.
+    #[serde(default = "default_remote_config_interval")]
+    pub interval: u64,
+}
+
+/// Settings for submitting add gateway transactions to an onboarding server
+#[derive(Debug, Deserialize, Clone)]
+pub struct OnboardingServerSettings {
+    /// The endpoint add gateway transactions are posted to as JSON, for
+    /// example "https://onboarding.example.com/api/gateways"
+    #[serde(with = "http_serde::uri")]
+    pub uri: Uri,
+    /// Access token sent as a bearer token, only over https. Not set by
+    /// default.
+    #[serde(default)]
+    pub token: Option<Secret>,
+    /// Number of times a failed submission is retried. Defaults to 3.
+    #[serde(default = "default_onboarding_server_retries")]
+    pub retries: u32,
 }
 
 /// Settings for packet routing
@@ -102,24 +936,182 @@ pub struct RouterSettings {
     pub uri: Uri,
     // Maximum number of packets to queue up for the packet router
     pub queue: u16,
+    /// Whether the queue is sized from the memory available at startup
+    /// instead of `queue`. The queued packets may then take up to a tenth of
+    /// the available memory, capped at `queue_max_bytes` when set. Defaults to
+    /// false.
+    #[serde(default)]
+    pub queue_auto: bool,
+    /// Maximum total size in bytes of the queued packets. A value of 0 limits
+    /// the queue by the number of packets only. Defaults to 0.
+    #[serde(default)]
+    pub queue_max_bytes: u64,
+    /// Whether uplinks are delivered to this router. Defaults to true.
+    #[serde(default = "default_router_enabled")]
+    pub enabled: bool,
+    /// Whether downlinks from this router are transmitted. Only one router
+    /// should have downlinks enabled to avoid duplicate transmissions. Defaults
+    /// to true.
+    #[serde(default = "default_router_downlinks")]
+    pub downlinks: bool,
+    /// Seconds a queued packet is held before it is discarded. Defaults to
+    /// 60.
+    #[serde(default = "default_router_max_hold_time")]
+    pub max_hold_time: u64,
+    /// Seconds a queued join request is held before it is discarded. A value
+    /// of 0 holds join requests as long as other packets. Defaults to 0.
+    #[serde(default)]
+    pub join_max_hold_time: u64,
+    /// Whether queued packets are kept in a journal file in the data directory
+    /// so they survive restarts. Defaults to false.
+    #[serde(default)]
+    pub persist: bool,
+    /// Maximum size in bytes of the queue journal file. The journal is
+    /// compacted when it reaches this size. Defaults to 1 MiB.
+    #[serde(default = "default_router_persist_max_size")]
+    pub persist_max_size: u64,
+    /// Seconds after which the router session is renewed by reconnecting for
+    /// a new session offer. A value of 0 keeps sessions until the router
+    /// replaces them or the connection drops. Defaults to 0.
+    #[serde(default)]
+    pub session_max_age: u64,
+    /// Hash function for packet payload hashes in router sessions, "sha256"
+    /// or "blake3". This has to match the hash function the router expects.
+    /// Defaults to "sha256".
+    #[serde(default)]
+    pub payload_hash: PayloadHash,
+    /// NetIDs, in hex, whose data uplinks are delivered to this router.
+    ///
+    /// A router with any NetID, device address or join EUI rules only
+    /// receives the uplinks that match its rules. Uplinks that match the rules
+    /// of no router are delivered to the routers without rules.
+    #[serde(default, deserialize_with = "deserialize_net_ids")]
+    pub net_ids: Vec<NetId>,
+    /// Device address subnets whose data uplinks are delivered to this
+    /// router.
+    #[serde(default)]
+    pub devaddrs: Vec<DevAddrSubnet>,
+    /// Join EUI prefixes whose join requests are delivered to this router.
+    #[serde(default)]
+    pub join_euis: Vec<JoinEuiPrefix>,
+}
+
+impl RouterSettings {
+    /// Whether the router only receives uplinks matching its NetID, devaddr
+    /// or join EUI rules
+    pub fn has_routing_rules(&self) -> bool {
+        !(self.net_ids.is_empty() && self.devaddrs.is_empty() && self.join_euis.is_empty())
+    }
+
+    /// The maximum number of queued packets and, if limited, their maximum
+    /// total size in bytes. In auto mode the number of packets is not limited
+    /// beyond what the queue can hold and the size is limited by the available
+    /// memory. If the available memory can not be determined the queue falls
+    /// back to the configured number of packets.
+    pub fn queue_limits(&self) -> (u16, Option<usize>) {
+        let max_bytes = (self.queue_max_bytes > 0).then_some(self.queue_max_bytes as usize);
+        if !self.queue_auto {
+            return (self.queue, max_bytes);
+        }
+        match available_memory() {
+            Some(available) => {
+                let budget = (available / AUTO_QUEUE_MEMORY_SHARE) as usize;
+                (
+                    u16::MAX,
+                    Some(max_bytes.map_or(budget, |max| max.min(budget))),
+                )
+            }
+            None => {
+                warn!("available memory unknown, using configured queue size");
+                (self.queue, max_bytes)
+            }
+        }
+    }
+
+    /// The queue journal file of this router in the given data directory. The
+    /// file is named after the router host and port so each router has its
+    /// own journal.
+    pub fn queue_path(&self, data_dir: &Path) -> PathBuf {
+        let name: String = self
+            .uri
+            .authority()
+            .map(|authority| authority.as_str())
+            .unwrap_or_default()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        data_dir.join(format!("router_queue_{name}.bin"))
+    }
 }
 
 impl Settings {
     /// Settings are loaded from the file in the given path.
     ///
     /// Environemnt overrides have the same name as the entries in the settings
-    /// file in uppercase and prefixed with "GW_". For example "GW_KEY" will
-    /// override the key file location.
+    /// file in uppercase and prefixed with "GW_". For example "GW_KEYPAIR" will
+    /// override the key file location. Entries in a section are prefixed with
+    /// the section name as well, like "GW_LOG_LEVEL".
     pub fn new(path: &Path) -> Result<Self> {
-        Config::builder()
-            // Source settings file
-            .add_source(File::with_name(path.to_str().expect("file name")).required(false))
-            // Add in settings from the environment (with a prefix of APP)
-            // Eg.. `GW_DEBUG=1 ./target/app` would set the `debug` key
-            .add_source(Environment::with_prefix("gw").separator("_"))
-            .build()
-            .and_then(|config| config.try_deserialize())
-            .map_err(|e| e.into())
+        Self::build(SettingsSource {
+            path: path.to_path_buf(),
+            dev: false,
+        })
+    }
+
+    /// Settings for local development, for example on a laptop with a
+    /// simulated packet forwarder.
+    ///
+    /// These start from the default settings file shipped with the gateway,
+    /// with a file based keypair and data directory in the current working
+    /// directory and a fixed default region. Settings in the file in the given
+    /// path and environment overrides are applied on top of these.
+    pub fn dev(path: &Path) -> Result<Self> {
+        Self::build(SettingsSource {
+            path: path.to_path_buf(),
+            dev: true,
+        })
+    }
+
+    fn build(source: SettingsSource) -> Result<Self> {
+        let (config, deprecated, env_overrides) = source.config()?;
+        let mut settings: Self = config
+            .try_deserialize()
+            .map_err(|err| env::annotate(err, &env_overrides))?;
+        settings.deprecated = deprecated;
+        settings.env_overrides = env_overrides;
+        settings.source = source;
+        settings.unseal_secrets()?;
+        Ok(settings)
+    }
+
+    /// Unseals all sealed secret settings with the gateway keypair, so the
+    /// rest of the gateway only ever sees plain text secrets.
+    fn unseal_secrets(&mut self) -> Result {
+        let keypair = self.keypair.clone();
+        for secret in self.secrets_mut() {
+            secret.unseal(&keypair)?;
+        }
+        Ok(())
+    }
+
+    /// Returns all secret settings. Settings of type [`Secret`] must be
+    /// listed here to be unsealed at load time.
+    fn secrets_mut(&mut self) -> Vec<&mut Secret> {
+        self.mqtt
+            .iter_mut()
+            .filter_map(|mqtt| mqtt.password.as_mut())
+            .chain(
+                self.packet_broker
+                    .iter_mut()
+                    .filter_map(|packet_broker| packet_broker.token.as_mut()),
+            )
+            .chain(
+                self.onboarding_server
+                    .iter_mut()
+                    .filter_map(|onboarding_server| onboarding_server.token.as_mut()),
+            )
+            .chain(self.api_auth.token.as_mut())
+            .collect()
     }
 
     /// Returns the onboarding key for this gateway. The onboarding key is
@@ -138,19 +1130,188 @@ impl Settings {
     }
 }
 
-fn default_listen() -> String {
-    "127.0.0.1:1680".to_string()
+fn default_log_buffer() -> u16 {
+    500
+}
+
+fn default_listen() -> Vec<String> {
+    vec!["127.0.0.1:1680".to_string()]
+}
+
+/// Deserializes a single listen address or a non empty list of them
+fn deserialize_listen<'de, D>(deserializer: D) -> std::result::Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Listen {
+        One(String),
+        Many(Vec<String>),
+    }
+    match Listen::deserialize(deserializer)? {
+        Listen::One(listen) => Ok(vec![listen]),
+        Listen::Many(listen) if listen.is_empty() => {
+            Err(serde::de::Error::custom("empty list of listen addresses"))
+        }
+        Listen::Many(listen) => Ok(listen),
+    }
+}
+
+fn default_backpressure_threshold() -> usize {
+    128
+}
+
+fn default_uplink_dedup_window() -> u64 {
+    200
+}
+
+fn default_http2_keepalive_timeout() -> u64 {
+    20
 }
 
 fn default_api() -> ListenAddress {
     ListenAddress::Address("127.0.0.1:4467".to_string())
 }
 
+fn default_data_dir() -> PathBuf {
+    PathBuf::from("/etc/helium_gateway")
+}
+
 fn default_poc_interval() -> u64 {
     // every 6 hours
     6 * 3600
 }
 
+fn default_poc_entropy_max_age() -> u64 {
+    300
+}
+
+fn default_beacon_tx_failures() -> u32 {
+    3
+}
+
+fn default_beacon_tx_cooldown() -> u64 {
+    // 24 hours
+    24 * 3600
+}
+
+fn default_poc_history_days() -> u16 {
+    30
+}
+
+fn default_poc_beacon_history() -> u16 {
+    100
+}
+
+fn default_poc_witness_dedup_size() -> u16 {
+    15
+}
+
+fn default_poc_witness_alert_drop() -> u8 {
+    75
+}
+
+fn default_poc_witness_alert_days() -> u16 {
+    7
+}
+
+fn default_poc_witness_alert_min() -> u32 {
+    10
+}
+
+fn default_poc_witness_queue() -> u16 {
+    100
+}
+
+fn default_poc_witness_queue_batch() -> u16 {
+    10
+}
+
+fn default_poc_witness_queue_max_age() -> u64 {
+    3600
+}
+
+fn default_hook_up_value() -> String {
+    "1".to_string()
+}
+
+fn default_hook_down_value() -> String {
+    "0".to_string()
+}
+
+fn default_router_enabled() -> bool {
+    true
+}
+
+fn default_router_downlinks() -> bool {
+    true
+}
+
+fn default_router_max_hold_time() -> u64 {
+    60
+}
+
+fn default_router_persist_max_size() -> u64 {
+    1024 * 1024
+}
+
+fn default_mqtt_topic_prefix() -> String {
+    "gateway".to_string()
+}
+
+fn default_packet_broker_queue() -> u16 {
+    20
+}
+
+fn default_packet_broker_max_hold_time() -> u64 {
+    5
+}
+
+fn default_mqtt_keep_alive() -> u64 {
+    30
+}
+
+fn default_onboarding_server_retries() -> u32 {
+    3
+}
+
+fn default_remote_config_interval() -> u64 {
+    3600
+}
+
+fn default_duty_cycle_enabled() -> bool {
+    true
+}
+
+fn default_duty_cycle_window() -> u64 {
+    3600
+}
+
+fn default_gps_region_confirm() -> u32 {
+    5
+}
+
+fn default_gps_region_margin_km() -> f64 {
+    10.0
+}
+
+/// Part of the available memory an auto sized router queue may use, as a
+/// divisor
+const AUTO_QUEUE_MEMORY_SHARE: u64 = 10;
+
+/// The memory available for new allocations in bytes, as reported by the
+/// kernel
+fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))
+        .and_then(|value| value.trim().strip_suffix("kB"))
+        .and_then(|kb| kb.trim().parse::<u64>().ok())
+        .map(|kb| kb * 1024)
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Copy, clap::ValueEnum)]
 #[clap(rename_all = "lower")]
 #[repr(u8)]
@@ -190,6 +1351,11 @@ impl fmt::Display for StakingMode {
     }
 }
 
+/// Scheme of a listen address for a unix domain socket
+const UNIX_SCHEME: &str = "unix://";
+
+/// A port on the loopback interface, an `ip:port` address or a unix domain
+/// socket as `unix://<path>`, like `unix:///var/run/helium_gateway.sock`
 #[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
 #[serde(untagged)]
 pub enum ListenAddress {
@@ -197,12 +1363,28 @@ pub enum ListenAddress {
     Address(String),
 }
 
+impl ListenAddress {
+    /// The path of the unix domain socket, if this is a unix socket address
+    pub fn unix_path(&self) -> Option<&Path> {
+        match self {
+            Self::Address(str) => str.strip_prefix(UNIX_SCHEME).map(Path::new),
+            Self::Port(_) => None,
+        }
+    }
+}
+
 impl TryFrom<&ListenAddress> for std::net::SocketAddr {
     type Error = crate::Error;
     fn try_from(value: &ListenAddress) -> std::result::Result<Self, Self::Error> {
         fn local_addr_from_port(v: &u16) -> String {
             format!("127.0.0.1:{v}")
         }
+        if let Some(path) = value.unix_path() {
+            return Err(crate::Error::custom(format!(
+                "not a socket address: unix socket {}",
+                path.display()
+            )));
+        }
         match value {
             ListenAddress::Address(str) => {
                 if let Ok(v) = str.parse::<u16>() {
@@ -235,11 +1417,105 @@ impl TryFrom<&ListenAddress> for http::Uri {
     }
 }
 
+/// A maximum EIRP for transmissions in a frequency range
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+pub struct TxPowerLimit {
+    /// Lowest frequency in Hz of the range
+    pub min_frequency: u32,
+    /// Highest frequency in Hz of the range
+    pub max_frequency: u32,
+    /// Maximum EIRP in tenths of dBm
+    pub max_eirp: u32,
+}
+
+impl TxPowerLimit {
+    pub fn contains(&self, frequency: u32) -> bool {
+        (self.min_frequency..=self.max_frequency).contains(&frequency)
+    }
+
+    /// The maximum EIRP in dBm
+    pub fn max_eirp(&self) -> Decimal {
+        Decimal::new(self.max_eirp.into(), 1)
+    }
+
+    /// The maximum conducted transmit power in dBm with an antenna of the
+    /// given gain in dBi
+    pub fn max_conducted_power(&self, gain: Decimal) -> u32 {
+        (self.max_eirp() - gain).trunc().to_u32().unwrap_or(0)
+    }
+}
+
+fn deserialize_tx_power_limits<'de, D>(d: D) -> std::result::Result<Vec<TxPowerLimit>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let limits = Vec::<TxPowerLimit>::deserialize(d)?;
+    if let Some(limit) = limits
+        .iter()
+        .find(|limit| limit.min_frequency > limit.max_frequency)
+    {
+        return Err(serde::de::Error::custom(format!(
+            "tx power limit from {} above {} Hz",
+            limit.min_frequency, limit.max_frequency
+        )));
+    }
+    Ok(limits)
+}
+
+fn deserialize_datarate<'de, D>(
+    deserializer: D,
+) -> std::result::Result<helium_proto::DataRate, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    helium_proto::DataRate::from_str_name(&name.to_uppercase())
+        .ok_or_else(|| serde::de::Error::custom(format!("invalid datarate {name}")))
+}
+
+fn deserialize_optional_uri<'de, D>(deserializer: D) -> std::result::Result<Option<Uri>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| s.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+fn deserialize_uris<'de, D>(deserializer: D) -> std::result::Result<Vec<Uri>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| s.parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+fn deserialize_net_id<'de, D>(deserializer: D) -> std::result::Result<NetId, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_net_ids<'de, D>(deserializer: D) -> std::result::Result<Vec<NetId>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| s.parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
 pub mod log_level {
     use serde::de::{self, Deserialize, Deserializer, Visitor};
     use std::fmt;
 
-    #[derive(Debug, Clone, Copy)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct Level(tracing::Level);
 
     impl std::fmt::Display for Level {
@@ -293,6 +1569,25 @@ mod test {
     use super::*;
     use std::net::SocketAddr;
 
+    #[test]
+    fn listen_list() {
+        #[derive(Deserialize)]
+        struct Listen {
+            #[serde(deserialize_with = "deserialize_listen")]
+            listen: Vec<String>,
+        }
+        let listen = |json| serde_json::from_str::<Listen>(json).map(|listen| listen.listen);
+        assert_eq!(
+            vec!["0.0.0.0:1680"],
+            listen(r#"{"listen": "0.0.0.0:1680"}"#).expect("single listen address")
+        );
+        assert_eq!(
+            vec!["0.0.0.0:1680", "0.0.0.0:1681"],
+            listen(r#"{"listen": ["0.0.0.0:1680", "0.0.0.0:1681"]}"#).expect("listen addresses")
+        );
+        assert!(listen(r#"{"listen": []}"#).is_err());
+    }
+
     #[test]
     fn listen_addr() {
         assert_eq!(
@@ -324,5 +1619,37 @@ mod test {
                 .expect("uri from addr string"),
             Uri::from_static("http://1.2.3.4:4468")
         );
+
+        // And unix sockets
+        let unix = ListenAddress::Address("unix:///var/run/helium_gateway.sock".to_string());
+        assert_eq!(
+            Some(Path::new("/var/run/helium_gateway.sock")),
+            unix.unix_path()
+        );
+        assert!(SocketAddr::try_from(&unix).is_err());
+        assert_eq!(None, ListenAddress::Port(4468).unix_path());
+    }
+
+    #[test]
+    fn tx_power_limits() {
+        #[derive(Deserialize)]
+        struct Limits {
+            #[serde(deserialize_with = "deserialize_tx_power_limits")]
+            limits: Vec<TxPowerLimit>,
+        }
+        let limits = |json| serde_json::from_str::<Limits>(json).map(|limits| limits.limits);
+        let limit = limits(
+            r#"{"limits": [{"min_frequency": 869400000, "max_frequency": 869650000, "max_eirp": 140}]}"#,
+        )
+        .expect("tx power limits")[0];
+        assert!(limit.contains(869_525_000));
+        assert!(!limit.contains(868_100_000));
+        // 14 dBm EIRP with a 5.8 dBi antenna
+        assert_eq!(8, limit.max_conducted_power(Decimal::new(58, 1)));
+        assert_eq!(0, limit.max_conducted_power(Decimal::new(200, 1)));
+        assert!(limits(
+            r#"{"limits": [{"min_frequency": 869650000, "max_frequency": 869400000, "max_eirp": 140}]}"#
+        )
+        .is_err());
     }
 }
@@ -0,0 +1,168 @@
+//! Settings overrides from the environment.
+//!
+//! Every environment variable starting with `GW_` overrides the settings key
+//! of the same name in lowercase. Since settings keys contain underscores
+//! themselves, a leading section name followed by an underscore selects the
+//! key in that section, so `GW_LOG_LEVEL` sets `level` in `[log]` while
+//! `GW_LISTEN_INTERFACE` sets the top level `listen_interface`.
+//!
+//! Environment values are always strings, so values that read as a boolean,
+//! an integer or a float are passed on as such. A value is only converted if
+//! it reads back the same, so for example "0012" stays a string. A settings
+//! error caused by an override names the environment variable.
+
+use config::{ConfigError, Value};
+use serde::Serialize;
+use std::fmt;
+
+/// Prefix of environment overrides
+const PREFIX: &str = "GW_";
+
+/// Settings sections whose keys can be overridden, as the name of the
+/// section in the settings file
+const SECTIONS: &[&str] = &[
+    "backpressure",
+    "config",
+    "duty_cycle",
+    "forwarder_watchdog",
+    "gps",
+    "gps_region",
+    "join_vendors",
+    "log",
+    "metrics",
+    "mqtt",
+    "network",
+    "onboarding_server",
+    "packet_broker",
+    "ping",
+    "poc",
+    "remote_config",
+    "router",
+    "rx2",
+    "uplink_dedup",
+    "uplink_filter",
+];
+
+/// A settings key set from the environment
+#[derive(Debug, Clone, Serialize)]
+pub struct EnvOverride {
+    /// The environment variable
+    pub var: String,
+    /// The settings key it sets
+    pub key: String,
+}
+
+impl fmt::Display for EnvOverride {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.var, self.key)
+    }
+}
+
+/// The settings key for the name of an environment variable without prefix
+fn settings_key(name: &str) -> String {
+    let name = name.to_lowercase();
+    SECTIONS
+        .iter()
+        .filter_map(|section| {
+            name.strip_prefix(section)
+                .and_then(|key| key.strip_prefix('_'))
+                .filter(|key| !key.is_empty())
+                .map(|key| (section, key))
+        })
+        .max_by_key(|(section, _)| section.len())
+        .map_or_else(|| name.clone(), |(section, key)| format!("{section}.{key}"))
+}
+
+/// The settings value for an environment value
+fn settings_value(value: &str) -> Value {
+    if let Ok(value) = value.parse::<bool>() {
+        return value.into();
+    }
+    if let Ok(int) = value.parse::<i64>() {
+        if int.to_string() == value {
+            return int.into();
+        }
+    }
+    if let Ok(float) = value.parse::<f64>() {
+        if float.is_finite() && float.to_string() == value {
+            return float.into();
+        }
+    }
+    value.into()
+}
+
+/// The settings overrides of the given environment variables, sorted by
+/// variable name
+pub fn overrides(vars: impl Iterator<Item = (String, String)>) -> Vec<(EnvOverride, Value)> {
+    let mut overrides: Vec<(EnvOverride, Value)> = vars
+        .filter_map(|(var, value)| {
+            let name = var
+                .get(..PREFIX.len())
+                .filter(|prefix| prefix.eq_ignore_ascii_case(PREFIX))
+                .and_then(|_| var.get(PREFIX.len()..))
+                .filter(|name| !name.is_empty())?;
+            let key = settings_key(name);
+            let value = settings_value(&value);
+            Some((EnvOverride { var, key }, value))
+        })
+        .collect();
+    overrides.sort_by(|(a, _), (b, _)| a.var.cmp(&b.var));
+    overrides
+}
+
+/// Names the environment variable in a settings error for a key set from
+/// the environment
+pub fn annotate(err: ConfigError, overrides: &[EnvOverride]) -> crate::Error {
+    let key = match &err {
+        ConfigError::Type { key: Some(key), .. } => key,
+        _ => return err.into(),
+    };
+    match overrides.iter().find(|o| &o.key == key) {
+        Some(env_override) => crate::Error::custom(format!("{err} (set by {})", env_override.var)),
+        None => err.into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_env_overrides() {
+        let vars = [
+            ("GW_LISTEN", "0.0.0.0:1680"),
+            ("GW_LISTEN_INTERFACE", "eth0"),
+            ("GW_API", "4467"),
+            ("GW_LOG_LEVEL", "debug"),
+            ("GW_GPS_REGION_ENABLED", "true"),
+            ("GW_GPS_REGION_MARGIN_KM", "2.5"),
+            ("GW_ONBOARDING", "0012"),
+            ("GW_", "ignored"),
+            ("HOME", "/root"),
+        ]
+        .into_iter()
+        .map(|(var, value)| (var.to_string(), value.to_string()));
+        let overrides: Vec<(String, String)> = overrides(vars)
+            .into_iter()
+            .map(|(env_override, value)| (env_override.key, format!("{:?}", value.kind)))
+            .collect();
+        assert_eq!(
+            vec![
+                ("api".to_string(), "I64(4467)".to_string()),
+                (
+                    "gps_region.enabled".to_string(),
+                    "Boolean(true)".to_string()
+                ),
+                ("gps_region.margin_km".to_string(), "Float(2.5)".to_string()),
+                ("listen".to_string(), "String(\"0.0.0.0:1680\")".to_string()),
+                (
+                    "listen_interface".to_string(),
+                    "String(\"eth0\")".to_string()
+                ),
+                ("log.level".to_string(), "String(\"debug\")".to_string()),
+                ("onboarding".to_string(), "String(\"0012\")".to_string()),
+            ],
+            overrides
+        );
+    }
+}
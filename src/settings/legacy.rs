@@ -0,0 +1,137 @@
+//! Recognized settings keys of older settings files.
+//!
+//! Settings files of older releases can contain sections like `gateways`,
+//! `routers` and `update` that current releases do not use. Rather than
+//! silently ignoring them, they are recognized at load time, mapped to their
+//! current equivalent where there is one, and reported so operators can
+//! migrate their settings files.
+
+use config::{Config, Value};
+use serde::Serialize;
+use std::fmt;
+
+/// A legacy settings key found in the loaded settings
+#[derive(Debug, Clone, Serialize)]
+pub struct DeprecatedSetting {
+    /// The legacy key
+    pub key: &'static str,
+    /// The current key the value was mapped to, if any
+    pub mapped_to: Option<&'static str>,
+    /// What happened to the value and what to use instead
+    pub message: String,
+}
+
+impl fmt::Display for DeprecatedSetting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deprecated setting \"{}\": {}", self.key, self.message)
+    }
+}
+
+struct LegacyKey {
+    key: &'static str,
+    /// The current key for the same value, if there is one
+    replacement: Option<&'static str>,
+    /// Why the key is ignored, for keys without a replacement
+    reason: &'static str,
+}
+
+const LEGACY_KEYS: &[LegacyKey] = &[
+    LegacyKey {
+        key: "gateways",
+        replacement: None,
+        reason: "validator gateways are no longer used, region parameters are fetched from the \
+            config service in \"config\"",
+    },
+    LegacyKey {
+        key: "routers",
+        replacement: None,
+        reason: "state channel routers are no longer used, uplinks are delivered to the packet \
+            router in \"router\"",
+    },
+    LegacyKey {
+        key: "update",
+        replacement: None,
+        reason: "automatic updates were removed, update the gateway through its firmware or \
+            package manager",
+    },
+    LegacyKey {
+        key: "log.method",
+        replacement: None,
+        reason: "logs are always written to stdout",
+    },
+    LegacyKey {
+        key: "router.sign_uplinks",
+        replacement: None,
+        reason: "uplinks are always signed with the session key, since routers can not tell the \
+            gateway whether they verify signatures",
+    },
+    LegacyKey {
+        key: "listen_addr",
+        replacement: Some("listen"),
+        reason: "",
+    },
+];
+
+/// Checks the given configuration for legacy keys. Returns the deprecation
+/// notices and the values to set for the current keys of mapped legacy keys.
+pub fn check(config: &Config) -> (Vec<DeprecatedSetting>, Vec<(&'static str, Value)>) {
+    let mut deprecated = vec![];
+    let mut overrides = vec![];
+    for legacy in LEGACY_KEYS {
+        let Ok(value) = config.get::<Value>(legacy.key) else {
+            continue;
+        };
+        let (mapped_to, message) = match legacy.replacement {
+            Some(replacement) if config.get::<Value>(replacement).is_ok() => (
+                None,
+                format!("ignored since \"{replacement}\" is set, remove it"),
+            ),
+            Some(replacement) => {
+                overrides.push((replacement, value));
+                (
+                    Some(replacement),
+                    format!("used as \"{replacement}\", rename it"),
+                )
+            }
+            None => (None, format!("ignored, {}", legacy.reason)),
+        };
+        deprecated.push(DeprecatedSetting {
+            key: legacy.key,
+            mapped_to,
+            message,
+        });
+    }
+    (deprecated, overrides)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use config::{File, FileFormat};
+
+    #[test]
+    fn legacy_keys() {
+        let config = Config::builder()
+            .add_source(File::from_str(
+                r#"
+                listen_addr = "0.0.0.0:1680"
+                [log]
+                method = "syslog"
+                [update]
+                enabled = true
+                "#,
+                FileFormat::Toml,
+            ))
+            .build()
+            .expect("config");
+        let (deprecated, overrides) = check(&config);
+        let keys: Vec<&str> = deprecated.iter().map(|setting| setting.key).collect();
+        assert_eq!(vec!["update", "log.method", "listen_addr"], keys);
+        assert_eq!(1, overrides.len());
+        assert_eq!("listen", overrides[0].0);
+        assert_eq!(
+            "0.0.0.0:1680",
+            overrides[0].1.clone().into_string().expect("string")
+        );
+    }
+}
@@ -0,0 +1,213 @@
+//! Simulated packet forwarder traffic.
+//!
+//! `helium_gateway server --sim` runs the service with a simulated packet
+//! forwarder that injects synthetic uplinks and beacons into the gateway at a
+//! fixed interval, without a radio or packet forwarder. This exercises the
+//! packet router, Packet Broker, mqtt and beaconer paths end to end in CI and
+//! integration environments, for example against the services of
+//! `helium_gateway mock`.
+//!
+//! * Uplinks are unconfirmed data frames with an increasing frame counter
+//!   from a device address in the Helium range. The MIC is not valid, which
+//!   the gateway does not check.
+//! * Beacons are proprietary frames with a beacon payload generated from
+//!   local entropy, which the beaconer witnesses like any received beacon.
+//!
+//! Channels and datarates are selected from the region parameters the same
+//! way they are for beacons, so nothing is injected until region parameters
+//! are known. Injected packets are handled as packets of a packet forwarder
+//! with the MAC address `53494d0000000001`. Downlinks sent in response go to the
+//! connected packet forwarders, if any.
+
+use crate::{gateway, packet, region_watcher, RegionParams, Result};
+use beacon::{Beacon, Entropy};
+use lorawan::PHYPayload;
+use semtech_udp::{
+    push_data::{RxPk, RxPkV1, CRC},
+    CodingRate, MacAddress, Modulation,
+};
+use std::time::{Duration, Instant};
+use tokio::time::{self, Interval, MissedTickBehavior};
+use tracing::{debug, info};
+
+/// MAC address of the simulated packet forwarder
+const SIM_MAC: [u8; 8] = [0x53, 0x49, 0x4d, 0, 0, 0, 0, 1];
+/// Device address of the simulated device, in the Helium devaddr range
+const SIM_DEVADDR: u32 = 0x4800_0800;
+/// Frame port and application payload of simulated uplinks
+const SIM_FPORT: u8 = 1;
+const SIM_PAYLOAD: &[u8] = b"sim";
+/// Signal of simulated packets
+const SIM_RSSI: i32 = -80;
+const SIM_SNR: f32 = 7.5;
+
+/// Intervals at which synthetic packets are injected
+#[derive(Debug, Clone, Copy)]
+pub struct SimOptions {
+    /// Seconds between uplinks, 0 to disable
+    pub uplink_interval: u64,
+    /// Seconds between beacons, 0 to disable
+    pub beacon_interval: u64,
+}
+
+pub struct Simulator {
+    gateway: gateway::MessageSender,
+    region_watch: region_watcher::MessageReceiver,
+    options: SimOptions,
+    /// Frame counter of the next uplink
+    fcnt: u16,
+    /// Start of the simulated concentrator timestamp counter
+    start: Instant,
+}
+
+impl Simulator {
+    pub fn new(
+        options: SimOptions,
+        gateway: gateway::MessageSender,
+        region_watch: region_watcher::MessageReceiver,
+    ) -> Self {
+        Self {
+            gateway,
+            region_watch,
+            options,
+            fcnt: 0,
+            start: Instant::now(),
+        }
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn run(&mut self, shutdown: &triggered::Listener) -> Result {
+        info!(
+            uplink_interval = self.options.uplink_interval,
+            beacon_interval = self.options.beacon_interval,
+            "starting"
+        );
+        let mut uplink_timer = timer(self.options.uplink_interval);
+        let mut beacon_timer = timer(self.options.beacon_interval);
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => {
+                    info!("shutting down");
+                    return Ok(())
+                },
+                _ = tick(&mut uplink_timer) => {
+                    let frame = uplink_frame(SIM_DEVADDR, self.fcnt);
+                    if self.inject("uplink", |_| Ok(frame)).await {
+                        self.fcnt = self.fcnt.wrapping_add(1);
+                    }
+                }
+                _ = tick(&mut beacon_timer) => {
+                    self.inject("beacon", beacon_frame).await;
+                }
+            }
+        }
+    }
+
+    /// Injects the frame built for a beacon of the current region parameters
+    /// on the channel and datarate of that beacon. Returns whether the frame
+    /// was injected.
+    async fn inject<F>(&self, kind: &str, frame: F) -> bool
+    where
+        F: FnOnce(&Beacon) -> Result<Vec<u8>>,
+    {
+        let region_params = self.region_watch.borrow().clone();
+        let rxpk = mk_beacon(&region_params)
+            .and_then(|beacon| frame(&beacon).and_then(|data| self.rxpk(&beacon, data)));
+        match rxpk {
+            Ok(rxpk) => {
+                debug!(kind, frequency = *rxpk.get_frequency(), "injecting packet");
+                self.gateway
+                    .simulated(MacAddress::from(SIM_MAC), rxpk)
+                    .await;
+                true
+            }
+            Err(err) => {
+                debug!(kind, %err, "skipped packet");
+                false
+            }
+        }
+    }
+
+    fn rxpk(&self, channel: &Beacon, frame: Vec<u8>) -> Result<RxPk> {
+        Ok(RxPk::V1(RxPkV1 {
+            chan: 0,
+            codr: CodingRate::_4_5,
+            size: frame.len() as u64,
+            data: frame,
+            datr: packet::datarate::from_proto(channel.datarate)?,
+            freq: channel.frequency as f64 / 1_000_000.0,
+            lsnr: SIM_SNR,
+            modu: Modulation::LORA,
+            rfch: 0,
+            rssi: SIM_RSSI,
+            rssis: Some(SIM_RSSI),
+            stat: CRC::OK,
+            tmst: self.start.elapsed().as_micros() as u32,
+            time: None,
+        }))
+    }
+}
+
+/// A timer ticking at the given interval in seconds, None if disabled
+fn timer(interval: u64) -> Option<Interval> {
+    (interval > 0).then(|| {
+        let mut timer = time::interval(Duration::from_secs(interval));
+        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        timer
+    })
+}
+
+async fn tick(timer: &mut Option<Interval>) {
+    match timer {
+        Some(timer) => {
+            timer.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// A beacon of the given region parameters, on a channel and datarate
+/// selected from them
+fn mk_beacon(region_params: &RegionParams) -> Result<Beacon> {
+    region_params.check_valid()?;
+    Ok(Beacon::new(
+        Entropy::local()?,
+        Entropy::local()?,
+        region_params,
+    )?)
+}
+
+/// An unconfirmed data uplink frame of the given device address
+fn uplink_frame(devaddr: u32, fcnt: u16) -> Vec<u8> {
+    let mut frame = vec![0x40];
+    frame.extend_from_slice(&devaddr.to_le_bytes());
+    // No frame options
+    frame.push(0x00);
+    frame.extend_from_slice(&fcnt.to_le_bytes());
+    frame.push(SIM_FPORT);
+    frame.extend_from_slice(SIM_PAYLOAD);
+    // The MIC is not checked by the gateway
+    frame.extend_from_slice(&[0; 4]);
+    frame
+}
+
+/// The proprietary frame of the given beacon
+fn beacon_frame(beacon: &Beacon) -> Result<Vec<u8>> {
+    Ok(PHYPayload::proprietary(beacon.data.as_slice()).try_into()?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::PacketUp;
+    use lorawan::{Direction, PHYPayloadFrame};
+
+    #[test]
+    fn sim_uplink() {
+        let frame = uplink_frame(SIM_DEVADDR, 7);
+        match PacketUp::parse_frame(Direction::Uplink, &frame).expect("uplink frame") {
+            PHYPayloadFrame::MACPayload(payload) => assert_eq!(SIM_DEVADDR, payload.dev_addr()),
+            frame => panic!("unexpected frame {frame:?}"),
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! Crash safe writes of the state files in the data directory.
+//!
+//! A state file is written to a temporary file next to it first, which is
+//! synced to disk and then renamed over the state file. A crash or power loss
+//! during the write leaves the previous state file intact instead of a
+//! truncated one.
+
+use crate::Result;
+use std::{
+    ffi::OsString,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Replaces the file at the given path with the given data
+pub fn write(path: &Path, data: &[u8]) -> Result {
+    let tmp_path = tmp_path(path);
+    {
+        let mut tmp = fs::File::create(&tmp_path)?;
+        tmp.write_all(data)?;
+        tmp.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Replaces the file at the given path with the JSON encoding of the given
+/// value
+pub fn write_json<T: serde::Serialize + ?Sized>(path: &Path, value: &T) -> Result {
+    write(path, &serde_json::to_vec(value)?)
+}
+
+/// The temporary file for the given path, the path with a `.tmp` suffix
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp_path = OsString::from(path.as_os_str());
+    tmp_path.push(".tmp");
+    PathBuf::from(tmp_path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn replace() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let path = dir.path().join("state.json");
+        write_json(&path, &[1, 2]).expect("write");
+        write_json(&path, &[3]).expect("replace");
+        assert_eq!(b"[3]".as_slice(), fs::read(&path).expect("read"));
+        assert!(!tmp_path(&path).exists());
+    }
+}
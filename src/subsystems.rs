@@ -0,0 +1,101 @@
+//! Runtime pausing of optional subsystems.
+//!
+//! Operators sometimes need to silence proof of coverage or the MQTT bridge
+//! for a while, for example during RF testing, without editing the settings
+//! and restarting the gateway. A subsystem paused through the local API stays
+//! paused until it is resumed or the service restarts. A pause can optionally
+//! be persisted in a small file in the data directory, in which case it also
+//! survives restarts.
+//!
+//! Pausing only silences a subsystem that is enabled in the settings. Resuming
+//! a subsystem that is disabled in the settings does not enable it.
+
+use crate::{state_file, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tracing::warn;
+
+/// Name of the file with persisted pauses in the data directory
+const PAUSED_FILE: &str = "paused.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subsystem {
+    /// Beaconing and witness reports
+    Poc,
+    /// Uplink publishing and downlink commands of the MQTT bridge
+    Mqtt,
+}
+
+impl Subsystem {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Poc => "poc",
+            Self::Mqtt => "mqtt",
+        }
+    }
+}
+
+impl fmt::Display for Subsystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Subsystem {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "poc" => Ok(Self::Poc),
+            "mqtt" => Ok(Self::Mqtt),
+            _ => Err(Error::custom(format!("unknown subsystem \"{s}\""))),
+        }
+    }
+}
+
+/// The persisted pauses of subsystems
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PausedSubsystems {
+    #[serde(default)]
+    pub poc: bool,
+    #[serde(default)]
+    pub mqtt: bool,
+}
+
+impl PausedSubsystems {
+    /// Reads the persisted pauses from the given data directory. A missing or
+    /// invalid file pauses nothing.
+    pub fn load(data_dir: &Path) -> Self {
+        let path = paused_path(data_dir);
+        let Ok(data) = std::fs::read(&path) else {
+            return Self::default();
+        };
+        serde_json::from_slice(&data)
+            .map_err(
+                |err| warn!(path = %path.display(), %err, "ignoring invalid paused subsystems"),
+            )
+            .unwrap_or_default()
+    }
+
+    /// Persists the pause of the given subsystem in the given data directory
+    pub fn store(data_dir: &Path, subsystem: Subsystem, paused: bool) -> Result {
+        let mut persisted = Self::load(data_dir);
+        persisted.set(subsystem, paused);
+        state_file::write_json(&paused_path(data_dir), &persisted)
+    }
+
+    fn set(&mut self, subsystem: Subsystem, paused: bool) {
+        match subsystem {
+            Subsystem::Poc => self.poc = paused,
+            Subsystem::Mqtt => self.mqtt = paused,
+        }
+    }
+}
+
+fn paused_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(PAUSED_FILE)
+}
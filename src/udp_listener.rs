@@ -0,0 +1,236 @@
+//! Udp listeners serving semtech packet forwarders.
+//!
+//! The udp runtime binds its own socket and does not hand it out. A listener
+//! bound to an interface binds the runtime to the current address of that
+//! interface. A listener with a DSCP value looks up the socket of the runtime
+//! by its address after the bind to mark it, see [`crate::qos`], which is only
+//! supported on Linux.
+//!
+//! The udp runtime does not report socket errors, so a socket that can no
+//! longer receive only shows up as silence from the packet forwarder. After
+//! a configured time without traffic the listener checks whether the address
+//! it is bound to is still assigned to the host, or to its interface. Only a
+//! listener that fails this check is bound again.
+//!
+//! The tasks of a udp runtime keep its socket open and fail once the runtime
+//! is dropped, so a replaced runtime is kept. Binding the same address again
+//! fails while the replaced socket holds it, in which case the replaced
+//! runtime is kept in use, since its socket receives again once its address
+//! is assigned again.
+
+use crate::{interface, qos, Error, Result};
+use semtech_udp::server_runtime::{Event, UdpRuntime};
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// Wait before the first retry of a failed rebuild. The wait doubles with
+/// every failed retry.
+const REBUILD_RETRY_MIN: Duration = Duration::from_secs(10);
+/// Longest wait between retries of a failed rebuild
+const REBUILD_RETRY_MAX: Duration = Duration::from_secs(300);
+
+/// A udp runtime serving packet forwarders on one of the listen addresses
+pub struct UdpListener {
+    /// The listen address as configured
+    pub listen: String,
+    /// DSCP value to mark the socket with
+    dscp: u8,
+    /// The runtime in use and its address
+    bound: Bound,
+    /// Whether the last rebuild failed, which keeps the runtime in use until
+    /// a retry succeeds
+    rebuild_failed: bool,
+    /// Runtimes replaced by a rebuild. Their tasks fail on their next event
+    /// once a runtime is dropped, so they are kept without being received
+    /// from.
+    replaced: Vec<UdpRuntime>,
+    /// Time at which the socket is checked if no packet forwarder traffic is
+    /// received on it before then
+    pub check_at: Instant,
+    /// Wait before the next retry of a failed rebuild
+    retry_wait: Duration,
+}
+
+struct Bound {
+    runtime: UdpRuntime,
+    /// The address the runtime is bound to
+    addr: SocketAddr,
+    /// The interface the address was taken from, if any
+    interface: Option<String>,
+}
+
+impl UdpListener {
+    /// Binds a new listener for the given listen address and interface,
+    /// marking its traffic with the given DSCP value
+    pub async fn bind(listen: String, interface: Option<&str>, dscp: u8) -> Result<Self> {
+        let bound = Bound::new(&listen, interface, dscp).await?;
+        Ok(Self {
+            listen,
+            dscp,
+            bound,
+            rebuild_failed: false,
+            replaced: vec![],
+            check_at: Instant::now(),
+            retry_wait: REBUILD_RETRY_MIN,
+        })
+    }
+
+    pub fn runtime(&self) -> &UdpRuntime {
+        &self.bound.runtime
+    }
+
+    /// Receives the next event of the runtime
+    pub async fn recv(&mut self) -> Event {
+        self.bound.runtime.recv().await
+    }
+
+    /// Whether the last rebuild failed and is retried on the next check
+    pub fn rebuild_failed(&self) -> bool {
+        self.rebuild_failed
+    }
+
+    /// Schedules the next check of the socket after the given idle time
+    pub fn reset_idle(&mut self, idle_timeout: Duration) {
+        self.check_at = Instant::now() + idle_timeout;
+    }
+
+    /// Whether the listener has to be bound again to receive packets. This
+    /// is the case after a failed rebuild, when the bound address is no
+    /// longer assigned to the host, or when it is no longer an address of the
+    /// interface the listener is bound to.
+    pub fn needs_rebuild(&self) -> bool {
+        if self.rebuild_failed {
+            return true;
+        }
+        let bound = &self.bound;
+        match &bound.interface {
+            Some(interface) => interface::addresses(interface)
+                .map_or(true, |addresses| !addresses.contains(&bound.addr.ip())),
+            None => !is_assigned(bound.addr),
+        }
+    }
+
+    /// Binds the listen address again. The current runtime is kept until the
+    /// new one is bound. On failure the current runtime stays in use and the
+    /// next attempt is scheduled with a backoff.
+    pub async fn rebuild(&mut self, interface: Option<&str>) -> Result<SocketAddr> {
+        match Bound::new(&self.listen, interface, self.dscp).await {
+            Ok(bound) => {
+                let addr = bound.addr;
+                let replaced = std::mem::replace(&mut self.bound, bound);
+                self.replaced.push(replaced.runtime);
+                self.rebuild_failed = false;
+                self.retry_wait = REBUILD_RETRY_MIN;
+                Ok(addr)
+            }
+            Err(err) => {
+                self.rebuild_failed = true;
+                self.check_at = Instant::now() + self.retry_wait;
+                self.retry_wait = (self.retry_wait * 2).min(REBUILD_RETRY_MAX);
+                Err(err)
+            }
+        }
+    }
+}
+
+impl Bound {
+    async fn new(listen_address: &str, interface: Option<&str>, dscp: u8) -> Result<Self> {
+        let mut addr = resolve(listen_address)?;
+        if let Some(interface) = interface {
+            // A wildcard address is bound to the current address of the
+            // interface, a specific address has to be one of its addresses
+            if addr.ip().is_unspecified() {
+                addr.set_ip(interface::bind_address(interface, &addr)?);
+            } else if !interface::addresses(interface)?.contains(&addr.ip()) {
+                return Err(Error::custom(format!(
+                    "listen address {addr} is not an address of interface {interface}"
+                )));
+            }
+        }
+        let runtime = UdpRuntime::new(addr).await.map_err(Box::new)?;
+        if dscp > 0 {
+            let socket = runtime_socket(addr)?;
+            qos::set_dscp((&socket).into(), &addr, dscp)?;
+        }
+        Ok(Self {
+            runtime,
+            addr,
+            interface: interface.map(str::to_string),
+        })
+    }
+}
+
+/// Finds the udp socket of this process that is bound to the given address,
+/// which is the socket of the udp runtime just bound to it. Returns a new
+/// handle to the socket.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn runtime_socket(addr: SocketAddr) -> io::Result<UdpSocket> {
+    use socket2::{SockRef, Type};
+    use std::os::fd::{BorrowedFd, RawFd};
+
+    for entry in std::fs::read_dir("/proc/self/fd")? {
+        let Ok(fd) = entry?.file_name().to_string_lossy().parse::<RawFd>() else {
+            continue;
+        };
+        // SAFETY: the descriptor is only used for the checks and the dup
+        // below. A descriptor that was closed since it was listed fails them.
+        let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+        let socket = SockRef::from(&fd);
+        let is_runtime_socket = socket.r#type().is_ok_and(|ty| ty == Type::DGRAM)
+            && socket
+                .local_addr()
+                .is_ok_and(|local| local.as_socket() == Some(addr));
+        if is_runtime_socket {
+            return Ok(UdpSocket::from(fd.try_clone_to_owned()?));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no udp socket bound to {addr}"),
+    ))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn runtime_socket(addr: SocketAddr) -> io::Result<UdpSocket> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("marking the udp socket on {addr} is not supported on this platform"),
+    ))
+}
+
+fn resolve(listen_address: &str) -> Result<SocketAddr> {
+    listen_address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| Error::custom(format!("invalid listen address {listen_address}")))
+}
+
+/// Whether the ip of the given address is assigned to the host. Wildcard
+/// addresses are always assigned.
+fn is_assigned(addr: SocketAddr) -> bool {
+    if addr.ip().is_unspecified() {
+        return true;
+    }
+    !matches!(
+        UdpSocket::bind(SocketAddr::new(addr.ip(), 0)),
+        Err(err) if err.kind() == io::ErrorKind::AddrNotAvailable
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn assigned_addresses() {
+        assert!(is_assigned("0.0.0.0:1680".parse().unwrap()));
+        assert!(is_assigned("127.0.0.1:1680".parse().unwrap()));
+        // An address from the TEST-NET-1 documentation range is never
+        // assigned
+        assert!(!is_assigned("192.0.2.1:1680".parse().unwrap()));
+    }
+}
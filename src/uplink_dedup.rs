@@ -0,0 +1,150 @@
+//! Deduplication of uplinks heard by several concentrators.
+//!
+//! Gateways with several concentrators, each with its own packet forwarder,
+//! often hear the same frame on more than one of them. While more than one
+//! packet forwarder is connected, an uplink is held for a short window, and
+//! copies of it received from other forwarders in that window are dropped,
+//! keeping the copy with the best RSSI and SNR. The best copy is forwarded
+//! once the window of the first copy passes.
+//!
+//! Copies are matched on their payload. The concentrator timestamps of
+//! different forwarders come from separate counters and do not match.
+
+use crate::PacketUp;
+use semtech_udp::MacAddress;
+use std::time::{Duration, Instant};
+use tokio::time::Instant as TokioInstant;
+
+/// An uplink held for duplicates
+#[derive(Debug)]
+pub struct HeldUplink {
+    pub packet: PacketUp,
+    /// When the packet was received from its forwarder
+    pub received: Instant,
+    /// The forwarder the packet was received from
+    pub mac: MacAddress,
+    release_at: TokioInstant,
+}
+
+impl HeldUplink {
+    /// Whether this copy was received with a better signal than the given one
+    fn is_better(&self, other: &Self) -> bool {
+        (self.packet.rssi, self.packet.snr) > (other.packet.rssi, other.packet.snr)
+    }
+}
+
+#[derive(Debug)]
+pub struct UplinkDedup {
+    window: Duration,
+    /// Held uplinks in the order they are released
+    held: Vec<HeldUplink>,
+}
+
+impl UplinkDedup {
+    /// A window of zero disables deduplication
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            held: vec![],
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.window.is_zero()
+    }
+
+    /// The release time of the first held uplink, if any
+    pub fn next_release(&self) -> Option<TokioInstant> {
+        self.held.first().map(|held| held.release_at)
+    }
+
+    /// Holds the given uplink for duplicates. Returns the dropped copy if the
+    /// uplink is a copy of a held one, which is either the given uplink or
+    /// the held copy it replaces.
+    pub fn push(
+        &mut self,
+        packet: PacketUp,
+        received: Instant,
+        mac: MacAddress,
+        now: TokioInstant,
+    ) -> Option<HeldUplink> {
+        let mut uplink = HeldUplink {
+            packet,
+            received,
+            mac,
+            release_at: now + self.window,
+        };
+        match self
+            .held
+            .iter_mut()
+            .find(|held| held.packet.payload() == uplink.packet.payload())
+        {
+            Some(held) if uplink.is_better(held) => {
+                // The better copy keeps the release time of the first copy
+                uplink.release_at = held.release_at;
+                Some(std::mem::replace(held, uplink))
+            }
+            Some(_) => Some(uplink),
+            None => {
+                self.held.push(uplink);
+                None
+            }
+        }
+    }
+
+    /// Takes the uplinks whose window passed
+    pub fn release(&mut self, now: TokioInstant) -> Vec<HeldUplink> {
+        let released = self
+            .held
+            .iter()
+            .take_while(|held| held.release_at <= now)
+            .count();
+        self.held.drain(..released).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message_cache::Persist;
+    use helium_proto::{services::router::PacketRouterPacketUpV1, Message};
+
+    fn uplink(payload: u8, rssi: i32) -> PacketUp {
+        let packet = PacketRouterPacketUpV1 {
+            payload: vec![payload],
+            rssi,
+            ..Default::default()
+        };
+        PacketUp::from_bytes(&packet.encode_to_vec()).unwrap()
+    }
+
+    #[test]
+    fn dedup() {
+        let now = TokioInstant::now();
+        let received = Instant::now();
+        let mac_a = MacAddress::from([1, 0, 0, 0, 0, 0, 0, 1]);
+        let mac_b = MacAddress::from([2, 0, 0, 0, 0, 0, 0, 2]);
+        let mut dedup = UplinkDedup::new(Duration::from_millis(200));
+        assert!(dedup.push(uplink(1, -110), received, mac_a, now).is_none());
+        assert!(dedup.push(uplink(2, -100), received, mac_a, now).is_none());
+        // A better copy replaces the held one
+        let later = now + Duration::from_millis(50);
+        let dropped = dedup.push(uplink(1, -90), received, mac_b, later);
+        assert_eq!(Some(mac_a), dropped.map(|dropped| dropped.mac));
+        // A worse copy is dropped
+        let dropped = dedup.push(uplink(2, -120), received, mac_b, later);
+        assert_eq!(Some(mac_b), dropped.map(|dropped| dropped.mac));
+
+        assert_eq!(Some(now + Duration::from_millis(200)), dedup.next_release());
+        assert!(dedup.release(later).is_empty());
+        let released = dedup.release(now + Duration::from_millis(200));
+        assert_eq!(
+            vec![(mac_b, -90), (mac_a, -100)],
+            released
+                .iter()
+                .map(|held| (held.mac, held.packet.rssi))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(None, dedup.next_release());
+    }
+}
@@ -0,0 +1,153 @@
+//! Allowlist of the networks whose uplinks are forwarded.
+//!
+//! A gateway hears the traffic of every LoRaWAN network around it. Operators
+//! on metered backhaul can drop the uplinks of foreign networks at the gateway
+//! instead of paying to ship them to the packet router:
+//!
+//! * Data frames are forwarded if their device address belongs to one of the
+//!   allowed NetIDs or device address subnets.
+//! * Join requests are forwarded if their join EUI has one of the allowed
+//!   prefixes.
+//!
+//! Frames of a kind without allowlist entries, and frames without a device
+//! address or join EUI, are always forwarded.
+//!
+//! Fport filters further restrict the frame ports permitted for the data
+//! frames of a device address subnet. The number of uplinks dropped by the
+//! network filter and by each fport filter is reported in [`FilterStatus`].
+
+use crate::{
+    matcher::DevAddrJoinEuiMatcher,
+    settings::{FportFilterSettings, UplinkFilterSettings},
+    PacketUp,
+};
+use serde::Serialize;
+use tracing::info;
+
+/// Number of uplinks dropped by the filters since startup
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FilterStatus {
+    /// Uplinks dropped since their network is not in the uplink filter
+    pub network_dropped: u64,
+    /// Uplinks dropped by each fport filter, in configured order
+    pub fport_filters: Vec<FportFilterStatus>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FportFilterStatus {
+    /// The device address subnet of the filter
+    pub subnet: String,
+    /// Uplinks dropped on a frame port not permitted for the subnet
+    pub dropped: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct UplinkFilter {
+    matcher: DevAddrJoinEuiMatcher,
+    /// Number of uplinks dropped by the filter
+    dropped: u64,
+}
+
+impl UplinkFilter {
+    pub fn new(settings: &UplinkFilterSettings) -> Self {
+        Self {
+            matcher: DevAddrJoinEuiMatcher::new(
+                &settings.net_ids,
+                &settings.devaddrs,
+                &settings.join_euis,
+            ),
+            dropped: 0,
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Returns true and counts the uplink if it is dropped by the filter
+    pub fn is_filtered(&mut self, packet: &PacketUp) -> bool {
+        let filtered = !self.permits(packet.dev_addr(), packet.join_eui());
+        if filtered {
+            self.dropped += 1;
+        }
+        filtered
+    }
+
+    fn permits(&self, devaddr: Option<u32>, join_eui: Option<u64>) -> bool {
+        if let Some(devaddr) = devaddr {
+            return !self.matcher.has_devaddrs() || self.matcher.matches_devaddr(devaddr);
+        }
+        if let Some(join_eui) = join_eui {
+            return !self.matcher.has_join_euis() || self.matcher.matches_join_eui(join_eui);
+        }
+        true
+    }
+}
+
+/// An fport filter with the number of uplinks it dropped
+#[derive(Debug)]
+struct FportFilter {
+    settings: FportFilterSettings,
+    dropped: u64,
+}
+
+/// The fport filters of the device address subnets, in configured order
+#[derive(Debug, Default)]
+pub struct FportFilters(Vec<FportFilter>);
+
+impl FportFilters {
+    pub fn new(settings: &[FportFilterSettings]) -> Self {
+        Self(
+            settings
+                .iter()
+                .cloned()
+                .map(|settings| FportFilter {
+                    settings,
+                    dropped: 0,
+                })
+                .collect(),
+        )
+    }
+
+    pub fn status(&self) -> Vec<FportFilterStatus> {
+        self.0
+            .iter()
+            .map(|filter| FportFilterStatus {
+                subnet: filter.settings.subnet.to_string(),
+                dropped: filter.dropped,
+            })
+            .collect()
+    }
+
+    /// Returns true and counts the uplink if it is dropped by the first fport
+    /// filter whose subnet contains the device address of the uplink
+    pub fn is_filtered(&mut self, packet: &PacketUp) -> bool {
+        let Some(devaddr) = packet.dev_addr() else {
+            return false;
+        };
+        let fport = match packet.fport() {
+            // MAC command only frames are always permitted
+            None | Some(0) => return false,
+            Some(fport) => fport,
+        };
+        let Some(filter) = self
+            .0
+            .iter_mut()
+            .find(|filter| filter.settings.subnet.contains(devaddr))
+        else {
+            return false;
+        };
+        if filter.settings.fports.contains(&fport) {
+            return false;
+        }
+        filter.dropped += 1;
+        info!(
+            devaddr = format!("{devaddr:08X}"),
+            fport,
+            subnet = %filter.settings.subnet,
+            dropped = filter.dropped,
+            "dropped uplink on filtered fport"
+        );
+        true
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{state_file, Result, Settings};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+use tracing::{info, warn};
+
+/// Name of the breadcrumb file in the data directory
+const BREADCRUMB_FILE: &str = "restart.json";
+
+/// The reason the gateway service was (re)started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartReason {
+    /// No record of a previous run was found
+    FirstStart,
+    /// The previous run shut down cleanly
+    Clean,
+    /// The previous run exited without a clean shutdown
+    Crash,
+    /// The previous run was restarted by a watchdog
+    Watchdog,
+}
+
+impl RestartReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::FirstStart => "first_start",
+            Self::Clean => "clean",
+            Self::Crash => "crash",
+            Self::Watchdog => "watchdog",
+        }
+    }
+}
+
+impl fmt::Display for RestartReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The state of the gateway service as recorded in the breadcrumb file.
+///
+/// The state is set to `running` on startup and to `stopped` on a clean
+/// shutdown. An external watchdog that restarts the service can set the state
+/// to `watchdog` before doing so to have the restart reported as such.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RunState {
+    Running,
+    Stopped,
+    Watchdog,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Breadcrumb {
+    state: RunState,
+    #[serde(default)]
+    restart_count: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UptimeStatus {
+    /// Seconds since the service started
+    pub uptime: u64,
+    pub restart_count: u32,
+    pub last_restart_reason: RestartReason,
+}
+
+/// Tracks uptime of the gateway service and the reason and number of restarts
+/// using a small breadcrumb file in the configured data directory.
+#[derive(Debug, Clone)]
+pub struct Uptime {
+    started: Instant,
+    restart_count: u32,
+    last_restart_reason: RestartReason,
+    path: PathBuf,
+}
+
+impl Uptime {
+    /// Reads the breadcrumb left by the previous run to determine the restart
+    /// reason and marks the service as running. Failures to read or write the
+    /// breadcrumb are logged but do not stop the service.
+    pub fn start(settings: &Settings) -> Self {
+        let path = settings.data_dir.join(BREADCRUMB_FILE);
+        let (last_restart_reason, restart_count) = match read_breadcrumb(&path) {
+            None => (RestartReason::FirstStart, 0),
+            Some(breadcrumb) => {
+                let reason = match breadcrumb.state {
+                    RunState::Running => RestartReason::Crash,
+                    RunState::Stopped => RestartReason::Clean,
+                    RunState::Watchdog => RestartReason::Watchdog,
+                };
+                (reason, breadcrumb.restart_count.saturating_add(1))
+            }
+        };
+        let uptime = Self {
+            started: Instant::now(),
+            restart_count,
+            last_restart_reason,
+            path,
+        };
+        info!(
+            restart_count,
+            last_restart_reason = %last_restart_reason,
+            "gateway started"
+        );
+        uptime.write_state(RunState::Running);
+        uptime
+    }
+
+    /// Marks the service as cleanly shut down.
+    pub fn stopped(&self) {
+        self.write_state(RunState::Stopped);
+    }
+
+    pub fn status(&self) -> UptimeStatus {
+        UptimeStatus {
+            uptime: self.started.elapsed().as_secs(),
+            restart_count: self.restart_count,
+            last_restart_reason: self.last_restart_reason,
+        }
+    }
+
+    fn write_state(&self, state: RunState) {
+        let breadcrumb = Breadcrumb {
+            state,
+            restart_count: self.restart_count,
+        };
+        if let Err(err) = write_breadcrumb(&self.path, &breadcrumb) {
+            warn!(path = %self.path.display(), %err, "failed to write restart breadcrumb");
+        }
+    }
+}
+
+fn read_breadcrumb(path: &Path) -> Option<Breadcrumb> {
+    let data = std::fs::read(path).ok()?;
+    serde_json::from_slice(&data)
+        .map_err(|err| warn!(path = %path.display(), %err, "ignoring invalid restart breadcrumb"))
+        .ok()
+}
+
+fn write_breadcrumb(path: &Path, breadcrumb: &Breadcrumb) -> Result {
+    state_file::write_json(path, breadcrumb)
+}
@@ -0,0 +1,255 @@
+//! Retry queue for witness reports.
+//!
+//! Witness reports that fail to submit, for example on transient ingest
+//! errors during busy beacon windows, are kept in a small queue backed by a
+//! journal in the data directory instead of being dropped. The queue is
+//! retried in batches with an exponential backoff between failed attempts.
+//! While reports are waiting, new witness reports are queued behind them so
+//! reports are submitted in order and a struggling ingest is not flooded.
+//!
+//! Reports held longer than the maximum age are dropped since the ingest
+//! rejects stale witnesses anyway.
+
+use crate::{
+    clock::SharedClock,
+    message_cache::{CacheMessage, MessageCache, MessageSize, Persist},
+    settings::Settings,
+    Result,
+};
+use helium_proto::{services::poc_lora::LoraWitnessReportReqV1, Message};
+use serde::Serialize;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+/// Name of the queue journal in the data directory
+const WITNESS_QUEUE_FILE: &str = "witness_queue.bin";
+/// Maximum size of the queue journal before it is compacted
+const WITNESS_QUEUE_MAX_SIZE: u64 = 1024 * 1024;
+/// Retries until the backoff reaches its maximum wait
+const WITNESS_RETRY_BACKOFF_RETRIES: u32 = 10;
+const WITNESS_RETRY_BACKOFF_MIN_WAIT: Duration = Duration::from_secs(10);
+const WITNESS_RETRY_BACKOFF_MAX_WAIT: Duration = Duration::from_secs(600);
+
+impl Persist for LoraWitnessReportReqV1 {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.encode_to_vec()
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Self> {
+        Ok(Self::decode(data)?)
+    }
+}
+
+impl MessageSize for LoraWitnessReportReqV1 {
+    fn message_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.data.len() + self.pub_key.len() + self.signature.len()
+    }
+}
+
+/// Counters of the witness retry queue
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct WitnessQueueStatus {
+    /// Number of witness reports waiting to be retried
+    pub queued: u32,
+    /// Number of witness report submissions that failed
+    pub failed: u64,
+    /// Number of queued witness reports that were submitted on a retry
+    pub retried: u64,
+    /// Number of queued witness reports dropped because the queue was full or
+    /// they were held too long
+    pub dropped: u64,
+}
+
+#[derive(Debug)]
+pub struct WitnessQueue {
+    store: MessageCache<LoraWitnessReportReqV1>,
+    enabled: bool,
+    /// Maximum number of reports submitted per retry
+    batch: u16,
+    /// Maximum time a report is held in the queue
+    max_age: Duration,
+    backoff: exponential_backoff::Backoff,
+    retry_count: u32,
+    next_retry: Instant,
+    clock: SharedClock,
+    status: WitnessQueueStatus,
+}
+
+impl WitnessQueue {
+    pub fn new(settings: &Settings, clock: SharedClock) -> Self {
+        let size = settings.poc.witness_queue;
+        let max_age = Duration::from_secs(settings.poc.witness_queue_max_age);
+        let path = settings.data_dir.join(WITNESS_QUEUE_FILE);
+        let store = if size > 0 {
+            MessageCache::persistent(size, &path, WITNESS_QUEUE_MAX_SIZE, max_age)
+                .unwrap_or_else(|err| {
+                    warn!(path = %path.display(), %err, "failed to open witness queue journal, queue not persisted");
+                    MessageCache::new(size)
+                })
+        } else {
+            MessageCache::new(size)
+        };
+        let mut queue = Self::with_store(store, settings.poc.witness_queue_batch, max_age, clock);
+        queue.enabled = size > 0;
+        queue
+    }
+
+    fn with_store(
+        store: MessageCache<LoraWitnessReportReqV1>,
+        batch: u16,
+        max_age: Duration,
+        clock: SharedClock,
+    ) -> Self {
+        let mut queue = Self {
+            enabled: true,
+            batch: batch.max(1),
+            max_age,
+            backoff: exponential_backoff::Backoff::new(
+                WITNESS_RETRY_BACKOFF_RETRIES,
+                WITNESS_RETRY_BACKOFF_MIN_WAIT,
+                WITNESS_RETRY_BACKOFF_MAX_WAIT,
+            ),
+            retry_count: 0,
+            next_retry: clock.now() + WITNESS_RETRY_BACKOFF_MIN_WAIT,
+            clock,
+            store,
+            status: WitnessQueueStatus::default(),
+        };
+        queue.update_queued();
+        queue
+    }
+
+    pub fn status(&self) -> WitnessQueueStatus {
+        self.status
+    }
+
+    /// Whether reports are waiting to be retried
+    pub fn is_waiting(&self) -> bool {
+        !self.store.is_empty()
+    }
+
+    /// The time of the next retry
+    pub fn next_retry(&self) -> Instant {
+        self.next_retry
+    }
+
+    /// Retry the queued reports right away, for example once a new ingest
+    /// session is established
+    pub fn retry_now(&mut self) {
+        self.next_retry = self.clock.now();
+    }
+
+    /// Maximum number of reports submitted per retry
+    pub fn batch(&self) -> u16 {
+        self.batch
+    }
+
+    /// Queues a report whose submission failed
+    pub fn failed(&mut self, report: LoraWitnessReportReqV1) {
+        self.status.failed += 1;
+        self.push_back(report);
+    }
+
+    /// Queues a new report behind the waiting ones
+    pub fn push_back(&mut self, report: LoraWitnessReportReqV1) {
+        if !self.enabled {
+            self.status.dropped += 1;
+            return;
+        }
+        if self.store.is_empty() {
+            self.schedule_retry();
+        }
+        let dropped = self.store.push_back(report, self.clock.now().into_std());
+        if dropped > 0 {
+            warn!(dropped, "witness queue full, dropped oldest reports");
+        }
+        self.status.dropped += dropped as u64;
+        self.update_queued();
+    }
+
+    /// Takes the oldest queued report, dropping reports held too long
+    pub fn pop_front(&mut self) -> Option<CacheMessage<LoraWitnessReportReqV1>> {
+        let (dropped, report) = self.store.pop_front(self.max_age);
+        if dropped > 0 {
+            info!(dropped, "dropped stale queued witness reports");
+        }
+        self.status.dropped += dropped as u64;
+        self.update_queued();
+        report
+    }
+
+    /// Puts back a report that failed to submit on a retry and backs off the
+    /// next retry
+    pub fn retry_failed(&mut self, report: CacheMessage<LoraWitnessReportReqV1>) {
+        self.status.failed += 1;
+        self.store.push_front(report);
+        self.update_queued();
+        self.retry_count = (self.retry_count + 1).min(WITNESS_RETRY_BACKOFF_RETRIES);
+        self.schedule_retry();
+    }
+
+    /// Counts a report submitted on a retry
+    pub fn retried(&mut self) {
+        self.status.retried += 1;
+        self.retry_count = 0;
+        self.schedule_retry();
+    }
+
+    fn schedule_retry(&mut self) {
+        let wait = self
+            .backoff
+            .next(self.retry_count)
+            .unwrap_or(WITNESS_RETRY_BACKOFF_MAX_WAIT);
+        self.next_retry = self.clock.now() + wait;
+    }
+
+    fn update_queued(&mut self) {
+        self.status.queued = self.store.len() as u32;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::TestClock;
+    use time::macros::datetime;
+
+    fn report(data: u8) -> LoraWitnessReportReqV1 {
+        LoraWitnessReportReqV1 {
+            data: vec![data],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn witness_queue() {
+        let clock = TestClock::shared(datetime!(2024-05-01 12:00 UTC));
+        let mut queue =
+            WitnessQueue::with_store(MessageCache::new(2), 10, Duration::from_secs(3600), clock);
+        assert!(!queue.is_waiting());
+        queue.failed(report(1));
+        queue.push_back(report(2));
+        queue.failed(report(3));
+        assert_eq!(
+            WitnessQueueStatus {
+                queued: 2,
+                failed: 2,
+                retried: 0,
+                dropped: 1,
+            },
+            queue.status()
+        );
+
+        let first = queue.pop_front().expect("queued report");
+        assert_eq!(vec![2], first.data);
+        queue.retry_failed(first);
+        assert_eq!(2, queue.status().queued);
+        assert_eq!(vec![2], queue.pop_front().expect("queued report").data);
+        queue.retried();
+        assert_eq!(vec![3], queue.pop_front().expect("queued report").data);
+        queue.retried();
+        assert!(!queue.is_waiting());
+        assert_eq!(2, queue.status().retried);
+    }
+}